@@ -243,6 +243,22 @@ public class AppView extends FragmentActivity implements AdapterFragmentCallback
 
         if (poller == null) {
             poller = managerBinder.createAppListPoller(computer);
+            poller.setAppListener(app -> {
+                // Only fill in the grid app by app when there's no cached list showing
+                if (suspendGridUpdates || lastRawApplist != null) {
+                    return;
+                }
+
+                AppView.this.runOnUiThread(() -> {
+                    appGridAdapter.addApp(new AppObject(app));
+                    appGridAdapter.notifyDataSetChanged();
+
+                    if (blockingLoadSpinner != null) {
+                        blockingLoadSpinner.dismiss();
+                        blockingLoadSpinner = null;
+                    }
+                });
+            });
         }
         poller.start();
     }
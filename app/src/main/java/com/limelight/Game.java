@@ -205,6 +205,9 @@ public class Game extends Activity implements SurfaceHolder.Callback,
     private static final Object sWireGuardLock = new Object();
     private static int sWireGuardGeneration = 0; // protected by sWireGuardLock
 
+    // How long the startup MTU probe waits for echo replies from the host
+    private static final int WG_MTU_PROBE_TIMEOUT_MS = 3000;
+
     private boolean connectedToUsbDriverService = false;
     private final ServiceConnection usbDriverServiceConnection = new ServiceConnection() {
         @Override
@@ -636,6 +639,9 @@ public class Game extends Activity implements SurfaceHolder.Callback,
     }
 
     private int getSupportedVideoFormats(boolean willStreamHdr) {
+        // Let the native layer drop anything the decoder can't actually handle
+        MoonBridge.nativeSetDecoderVideoFormats(getDecoderVideoFormats());
+
         int supportedVideoFormats = MoonBridge.VIDEO_FORMAT_H265;
         if (willStreamHdr && decoderRenderer.isHevcMain10Hdr10Supported()) {
             supportedVideoFormats |= MoonBridge.VIDEO_FORMAT_H265_MAIN10;
@@ -647,7 +653,24 @@ public class Game extends Activity implements SurfaceHolder.Callback,
                 supportedVideoFormats |= MoonBridge.VIDEO_FORMAT_AV1_MAIN10;
             }
         }
-        return supportedVideoFormats;
+        return MoonBridge.nativeComputeSupportedVideoFormats(supportedVideoFormats);
+    }
+
+    private int getDecoderVideoFormats() {
+        int formats = MoonBridge.VIDEO_FORMAT_H264;
+        if (decoderRenderer.isHevcSupported()) {
+            formats |= MoonBridge.VIDEO_FORMAT_H265;
+            if (decoderRenderer.isHevcMain10Hdr10Supported()) {
+                formats |= MoonBridge.VIDEO_FORMAT_H265_MAIN10;
+            }
+        }
+        if (decoderRenderer.isAv1Supported()) {
+            formats |= MoonBridge.VIDEO_FORMAT_AV1_MAIN8;
+            if (decoderRenderer.isAv1Main10Supported()) {
+                formats |= MoonBridge.VIDEO_FORMAT_AV1_MAIN10;
+            }
+        }
+        return formats;
     }
 
     private void setPreferredOrientationForCurrentDisplay() {
@@ -1291,12 +1314,31 @@ public class Game extends Activity implements SurfaceHolder.Callback,
                 // Register network change callback to rebind WG endpoint socket
                 // when the device switches between WiFi and mobile data.
                 registerWgNetworkCallback();
+
+                startWgMtuProbe(host);
             } catch (Exception e) {
                 Log.e(TAG, "Failed to setup WireGuard routing", e);
             }
         }
     }
 
+    /**
+     * Check in the background that full-size packets make it through the tunnel to
+     * the host, so a too-large MTU is reported instead of showing up as a black
+     * screen with working audio. Hosts that don't answer pings are left alone.
+     */
+    private void startWgMtuProbe(final String host) {
+        Thread probeThread = new Thread(() -> {
+            int result = WireGuardManager.probeMtu(host, WG_MTU_PROBE_TIMEOUT_MS);
+            if (result == WireGuardManager.MTU_PROBE_POSSIBLE_ISSUE) {
+                runOnUiThread(() -> Toast.makeText(Game.this,
+                        R.string.wg_mtu_issue_suspected, Toast.LENGTH_LONG).show());
+            }
+        }, "WgMtuProbe");
+        probeThread.setDaemon(true);
+        probeThread.start();
+    }
+
     /**
      * Register a NetworkCallback to detect network changes (WiFi ↔ mobile)
      * and rebind the WireGuard endpoint socket so the tunnel survives handoffs.
@@ -2457,16 +2499,17 @@ public class Game extends Activity implements SurfaceHolder.Callback,
                 // significantly different than before.
                 if (inputCaptureProvider.eventHasRelativeMouseAxes(event)) {
                     // Send the deltas straight from the motion event
-                    short deltaX = (short) inputCaptureProvider.getRelativeAxisX(event);
-                    short deltaY = (short) inputCaptureProvider.getRelativeAxisY(event);
+                    float deltaX = inputCaptureProvider.getRelativeAxisX(event);
+                    float deltaY = inputCaptureProvider.getRelativeAxisY(event);
 
                     if (deltaX != 0 || deltaY != 0) {
                         if (prefConfig.absoluteMouseMode) {
                             // NB: view may be null, but we can unconditionally use streamView because we don't need to adjust
                             // relative axis deltas for the position of the streamView within the parent's coordinate system.
-                            conn.sendMouseMoveAsMousePosition(deltaX, deltaY, (short) streamView.getWidth(), (short) streamView.getHeight());
+                            conn.sendMouseMoveAsMousePosition((short) deltaX, (short) deltaY, (short) streamView.getWidth(), (short) streamView.getHeight());
                         } else {
-                            conn.sendMouseMove(deltaX, deltaY);
+                            // Keep the sub-pixel part of high-DPI mouse and touchpad movement
+                            conn.sendMouseMoveHighRes(deltaX, deltaY);
                         }
                     }
                 } else if ((eventSource & InputDevice.SOURCE_CLASS_POSITION) != 0) {
@@ -2777,19 +2820,23 @@ public class Game extends Activity implements SurfaceHolder.Callback,
     }
 
     @Override
-    public void stageStarting(final String stage) {
+    public void stageStarting(final String stage, final int stageIndex, final int totalStages) {
         runOnUiThread(new Runnable() {
             @Override
             public void run() {
                 if (spinner != null) {
-                    spinner.setMessage(getResources().getString(R.string.conn_starting) + " " + stage);
+                    String message = getResources().getString(R.string.conn_starting) + " " + stage;
+                    if (stageIndex > 0) {
+                        message += " (" + stageIndex + "/" + totalStages + ")";
+                    }
+                    spinner.setMessage(message);
                 }
             }
         });
     }
 
     @Override
-    public void stageComplete(String stage) {
+    public void stageComplete(String stage, int stageIndex, int totalStages) {
     }
 
     private void stopConnection() {
@@ -2842,7 +2889,8 @@ public class Game extends Activity implements SurfaceHolder.Callback,
     }
 
     @Override
-    public void stageFailed(final String stage, final int portFlags, final int errorCode) {
+    public void stageFailed(final String stage, final int stageIndex, final int totalStages,
+                            final int portFlags, final int errorCode) {
         // Perform a connection test if the failure could be due to a blocked port
         // This does network I/O, so don't do it on the main thread.
         final int portTestResult = MoonBridge.testClientConnectivity(ServerHelper.CONNECTION_TEST_SERVER, 443, portFlags);
@@ -2946,9 +2994,13 @@ public class Game extends Activity implements SurfaceHolder.Callback,
                             }
                         }
 
-                        if (portFlags != 0) {
-                            message += "\n\n" + getResources().getString(R.string.check_ports_msg) + "\n" +
-                                    MoonBridge.stringifyPortFlags(portFlags, "\n");
+                        MoonBridge.PortInfo[] ports = MoonBridge.getTerminationPortInfo(errorCode);
+                        if (ports.length != 0) {
+                            StringBuilder portList = new StringBuilder();
+                            for (MoonBridge.PortInfo port : ports) {
+                                portList.append('\n').append(port.portName);
+                            }
+                            message += "\n\n" + getResources().getString(R.string.check_ports_msg) + portList;
                         }
 
                         Dialog.displayDialog(Game.this, getResources().getString(R.string.conn_terminated_title),
@@ -3066,6 +3118,29 @@ public class Game extends Activity implements SurfaceHolder.Callback,
         controllerHandler.handleSetControllerLED(controllerNumber, r, g, b);
     }
 
+    @Override
+    public void tunnelDataFlowing() {
+        Log.i(TAG, "WireGuard tunnel is carrying stream data");
+    }
+
+    @Override
+    public void tunnelNoData(int waitedSeconds) {
+        Log.w(TAG, "WireGuard handshake completed but no data for " + waitedSeconds +
+                "s; check the server's AllowedIPs for this peer");
+    }
+
+    @Override
+    public void decoderOverloaded(int averageLatencyMs, int windowMs) {
+        Log.w(TAG, "Decoder overloaded: " + averageLatencyMs + "ms per frame for " + windowMs +
+                "ms; consider a lower resolution or frame rate");
+    }
+
+    @Override
+    public void videoFormatNegotiated(int videoFormat, int width, int height, int redrawRate) {
+        Log.i(TAG, "Negotiated video format: " + MoonBridge.getVideoFormatName(videoFormat) +
+                " " + width + "x" + height + "@" + redrawRate);
+    }
+
     @Override
     public void surfaceChanged(@NonNull SurfaceHolder holder, int format, int width, int height) {
         if (!surfaceCreated) {
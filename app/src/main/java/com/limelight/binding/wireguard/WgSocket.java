@@ -28,6 +28,21 @@ public class WgSocket extends Socket {
         }
     }
 
+    // Native result when the tunnel the connection was opened on has been stopped or
+    // restarted (e.g. a DDNS rebind recreated it). The connection is gone for good.
+    private static final int ERR_TUNNEL_RESTARTED = -4;
+
+    /**
+     * Thrown when the WireGuard tunnel behind this socket was restarted. The
+     * connection no longer exists; open a new socket rather than treating this
+     * as a data error.
+     */
+    public static class TunnelRestartedException extends SocketException {
+        TunnelRestartedException() {
+            super("WireGuard tunnel restarted, reconnect");
+        }
+    }
+
     // Native connection handle (managed by Rust)
     private long nativeHandle = 0;
 
@@ -51,10 +66,24 @@ public class WgSocket extends Socket {
     private WgInputStream inputStream;
     private WgOutputStream outputStream;
 
+    // Hard deadline for the connect and every read, in ms from connect (0 = none)
+    private final int deadlineMs;
+
     /**
      * Create an unconnected WgSocket
      */
     public WgSocket() {
+        this(0);
+    }
+
+    /**
+     * Create an unconnected WgSocket whose connect and reads must all finish
+     * within deadlineMs of the connect call. Once the deadline passes, reads
+     * throw SocketTimeoutException regardless of the SO_TIMEOUT setting.
+     * @param deadlineMs Overall deadline in milliseconds, or 0 for none
+     */
+    public WgSocket(int deadlineMs) {
+        this.deadlineMs = deadlineMs;
         // Allocate a local port placeholder
         localAddress = new InetSocketAddress("0.0.0.0", 0);
     }
@@ -82,10 +111,15 @@ public class WgSocket extends Socket {
                 : inetEndpoint.getHostName();
         int port = inetEndpoint.getPort();
 
-        Log.i(TAG, "Connecting to " + host + ":" + port + " via WireGuard (timeout=" + timeout + "ms)");
+        Log.i(TAG, "Connecting to " + host + ":" + port + " via WireGuard (timeout=" + timeout
+                + "ms, deadline=" + deadlineMs + "ms)");
 
         // Create native connection through VirtualStack
-        nativeHandle = nativeConnect(host, port, timeout > 0 ? timeout : 10000);
+        if (deadlineMs > 0) {
+            nativeHandle = nativeConnectWithDeadline(host, port, deadlineMs);
+        } else {
+            nativeHandle = nativeConnect(host, port, timeout > 0 ? timeout : 10000);
+        }
 
         if (nativeHandle == 0) {
             throw new IOException("Failed to establish WireGuard connection to " + host + ":" + port);
@@ -169,11 +203,24 @@ public class WgSocket extends Socket {
     }
 
     @Override
-    public void shutdownOutput() throws IOException {
+    public synchronized void shutdownOutput() throws IOException {
         if (closed) {
             throw new SocketException("Socket is closed");
         }
+        if (outputShutdown) {
+            return;
+        }
         outputShutdown = true;
+
+        // Send our FIN but keep reading until the server closes its side
+        if (nativeHandle != 0) {
+            int result = nativeShutdownOutput(nativeHandle);
+            if (result == ERR_TUNNEL_RESTARTED) {
+                throw new TunnelRestartedException();
+            } else if (result < 0) {
+                throw new IOException("Failed to shut down output");
+            }
+        }
     }
 
     @Override
@@ -342,6 +389,8 @@ public class WgSocket extends Socket {
 
         if (result == -2) {
             throw new SocketTimeoutException("Read timed out");
+        } else if (result == ERR_TUNNEL_RESTARTED) {
+            throw new TunnelRestartedException();
         } else if (result < 0) {
             throw new IOException("Native read error: " + result);
         } else if (result == 0) {
@@ -353,6 +402,31 @@ public class WgSocket extends Socket {
         return result;
     }
 
+    /**
+     * Read whatever data has already arrived, without waiting for more
+     * @param buffer Buffer to read into
+     * @param offset Start offset in buffer
+     * @param length Maximum bytes to read
+     * @return Number of bytes read, 0 if none are available, or -1 on EOF
+     */
+    public int readAvailable(byte[] buffer, int offset, int length) throws IOException {
+        if (closed || inputShutdown) {
+            return -1;
+        }
+
+        int result = nativeRecvAvailable(nativeHandle, buffer, offset, length);
+
+        if (result == -3) {
+            return -1;
+        } else if (result == ERR_TUNNEL_RESTARTED) {
+            throw new TunnelRestartedException();
+        } else if (result < 0) {
+            throw new IOException("Native read error: " + result);
+        }
+
+        return result;
+    }
+
     /**
      * Write data to the native socket
      * @param buffer Data to write
@@ -366,7 +440,29 @@ public class WgSocket extends Socket {
 
         int result = nativeSend(nativeHandle, buffer, offset, length);
 
-        if (result < 0) {
+        if (result == ERR_TUNNEL_RESTARTED) {
+            throw new TunnelRestartedException();
+        } else if (result < 0) {
+            throw new IOException("Native write error: " + result);
+        }
+    }
+
+    /**
+     * Write several buffers to the native socket in a single native call.
+     * The buffers are sent back-to-back in array order, so many small writes
+     * (e.g. a streamed certificate upload) cost one JNI transition.
+     * @param buffers Data to write; null entries are skipped
+     */
+    public void writev(byte[][] buffers) throws IOException {
+        if (closed || outputShutdown) {
+            throw new IOException("Socket is closed or output shutdown");
+        }
+
+        int result = nativeSendv(nativeHandle, buffers);
+
+        if (result == ERR_TUNNEL_RESTARTED) {
+            throw new TunnelRestartedException();
+        } else if (result < 0) {
             throw new IOException("Native write error: " + result);
         }
     }
@@ -388,6 +484,15 @@ public class WgSocket extends Socket {
      */
     private static native long nativeConnect(String host, int port, int timeoutMs);
 
+    /**
+     * Create a TCP connection whose connect and reads share one hard deadline
+     * @param host Target host IP
+     * @param port Target port
+     * @param deadlineMs Time from now by which the connect and every read must finish
+     * @return Native handle, or 0 on failure
+     */
+    private static native long nativeConnectWithDeadline(String host, int port, int deadlineMs);
+
     /**
      * Get the local port allocated for this connection
      */
@@ -400,24 +505,53 @@ public class WgSocket extends Socket {
      * @param offset Offset in buffer
      * @param length Maximum bytes to receive
      * @param timeoutMs Read timeout (0 = no timeout)
-     * @return Bytes received, 0 on EOF, -1 on error, -2 on timeout
+     * @return Bytes received, 0 on EOF, -1 on error, -2 on timeout,
+     *         -4 if the tunnel restarted since the connection was opened
      */
     private static native int nativeRecv(long handle, byte[] buffer, int offset, int length, int timeoutMs);
 
+    /**
+     * Receive already-buffered data without blocking
+     * @param handle Native handle
+     * @param buffer Buffer to receive into
+     * @param offset Offset in buffer
+     * @param maxLen Maximum bytes to receive
+     * @return Bytes received, 0 if none available, -1 on error, -3 on EOF,
+     *         -4 if the tunnel restarted since the connection was opened
+     */
+    private static native int nativeRecvAvailable(long handle, byte[] buffer, int offset, int maxLen);
+
     /**
      * Send data through the connection
      * @param handle Native handle
      * @param buffer Data to send
      * @param offset Offset in buffer
      * @param length Number of bytes to send
-     * @return Bytes sent, or negative on error
+     * @return Bytes sent, or negative on error (-4 if the tunnel restarted)
      */
     private static native int nativeSend(long handle, byte[] buffer, int offset, int length);
 
     /**
-     * Close the connection
+     * Send several buffers through the connection, contiguously and in order
+     * @param handle Native handle
+     * @param buffers Data to send
+     * @return Total bytes sent, or negative on error (-4 if the tunnel restarted)
+     */
+    private static native int nativeSendv(long handle, byte[][] buffers);
+
+    /**
+     * Half-close the connection: send FIN but keep receiving
+     * @param handle Native handle
+     * @return 0 on success, -1 on error, -4 if the tunnel restarted
+     */
+    private static native int nativeShutdownOutput(long handle);
+
+    /**
+     * Close the connection. The handle is released in every case.
+     * @return 0 on success, -1 for an unknown handle, -4 if the tunnel had already
+     *         restarted (nothing left to close)
      */
-    private static native void nativeClose(long handle);
+    private static native int nativeClose(long handle);
 }
 
 /**
@@ -7,9 +7,12 @@ import java.net.InetAddress;
 import java.net.InetSocketAddress;
 import java.net.Socket;
 import java.net.UnknownHostException;
+import java.util.concurrent.TimeUnit;
 
 import javax.net.SocketFactory;
 
+import okhttp3.ConnectionPool;
+
 /**
  * Custom SocketFactory that creates WgSocket instances for routing TCP traffic
  * directly through WireGuard via JNI.
@@ -25,6 +28,15 @@ public class WgSocketFactory extends SocketFactory {
     private static final String TAG = "WgSocketFactory";
     
     private static volatile WgSocketFactory instance;
+
+    // Idle keep-alive connections kept per process. Grid population issues dozens of
+    // sequential requests to the same host, so reusing the tunneled TCP connection
+    // avoids a full connect over the tunnel for each one.
+    private static final int MAX_IDLE_CONNECTIONS = 4;
+    private static final long KEEP_ALIVE_DURATION_MS = 30000;
+
+    private static final ConnectionPool connectionPool =
+            new ConnectionPool(MAX_IDLE_CONNECTIONS, KEEP_ALIVE_DURATION_MS, TimeUnit.MILLISECONDS);
     
     private WgSocketFactory() {
     }
@@ -44,6 +56,22 @@ public class WgSocketFactory extends SocketFactory {
         return instance;
     }
     
+    /**
+     * Get the shared OkHttp connection pool for WireGuard-routed requests.
+     * Connections are only closed on idle timeout, server FIN, or when the
+     * server does not advertise keep-alive.
+     */
+    public static ConnectionPool getConnectionPool() {
+        return connectionPool;
+    }
+
+    /**
+     * Close all idle pooled connections (e.g. when the tunnel configuration changes).
+     */
+    public static void evictIdleConnections() {
+        connectionPool.evictAll();
+    }
+    
     /**
      * Check if WgSocketFactory should be used (i.e., WireGuard is configured).
      */
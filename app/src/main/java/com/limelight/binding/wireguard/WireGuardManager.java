@@ -76,6 +76,11 @@ public class WireGuardManager {
             return this;
         }
 
+        /**
+         * Set the client's tunnel address. A dual-stack tunnel takes its IPv4 and
+         * IPv6 addresses comma-separated (e.g. "10.0.0.2, fd00::2"); each inner
+         * packet is sent from the address of its destination's family.
+         */
         public Config setTunnelAddress(String tunnelAddress) {
             this.tunnelAddress = tunnelAddress;
             return this;
@@ -140,6 +145,266 @@ public class WireGuardManager {
         statusCallback = callback;
     }
 
+    /** Tunnel is not running */
+    public static final int STATE_STOPPED = 0;
+    /** Tunnel is performing its initial handshake */
+    public static final int STATE_CONNECTING = 1;
+    /** Handshake completed, traffic is flowing */
+    public static final int STATE_CONNECTED = 2;
+    /** Tunnel lost its session (network change, DDNS re-resolution) and is re-handshaking */
+    public static final int STATE_RECONNECTING = 3;
+    /** Repeated handshakes got no answer from the server; still retrying in the background */
+    public static final int STATE_UNREACHABLE = 4;
+
+    /**
+     * Listener for tunnel state transitions reported by the native tunnel.
+     * Invoked from a native thread, not the UI thread.
+     */
+    public interface StateListener {
+        void onTunnelStateChanged(int state);
+    }
+
+    private static volatile StateListener stateListener;
+
+    /**
+     * Set the listener for tunnel state transitions, or null to stop receiving them
+     */
+    public static void setStateListener(StateListener listener) {
+        stateListener = listener;
+        nativeSetWgStateListener(listener != null);
+    }
+
+    /**
+     * Set how many consecutive failed handshake attempts (about 90s each) are
+     * reported as STATE_UNREACHABLE. The tunnel keeps retrying either way, so a
+     * manual retry (e.g. an endpoint rebind) can still recover it. 0 disables
+     * escalation; the default is 3.
+     */
+    public static void setHandshakeEscalationThreshold(int attempts) {
+        nativeWgSetHandshakeEscalationThreshold(attempts);
+    }
+
+    /**
+     * Set how many unanswered handshake retries are sent from one source port
+     * while starting the tunnel before the endpoint socket moves to a fresh
+     * ephemeral port. A new port gets a new NAT mapping, which helps on networks
+     * where the original port is blocked. 0 keeps the original port; the
+     * default is 3. Takes effect on the next start.
+     */
+    public static void setSourcePortRotationRetries(int retries) {
+        nativeWgSetSourcePortRotationRetries(retries);
+    }
+
+    /**
+     * Let packets sent through the tunnel while {@link #startTunnel(Config)} is still
+     * waiting for its handshake be held, up to maxPackets, and sent the moment the
+     * session keys exist, instead of failing. This saves a handshake round trip for
+     * callers that start sending early. Packets held when the handshake fails are
+     * dropped. 0 disables it (the default). Takes effect on the next start.
+     */
+    public static void setEarlySendQueue(int maxPackets) {
+        nativeWgSetEarlySendQueue(maxPackets);
+    }
+
+    /**
+     * Set how the handshake initiation is re-sent while {@link #startTunnel(Config)}
+     * waits for the initial handshake. The first retry comes after initialMs and
+     * each following interval grows by multiplier, capped at maxMs. Retries stop
+     * at the 15s start timeout, so slower schedules make fewer attempts. The
+     * default is 1000ms doubling up to 4000ms; on satellite links an initial
+     * interval of 3000ms avoids retrying while the first initiation is in flight.
+     * Applies to tunnels started after the call.
+     *
+     * @return false if the schedule is invalid (zero interval, multiplier outside
+     *         1 to 10, or cap below the initial interval)
+     */
+    public static boolean setHandshakeRetrySchedule(int initialMs, float multiplier, int maxMs) {
+        return nativeWgSetHandshakeRetrySchedule(initialMs, multiplier, maxMs);
+    }
+
+    /**
+     * Set how many seconds before a session is due for re-keying (every 120s)
+     * the tunnel starts the next handshake, so the new keys are ready before
+     * the old ones are retired and the stream sees no gap. 0 disables proactive
+     * re-keying; values above 60 are clamped. The default is 15.
+     */
+    public static void setRekeyLeadTime(int seconds) {
+        nativeWgSetRekeyLeadTime(seconds);
+    }
+
+    /**
+     * Set how many consecutive sends to the WireGuard endpoint must fail with
+     * EPERM before the endpoint socket is rebound automatically. Android returns
+     * EPERM for a moment during network changes, which is ignored, but a socket
+     * that keeps failing is stuck on a network that went away and won't recover
+     * until rebound. Automatic rebinds are at least 5 seconds apart. 0 disables
+     * them; the default is 50.
+     */
+    public static void setEpermRebindThreshold(int threshold) {
+        nativeWgSetEpermRebindThreshold(threshold);
+    }
+
+    /**
+     * Set how long the tunnel may go after a completed handshake without
+     * receiving a single data packet before it is reported as "tunnel up but no
+     * data". That pattern points at the server side (AllowedIPs or routing for
+     * this peer) rather than at reachability. Reported through
+     * {@link com.limelight.nvstream.NvConnectionListener#tunnelNoData(int)} and
+     * {@link #isNoDataSuspected()}. 0 disables it; the default is 30.
+     */
+    public static void setNoDataWindow(int seconds) {
+        nativeWgSetNoDataWindow(seconds);
+    }
+
+    /**
+     * Whether the tunnel completed its handshake but has received no data for
+     * the whole no-data window since it started.
+     */
+    public static boolean isNoDataSuspected() {
+        return nativeWgIsNoDataSuspected();
+    }
+
+    /**
+     * Set how long the tunnel's receiver thread may go without making progress
+     * before it is considered stuck. A stuck receiver is restarted on a fresh
+     * endpoint socket. 0 disables the check; the default is 5000.
+     */
+    public static void setReceiverStallWindow(int windowMs) {
+        nativeWgSetReceiverStallWindow(windowMs);
+    }
+
+    /**
+     * Number of times the receiver thread was found stuck and restarted since
+     * the tunnel started.
+     */
+    public static long getReceiverStallCount() {
+        return nativeWgGetReceiverStallCount();
+    }
+
+    /** Both the small and the full-MTU probe came back */
+    public static final int MTU_PROBE_OK = 0;
+    /** Only the small probe came back: full-size packets (video) are lost on the path */
+    public static final int MTU_PROBE_POSSIBLE_ISSUE = 1;
+    /** No probe came back (host ignores pings, IPv6 tunnel, or no tunnel running) */
+    public static final int MTU_PROBE_INCONCLUSIVE = 2;
+
+    /**
+     * Startup diagnostic for the "audio works, video stays black" failure, run by Game:
+     * pings the host's tunnel address with a small and a full-MTU packet and checks
+     * which come back. Blocks for at most timeoutMs, so call it off the UI thread
+     * after the tunnel has started. A possible issue is logged and reported by
+     * {@link #isMtuIssueSuspected()}.
+     *
+     * @param serverAddress IPv4 address of the host inside the tunnel
+     * @return One of the MTU_PROBE_* values
+     */
+    public static int probeMtu(String serverAddress, int timeoutMs) {
+        return nativeWgProbeMtu(serverAddress, timeoutMs);
+    }
+
+    /**
+     * Whether the last {@link #probeMtu(String, int)} since the tunnel started saw
+     * small packets get through but full-size ones lost.
+     */
+    public static boolean isMtuIssueSuspected() {
+        return nativeWgIsMtuIssueSuspected();
+    }
+
+    /**
+     * Describe reconnect activity since the tunnel was started, for field debugging.
+     * One "key=value" per line: rehandshakes, ddns_reresolutions, endpoint_rebinds
+     * and last_error (empty if none).
+     */
+    public static String getTunnelStats() {
+        return nativeGetTunnelStats();
+    }
+
+    /**
+     * Dump the routing state of every socket the tunnel tracks, for attaching to
+     * bug reports: zero-copy UDP sockets with their ports and queue depths,
+     * virtual TCP sockets, inject mappings and packets waiting for a socket.
+     * Safe to call while streaming.
+     */
+    public static String dumpSockets() {
+        return nativeWgDumpSockets();
+    }
+
+    /**
+     * Describe how full the WireGuard endpoint socket's kernel receive queue has been
+     * since the tunnel was started, as "high_water=N capacity=N over_threshold=N".
+     * The byte figures are -1 where the platform can't report them; over_threshold
+     * counts samples (every 250ms) that found the queue above 75% full. A queue that
+     * keeps running near capacity means the receiver thread isn't draining fast
+     * enough and the kernel is about to drop packets.
+     */
+    public static String getRxQueueStats() {
+        return nativeWgGetRxQueueStats();
+    }
+
+    /**
+     * Describe the current WireGuard session for auditing re-keying, as
+     * "session=N keys_current=0|1 tx_bytes=N rx_bytes=N since_handshake_ms=N".
+     * The session number goes up with every completed handshake and the byte
+     * counts restart with it. No key material is included.
+     *
+     * Only available with a debug build of the native library.
+     *
+     * @return The session info, or null if no tunnel is running or the native
+     *         library is a release build
+     */
+    public static String getDebugSessionInfo() {
+        try {
+            return nativeWgDebugSessionInfo();
+        } catch (UnsatisfiedLinkError e) {
+            return null;
+        }
+    }
+
+    /**
+     * Act as if the device switched networks: the endpoint socket is rebound and
+     * a new handshake started, as {@link com.limelight.nvstream.jni.MoonBridge#wgRebindEndpoint()}
+     * does on a real change.
+     * For exercising recovery without toggling Wi-Fi.
+     *
+     * Only available with a debug build of the native library.
+     *
+     * @return true if the rebind succeeded; false if no tunnel is running or the
+     *         native library is a release build
+     */
+    public static boolean debugSimulateNetworkChange() {
+        try {
+            return nativeWgDebugSimulateNetworkChange();
+        } catch (UnsatisfiedLinkError e) {
+            return false;
+        }
+    }
+
+    /**
+     * Act as if the endpoint's DNS record changed: its next resolution returns
+     * newIp (with the configured port) and the tunnel re-resolves right away,
+     * going through the same reconnect path as a real DDNS change.
+     *
+     * Only available with a debug build of the native library.
+     *
+     * @return true if the change was scheduled; false if no tunnel is running,
+     *         the address is invalid or the native library is a release build
+     */
+    public static boolean debugSimulateEndpointIpChange(String newIp) {
+        try {
+            return nativeWgDebugSimulateEndpointIpChange(newIp);
+        } catch (UnsatisfiedLinkError e) {
+            return false;
+        }
+    }
+
+    // Called by native code when the tunnel state changes
+    private static void bridgeWgStateChanged(int state) {
+        StateListener listener = stateListener;
+        if (listener != null) {
+            listener.onTunnelStateChanged(state);
+        }
+    }
+
     /**
      * Resolve endpoint hostname to IP address for DDNS support.
      * The endpoint format is "hostname:port" or "ip:port".
@@ -241,6 +506,47 @@ public class WireGuardManager {
         }
     }
 
+    /**
+     * Resolve the tunnel endpoint ahead of {@link #startTunnel(Config)}.
+     * The result is cached briefly so starting the tunnel doesn't wait on DNS.
+     * Blocks on DNS resolution; do not call from the UI thread.
+     * @param endpoint Endpoint as "host:port", the same string passed in the Config
+     * @return The resolved address, or null if resolution failed
+     */
+    public static String resolveEndpoint(String endpoint) {
+        if (endpoint == null || endpoint.isEmpty()) {
+            return null;
+        }
+        return nativeWgResolveEndpoint(endpoint);
+    }
+
+    /**
+     * Cancel a {@link #startTunnel(Config)} that is still waiting for the initial
+     * handshake (up to 15 seconds). The blocked start returns false promptly and
+     * no tunnel is left running. Safe to call from the UI thread.
+     */
+    public static void cancelConnect() {
+        nativeWgCancelConnect();
+    }
+
+    /**
+     * Quiesce the tunnel while the app is backgrounded. The receive thread stops
+     * reading and keepalives/DDNS are suspended, but the session is kept so
+     * {@link #resume()} needs no reconnect. Unlike the device sleep notification,
+     * this also idles the hot receive thread.
+     */
+    public static void pause() {
+        nativeWgPause();
+    }
+
+    /**
+     * Resume a tunnel paused by {@link #pause()}. A fresh handshake is started
+     * if the pause was long enough for the NAT mapping to expire.
+     */
+    public static void resume() {
+        nativeWgResume();
+    }
+
     /**
      * Stop the WireGuard tunnel
      */
@@ -325,9 +631,33 @@ public class WireGuardManager {
     );
 
     private static native void nativeStopTunnel();
+    private static native void nativeWgCancelConnect();
+    private static native void nativeWgPause();
+    private static native void nativeWgResume();
     private static native boolean nativeIsTunnelActive();
     private static native byte[] nativeGeneratePrivateKey();
     private static native byte[] nativeDerivePublicKey(byte[] privateKey);
+    private static native void nativeSetWgStateListener(boolean enabled);
+    private static native void nativeWgSetHandshakeEscalationThreshold(int attempts);
+    private static native void nativeWgSetSourcePortRotationRetries(int retries);
+    private static native void nativeWgSetEarlySendQueue(int maxPackets);
+    private static native void nativeWgSetRekeyLeadTime(int seconds);
+    private static native void nativeWgSetEpermRebindThreshold(int threshold);
+    private static native void nativeWgSetNoDataWindow(int seconds);
+    private static native boolean nativeWgIsNoDataSuspected();
+    private static native void nativeWgSetReceiverStallWindow(int windowMs);
+    private static native long nativeWgGetReceiverStallCount();
+    private static native boolean nativeWgSetHandshakeRetrySchedule(int initialMs, float multiplier, int maxMs);
+    private static native int nativeWgProbeMtu(String serverAddress, int timeoutMs);
+    private static native boolean nativeWgIsMtuIssueSuspected();
+    private static native String nativeGetTunnelStats();
+    private static native String nativeWgDumpSockets();
+    private static native String nativeWgGetRxQueueStats();
+    // Debug native builds only
+    private static native String nativeWgDebugSessionInfo();
+    private static native boolean nativeWgDebugSimulateNetworkChange();
+    private static native boolean nativeWgDebugSimulateEndpointIpChange(String newIp);
+    private static native String nativeWgResolveEndpoint(String endpoint);
 
     // ========================================================================
     // Direct HTTP through WireGuard (bypasses OkHttp)
@@ -351,7 +681,7 @@ public class WireGuardManager {
     }
 
     /**
-     * Get the currently configured tunnel address.
+     * Get the currently configured tunnel address (the first one of a dual-stack tunnel).
      * @return The tunnel address (e.g. "10.0.0.2"), or null if not configured
      */
     public static String getCurrentTunnelAddress() {
@@ -387,9 +717,11 @@ public class WireGuardManager {
             );
 
             if (result) {
+                // Pooled connections belong to the previous tunnel configuration
+                WgSocketFactory.evictIdleConnections();
                 httpConfigured = true;
                 httpConfigGeneration++;
-                currentTunnelAddress = config.tunnelAddress;
+                currentTunnelAddress = config.tunnelAddress.split(",")[0].trim();
                 Log.i(TAG, "WireGuard HTTP client configured, tunnel address: " + currentTunnelAddress + ", generation: " + httpConfigGeneration);
             }
             return result;
@@ -403,6 +735,7 @@ public class WireGuardManager {
      * Clear the WireGuard HTTP client configuration.
      */
     public static void clearHttpConfig() {
+        WgSocketFactory.evictIdleConnections();
         nativeHttpClearConfig();
         httpConfigured = false;
         currentTunnelAddress = null;
@@ -416,6 +749,80 @@ public class WireGuardManager {
         return httpConfigured && nativeHttpIsConfigured();
     }
 
+    /**
+     * Apply a new MTU (e.g. from MTU probing) to TCP connections through WireGuard.
+     * Open connections use the new segment size for subsequent sends.
+     *
+     * @param mtu Tunnel MTU (576-65535)
+     */
+    public static void setHttpMtu(int mtu) {
+        if (mtu < 576 || mtu > 65535) {
+            Log.e(TAG, "Invalid HTTP MTU: " + mtu);
+            return;
+        }
+        nativeHttpSetMtu(mtu);
+    }
+
+    /**
+     * Configure SYN retransmission for TCP connections through WireGuard.
+     * A lost SYN is re-sent after initialMs, doubling up to maxMs, until the
+     * connect timeout expires. Pass 0 to restore the defaults (500ms / 4000ms).
+     */
+    public static void setHttpConnectRetry(int initialMs, int maxMs) {
+        nativeHttpSetConnectRetry(initialMs, maxMs);
+    }
+
+    /**
+     * Set how many times a TCP connection through WireGuard is attempted.
+     * Tunnel handshake timeouts and refused connections (common on the first
+     * request after the device wakes) are retried with backoff within the
+     * connect timeout; other errors fail at once. 1 disables retries, 0 restores
+     * the default of 3.
+     */
+    public static void setHttpConnectAttempts(int attempts) {
+        nativeHttpSetConnectAttempts(attempts);
+    }
+
+    /**
+     * Cap how many TCP connections may be open or connecting through WireGuard at
+     * once. Connects beyond the cap fail immediately, which protects low-end
+     * devices from a runaway reconnect loop. Pass 0 for the default of 128.
+     */
+    public static void setHttpMaxConnections(int maxConnections) {
+        nativeHttpSetMaxConnections(maxConnections);
+    }
+
+    /**
+     * Configure TCP keepalive for idle connections through WireGuard (e.g. a pairing
+     * connection waiting on the user). After idleSeconds without traffic a probe is
+     * sent every intervalSeconds; after maxProbes unanswered probes the connection
+     * is closed. Pass idleSeconds <= 0 to disable (default: 60s / 15s / 4 probes).
+     */
+    public static void setHttpTcpKeepalive(int idleSeconds, int intervalSeconds, int maxProbes) {
+        nativeHttpSetTcpKeepalive(idleSeconds, intervalSeconds, maxProbes);
+    }
+
+    /**
+     * Establish the WireGuard session used for HTTP in the background, so the first
+     * request after configureHttp() doesn't wait for the handshake. Does nothing if
+     * the session already exists or the streaming tunnel is active.
+     *
+     * @return true if a prewarm was started
+     */
+    public static boolean prewarmHttp() {
+        return nativeHttpPrewarm();
+    }
+
+    /**
+     * Describe the TCP connections currently proxied through WireGuard, for diagnostics.
+     * The first line is "shared_tunnel=active|inactive connections=N/MAX" (open or
+     * connecting connections against the cap), followed by one
+     * "targetPort localPort running|connecting" line per connection.
+     */
+    public static String getHttpProxyDiagnostics() {
+        return nativeHttpListProxies();
+    }
+
     // Direct HTTP native methods (config only - actual HTTP now goes through OkHttp + WgSocket)
     private static native boolean nativeHttpSetConfig(
         byte[] privateKey,
@@ -428,4 +835,11 @@ public class WireGuardManager {
     );
     private static native void nativeHttpClearConfig();
     private static native boolean nativeHttpIsConfigured();
+    private static native String nativeHttpListProxies();
+    private static native boolean nativeHttpPrewarm();
+    private static native void nativeHttpSetConnectRetry(int initialMs, int maxMs);
+    private static native void nativeHttpSetConnectAttempts(int attempts);
+    private static native void nativeHttpSetMaxConnections(int maxConnections);
+    private static native void nativeHttpSetMtu(int mtu);
+    private static native void nativeHttpSetTcpKeepalive(int idleSeconds, int intervalSeconds, int maxProbes);
 }
\ No newline at end of file
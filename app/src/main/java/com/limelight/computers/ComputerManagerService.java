@@ -2,7 +2,6 @@ package com.limelight.computers;
 
 import java.io.IOException;
 import java.io.OutputStream;
-import java.io.StringReader;
 import java.net.Inet4Address;
 import java.util.HashSet;
 import java.util.LinkedList;
@@ -1017,11 +1016,18 @@ public class ComputerManagerService extends Service {
         private final ComputerDetails computer;
         private final Object pollEvent = new Object();
         private boolean receivedAppList = false;
+        private volatile NvHTTP.AppListListener appListener;
 
         public ApplistPoller(ComputerDetails computer) {
             this.computer = computer;
         }
 
+        // Receives each app as soon as it arrives until the first complete list has been
+        // received, so a large library can fill in before notifyComputerUpdated() reports it
+        public void setAppListener(NvHTTP.AppListListener appListener) {
+            this.appListener = appListener;
+        }
+
         public void pollNow() {
             synchronized (pollEvent) {
                 pollEvent.notify();
@@ -1083,20 +1089,28 @@ public class ComputerManagerService extends Service {
                         NvHTTP http = new NvHTTP(ServerHelper.getCurrentAddressFromComputer(computer), computer.httpsPort, idManager.getUniqueId(),
                                 computer.serverCert, PlatformBinding.getCryptoProvider(ComputerManagerService.this));
 
+                        List<NvApp> list = new LinkedList<>();
+                        NvHTTP.AppListListener progress = receivedAppList ? null : appListener;
+                        NvHTTP.AppListListener onApp = app -> {
+                            list.add(app);
+                            if (progress != null) {
+                                progress.onApp(app);
+                            }
+                        };
+
                         String appList;
                         if (tuple != null) {
                             // If we're polling this machine too, grab the network lock
                             // while doing the app list request to prevent other requests
                             // from being issued in the meantime.
                             synchronized (tuple.networkLock) {
-                                appList = http.getAppListRaw();
+                                appList = http.getAppList(onApp);
                             }
                         } else {
                             // No polling is happening now, so we just call it directly
-                            appList = http.getAppListRaw();
+                            appList = http.getAppList(onApp);
                         }
 
-                        List<NvApp> list = NvHTTP.getAppListByReader(new StringReader(appList));
                         if (list.isEmpty()) {
                             Log.i(TAG, "Empty app list received from " + computer.uuid);
 
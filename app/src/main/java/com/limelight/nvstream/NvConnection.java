@@ -456,24 +456,27 @@ public class NvConnection {
             context.videoCapabilities = videoDecoderRenderer.getCapabilities();
 
             String appName = context.streamConfig.getApp().getAppName();
+            // The app launch comes before the native stages, as stage 0
+            int totalStages = MoonBridge.getTotalStages();
 
-            context.connListener.stageStarting(appName);
+            context.connListener.stageStarting(appName, 0, totalStages);
 
             try {
                 if (!startApp()) {
-                    context.connListener.stageFailed(appName, 0, 0);
+                    context.connListener.stageFailed(appName, 0, totalStages, 0, 0);
                     return;
                 }
-                context.connListener.stageComplete(appName);
+                context.connListener.stageComplete(appName, 0, totalStages);
             } catch (HostHttpResponseException e) {
                 Log.e(TAG, "run: " + e.getMessage(), e);
                 context.connListener.displayMessage(e.getMessage());
-                context.connListener.stageFailed(appName, 0, e.getErrorCode());
+                context.connListener.stageFailed(appName, 0, totalStages, 0, e.getErrorCode());
                 return;
             } catch (XmlPullParserException | IOException e) {
                 Log.e(TAG, "run: " + e.getMessage(), e);
                 context.connListener.displayMessage(e.getMessage());
-                context.connListener.stageFailed(appName, MoonBridge.ML_PORT_FLAG_TCP_47984 | MoonBridge.ML_PORT_FLAG_TCP_47989, 0);
+                context.connListener.stageFailed(appName, 0, totalStages,
+                        MoonBridge.ML_PORT_FLAG_TCP_47984 | MoonBridge.ML_PORT_FLAG_TCP_47989, 0);
                 return;
             }
 
@@ -486,7 +489,7 @@ public class NvConnection {
                 connectionAllowed.acquire();
             } catch (InterruptedException e) {
                 context.connListener.displayMessage(e.getMessage());
-                context.connListener.stageFailed(appName, 0, 0);
+                context.connListener.stageFailed(appName, 0, totalStages, 0, 0);
                 return;
             }
 
@@ -521,6 +524,9 @@ public class NvConnection {
                         context.streamConfig.getColorSpace(),
                         context.streamConfig.getColorRange(),
                         disableEncryption);
+                if (ret == MoonBridge.ML_ERROR_CONNECTION_IN_PROGRESS) {
+                    Log.w(TAG, "startConnection rejected: another connection is still active");
+                }
                 if (ret != 0) {
                     // LiStartConnection() failed, so the caller is not expected
                     // to stop the connection themselves. We need to release their
@@ -538,6 +544,13 @@ public class NvConnection {
         }
     }
 
+    public void sendMouseMoveHighRes(final float deltaX, final float deltaY) {
+        if (!isMonkey) {
+            MoonBridge.sendMouseMoveHighRes((int) (deltaX * MoonBridge.MOUSE_MOVE_HIGH_RES_UNITS),
+                    (int) (deltaY * MoonBridge.MOUSE_MOVE_HIGH_RES_UNITS));
+        }
+    }
+
     public void sendMousePosition(short x, short y, short referenceWidth, short referenceHeight) {
         if (!isMonkey) {
             MoonBridge.sendMousePosition(x, y, referenceWidth, referenceHeight);
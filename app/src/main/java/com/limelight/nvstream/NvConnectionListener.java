@@ -1,9 +1,11 @@
 package com.limelight.nvstream;
 
 public interface NvConnectionListener {
-    void stageStarting(String stage);
-    void stageComplete(String stage);
-    void stageFailed(String stage, int portFlags, int errorCode);
+    // stageIndex runs from 1 to totalStages for the native connection stages, and is
+    // 0 for the app launch that precedes them
+    void stageStarting(String stage, int stageIndex, int totalStages);
+    void stageComplete(String stage, int stageIndex, int totalStages);
+    void stageFailed(String stage, int stageIndex, int totalStages, int portFlags, int errorCode);
     
     void connectionStarted();
     void connectionTerminated(int errorCode);
@@ -20,4 +22,20 @@ public interface NvConnectionListener {
     void setMotionEventState(short controllerNumber, byte motionType, short reportRateHz);
 
     void setControllerLED(short controllerNumber, byte r, byte g, byte b);
+
+    // First data packet received through the WireGuard tunnel since it was
+    // started or rebound (the data plane works, not just the handshake)
+    void tunnelDataFlowing();
+
+    // The WireGuard handshake completed but no data packet arrived for waitedSeconds,
+    // which usually means the server's AllowedIPs/routing doesn't cover this peer
+    void tunnelNoData(int waitedSeconds);
+
+    // The decoder took averageLatencyMs on average to accept each frame for at least windowMs,
+    // so it can't keep up with the stream. Fires once per overload until latency recovers.
+    void decoderOverloaded(int averageLatencyMs, int windowMs);
+
+    // Video decoder set up with the format the host chose (a VIDEO_FORMAT_* value),
+    // which may differ from the one the renderer expected
+    void videoFormatNegotiated(int videoFormat, int width, int height, int redrawRate);
 }
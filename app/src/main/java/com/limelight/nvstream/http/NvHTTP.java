@@ -4,6 +4,7 @@ import android.annotation.SuppressLint;
 import android.util.Log;
 
 import java.io.FileNotFoundException;
+import java.io.FilterReader;
 import java.io.IOException;
 import java.io.InputStream;
 import java.io.InterruptedIOException;
@@ -23,7 +24,6 @@ import java.security.cert.Certificate;
 import java.security.cert.CertificateException;
 import java.security.cert.X509Certificate;
 import java.util.LinkedList;
-import java.util.ListIterator;
 import java.util.Stack;
 import java.util.UUID;
 import java.util.concurrent.TimeUnit;
@@ -35,6 +35,7 @@ import javax.net.ssl.SSLContext;
 import javax.net.ssl.SSLHandshakeException;
 import javax.net.ssl.SSLPeerUnverifiedException;
 import javax.net.ssl.SSLSession;
+import javax.net.ssl.SSLSocketFactory;
 import javax.net.ssl.TrustManager;
 import javax.net.ssl.TrustManagerFactory;
 import javax.net.ssl.X509KeyManager;
@@ -94,8 +95,18 @@ public class NvHTTP {
     private X509KeyManager keyManager;
     private X509Certificate serverCert;
 
+    // TLS socket factory reused for WireGuard-routed requests. OkHttp only reuses a
+    // pooled connection when the SSLSocketFactory is identical, so it must be stable.
+    // It is dropped whenever the key or trust material changes, so connections
+    // negotiated under the old material are never picked from the pool again.
+    private SSLSocketFactory wgSslSocketFactory;
+
     void setServerCert(X509Certificate serverCert) {
-        this.serverCert = serverCert;
+        synchronized (this) {
+            this.serverCert = serverCert;
+            // The pinned cert was only checked at handshake time
+            wgSslSocketFactory = null;
+        }
     }
 
     private static X509TrustManager getDefaultTrustManager() {
@@ -117,6 +128,9 @@ public class NvHTTP {
 
     @SuppressLint("CustomX509TrustManager")
     private void initializeHttpState(final LimelightCryptoProvider cryptoProvider) {
+        synchronized (this) {
+            wgSslSocketFactory = null;
+        }
         keyManager = new X509KeyManager() {
             public String chooseClientAlias(String[] keyTypes,
                                             Principal[] issuers, Socket socket) {
@@ -416,20 +430,35 @@ public class NvHTTP {
     // because it doesn't really matter
     private OkHttpClient performAndroidTlsHack(OkHttpClient client) {
         // Doing this each time we create a socket is required
-        // to avoid the SSLv3 fallback that causes connection failures
+        // to avoid the SSLv3 fallback that causes connection failures.
+        // WireGuard-routed requests are the exception: they share one context so
+        // pooled keep-alive connections can be reused. Those connections are never
+        // renegotiated, so a fallback can't creep in through the shared context.
         try {
-            SSLContext sc = SSLContext.getInstance("TLS");
-            sc.init(new KeyManager[]{keyManager}, new TrustManager[]{trustManager}, new SecureRandom());
-            OkHttpClient.Builder builder = client.newBuilder()
-                    .sslSocketFactory(sc.getSocketFactory(), trustManager);
-            
             // When direct WireGuard HTTP is enabled, use WgSocketFactory to route
-            // TCP connections directly through WireGuard without local proxy ports
+            // TCP connections directly through WireGuard without local proxy ports.
+            // These requests use HTTP/1.1 keep-alive so sequential requests to the
+            // same host reuse the tunneled connection instead of reconnecting.
             if (isDirectWgHttpEnabled()) {
-                builder.socketFactory(WgSocketFactory.getInstance());
+                synchronized (this) {
+                    if (wgSslSocketFactory == null) {
+                        SSLContext sc = SSLContext.getInstance("TLS");
+                        sc.init(new KeyManager[]{keyManager}, new TrustManager[]{trustManager}, new SecureRandom());
+                        wgSslSocketFactory = sc.getSocketFactory();
+                    }
+                }
+                return client.newBuilder()
+                        .sslSocketFactory(wgSslSocketFactory, trustManager)
+                        .socketFactory(WgSocketFactory.getInstance())
+                        .connectionPool(WgSocketFactory.getConnectionPool())
+                        .build();
             }
-            
-            return builder.build();
+
+            SSLContext sc = SSLContext.getInstance("TLS");
+            sc.init(new KeyManager[]{keyManager}, new TrustManager[]{trustManager}, new SecureRandom());
+            return client.newBuilder()
+                    .sslSocketFactory(sc.getSocketFactory(), trustManager)
+                    .build();
         } catch (NoSuchAlgorithmException | KeyManagementException e) {
             throw new RuntimeException(e);
         }
@@ -662,14 +691,27 @@ public class NvHTTP {
         return pm;
     }
 
+    // Receives apps one at a time as the app list is parsed
+    public interface AppListListener {
+        void onApp(NvApp app);
+    }
+
     public static LinkedList<NvApp> getAppListByReader(Reader r) throws XmlPullParserException, IOException {
+        LinkedList<NvApp> appList = new LinkedList<NvApp>();
+        parseAppList(r, appList::addLast);
+        return appList;
+    }
+
+    // Pulls from the reader only as far as needed, so each app reaches the listener
+    // as soon as its closing tag has been read. Incomplete apps are skipped.
+    private static void parseAppList(Reader r, AppListListener listener) throws XmlPullParserException, IOException {
         XmlPullParserFactory factory = XmlPullParserFactory.newInstance();
         factory.setNamespaceAware(true);
         XmlPullParser xpp = factory.newPullParser();
 
         xpp.setInput(r);
         int eventType = xpp.getEventType();
-        LinkedList<NvApp> appList = new LinkedList<NvApp>();
+        NvApp app = null;
         Stack<String> currentTag = new Stack<String>();
         boolean rootTerminated = false;
 
@@ -681,17 +723,26 @@ public class NvHTTP {
                     }
                     currentTag.push(xpp.getName());
                     if (xpp.getName().equals("App")) {
-                        appList.addLast(new NvApp());
+                        app = new NvApp();
                     }
                     break;
                 case (XmlPullParser.END_TAG):
                     currentTag.pop();
                     if (xpp.getName().equals("root")) {
                         rootTerminated = true;
+                    } else if (xpp.getName().equals("App") && app != null) {
+                        if (app.isInitialized()) {
+                            listener.onApp(app);
+                        } else {
+                            Log.w(TAG, "GFE returned incomplete app: " + app.getAppId() + " " + app.getAppName());
+                        }
+                        app = null;
                     }
                     break;
                 case (XmlPullParser.TEXT):
-                    NvApp app = appList.getLast();
+                    if (app == null) {
+                        break;
+                    }
                     if (currentTag.peek().equals("AppTitle")) {
                         app.setAppName(xpp.getText());
                     } else if (currentTag.peek().equals("ID")) {
@@ -708,20 +759,6 @@ public class NvHTTP {
         if (!rootTerminated) {
             throw new XmlPullParserException("Malformed XML: Root tag was not terminated");
         }
-
-        // Ensure that all apps in the list are initialized
-        ListIterator<NvApp> i = appList.listIterator();
-        while (i.hasNext()) {
-            NvApp app = i.next();
-
-            // Remove uninitialized apps
-            if (!app.isInitialized()) {
-                Log.w(TAG, "GFE returned incomplete app: " + app.getAppId() + " " + app.getAppName());
-                i.remove();
-            }
-        }
-
-        return appList;
     }
 
     public String getAppListRaw() throws IOException {
@@ -733,6 +770,56 @@ public class NvHTTP {
             return getAppListByReader(new StringReader(getAppListRaw()));
     }
 
+    /**
+     * Fetch the app list, handing each app to the listener as soon as its entry
+     * has arrived instead of after the whole response. With a large library over
+     * a slow tunnel this lets the UI fill in progressively. Use getAppList() for
+     * small lists, where it also logs the raw response.
+     *
+     * Apps are delivered on the calling thread. If an exception is thrown, apps
+     * already delivered are valid but the list is incomplete.
+     *
+     * @return The raw response, for the app list cache
+     */
+    public String getAppList(AppListListener listener) throws IOException, XmlPullParserException {
+        try (ResponseBody resp = openHttpConnection(httpClientLongConnectTimeout, getHttpsUrl(true), "applist", null)) {
+            RecordingReader reader = new RecordingReader(resp.charStream());
+            parseAppList(reader, listener);
+            return reader.getRecorded();
+        }
+    }
+
+    // Keeps a copy of everything read through it, so a streamed response can still be cached
+    private static class RecordingReader extends FilterReader {
+        private final StringBuilder recorded = new StringBuilder();
+
+        RecordingReader(Reader in) {
+            super(in);
+        }
+
+        @Override
+        public int read() throws IOException {
+            int c = super.read();
+            if (c != -1) {
+                recorded.append((char) c);
+            }
+            return c;
+        }
+
+        @Override
+        public int read(char[] cbuf, int off, int len) throws IOException {
+            int n = super.read(cbuf, off, len);
+            if (n > 0) {
+                recorded.append(cbuf, off, n);
+            }
+            return n;
+        }
+
+        String getRecorded() {
+            return recorded.toString();
+        }
+    }
+
     String executePairingCommand(String additionalArguments, boolean enableReadTimeout) throws IOException {
         return openHttpConnectionToString(enableReadTimeout ? httpClientLongConnectTimeout : httpClientLongConnectNoReadTimeout,
                 baseUrlHttp, "pair", "devicename=roth&updateState=1&" + additionalArguments);
@@ -63,6 +63,8 @@ public class MoonBridge {
     public static final int ML_ERROR_UNEXPECTED_EARLY_TERMINATION = -102;
     public static final int ML_ERROR_PROTECTED_CONTENT = -103;
     public static final int ML_ERROR_FRAME_CONVERSION = -104;
+    // Returned by startConnection() while another connection is starting or active
+    public static final int ML_ERROR_CONNECTION_IN_PROGRESS = -200;
 
     public static final int ML_PORT_INDEX_TCP_47984 = 0;
     public static final int ML_PORT_INDEX_TCP_47989 = 1;
@@ -230,6 +232,24 @@ public class MoonBridge {
         return VIDEO_FORMAT_H264;
     }
 
+    // Display name of a VIDEO_FORMAT_* value, e.g. "HEVC Main10"
+    public static String getVideoFormatName(int videoFormat) {
+        switch (videoFormat) {
+            case VIDEO_FORMAT_H264:
+                return "H.264";
+            case VIDEO_FORMAT_H265:
+                return "HEVC";
+            case VIDEO_FORMAT_H265_MAIN10:
+                return "HEVC Main10";
+            case VIDEO_FORMAT_AV1_MAIN8:
+                return "AV1";
+            case VIDEO_FORMAT_AV1_MAIN10:
+                return "AV1 Main10";
+            default:
+                return "Unknown (0x" + Integer.toHexString(videoFormat) + ")";
+        }
+    }
+
     public static int bridgeDrSetup(int videoFormat, int width, int height, int redrawRate) {
         if (videoRenderer != null) {
             return videoRenderer.setup(videoFormat, width, height, redrawRate);
@@ -257,6 +277,12 @@ public class MoonBridge {
         }
     }
 
+    public static void bridgeDrDecoderOverloaded(int averageLatencyMs, int windowMs) {
+        if (connectionListener != null) {
+            connectionListener.decoderOverloaded(averageLatencyMs, windowMs);
+        }
+    }
+
     public static int bridgeDrSubmitDecodeUnit(byte[] decodeUnitData, int decodeUnitLength, int decodeUnitType,
                                                int frameNumber, int frameType, char frameHostProcessingLatency,
                                                long receiveTimeUs, long enqueueTimeUs) {
@@ -302,21 +328,22 @@ public class MoonBridge {
         }
     }
 
-    public static void bridgeClStageStarting(int stage) {
+    public static void bridgeClStageStarting(int stage, int totalStages) {
         if (connectionListener != null) {
-            connectionListener.stageStarting(getStageName(stage));
+            connectionListener.stageStarting(getStageName(stage), stage, totalStages);
         }
     }
 
-    public static void bridgeClStageComplete(int stage) {
+    public static void bridgeClStageComplete(int stage, int totalStages) {
         if (connectionListener != null) {
-            connectionListener.stageComplete(getStageName(stage));
+            connectionListener.stageComplete(getStageName(stage), stage, totalStages);
         }
     }
 
-    public static void bridgeClStageFailed(int stage, int errorCode) {
+    public static void bridgeClStageFailed(int stage, int errorCode, int totalStages) {
         if (connectionListener != null) {
-            connectionListener.stageFailed(getStageName(stage), getPortFlagsFromStage(stage), errorCode);
+            connectionListener.stageFailed(getStageName(stage), stage, totalStages,
+                    getPortFlagsFromStage(stage), errorCode);
         }
     }
 
@@ -368,6 +395,24 @@ public class MoonBridge {
         }
     }
 
+    public static void bridgeClTunnelDataFlowing() {
+        if (connectionListener != null) {
+            connectionListener.tunnelDataFlowing();
+        }
+    }
+
+    public static void bridgeClTunnelNoData(int waitedSeconds) {
+        if (connectionListener != null) {
+            connectionListener.tunnelNoData(waitedSeconds);
+        }
+    }
+
+    public static void bridgeClVideoFormatNegotiated(int videoFormat, int width, int height, int redrawRate) {
+        if (connectionListener != null) {
+            connectionListener.videoFormatNegotiated(videoFormat, width, height, redrawRate);
+        }
+    }
+
     public static void setupBridge(VideoDecoderRenderer videoRenderer, AudioRenderer audioRenderer, NvConnectionListener connectionListener) {
         MoonBridge.videoRenderer = videoRenderer;
         MoonBridge.audioRenderer = audioRenderer;
@@ -397,6 +442,13 @@ public class MoonBridge {
 
     public static native void sendMouseMove(short deltaX, short deltaY);
 
+    // Sub-pixel units per pixel for sendMouseMoveHighRes()
+    public static final int MOUSE_MOVE_HIGH_RES_UNITS = 256;
+
+    // Relative move in 1/MOUSE_MOVE_HIGH_RES_UNITS pixels. Fractions of a pixel are carried
+    // over to the next call instead of being dropped.
+    public static native void sendMouseMoveHighRes(int deltaX, int deltaY);
+
     public static native void sendMousePosition(short x, short y, short referenceWidth, short referenceHeight);
 
     public static native void sendMouseMoveAsMousePosition(short deltaX, short deltaY, short referenceWidth, short referenceHeight);
@@ -434,12 +486,59 @@ public class MoonBridge {
 
     public static native String getStageName(int stage);
 
+    // Stage indices passed to the stage callbacks run from 1 to getTotalStages()
+    public static native int getTotalStages();
+
     public static native String findExternalAddressIP4(String stunHostName, int stunPort);
 
     public static native int getPendingAudioDuration();
 
     public static native int getPendingVideoFrames();
 
+    // Takes effect on the next stream. Surround streams are then rendered as stereo.
+    public static native void setAudioDownmixToStereo(boolean enabled);
+
+    // Returns the MAKE_AUDIO_CONFIGURATION() value of the active stream, or 0 if none
+    public static native int getActiveAudioConfiguration();
+
+    public static native boolean isAudioDownmixActive();
+
+    // Returns the VIDEO_FORMAT_* value the host chose for the active stream, or 0 before video setup
+    public static native int nativeGetNegotiatedVideoFormat();
+
+    // Returns the stream configuration the host agreed to for the current (or most recent)
+    // connection as "width=N height=N fps=N bitrate=N color_space=N color_range=N", or null
+    // before the connection has started. Resolution and fps reflect any clamping by the host;
+    // HDR streams report COLORSPACE_REC_2020. Bitrate and color range are as requested.
+    public static native String nativeGetActiveStreamConfig();
+
+    // Returns the latency milestones of the current (or most recent) connection as
+    // "handshake_ms=N first_data_ms=N first_frame_ms=N", in milliseconds since startConnection
+    // (or since the WireGuard tunnel was started, if that was for this connection), or null
+    // before the first connection. A milestone not reached yet is -1; the WireGuard ones stay -1
+    // without a tunnel and are 0 if the tunnel was left up from an earlier connection.
+    public static native String nativeGetConnectionTimings();
+
+    // Returns the bytes the active WireGuard tunnel adds to each stream packet: inner IP/UDP
+    // headers, the WireGuard header and tag, and outer IP/UDP headers to the endpoint (88 for
+    // IPv4 throughout). Padding adds up to 15 more per packet. Returns 0 without a tunnel.
+    // Scale the bitrate by packetSize / (packetSize + overhead) to stay within the link.
+    public static native int nativeGetWgOverheadBytes();
+
+    // Declares the VIDEO_FORMAT_* formats the decoder can handle (0 clears the declaration)
+    public static native void nativeSetDecoderVideoFormats(int formats);
+
+    // Sets when decoderOverloaded() fires: the smoothed time the decoder takes to accept a frame
+    // must stay above thresholdMs for windowMs. A threshold of 0 disables the signal; a window
+    // of 0 restores the default (20ms for 3000ms).
+    public static native void setDecoderOverloadThreshold(int thresholdMs, int windowMs);
+
+    // Filters a supportedVideoFormats mask for the stream config down to formats the declared
+    // decoder can handle. Main10 profiles need their 8-bit base profile; if nothing is left,
+    // returns VIDEO_FORMAT_H264. Returns the mask unchanged if no decoder formats were declared.
+    public static native int nativeComputeSupportedVideoFormats(int requested);
+
+    // With WireGuard routing active, probes the tunnel path instead of the test server
     public static native int testClientConnectivity(String testServerHostName, int referencePort, int testFlags);
 
     public static native int getPortFlagsFromStage(int stage);
@@ -448,13 +547,83 @@ public class MoonBridge {
 
     public static native String stringifyPortFlags(int portFlags, String separator);
 
+    // A port that may be blocked: its ML_PORT_FLAG_* value and a name like "UDP 47998"
+    public static class PortInfo {
+        public final int flag;
+        public final String portName;
+
+        public PortInfo(int flag, String portName) {
+            this.flag = flag;
+            this.portName = portName;
+        }
+    }
+
+    // Entries are "flag:portName", one per port flag set for the error code
+    private static native String[] nativeGetTerminationPortInfo(int errorCode);
+
+    // The ports a termination error code suggests checking, in flag order.
+    // Equivalent to getPortFlagsFromTerminationErrorCode() + stringifyPortFlags()
+    // without a separator or a size limit on the result.
+    public static PortInfo[] getTerminationPortInfo(int errorCode) {
+        String[] entries = nativeGetTerminationPortInfo(errorCode);
+        if (entries == null) {
+            return new PortInfo[0];
+        }
+
+        PortInfo[] ports = new PortInfo[entries.length];
+        for (int i = 0; i < entries.length; i++) {
+            String[] parts = entries[i].split(":", 2);
+            ports[i] = new PortInfo((int) Long.parseLong(parts[0]), parts[1]);
+        }
+        return ports;
+    }
+
     // The RTT is in the top 32 bits, and the RTT variance is in the bottom 32 bits
     public static native long getEstimatedRttInfo();
 
+    // The number of frames concealed by opus PLC since the audio renderer started is in
+    // the top 32 bits, the active OPUS_DECODE_MODE_* is in bits 24-31, and the current run
+    // of consecutive concealed frames is in the bottom 24 bits
+    public static native long nativeGetAudioConcealmentStats();
+
+    // In-band FEC recovery, then PLC. Falls back to basic if decoding keeps failing.
+    public static final int OPUS_DECODE_MODE_AUTO = 0;
+    // Decode received packets and conceal losses with PLC only
+    public static final int OPUS_DECODE_MODE_BASIC = 1;
+
+    // Takes effect on the next stream. Both modes use the software libopus decoder.
+    public static native void nativeSetOpusDecodeMode(int mode);
+
+    // The MAKE_AUDIO_CONFIGURATION() values (see AudioConfiguration.toInt()) the Opus
+    // decoder can handle, from stereo up to 7.1 surround
+    public static native int[] nativeGetSupportedAudioConfigs();
+
+    // Audio configuration for the next connection (0 clears it), replacing the one passed
+    // to startConnection(). It applies to that one connection only. Returns false if the
+    // decoder can't handle it.
+    public static native boolean nativeSetPreferredAudioConfig(int audioConfiguration);
+
+    // The audio configuration the current (or most recent) connection was started with,
+    // after any nativeSetPreferredAudioConfig() override. 0 if no connection has been started.
+    public static native int nativeGetAudioConfig();
+
+    // The packet size the current (or most recent) connection was started with, after
+    // clamping to the WireGuard tunnel MTU. 0 if no connection has been started.
+    public static native int nativeGetPacketSize();
+
+    // Packet size for the next connection (0 clears it). It does not affect a running
+    // stream, which can't renegotiate its packet size, and is still shrunk to fit the
+    // WireGuard tunnel MTU at connection start. Returns false if out of range (512-1408).
+    public static native boolean nativeSetPacketSize(int packetSize);
+
     public static native String getLaunchUrlQueryParameters();
 
     public static native void init();
 
+    // Set the native log level to an android.util.Log priority (e.g. Log.DEBUG to capture
+    // a repro, Log.INFO to go back). Defaults to DEBUG in debug builds and INFO in release.
+    public static native void nativeSetLogLevel(int priority);
+
     // ========================================================================
     // WireGuard tunnel (boringtun) - userspace WireGuard, no system TUN needed
     // ========================================================================
@@ -499,6 +668,17 @@ public class MoonBridge {
      */
     public static native boolean wgEnableDirectRouting(String serverAddr);
 
+    /**
+     * Set extra destinations routed through the WireGuard tunnel besides the
+     * server IP, e.g. the tunnel gateway when it is used as the streaming host.
+     * Applies to the active session and to later ones.
+     *
+     * @param networks Comma-separated CIDRs or addresses (e.g., "10.0.0.254/32,fd00::/64"),
+     *                 or an empty string to route only the server IP
+     * @return true on success, false if any entry is malformed
+     */
+    public static native boolean wgSetRoutedNetworks(String networks);
+
     /**
      * Rebind the WireGuard endpoint socket after a network change (WiFi ↔ mobile).
      * Creates a new UDP socket on the current default network and re-initiates the handshake.
@@ -519,6 +699,182 @@ public class MoonBridge {
      */
     public static native void wgNotifyDeviceWake();
 
+    /**
+     * Keep a closed zero-copy socket's port mapping alive for a short grace window,
+     * so a quick reconnect on the same local port reattaches without losing packets.
+     *
+     * @param enabled Whether port reservations are enabled
+     * @param ttlMs   Grace window in milliseconds
+     */
+    public static native void wgSetPortReservation(boolean enabled, int ttlMs);
+
+    /**
+     * Enable or disable sticky local ports for the video and audio stream sockets.
+     * A re-created stream socket binds to the local port its predecessor used, so the
+     * server-port mappings (and a port reservation) keep working across a reconnect.
+     * Falls back to an ephemeral port if the port is in use.
+     *
+     * @param enabled   Whether stream sockets reuse their previous local port
+     * @param videoPort Video socket port from a previous session, or 0 to keep the remembered one
+     * @param audioPort Audio socket port from a previous session, or 0 to keep the remembered one
+     */
+    public static native void wgSetStickyLocalPorts(boolean enabled, int videoPort, int audioPort);
+
+    /**
+     * Get the local ports remembered for the stream sockets, e.g. to persist them for
+     * {@link #wgSetStickyLocalPorts} in a later session.
+     *
+     * @return {videoPort, audioPort}, 0 where no port is known yet
+     */
+    public static native int[] wgGetStickyLocalPorts();
+
+    /**
+     * Get the traffic carried by the WireGuard tunnel since it was last started, counted
+     * as inner IP packets (WireGuard and outer headers, handshakes and keepalives are not
+     * included). The counters survive stopping the tunnel, so a final snapshot can be
+     * taken after the stream ends.
+     *
+     * @return {txBytes, rxBytes, txPackets, rxPackets}
+     */
+    public static native long[] wgGetTunnelStats();
+
+    /**
+     * Set the destination port whose packets (control/input) are sent through the
+     * WireGuard tunnel ahead of bulk traffic.
+     *
+     * @param port Destination port to prioritize, or 0 to disable
+     */
+    public static native void wgSetPriorityPort(int port);
+
+    /**
+     * Set how many packets are buffered per server port while no stream socket has
+     * registered for it yet. Each packet can be up to ~65 KB, so the worst case is
+     * maxPackets x 65 KB per port.
+     *
+     * @param maxPackets Packets to keep per port, or 0 for the default (512)
+     */
+    public static native void wgSetMaxPendingPackets(int maxPackets);
+
+    /**
+     * Set how long a server port may buffer packets while no stream socket has
+     * registered for it before they are delivered to a bound socket that never
+     * sent anything. This covers streams where the server speaks first and the
+     * client never sends, which would otherwise buffer and drop forever.
+     * Off by default, since the socket that gets the port is a guess: the one
+     * bound to the same port number, or else the only one left unregistered.
+     *
+     * @param windowMs Buffering time in milliseconds, or 0 to keep buffering (default 0)
+     */
+    public static native void wgSetPendingFallback(int windowMs);
+
+    /**
+     * Set how long a packet from the WireGuard tunnel waits for room in a full stream
+     * channel before it is dropped. A few hundred microseconds can ride out a brief
+     * decoder hitch during an I-frame burst, but the wait stalls the tunnel receiver
+     * for every stream, so keep it tiny. Values above 2000 are clamped.
+     *
+     * @param waitUs Wait in microseconds, or 0 to drop immediately (the default)
+     */
+    public static native void wgSetChannelFullWaitUs(int waitUs);
+
+    /**
+     * Get how full stream channels were handled, as "waited_delivered=N dropped=N":
+     * packets delivered after a brief wait versus packets dropped.
+     */
+    public static native String wgGetChannelFullStats();
+
+    /**
+     * Get zero-copy channel occupancy for the WireGuard-routed UDP socket bound to
+     * localPort, as "len=N high_water=N capacity=N recv_timeouts=N oversized_drops=N
+     * server_quiet=0|1". A high-water mark near capacity means the decoder isn't keeping
+     * up with bursts; an empty channel with growing timeouts means the network isn't
+     * delivering. Oversized drops are packets discarded because they didn't fit the
+     * receive buffer. server_quiet is described at wgIsUdpServerQuiet.
+     *
+     * @param localPort Local port of the stream socket
+     * @return The stats, or null if no such socket is routed through WireGuard
+     */
+    public static native String wgGetUdpChannelStats(int localPort);
+
+    /**
+     * Set how long a WireGuard-routed stream socket must go without data, after having
+     * received some, before its server is considered to have stopped sending. Keep it
+     * well above normal gaps in the stream.
+     *
+     * @param windowMs Silence in milliseconds, or 0 to disable detection (default 10000)
+     */
+    public static native void wgSetServerQuietWindow(int windowMs);
+
+    /**
+     * Check whether the server stopped sending to the WireGuard-routed stream socket
+     * bound to localPort: it carried data and then stayed silent for the quiet window.
+     * This tells a torn-down stream apart from a transient gap at the data-plane level,
+     * often before the connection-terminated callback. It clears when data resumes.
+     *
+     * @param localPort Local port of the stream socket
+     * @return true if the server went quiet; false otherwise or if no such socket exists
+     */
+    public static native boolean wgIsUdpServerQuiet(int localPort);
+
+    /**
+     * Pace delivery on the WireGuard-routed stream socket receiving from remotePort
+     * (e.g. 47998 for video). Packets of a frame arrive in bursts; pacing holds early
+     * frames briefly so frames reach the decoder at a steadier cadence. Each frame is
+     * held at most maxDelayUs, further capped by the measured RTT variance, and late
+     * frames are never delayed. Compare jitter with wgGetRecvPacingStats.
+     *
+     * @param remotePort Server port of the stream, or 0 to disable pacing (the default)
+     * @param maxDelayUs Longest hold in microseconds (at most 20000), or 0 to disable
+     */
+    public static native void wgSetRecvPacing(int remotePort, int maxDelayUs);
+
+    /**
+     * Get frame delivery regularity of the paced stream socket bound to localPort, as
+     * "intervals=N arrival_jitter_us=N release_jitter_us=N queued=N dropped=N": the
+     * standard deviation of the frame interval as received from the network and as
+     * released to the decoder, and the packets dropped because the pacer was full.
+     *
+     * @param localPort Local port of the stream socket
+     * @return The stats, or null if no such socket exists or it was never paced
+     */
+    public static native String wgGetRecvPacingStats(int localPort);
+
+    /**
+     * Mirror the inner IP packets of WireGuard-routed stream traffic to 127.0.0.1:port
+     * so an external analyzer can timestamp them. Outbound packets are always mirrored;
+     * received packets only if includeInbound. Copies are best-effort and dropped
+     * rather than delay the stream. For development and diagnostics only.
+     *
+     * @param port Loopback port of the analyzer, or 0 to turn the tap off (the default)
+     * @param includeInbound Whether to mirror packets received from the tunnel too
+     * @return true on success; false if the port is invalid or the tap socket failed
+     */
+    public static native boolean wgSetTapPort(int port, boolean includeInbound);
+
+    // Nothing is routed for the port; its packets are dropped
+    public static final int WG_PORT_MODE_UNROUTED = 0;
+    // Delivered over the zero-copy channel of a registered socket
+    public static final int WG_PORT_MODE_CHANNEL = 1;
+    // Delivered through the loopback inject socket
+    public static final int WG_PORT_MODE_INJECT = 2;
+    // Buffered until a socket for the port is registered
+    public static final int WG_PORT_MODE_PENDING = 3;
+
+    /**
+     * Get how packets arriving from a server port (e.g. 47998 for video) are
+     * currently delivered through WireGuard.
+     *
+     * @param port Server-side stream port
+     * @return One of the WG_PORT_MODE_* constants
+     */
+    public static native int wgGetPortMode(int port);
+
+    /**
+     * Get the worst-case time a prioritized packet waited for the tunnel lock
+     * since the last call, in microseconds.
+     */
+    public static native long wgTakePrioritySendMaxWaitUs();
+
     /**
      * Parse a base64-encoded WireGuard key into raw 32 bytes.
      *
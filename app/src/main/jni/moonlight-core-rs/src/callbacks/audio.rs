@@ -10,7 +10,7 @@ use crate::jni_helpers::*;
 use crate::opus::*;
 use libc::{c_char, c_int, c_void};
 use std::ptr;
-use std::sync::atomic::{AtomicPtr, AtomicBool, Ordering};
+use std::sync::atomic::{AtomicPtr, AtomicBool, AtomicI32, Ordering};
 use log::{info, error, debug};
 
 // Global state for audio callbacks
@@ -22,6 +22,31 @@ static mut OPUS_CONFIG: Option<OPUS_MULTISTREAM_CONFIGURATION> = None;
 static mut LAST_PACKET_DATA: Option<Vec<u8>> = None;
 static LAST_PACKET_VALID: AtomicBool = AtomicBool::new(false);
 
+// Surround handling: when enabled, multistream surround output is downmixed to
+// stereo before being handed to Java (for stereo-only output devices)
+static DOWNMIX_TO_STEREO: AtomicBool = AtomicBool::new(false);
+static DOWNMIX_ACTIVE: AtomicBool = AtomicBool::new(false);
+static mut DOWNMIX_BUFFER: Vec<i16> = Vec::new();
+
+// Negotiated audio configuration of the active stream (0 when no audio stream is active)
+static ACTIVE_AUDIO_CONFIGURATION: AtomicI32 = AtomicI32::new(0);
+
+/// Request stereo downmix of surround streams. Takes effect on the next audio init.
+pub fn set_downmix_to_stereo(enabled: bool) {
+    DOWNMIX_TO_STEREO.store(enabled, Ordering::Release);
+}
+
+/// Get the negotiated audio configuration (MAKE_AUDIO_CONFIGURATION() value) of the
+/// active stream, or 0 if no audio stream is active.
+pub fn get_active_audio_configuration() -> c_int {
+    ACTIVE_AUDIO_CONFIGURATION.load(Ordering::Acquire)
+}
+
+/// Check if the active stream is being downmixed to stereo
+pub fn is_downmix_active() -> bool {
+    DOWNMIX_ACTIVE.load(Ordering::Acquire)
+}
+
 pub extern "C" fn bridge_ar_init(
     audio_configuration: c_int,
     opus_config: *const OPUS_MULTISTREAM_CONFIGURATION,
@@ -38,6 +63,16 @@ pub extern "C" fn bridge_ar_init(
           config.streams, config.coupledStreams, config.samplesPerFrame);
     info!("Audio mapping: {:?}", &config.mapping[..config.channelCount as usize]);
 
+    // Downmix only applies to surround layouts; the Java renderer is then set up for stereo
+    let downmix = DOWNMIX_TO_STEREO.load(Ordering::Acquire) && config.channelCount > 2;
+    let output_configuration = if downmix {
+        info!("Downmixing {}-channel audio to stereo", config.channelCount);
+        AUDIO_CONFIGURATION_STEREO
+    } else {
+        audio_configuration
+    };
+    let output_channels = if downmix { 2 } else { config.channelCount };
+
     let env = match get_thread_env() {
         Some(e) => e,
         None => return -1,
@@ -50,7 +85,7 @@ pub extern "C" fn bridge_ar_init(
     }
 
     let args = [
-        JValue::int(audio_configuration),
+        JValue::int(output_configuration),
         JValue::int(config.sampleRate),
         JValue::int(config.samplesPerFrame),
     ];
@@ -69,8 +104,14 @@ pub extern "C" fn bridge_ar_init(
         OPUS_CONFIG = Some(config);
         // Initialize FEC state
         LAST_PACKET_DATA = None;
+        DOWNMIX_BUFFER = if downmix {
+            vec![0i16; (config.channelCount * config.samplesPerFrame) as usize]
+        } else {
+            Vec::new()
+        };
     }
     LAST_PACKET_VALID.store(false, Ordering::SeqCst);
+    DOWNMIX_ACTIVE.store(downmix, Ordering::Release);
 
     // Create opus decoder
     let mut error: c_int = 0;
@@ -96,9 +137,14 @@ pub extern "C" fn bridge_ar_init(
     }
 
     OPUS_DECODER.store(decoder, Ordering::SeqCst);
+    ACTIVE_AUDIO_CONFIGURATION.store(audio_configuration, Ordering::Release);
+    let decode_mode = start_decode_mode();
+    if decode_mode != OPUS_DECODE_MODE_AUTO {
+        info!("Opus decode mode: {}", decode_mode);
+    }
 
     // Pre-allocate the decoded audio buffer
-    let buffer_size = config.channelCount * config.samplesPerFrame;
+    let buffer_size = output_channels * config.samplesPerFrame;
     let audio_buffer = new_short_array(env, buffer_size);
     if audio_buffer.is_null() {
         error!("Failed to create audio buffer");
@@ -114,6 +160,8 @@ pub extern "C" fn bridge_ar_init(
 pub extern "C" fn bridge_ar_start() {
     debug!("Audio renderer start");
 
+    reset_concealment_stats();
+
     let env = match get_thread_env() {
         Some(e) => e,
         None => return,
@@ -154,8 +202,11 @@ pub extern "C" fn bridge_ar_cleanup() {
         OPUS_CONFIG = None;
         // Clear FEC state
         LAST_PACKET_DATA = None;
+        DOWNMIX_BUFFER = Vec::new();
     }
     LAST_PACKET_VALID.store(false, Ordering::SeqCst);
+    DOWNMIX_ACTIVE.store(false, Ordering::Release);
+    ACTIVE_AUDIO_CONFIGURATION.store(0, Ordering::Release);
 
     let env = match get_thread_env() {
         Some(e) => e,
@@ -204,12 +255,23 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
         return;
     }
 
+    // When downmixing, decode the full surround frame into the native scratch
+    // buffer and write only the stereo mix into the Java array
+    let downmix = DOWNMIX_ACTIVE.load(Ordering::Acquire);
+    let decode_target = if downmix {
+        unsafe { DOWNMIX_BUFFER.as_mut_ptr() }
+    } else {
+        decoded_data
+    };
+
     let decode_len: c_int;
     let is_packet_loss = sample_data.is_null() || sample_length == 0;
 
     if is_packet_loss {
         // Packet loss detected - try to use FEC from previous packet first
-        let has_fec = LAST_PACKET_VALID.load(Ordering::Acquire);
+        // (skipped in basic mode, which only decodes packets and conceals losses)
+        let has_fec = LAST_PACKET_VALID.load(Ordering::Acquire)
+            && active_decode_mode() == OPUS_DECODE_MODE_AUTO;
 
         if has_fec {
             // Try FEC recovery using the previous packet's embedded FEC data
@@ -219,7 +281,7 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
                         decoder,
                         last_data.as_ptr(),
                         last_data.len() as c_int,
-                        decoded_data,
+                        decode_target,
                         config.samplesPerFrame,
                         1, // decode_fec = 1 to use FEC
                     )
@@ -230,6 +292,7 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
 
             if fec_result > 0 {
                 decode_len = fec_result;
+                record_decoded_frame();
                 debug!("FEC recovery successful: {} samples", decode_len);
             } else {
                 // FEC failed, fall back to PLC (packet loss concealment)
@@ -238,11 +301,12 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
                         decoder,
                         ptr::null(),
                         0,
-                        decoded_data,
+                        decode_target,
                         config.samplesPerFrame,
                         0,
                     )
                 };
+                record_concealed_frame();
                 debug!("PLC used after FEC failure: {} samples", decode_len);
             }
         } else {
@@ -252,11 +316,12 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
                     decoder,
                     ptr::null(),
                     0,
-                    decoded_data,
+                    decode_target,
                     config.samplesPerFrame,
                     0,
                 )
             };
+            record_concealed_frame();
             debug!("PLC used (no FEC available): {} samples", decode_len);
         }
 
@@ -271,7 +336,7 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
                 decoder,
                 data_ptr,
                 sample_length,
-                decoded_data,
+                decode_target,
                 config.samplesPerFrame,
                 0,
             )
@@ -280,6 +345,7 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
         // Store this packet for potential FEC recovery on next packet loss
         // Only store if decode was successful
         if decode_len > 0 {
+            record_decoded_frame();
             unsafe {
                 let data_slice = std::slice::from_raw_parts(data_ptr, sample_length as usize);
                 LAST_PACKET_DATA = Some(data_slice.to_vec());
@@ -288,6 +354,23 @@ pub extern "C" fn bridge_ar_decode_and_play_sample(sample_data: *mut c_char, sam
         }
     }
 
+    if record_decode_result(decode_len > 0) {
+        // Start the basic path from a clean decoder state
+        unsafe {
+            opus_multistream_decoder_ctl(decoder, OPUS_RESET_STATE);
+        }
+        LAST_PACKET_VALID.store(false, Ordering::SeqCst);
+    }
+
+    if decode_len > 0 && downmix {
+        unsafe {
+            let channels = config.channelCount as usize;
+            let frames = decode_len as usize;
+            let output = std::slice::from_raw_parts_mut(decoded_data, frames * 2);
+            downmix_to_stereo(&DOWNMIX_BUFFER[..frames * channels], channels, output);
+        }
+    }
+
     if decode_len > 0 {
         // Release the array before making JNI calls (commit changes with mode 0)
         release_primitive_array_critical(env, audio_buffer, decoded_data as *mut c_void, 0);
@@ -9,10 +9,192 @@ use crate::ffi::*;
 use crate::jni_helpers::*;
 use libc::c_int;
 use std::ptr;
-use log::{info, error, debug};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use log::{info, warn, error, debug};
+
+// ============================================================================
+// Active stream configuration
+// ============================================================================
+
+/// Stream parameters of a connection: as requested in startConnection, or as in
+/// effect once the host has agreed to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamConfigSummary {
+    pub width: c_int,
+    pub height: c_int,
+    pub fps: c_int,
+    /// Kbps
+    pub bitrate: c_int,
+    /// COLORSPACE_* value
+    pub color_space: c_int,
+    /// COLOR_RANGE_* value
+    pub color_range: c_int,
+}
+
+// Configuration passed to LiStartConnection for the current connection
+static REQUESTED_STREAM_CONFIG: Mutex<Option<StreamConfigSummary>> = Mutex::new(None);
+
+// Effective configuration of the current (or most recent) connection, captured
+// when it started (None until then)
+static ACTIVE_STREAM_CONFIG: Mutex<Option<StreamConfigSummary>> = Mutex::new(None);
+
+/// Record the configuration a connection is being started with. Clears the
+/// active configuration of any previous connection.
+pub fn set_requested_stream_config(config: &STREAM_CONFIGURATION) {
+    *REQUESTED_STREAM_CONFIG.lock() = Some(StreamConfigSummary {
+        width: config.width,
+        height: config.height,
+        fps: config.fps,
+        bitrate: config.bitrate,
+        color_space: config.colorSpace,
+        color_range: config.colorRange,
+    });
+    *ACTIVE_STREAM_CONFIG.lock() = None;
+}
+
+/// Get the configuration in effect for the current (or most recent) connection,
+/// or None if no connection has started since the last startConnection.
+pub fn get_active_stream_config() -> Option<StreamConfigSummary> {
+    *ACTIVE_STREAM_CONFIG.lock()
+}
+
+/// Combine the requested configuration with what the host negotiated. The
+/// resolution and frame rate come from video setup, which reflects any clamping
+/// by the host; HDR (10-bit) formats are always encoded in Rec. 2020. The host
+/// reports no bitrate or color range back, so those stay as requested.
+fn resolve_stream_config(
+    requested: StreamConfigSummary,
+    video_mode: Option<(c_int, c_int, c_int)>,
+    video_format: c_int,
+) -> StreamConfigSummary {
+    let mut config = requested;
+    if let Some((width, height, fps)) = video_mode {
+        config.width = width;
+        config.height = height;
+        config.fps = fps;
+    }
+    if video_format & VIDEO_FORMAT_MASK_10BIT != 0 {
+        config.color_space = COLORSPACE_REC_2020;
+    }
+    config
+}
+
+// ============================================================================
+// Connection timings
+// ============================================================================
+
+/// Latency milestones of a connection, measured from its startConnection call,
+/// or from wg_start_tunnel if the tunnel was brought up for this connection.
+/// None until the milestone is reached. Without WireGuard there is no handshake
+/// or tunnel data, so those stay None.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionTimings {
+    /// WireGuard handshake completed
+    pub handshake: Option<Duration>,
+    /// First data packet decapsulated from the tunnel
+    pub first_data: Option<Duration>,
+    /// First frame submitted to the decoder
+    pub first_frame: Option<Duration>,
+}
+
+// Start of the current (or most recent) connection and its milestones so far
+static CONNECTION_TIMINGS: Mutex<Option<(Instant, ConnectionTimings)>> = Mutex::new(None);
+
+/// When wg_start_tunnel began and when the tunnel's handshake and first data
+/// followed. Taken by the next connection as its starting point.
+#[derive(Debug, Clone, Copy)]
+struct TunnelTimings {
+    started: Instant,
+    handshake: Option<Instant>,
+    first_data: Option<Instant>,
+}
+
+static TUNNEL_TIMINGS: Mutex<Option<TunnelTimings>> = Mutex::new(None);
+
+// Set once the current connection's first frame is recorded, so the per-frame
+// submit path skips CONNECTION_TIMINGS after that
+static FIRST_FRAME_RECORDED: AtomicBool = AtomicBool::new(false);
+
+/// Start timing a tunnel; called from wg_start_tunnel.
+pub fn start_tunnel_timings() {
+    *TUNNEL_TIMINGS.lock() = Some(TunnelTimings { started: Instant::now(), handshake: None, first_data: None });
+}
+
+/// Start timing a connection; called from startConnection. A tunnel started
+/// since the last connection (it is normally brought up first) moves the
+/// starting point back to wg_start_tunnel, so its milestones show the real
+/// time to handshake. One left up from an earlier connection counts as
+/// reaching its milestones at zero.
+pub fn start_connection_timings(handshake_done: bool, data_flowing: bool) {
+    let now = Instant::now();
+    // A tunnel that never completed its handshake isn't this connection's
+    let tunnel = TUNNEL_TIMINGS.lock().take().filter(|_| handshake_done);
+    let origin = tunnel.map_or(now, |t| t.started.min(now));
+    let reached = |done: bool, at: Option<Instant>| match at {
+        Some(at) => Some(at.saturating_duration_since(origin)),
+        None if done => Some(Duration::ZERO),
+        None => None,
+    };
+    let mut timings = CONNECTION_TIMINGS.lock();
+    *timings = Some((
+        origin,
+        ConnectionTimings {
+            handshake: reached(handshake_done, tunnel.and_then(|t| t.handshake)),
+            first_data: reached(data_flowing, tunnel.and_then(|t| t.first_data)),
+            first_frame: None,
+        },
+    ));
+    FIRST_FRAME_RECORDED.store(false, Ordering::Release);
+}
+
+/// Record a milestone for the current connection; only the first one counts.
+fn record_milestone(milestone: fn(&mut ConnectionTimings) -> &mut Option<Duration>) {
+    if let Some((start, timings)) = CONNECTION_TIMINGS.lock().as_mut() {
+        let slot = milestone(timings);
+        if slot.is_none() {
+            *slot = Some(start.elapsed());
+        }
+    }
+}
+
+/// Record a milestone of a tunnel not yet taken by a connection; only the first one counts.
+fn record_tunnel_milestone(milestone: fn(&mut TunnelTimings) -> &mut Option<Instant>) {
+    if let Some(timings) = TUNNEL_TIMINGS.lock().as_mut() {
+        milestone(timings).get_or_insert_with(Instant::now);
+    }
+}
+
+/// The WireGuard handshake completed.
+pub fn record_handshake_complete() {
+    record_tunnel_milestone(|t| &mut t.handshake);
+    record_milestone(|t| &mut t.handshake);
+}
+
+/// The first data packet came out of the WireGuard tunnel.
+pub fn record_first_data_packet() {
+    record_tunnel_milestone(|t| &mut t.first_data);
+    record_milestone(|t| &mut t.first_data);
+}
+
+/// The decoder accepted a frame. Called for every frame, so only the first
+/// one takes the lock.
+pub fn record_first_frame() {
+    if FIRST_FRAME_RECORDED.load(Ordering::Acquire) || FIRST_FRAME_RECORDED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    record_milestone(|t| &mut t.first_frame);
+}
+
+/// Get the milestones of the current (or most recent) connection, or None
+/// before the first startConnection.
+pub fn get_connection_timings() -> Option<ConnectionTimings> {
+    CONNECTION_TIMINGS.lock().map(|(_, timings)| timings)
+}
 
 pub extern "C" fn bridge_cl_stage_starting(stage: c_int) {
-    debug!("Connection stage starting: {}", stage);
+    debug!("Connection stage starting: {}/{}", stage, TOTAL_STAGES);
 
     let env = match get_thread_env() {
         Some(e) => e,
@@ -21,13 +203,13 @@ pub extern "C" fn bridge_cl_stage_starting(stage: c_int) {
 
     let method = get_cl_stage_starting_method();
     if !method.is_null() {
-        let args = [JValue::int(stage)];
+        let args = [JValue::int(stage), JValue::int(TOTAL_STAGES)];
         call_static_void_method(env, method, &args);
     }
 }
 
 pub extern "C" fn bridge_cl_stage_complete(stage: c_int) {
-    debug!("Connection stage complete: {}", stage);
+    debug!("Connection stage complete: {}/{}", stage, TOTAL_STAGES);
 
     let env = match get_thread_env() {
         Some(e) => e,
@@ -36,7 +218,7 @@ pub extern "C" fn bridge_cl_stage_complete(stage: c_int) {
 
     let method = get_cl_stage_complete_method();
     if !method.is_null() {
-        let args = [JValue::int(stage)];
+        let args = [JValue::int(stage), JValue::int(TOTAL_STAGES)];
         call_static_void_method(env, method, &args);
     }
 }
@@ -51,7 +233,7 @@ pub extern "C" fn bridge_cl_stage_failed(stage: c_int, error_code: c_int) {
 
     let method = get_cl_stage_failed_method();
     if !method.is_null() {
-        let args = [JValue::int(stage), JValue::int(error_code)];
+        let args = [JValue::int(stage), JValue::int(error_code), JValue::int(TOTAL_STAGES)];
         call_static_void_method(env, method, &args);
     }
 }
@@ -59,6 +241,18 @@ pub extern "C" fn bridge_cl_stage_failed(stage: c_int, error_code: c_int) {
 pub extern "C" fn bridge_cl_connection_started() {
     info!("Connection started successfully");
 
+    if let Some(requested) = *REQUESTED_STREAM_CONFIG.lock() {
+        let active = resolve_stream_config(
+            requested,
+            super::video::get_negotiated_video_mode(),
+            super::video::get_negotiated_video_format(),
+        );
+        if active != requested {
+            info!("Host adjusted stream config: requested {:?}, active {:?}", requested, active);
+        }
+        *ACTIVE_STREAM_CONFIG.lock() = Some(active);
+    }
+
     let env = match get_thread_env() {
         Some(e) => e,
         None => return,
@@ -258,6 +452,41 @@ pub extern "C" fn bridge_cl_set_controller_led(
     }
 }
 
+/// Not a moonlight-common-c callback: fired by the WireGuard receiver thread the
+/// first time a data packet comes out of the tunnel after start or rebind.
+pub extern "C" fn bridge_cl_tunnel_data_flowing() {
+    let env = match get_thread_env() {
+        Some(e) => e,
+        None => return,
+    };
+
+    let method = get_cl_tunnel_data_flowing_method();
+    if !method.is_null() {
+        call_static_void_method(env, method, &[]);
+        // The receiver thread stays attached for its next upcall
+        if check_exception(env) {
+            warn!("bridgeClTunnelDataFlowing threw; exception cleared");
+        }
+    }
+}
+
+/// Not a moonlight-common-c callback: fired by the WireGuard timer thread when a
+/// completed handshake has carried no data for `waited_secs`.
+pub extern "C" fn bridge_cl_tunnel_no_data(waited_secs: c_int) {
+    let env = match get_thread_env() {
+        Some(e) => e,
+        None => return,
+    };
+
+    let method = get_cl_tunnel_no_data_method();
+    if !method.is_null() {
+        let args = [JValue::int(waited_secs)];
+        call_static_void_method(env, method, &args);
+        // The timer thread stays attached for its next upcall
+        describe_and_clear_exception(env);
+    }
+}
+
 // ============================================================================
 // Static Callback Structure
 // ============================================================================
@@ -278,3 +507,94 @@ pub static CONNECTION_CALLBACKS: CONNECTION_LISTENER_CALLBACKS = CONNECTION_LIST
     setAdaptiveTriggers: None, // Not implemented yet
 };
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_stream_config() {
+        let requested = StreamConfigSummary {
+            width: 3840,
+            height: 2160,
+            fps: 120,
+            bitrate: 80_000,
+            color_space: COLORSPACE_REC_709,
+            color_range: COLOR_RANGE_LIMITED,
+        };
+
+        // No video setup yet: the request stands
+        assert_eq!(resolve_stream_config(requested, None, 0), requested);
+
+        // Host clamped to 1080p60 in H.265 Main
+        let active = resolve_stream_config(requested, Some((1920, 1080, 60)), VIDEO_FORMAT_H265);
+        assert_eq!((active.width, active.height, active.fps), (1920, 1080, 60));
+        assert_eq!(active.bitrate, 80_000);
+        assert_eq!(active.color_space, COLORSPACE_REC_709);
+
+        // HDR streams use Rec. 2020 whatever was requested
+        let hdr = resolve_stream_config(requested, Some((3840, 2160, 120)), VIDEO_FORMAT_H265_MAIN10);
+        assert_eq!(hdr.color_space, COLORSPACE_REC_2020);
+        assert_eq!(hdr.color_range, COLOR_RANGE_LIMITED);
+    }
+
+    #[test]
+    fn test_connection_timings() {
+        // Milestones before any connection are ignored
+        *CONNECTION_TIMINGS.lock() = None;
+        *TUNNEL_TIMINGS.lock() = None;
+        record_first_frame();
+        assert_eq!(get_connection_timings(), None);
+
+        // Tunnel already up when the connection starts
+        start_connection_timings(true, false);
+        let timings = get_connection_timings().unwrap();
+        assert_eq!(timings.handshake, Some(Duration::ZERO));
+        assert_eq!(timings.first_data, None);
+
+        // Only the first occurrence of a milestone counts
+        record_first_data_packet();
+        let first_data = get_connection_timings().unwrap().first_data.unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        record_first_data_packet();
+        record_handshake_complete();
+        let timings = get_connection_timings().unwrap();
+        assert_eq!(timings.first_data, Some(first_data));
+        assert_eq!(timings.handshake, Some(Duration::ZERO));
+        assert_eq!(timings.first_frame, None);
+
+        record_first_frame();
+        let first_frame = get_connection_timings().unwrap().first_frame.unwrap();
+        assert!(first_frame >= first_data);
+        std::thread::sleep(Duration::from_millis(2));
+        record_first_frame();
+        assert_eq!(get_connection_timings().unwrap().first_frame, Some(first_frame));
+
+        // A new connection starts over
+        start_connection_timings(false, false);
+        assert_eq!(get_connection_timings(), Some(ConnectionTimings::default()));
+        record_first_frame();
+        assert!(get_connection_timings().unwrap().first_frame.is_some());
+
+        // A tunnel brought up for the connection is timed from its start
+        start_tunnel_timings();
+        std::thread::sleep(Duration::from_millis(5));
+        record_handshake_complete();
+        record_first_data_packet();
+        start_connection_timings(true, true);
+        let timings = get_connection_timings().unwrap();
+        let handshake = timings.handshake.unwrap();
+        assert!(handshake >= Duration::from_millis(5));
+        assert!(timings.first_data.unwrap() >= handshake);
+
+        // The next connection on the same tunnel sees it as already up
+        start_connection_timings(true, true);
+        let timings = get_connection_timings().unwrap();
+        assert_eq!(timings.handshake, Some(Duration::ZERO));
+        assert_eq!(timings.first_data, Some(Duration::ZERO));
+
+        // A tunnel whose handshake failed doesn't move the starting point
+        start_tunnel_timings();
+        start_connection_timings(false, false);
+        assert_eq!(get_connection_timings(), Some(ConnectionTimings::default()));
+    }
+}
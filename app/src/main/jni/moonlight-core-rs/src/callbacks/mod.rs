@@ -10,8 +10,8 @@ mod video;
 mod audio;
 mod connection;
 
-use std::sync::atomic::{AtomicBool, Ordering};
-use log::info;
+use std::sync::atomic::{AtomicBool, AtomicI32, Ordering};
+use log::{info, warn};
 
 // Re-export callback structures
 pub use video::VIDEO_CALLBACKS;
@@ -21,11 +21,14 @@ pub use connection::CONNECTION_CALLBACKS;
 // Re-export video callbacks
 pub use video::{
     bridge_dr_setup, bridge_dr_start, bridge_dr_stop, bridge_dr_cleanup, bridge_dr_submit_decode_unit,
+    bridge_dr_decoder_overloaded, set_decoder_overload_threshold,
+    get_negotiated_video_format, set_decoder_video_formats, compute_supported_video_formats,
 };
 
 // Re-export audio callbacks
 pub use audio::{
     bridge_ar_init, bridge_ar_start, bridge_ar_stop, bridge_ar_cleanup, bridge_ar_decode_and_play_sample,
+    set_downmix_to_stereo, get_active_audio_configuration, is_downmix_active,
 };
 
 // Re-export connection callbacks
@@ -33,7 +36,11 @@ pub use connection::{
     bridge_cl_stage_starting, bridge_cl_stage_complete, bridge_cl_stage_failed,
     bridge_cl_connection_started, bridge_cl_connection_terminated, bridge_cl_rumble,
     bridge_cl_connection_status_update, bridge_cl_set_hdr_mode, bridge_cl_rumble_triggers,
-    bridge_cl_set_motion_event_state, bridge_cl_set_controller_led,
+    bridge_cl_set_motion_event_state, bridge_cl_set_controller_led, bridge_cl_tunnel_data_flowing,
+    bridge_cl_tunnel_no_data,
+    set_requested_stream_config, get_active_stream_config, StreamConfigSummary,
+    start_tunnel_timings, start_connection_timings, record_handshake_complete, record_first_data_packet, record_first_frame,
+    get_connection_timings, ConnectionTimings,
 };
 
 // Flag to indicate if JNI callbacks are enabled
@@ -67,3 +74,98 @@ pub fn has_fast_aes() -> bool {
     }
 }
 
+// ============================================================================
+// Stream packet size
+// ============================================================================
+
+/// Smallest stream packet size accepted from Java
+pub const MIN_PACKET_SIZE: i32 = 512;
+
+/// Largest stream packet size accepted from Java: fills a 1500-byte Ethernet MTU
+/// after the IPv4/UDP and stream packet headers
+pub const MAX_PACKET_SIZE: i32 =
+    1500 - 28 - crate::wireguard::STREAM_PACKET_HEADER_OVERHEAD as i32;
+
+/// Packet size requested for the next connection (0 = use the value passed to
+/// startConnection). Consumed by the connection start that uses it.
+static NEXT_PACKET_SIZE: AtomicI32 = AtomicI32::new(0);
+
+/// Packet size the current (or most recent) connection was started with (0 = none yet)
+static ACTIVE_PACKET_SIZE: AtomicI32 = AtomicI32::new(0);
+
+/// Record the packet size to use for the next connection, or 0 to clear it.
+/// moonlight-common-c has no way to renegotiate the packet size mid-stream, so
+/// the running connection keeps its size. Returns false if out of range.
+pub fn set_next_packet_size(size: i32) -> bool {
+    if size != 0 && !(MIN_PACKET_SIZE..=MAX_PACKET_SIZE).contains(&size) {
+        warn!("Rejected packet size {} (valid range {}-{})", size, MIN_PACKET_SIZE, MAX_PACKET_SIZE);
+        return false;
+    }
+    NEXT_PACKET_SIZE.store(size, Ordering::Relaxed);
+    info!("Packet size for next connection set to {}", size);
+    true
+}
+
+/// Packet size the current (or most recent) connection was started with
+pub fn active_packet_size() -> i32 {
+    ACTIVE_PACKET_SIZE.load(Ordering::Relaxed)
+}
+
+/// Pick the packet size for a connection being started: a pending
+/// set_next_packet_size() value wins over `requested` (and is used up), and the
+/// result is shrunk to fit the WireGuard tunnel MTU when the tunnel is up.
+pub fn resolve_packet_size(requested: i32) -> i32 {
+    let next = NEXT_PACKET_SIZE.swap(0, Ordering::Relaxed);
+    let wanted = if next != 0 { next } else { requested };
+    let size = fit_packet_size(wanted, crate::wireguard::wg_max_stream_packet_size());
+    if size != requested {
+        info!("Packet size adjusted from {} to {}", requested, size);
+    }
+    ACTIVE_PACKET_SIZE.store(size, Ordering::Relaxed);
+    size
+}
+
+/// Clamp `requested` to the valid range and to the tunnel's packet size limit
+fn fit_packet_size(requested: i32, tunnel_max: Option<u16>) -> i32 {
+    let mut size = requested.clamp(MIN_PACKET_SIZE, MAX_PACKET_SIZE);
+    if let Some(max) = tunnel_max {
+        let max = max as i32;
+        if max < MIN_PACKET_SIZE {
+            warn!("Tunnel MTU only fits {}-byte stream packets, using {} (packets will fragment)",
+                  max, MIN_PACKET_SIZE);
+        }
+        size = size.min(max).max(MIN_PACKET_SIZE);
+    }
+    size
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_packet_size() {
+        // No tunnel: only the valid range applies
+        assert_eq!(fit_packet_size(1024, None), 1024);
+        assert_eq!(fit_packet_size(100, None), MIN_PACKET_SIZE);
+        assert_eq!(fit_packet_size(9000, None), MAX_PACKET_SIZE);
+
+        // A 1280-byte IPv4 tunnel fits 1280 - 28 - overhead bytes of payload
+        let tunnel_max = 1280 - 28 - crate::wireguard::STREAM_PACKET_HEADER_OVERHEAD;
+        assert_eq!(fit_packet_size(1392, Some(tunnel_max)), tunnel_max as i32);
+        assert_eq!(fit_packet_size(1024, Some(tunnel_max)), 1024);
+
+        // Never below the minimum, even if the tunnel is too small
+        assert_eq!(fit_packet_size(1024, Some(300)), MIN_PACKET_SIZE);
+    }
+
+    #[test]
+    fn test_next_packet_size_is_used_once() {
+        // Sizes small enough for any tunnel MTU, so only the override matters
+        assert!(set_next_packet_size(1000));
+        assert_eq!(resolve_packet_size(1200), 1000);
+        assert_eq!(active_packet_size(), 1000);
+        assert_eq!(resolve_packet_size(1200), 1200);
+        assert!(!set_next_packet_size(MAX_PACKET_SIZE + 1));
+    }
+}
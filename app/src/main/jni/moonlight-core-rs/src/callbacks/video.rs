@@ -2,6 +2,13 @@
 //!
 //! This module provides callback functions for video decoding that bridge
 //! between moonlight-common-c and the JNI layer.
+//!
+//! There is no mid-stream format change callback: moonlight-common-c negotiates
+//! the format and resolution once and calls `setup` only at stream start, and a
+//! DECODE_UNIT carries no dimensions to compare against. The host scales its
+//! output to the negotiated mode when a game changes display mode, so the stream
+//! itself does not change size. Any SPS-level change still reaches MediaCodec
+//! in-band and is handled there (adaptive playback, KEY_MAX_WIDTH/HEIGHT).
 
 #![allow(static_mut_refs)]
 
@@ -9,7 +16,176 @@ use crate::ffi::*;
 use crate::jni_helpers::*;
 use libc::{c_int, c_void};
 use std::ptr;
-use log::{info, error, debug};
+use std::sync::atomic::{AtomicI32, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+use parking_lot::Mutex;
+use log::{info, warn, error, debug};
+
+// Video format the host picked for the active stream (0 when no video stream is set up)
+static NEGOTIATED_VIDEO_FORMAT: AtomicI32 = AtomicI32::new(0);
+
+/// Get the VIDEO_FORMAT_* value negotiated for the active stream, or 0 if the
+/// video decoder hasn't been set up.
+pub fn get_negotiated_video_format() -> c_int {
+    NEGOTIATED_VIDEO_FORMAT.load(Ordering::Acquire)
+}
+
+// Width, height and frame rate the host picked for the active stream (None when no
+// video stream is set up)
+static NEGOTIATED_VIDEO_MODE: Mutex<Option<(c_int, c_int, c_int)>> = Mutex::new(None);
+
+/// Get the (width, height, fps) negotiated for the active stream, or None if the
+/// video decoder hasn't been set up.
+pub fn get_negotiated_video_mode() -> Option<(c_int, c_int, c_int)> {
+    *NEGOTIATED_VIDEO_MODE.lock()
+}
+
+// VIDEO_FORMAT_* mask the app's decoder can handle (0 when not declared)
+static DECODER_VIDEO_FORMATS: AtomicI32 = AtomicI32::new(0);
+
+/// Record which VIDEO_FORMAT_* formats the app's decoder can handle (0 clears it).
+pub fn set_decoder_video_formats(formats: c_int) {
+    info!("Decoder video formats declared: {:#06x}", formats);
+    DECODER_VIDEO_FORMATS.store(formats, Ordering::Release);
+}
+
+/// Validate the formats the app wants to offer in the stream config against the
+/// decoder's declared formats (see filter_video_formats).
+pub fn compute_supported_video_formats(requested: c_int) -> c_int {
+    filter_video_formats(requested, DECODER_VIDEO_FORMATS.load(Ordering::Acquire))
+}
+
+/// Keep only the requested formats the decoder can handle, so the host can't pick
+/// one the client then fails to decode. A Main10 profile is only kept alongside its
+/// 8-bit base profile. If nothing is left, H.264 is offered: it's the format the
+/// host falls back to anyway. Without declared decoder formats the request is
+/// returned unchanged.
+fn filter_video_formats(requested: c_int, decoder: c_int) -> c_int {
+    if decoder == 0 {
+        return requested;
+    }
+
+    let known = VIDEO_FORMAT_MASK_H264 | VIDEO_FORMAT_MASK_H265 | VIDEO_FORMAT_MASK_AV1;
+    let mut formats = requested & decoder & known;
+    if formats & VIDEO_FORMAT_H265 == 0 {
+        formats &= !VIDEO_FORMAT_H265_MAIN10;
+    }
+    if formats & VIDEO_FORMAT_AV1_MAIN8 == 0 {
+        formats &= !VIDEO_FORMAT_AV1_MAIN10;
+    }
+    if formats == 0 {
+        formats = VIDEO_FORMAT_H264;
+    }
+    formats
+}
+
+// ============================================================================
+// Decoder overload detection
+// ============================================================================
+
+/// Default smoothed submit latency above which the decoder counts as overloaded
+pub const DEFAULT_DECODER_OVERLOAD_THRESHOLD_MS: u32 = 20;
+
+/// Default time the smoothed latency must stay above the threshold before the
+/// overload is reported
+pub const DEFAULT_DECODER_OVERLOAD_WINDOW_MS: u32 = 3000;
+
+static DECODER_OVERLOAD_THRESHOLD_MS: AtomicU32 = AtomicU32::new(DEFAULT_DECODER_OVERLOAD_THRESHOLD_MS);
+static DECODER_OVERLOAD_WINDOW_MS: AtomicU32 = AtomicU32::new(DEFAULT_DECODER_OVERLOAD_WINDOW_MS);
+
+// Weight of a new sample in the smoothed latency, as a right shift (1/8)
+const DECODE_LATENCY_EWMA_SHIFT: u32 = 3;
+
+/// Set when bridge_dr_decoder_overloaded fires: the smoothed time the app's
+/// decoder takes to accept a frame must stay above `threshold_ms` for `window_ms`.
+/// A threshold of 0 disables the signal; a window of 0 restores the default.
+pub fn set_decoder_overload_threshold(threshold_ms: u32, window_ms: u32) {
+    let window_ms = if window_ms == 0 { DEFAULT_DECODER_OVERLOAD_WINDOW_MS } else { window_ms };
+    info!("Decoder overload threshold: {}ms for {}ms", threshold_ms, window_ms);
+    DECODER_OVERLOAD_THRESHOLD_MS.store(threshold_ms, Ordering::Relaxed);
+    DECODER_OVERLOAD_WINDOW_MS.store(window_ms, Ordering::Relaxed);
+}
+
+/// Smooths the per-frame submit latency and decides when a sustained overload
+/// is reported. An overload is reported once; the signal re-arms only after the
+/// smoothed latency falls back to the threshold.
+struct DecodeLatencyTracker {
+    avg_us: Option<u64>,
+    over_since: Option<Instant>,
+    reported: bool,
+}
+
+impl DecodeLatencyTracker {
+    const fn new() -> Self {
+        Self { avg_us: None, over_since: None, reported: false }
+    }
+
+    /// Add a sample; returns the smoothed latency in ms when this sample
+    /// completes an overload window.
+    fn observe(&mut self, latency: Duration, now: Instant, threshold: Duration, window: Duration) -> Option<u32> {
+        let sample = latency.as_micros().min(u64::MAX as u128) as u64;
+        let avg = match self.avg_us {
+            Some(avg) => avg - (avg >> DECODE_LATENCY_EWMA_SHIFT) + (sample >> DECODE_LATENCY_EWMA_SHIFT),
+            None => sample,
+        };
+        self.avg_us = Some(avg);
+
+        if avg <= threshold.as_micros() as u64 {
+            self.over_since = None;
+            self.reported = false;
+            return None;
+        }
+
+        let since = *self.over_since.get_or_insert(now);
+        if self.reported || now.duration_since(since) < window {
+            return None;
+        }
+        self.reported = true;
+        Some((avg / 1000).min(u32::MAX as u64) as u32)
+    }
+}
+
+// Submit latency of the active stream; reset on decoder setup and cleanup
+static DECODE_LATENCY: Mutex<DecodeLatencyTracker> = Mutex::new(DecodeLatencyTracker::new());
+
+/// Feed one picture-data submit time into the tracker and tell the app once the
+/// decoder has stayed overloaded for the configured window.
+fn record_submit_latency(latency: Duration) {
+    let threshold_ms = DECODER_OVERLOAD_THRESHOLD_MS.load(Ordering::Relaxed);
+    if threshold_ms == 0 {
+        return;
+    }
+    let window_ms = DECODER_OVERLOAD_WINDOW_MS.load(Ordering::Relaxed);
+
+    let overloaded = DECODE_LATENCY.lock().observe(
+        latency,
+        Instant::now(),
+        Duration::from_millis(threshold_ms as u64),
+        Duration::from_millis(window_ms as u64),
+    );
+    if let Some(average_ms) = overloaded {
+        warn!("Decoder overloaded: submit latency averaged {}ms for {}ms", average_ms, window_ms);
+        bridge_dr_decoder_overloaded(average_ms as c_int, window_ms as c_int);
+    }
+}
+
+/// Not a moonlight-common-c callback: fired from the submit path when the
+/// decoder's smoothed submit latency has stayed above the threshold for the
+/// whole window, so the app can lower the resolution or switch codecs.
+pub extern "C" fn bridge_dr_decoder_overloaded(average_latency_ms: c_int, window_ms: c_int) {
+    let env = match get_thread_env() {
+        Some(e) => e,
+        None => return,
+    };
+
+    let method = get_dr_decoder_overloaded_method();
+    if !method.is_null() {
+        let args = [JValue::int(average_latency_ms), JValue::int(window_ms)];
+        call_static_void_method(env, method, &args);
+        // Runs inside the submit path, which goes on to submit this frame
+        describe_and_clear_exception(env);
+    }
+}
 
 pub extern "C" fn bridge_dr_setup(
     video_format: c_int,
@@ -57,6 +233,22 @@ pub extern "C" fn bridge_dr_setup(
     delete_local_ref(env, buffer);
     set_decoded_frame_buffer(global_buffer);
 
+    NEGOTIATED_VIDEO_FORMAT.store(video_format, Ordering::Release);
+    *NEGOTIATED_VIDEO_MODE.lock() = Some((width, height, redraw_rate));
+    *DECODE_LATENCY.lock() = DecodeLatencyTracker::new();
+
+    // Tell the app what the host actually chose; the renderer was configured
+    // from the formats the client offered
+    let method = get_cl_video_format_negotiated_method();
+    if !method.is_null() {
+        call_static_void_method(env, method, &args);
+        // Only a notification: the decoder is already set up, so a throwing
+        // listener must not fail the stream
+        if describe_and_clear_exception(env) {
+            warn!("Video format listener threw; continuing with decoder setup");
+        }
+    }
+
     0
 }
 
@@ -96,6 +288,10 @@ pub extern "C" fn bridge_dr_cleanup() {
         None => return,
     };
 
+    NEGOTIATED_VIDEO_FORMAT.store(0, Ordering::Release);
+    *NEGOTIATED_VIDEO_MODE.lock() = None;
+    *DECODE_LATENCY.lock() = DecodeLatencyTracker::new();
+
     // Delete global frame buffer reference
     let buffer = get_decoded_frame_buffer();
     if !buffer.is_null() {
@@ -196,11 +392,17 @@ pub extern "C" fn bridge_dr_submit_decode_unit(decode_unit: *mut DECODE_UNIT) ->
         JValue::long(du.enqueueTimeUs as i64),
     ];
 
+    // The time the decoder takes to accept the frame grows when it can't keep up
+    let submit_start = Instant::now();
     let ret = call_static_int_method(env, method, &args);
     if check_exception(env) {
         detach_current_thread();
         return DR_OK;
     }
+    record_submit_latency(submit_start.elapsed());
+    if ret == DR_OK {
+        super::record_first_frame();
+    }
 
     ret
 }
@@ -218,3 +420,70 @@ pub static VIDEO_CALLBACKS: DECODER_RENDERER_CALLBACKS = DECODER_RENDERER_CALLBA
     capabilities: 0, // Will be set at runtime
 };
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_video_formats() {
+        let all = VIDEO_FORMAT_H264 | VIDEO_FORMAT_H265 | VIDEO_FORMAT_H265_MAIN10
+            | VIDEO_FORMAT_AV1_MAIN8 | VIDEO_FORMAT_AV1_MAIN10;
+        let requested = VIDEO_FORMAT_H265 | VIDEO_FORMAT_H265_MAIN10 | VIDEO_FORMAT_AV1_MAIN8;
+
+        // Nothing declared: the request passes through
+        assert_eq!(filter_video_formats(requested, 0), requested);
+        // A capable decoder keeps exactly the request
+        assert_eq!(filter_video_formats(requested, all), requested);
+
+        // AV1 isn't advertised to a decoder without it
+        let no_av1 = VIDEO_FORMAT_H264 | VIDEO_FORMAT_H265 | VIDEO_FORMAT_H265_MAIN10;
+        assert_eq!(filter_video_formats(requested, no_av1), VIDEO_FORMAT_H265 | VIDEO_FORMAT_H265_MAIN10);
+
+        // Main10 without its base profile is dropped
+        let av1_10 = VIDEO_FORMAT_AV1_MAIN10 | VIDEO_FORMAT_H265;
+        assert_eq!(filter_video_formats(av1_10, VIDEO_FORMAT_AV1_MAIN10 | VIDEO_FORMAT_H265), VIDEO_FORMAT_H265);
+
+        // Nothing usable left: fall back to H.264
+        assert_eq!(filter_video_formats(VIDEO_FORMAT_AV1_MAIN8, VIDEO_FORMAT_H264), VIDEO_FORMAT_H264);
+        // Unknown bits are never passed on
+        assert_eq!(filter_video_formats(0x0010_0000 | VIDEO_FORMAT_H265, -1), VIDEO_FORMAT_H265);
+    }
+
+    #[test]
+    fn test_decode_latency_tracker() {
+        let ms = Duration::from_millis;
+        let threshold = ms(20);
+        let window = ms(1000);
+        let start = Instant::now();
+        let mut tracker = DecodeLatencyTracker::new();
+
+        // Fast frames never report
+        assert_eq!(tracker.observe(ms(5), start, threshold, window), None);
+
+        // A single slow frame only nudges the average
+        assert_eq!(tracker.observe(ms(100), start + ms(10), threshold, window), None);
+        assert_eq!(tracker.over_since, None);
+
+        // Sustained slow frames report once the window has passed, and only once
+        let mut reports = Vec::new();
+        for i in 0..200u64 {
+            if let Some(avg) = tracker.observe(ms(50), start + ms(20 + i * 16), threshold, window) {
+                reports.push(avg);
+            }
+        }
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0] > 20 && reports[0] <= 50);
+
+        // Recovery re-arms the signal
+        let mut now = start + ms(5000);
+        while tracker.observe(ms(1), now, threshold, window).is_none() && tracker.over_since.is_some() {
+            now += ms(16);
+        }
+        assert!(!tracker.reported);
+        let mut reported = false;
+        for i in 0..200u64 {
+            reported |= tracker.observe(ms(50), now + ms(i * 16), threshold, window).is_some();
+        }
+        assert!(reported);
+    }
+}
@@ -19,6 +19,26 @@ pub const FRAME_TYPE_IDR: c_int = 1;
 // Decoder return codes
 pub const DR_OK: c_int = 0;
 
+// Connection stages (see STAGE_* in Limelight.h).
+// moonlight-common-c doesn't export the stage count, so STAGE_MAX is mirrored here
+// and must be kept in sync on version bumps.
+pub const STAGE_NONE: c_int = 0;
+pub const STAGE_PLATFORM_INIT: c_int = 1;
+pub const STAGE_NAME_RESOLUTION: c_int = 2;
+pub const STAGE_AUDIO_STREAM_INIT: c_int = 3;
+pub const STAGE_RTSP_HANDSHAKE: c_int = 4;
+pub const STAGE_CONTROL_STREAM_INIT: c_int = 5;
+pub const STAGE_VIDEO_STREAM_INIT: c_int = 6;
+pub const STAGE_INPUT_STREAM_INIT: c_int = 7;
+pub const STAGE_CONTROL_STREAM_START: c_int = 8;
+pub const STAGE_VIDEO_STREAM_START: c_int = 9;
+pub const STAGE_AUDIO_STREAM_START: c_int = 10;
+pub const STAGE_INPUT_STREAM_START: c_int = 11;
+pub const STAGE_MAX: c_int = 12;
+
+/// Number of real connection stages (STAGE_PLATFORM_INIT..STAGE_MAX)
+pub const TOTAL_STAGES: c_int = STAGE_MAX - 1;
+
 // Connection status codes
 pub const CONN_STATUS_OKAY: c_int = 0;
 pub const CONN_STATUS_POOR: c_int = 1;
@@ -37,9 +57,32 @@ pub const LI_CTYPE_XBOX: c_int = 0x01;
 pub const LI_CTYPE_PS: c_int = 0x02;
 pub const LI_CTYPE_NINTENDO: c_int = 0x03;
 
+// Audio configurations (see MAKE_AUDIO_CONFIGURATION() in Limelight.h)
+pub const AUDIO_CONFIGURATION_STEREO: c_int = (0x3 << 16) | (2 << 8) | 0xCA;
+pub const AUDIO_CONFIGURATION_51_SURROUND: c_int = (0x3F << 16) | (6 << 8) | 0xCA;
+pub const AUDIO_CONFIGURATION_71_SURROUND: c_int = (0x63F << 16) | (8 << 8) | 0xCA;
+
 /// Capability flags for audio renderer
 pub const CAPABILITY_SUPPORTS_ARBITRARY_AUDIO_DURATION: c_int = 0x10;
 
+// Video formats (see VIDEO_FORMAT_* in Limelight.h)
+pub const VIDEO_FORMAT_H264: c_int = 0x0001;
+pub const VIDEO_FORMAT_H265: c_int = 0x0100;
+pub const VIDEO_FORMAT_H265_MAIN10: c_int = 0x0200;
+pub const VIDEO_FORMAT_AV1_MAIN8: c_int = 0x1000;
+pub const VIDEO_FORMAT_AV1_MAIN10: c_int = 0x2000;
+pub const VIDEO_FORMAT_MASK_H264: c_int = 0x000F;
+pub const VIDEO_FORMAT_MASK_H265: c_int = 0x0F00;
+pub const VIDEO_FORMAT_MASK_AV1: c_int = 0xF000;
+pub const VIDEO_FORMAT_MASK_10BIT: c_int = 0x2200;
+
+// Color spaces and ranges (see COLORSPACE_* and COLOR_RANGE_* in Limelight.h)
+pub const COLORSPACE_REC_601: c_int = 0;
+pub const COLORSPACE_REC_709: c_int = 1;
+pub const COLORSPACE_REC_2020: c_int = 2;
+pub const COLOR_RANGE_LIMITED: c_int = 0;
+pub const COLOR_RANGE_FULL: c_int = 1;
+
 /// Linked list entry for decode unit buffer
 #[repr(C)]
 pub struct LENTRY {
@@ -310,6 +353,8 @@ extern "C" {
     ) -> c_int;
     pub fn LiGetPortFlagsFromStage(stage: c_int) -> c_int;
     pub fn LiGetPortFlagsFromTerminationErrorCode(errorCode: c_int) -> c_int;
+    pub fn LiGetPortFromPortFlagIndex(portFlagIndex: c_int) -> c_ushort;
+    pub fn LiGetProtocolFromPortFlagIndex(portFlagIndex: c_int) -> c_int;
     pub fn LiStringifyPortFlags(
         portFlags: c_int,
         separator: *const c_char,
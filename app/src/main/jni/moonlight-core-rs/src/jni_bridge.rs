@@ -7,20 +7,23 @@ use crate::callbacks::{
     has_fast_aes,
     bridge_dr_setup, bridge_dr_start, bridge_dr_stop, bridge_dr_cleanup, bridge_dr_submit_decode_unit,
     bridge_ar_init, bridge_ar_start, bridge_ar_stop, bridge_ar_cleanup, bridge_ar_decode_and_play_sample,
+    set_downmix_to_stereo, get_active_audio_configuration, is_downmix_active,
     bridge_cl_stage_starting, bridge_cl_stage_complete, bridge_cl_stage_failed,
     bridge_cl_connection_started, bridge_cl_connection_terminated, bridge_cl_rumble,
     bridge_cl_connection_status_update, bridge_cl_set_hdr_mode, bridge_cl_rumble_triggers,
     bridge_cl_set_motion_event_state, bridge_cl_set_controller_led,
     set_jni_callbacks,
+    set_next_packet_size, active_packet_size, resolve_packet_size,
 };
 use crate::ffi::*;
 use crate::jni_helpers;
-use libc::{c_char, c_void};
+use libc::{c_char, c_int, c_void};
 use std::ffi::{CStr, CString};
 use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+use parking_lot::Mutex;
 
-use log::{info, error, debug};
+use log::{info, warn, error, debug};
 
 // JNI type definitions
 pub type JNIEnv = *mut c_void;
@@ -70,20 +73,61 @@ pub fn get_jni_env() -> Option<JNIEnv> {
     jni_helpers::get_thread_env()
 }
 
+/// Log level after init: Debug in debug builds, Info in release builds where the
+/// per-packet debug! logs on the WireGuard hot paths cost measurable CPU
+const DEFAULT_LOG_LEVEL: log::LevelFilter = if cfg!(debug_assertions) {
+    log::LevelFilter::Debug
+} else {
+    log::LevelFilter::Info
+};
+
+/// Map an android.util.Log priority to a level filter.
+/// Priorities above ERROR (e.g. ASSERT) turn logging off.
+fn log_level_from_priority(priority: JInt) -> Option<log::LevelFilter> {
+    match priority {
+        2 => Some(log::LevelFilter::Trace), // VERBOSE
+        3 => Some(log::LevelFilter::Debug),
+        4 => Some(log::LevelFilter::Info),
+        5 => Some(log::LevelFilter::Warn),
+        6 => Some(log::LevelFilter::Error),
+        p if p > 6 => Some(log::LevelFilter::Off),
+        _ => None,
+    }
+}
+
+/// Change the native log level at runtime (MoonBridge.nativeSetLogLevel)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeSetLogLevel(
+    _env: JNIEnv,
+    _clazz: JClass,
+    priority: JInt,
+) {
+    match log_level_from_priority(priority) {
+        Some(level) => {
+            log::set_max_level(level);
+            // Logged at warn so the change is visible at every level but Error/Off
+            warn!("Native log level set to {}", level);
+        }
+        None => error!("nativeSetLogLevel: invalid priority {}", priority),
+    }
+}
+
 /// Initialize the MoonBridge native library
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_init(
     env: JNIEnv,
     clazz: JClass,
 ) {
-    // Initialize Android logger
+    // Initialize Android logger. The logger itself accepts everything; the
+    // effective level is the global max level, adjustable via nativeSetLogLevel.
     #[cfg(target_os = "android")]
     {
         android_logger::init_once(
             android_logger::Config::default()
-                .with_max_level(log::LevelFilter::Debug)
+                .with_max_level(log::LevelFilter::Trace)
                 .with_tag("moonlight-core-rs"),
         );
+        log::set_max_level(DEFAULT_LOG_LEVEL);
     }
 
     // Store JavaVM using jni_helpers
@@ -129,6 +173,53 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendMouseMove(
     }
 }
 
+/// Sub-pixel units per pixel in sendMouseMoveHighRes deltas
+pub const MOUSE_MOVE_HIGH_RES_UNITS: i32 = 256;
+
+// Movement (in MOUSE_MOVE_HIGH_RES_UNITS) too small to have been sent yet
+static MOUSE_MOVE_REMAINDER: Mutex<(i64, i64)> = Mutex::new((0, 0));
+
+/// Add a high-resolution delta to the carried remainder and return the whole
+/// pixels to send. The fraction is kept, with the sign of the movement, so slow
+/// movement adds up instead of being truncated away on every event.
+fn accumulate_high_res_move(remainder: &mut (i64, i64), delta_x: i32, delta_y: i32) -> (i64, i64) {
+    let units = MOUSE_MOVE_HIGH_RES_UNITS as i64;
+    let total_x = remainder.0 + delta_x as i64;
+    let total_y = remainder.1 + delta_y as i64;
+    *remainder = (total_x % units, total_y % units);
+    (total_x / units, total_y / units)
+}
+
+/// Split a movement into steps that fit LiSendMouseMoveEvent's short deltas
+fn mouse_move_steps(mut delta_x: i64, mut delta_y: i64) -> Vec<(i16, i16)> {
+    let mut steps = Vec::new();
+    while delta_x != 0 || delta_y != 0 {
+        let step_x = delta_x.clamp(i16::MIN as i64, i16::MAX as i64);
+        let step_y = delta_y.clamp(i16::MIN as i64, i16::MAX as i64);
+        steps.push((step_x as i16, step_y as i16));
+        delta_x -= step_x;
+        delta_y -= step_y;
+    }
+    steps
+}
+
+/// Send mouse move event with sub-pixel deltas (in MOUSE_MOVE_HIGH_RES_UNITS).
+/// The host only takes whole pixels, so fractions carry over to the next call.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendMouseMoveHighRes(
+    _env: JNIEnv,
+    _clazz: JClass,
+    delta_x: JInt,
+    delta_y: JInt,
+) {
+    let (pixels_x, pixels_y) = accumulate_high_res_move(&mut MOUSE_MOVE_REMAINDER.lock(), delta_x, delta_y);
+    for (step_x, step_y) in mouse_move_steps(pixels_x, pixels_y) {
+        unsafe {
+            LiSendMouseMoveEvent(step_x, step_y);
+        }
+    }
+}
+
 /// Send mouse position event
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendMousePosition(
@@ -381,7 +472,36 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendMouseHighResHSc
     }
 }
 
-/// Send UTF-8 text event
+/// Maximum bytes per LiSendUtf8TextEvent call. The control stream packet carries
+/// the text in a fixed 32-byte field; longer messages are rejected by the host.
+const UTF8_TEXT_MAX_CHUNK_BYTES: usize = 32;
+
+/// Split UTF-8 bytes into chunks of at most `max_len` bytes without splitting a
+/// multibyte sequence. A boundary is any byte that isn't a continuation byte.
+fn split_utf8_chunks(bytes: &[u8], max_len: usize) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+
+    while start < bytes.len() {
+        let mut end = std::cmp::min(start + max_len, bytes.len());
+        // Back off to the start of the sequence that straddles the limit
+        while end < bytes.len() && end > start && (bytes[end] & 0xC0) == 0x80 {
+            end -= 1;
+        }
+        if end == start {
+            // Malformed input with an over-long sequence; send it as-is
+            end = std::cmp::min(start + max_len, bytes.len());
+        }
+        chunks.push(&bytes[start..end]);
+        start = end;
+    }
+
+    chunks
+}
+
+/// Send UTF-8 text event.
+/// Long text (e.g. clipboard pastes) is sent in sequential chunks split on
+/// character boundaries.
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendUtf8Text(
     env: JNIEnv,
@@ -399,10 +519,16 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_sendUtf8Text(
     }
 
     let c_str = unsafe { CStr::from_ptr(utf8_text) };
-    let len = c_str.to_bytes().len();
+    let bytes = c_str.to_bytes();
 
     unsafe {
-        LiSendUtf8TextEvent(utf8_text, len);
+        if bytes.len() <= UTF8_TEXT_MAX_CHUNK_BYTES {
+            LiSendUtf8TextEvent(utf8_text, bytes.len());
+        } else {
+            for chunk in split_utf8_chunks(bytes, UTF8_TEXT_MAX_CHUNK_BYTES) {
+                LiSendUtf8TextEvent(chunk.as_ptr() as *const c_char, chunk.len());
+            }
+        }
         jni_release_string_utf_chars(env, text, utf8_text);
     }
 }
@@ -416,6 +542,7 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_stopConnection(
     unsafe {
         LiStopConnection();
     }
+    end_connection();
 }
 
 /// Interrupt connection
@@ -444,6 +571,23 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getStageName(
     unsafe { jni_new_string_utf(env, stage_name) }
 }
 
+/// Get the total number of connection stages, for computing progress from the
+/// stage index passed to the stage callbacks
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getTotalStages(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    TOTAL_STAGES
+}
+
+/// Format the address returned by LiFindExternalAddressIP4. The value is an
+/// `in_addr.s_addr`, i.e. the octets are in network byte order in memory
+/// regardless of the host's endianness.
+fn format_stun_wan_addr(wan_addr: u32) -> String {
+    std::net::Ipv4Addr::from(u32::from_be(wan_addr)).to_string()
+}
+
 /// Find external IPv4 address using STUN
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_findExternalAddressIP4(
@@ -469,9 +613,7 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_findExternalAddress
     }
 
     if err == 0 {
-        // Convert IP to string
-        let ip_bytes = wan_addr.to_ne_bytes();
-        let ip_str = format!("{}.{}.{}.{}", ip_bytes[0], ip_bytes[1], ip_bytes[2], ip_bytes[3]);
+        let ip_str = format_stun_wan_addr(wan_addr);
 
         info!("Resolved WAN address to {}", ip_str);
 
@@ -501,7 +643,145 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getPendingVideoFram
     unsafe { LiGetPendingVideoFrames() }
 }
 
-/// Test client connectivity
+/// Enable or disable stereo downmix of surround audio (applies to the next stream)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_setAudioDownmixToStereo(
+    _env: JNIEnv,
+    _clazz: JClass,
+    enabled: JBoolean,
+) {
+    set_downmix_to_stereo(enabled != JNI_FALSE);
+}
+
+/// Get the negotiated audio configuration of the active stream (0 if none)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getActiveAudioConfiguration(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    get_active_audio_configuration()
+}
+
+/// Get the video format (VIDEO_FORMAT_*) the host chose for the active stream (0 if none)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetNegotiatedVideoFormat(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    crate::callbacks::get_negotiated_video_format()
+}
+
+/// Get the stream configuration in effect for the current (or most recent) connection
+/// as "width=N height=N fps=N bitrate=N color_space=N color_range=N", or null if
+/// no connection has started.
+/// JNI interface: MoonBridge.nativeGetActiveStreamConfig()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetActiveStreamConfig(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    match crate::callbacks::get_active_stream_config() {
+        Some(config) => {
+            let text = format!(
+                "width={} height={} fps={} bitrate={} color_space={} color_range={}",
+                config.width, config.height, config.fps, config.bitrate, config.color_space,
+                config.color_range
+            );
+            let c_str = CString::new(text).unwrap_or_default();
+            unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Get the latency milestones of the current (or most recent) connection as
+/// "handshake_ms=N first_data_ms=N first_frame_ms=N", relative to startConnection or
+/// to wg_start_tunnel if the tunnel was started for this connection (-1 where not
+/// reached), or null before the first connection.
+/// JNI interface: MoonBridge.nativeGetConnectionTimings()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetConnectionTimings(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    match crate::callbacks::get_connection_timings() {
+        Some(timings) => {
+            let ms = |d: Option<std::time::Duration>| d.map_or(-1, |d| d.as_millis() as i64);
+            let text = format!(
+                "handshake_ms={} first_data_ms={} first_frame_ms={}",
+                ms(timings.handshake), ms(timings.first_data), ms(timings.first_frame)
+            );
+            let c_str = CString::new(text).unwrap_or_default();
+            unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Get the bytes the active WireGuard tunnel adds to each stream datagram (inner
+/// IP/UDP, WireGuard header and tag, outer IP/UDP), or 0 without a tunnel.
+/// JNI interface: MoonBridge.nativeGetWgOverheadBytes()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetWgOverheadBytes(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    crate::wireguard::wg_tunnel_overhead_bytes().map_or(0, |bytes| bytes as JInt)
+}
+
+/// Declare the VIDEO_FORMAT_* formats the app's decoder can handle (0 clears it)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeSetDecoderVideoFormats(
+    _env: JNIEnv,
+    _clazz: JClass,
+    formats: JInt,
+) {
+    crate::callbacks::set_decoder_video_formats(formats);
+}
+
+/// Set the submit latency threshold and window for the decoder overload signal
+/// (a threshold of 0 disables it, a window of 0 restores the default)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_setDecoderOverloadThreshold(
+    _env: JNIEnv,
+    _clazz: JClass,
+    threshold_ms: JInt,
+    window_ms: JInt,
+) {
+    crate::callbacks::set_decoder_overload_threshold(threshold_ms.max(0) as u32, window_ms.max(0) as u32);
+}
+
+/// Filter a supportedVideoFormats mask down to what the declared decoder can handle
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeComputeSupportedVideoFormats(
+    _env: JNIEnv,
+    _clazz: JClass,
+    requested: JInt,
+) -> JInt {
+    crate::callbacks::compute_supported_video_formats(requested)
+}
+
+/// Check if surround audio of the active stream is being downmixed to stereo
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_isAudioDownmixActive(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JBoolean {
+    if is_downmix_active() {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// How long the connectivity test waits for the WireGuard peer to answer
+const WG_CONNECTIVITY_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Test client connectivity.
+/// With WireGuard routing active the streaming ports travel inside the tunnel,
+/// so the direct test against the test server says nothing about them. The
+/// tunnel path is probed instead: 0 if the peer answers, otherwise all of the
+/// tested port flags are reported blocked.
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_testClientConnectivity(
     env: JNIEnv,
@@ -514,6 +794,16 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_testClientConnectiv
         return -1;
     }
 
+    if crate::platform_sockets::is_wg_routing_active() {
+        return if crate::wireguard::wg_probe_tunnel(WG_CONNECTIVITY_PROBE_TIMEOUT) {
+            info!("testClientConnectivity: WireGuard tunnel path reachable");
+            0
+        } else {
+            warn!("testClientConnectivity: WireGuard peer not answering through the tunnel");
+            test_flags
+        };
+    }
+
     let host_name_str = unsafe { jni_get_string_utf_chars(env, test_server_host_name) };
     if host_name_str.is_null() {
         return -1;
@@ -550,7 +840,14 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getPortFlagsFromTer
     unsafe { LiGetPortFlagsFromTerminationErrorCode(error_code) }
 }
 
-/// Stringify port flags
+/// Initial LiStringifyPortFlags buffer; doubled while the output fills it
+const PORT_FLAGS_STRING_INITIAL_LEN: usize = 512;
+
+/// Largest LiStringifyPortFlags buffer tried before accepting truncated output
+const PORT_FLAGS_STRING_MAX_LEN: usize = 16 * 1024;
+
+/// Stringify port flags. LiStringifyPortFlags silently truncates to the buffer,
+/// so output that fills the buffer is retried with a larger one.
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_stringifyPortFlags(
     env: JNIEnv,
@@ -567,26 +864,67 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_stringifyPortFlags(
         return ptr::null_mut();
     }
 
-    let mut output_buffer = [0u8; 512];
+    let mut output_buffer = vec![0u8; PORT_FLAGS_STRING_INITIAL_LEN];
+    let len = loop {
+        output_buffer.fill(0);
+        unsafe {
+            LiStringifyPortFlags(
+                port_flags,
+                separator_str,
+                output_buffer.as_mut_ptr() as *mut c_char,
+                output_buffer.len(),
+            );
+        }
 
-    unsafe {
-        LiStringifyPortFlags(
-            port_flags,
-            separator_str,
-            output_buffer.as_mut_ptr() as *mut c_char,
-            output_buffer.len(),
-        );
-        jni_release_string_utf_chars(env, separator, separator_str);
-    }
+        // Find null terminator; a string reaching the last byte may be cut short
+        let len = output_buffer.iter().position(|&c| c == 0).unwrap_or(output_buffer.len());
+        if len + 1 < output_buffer.len() {
+            break len;
+        }
+        if output_buffer.len() >= PORT_FLAGS_STRING_MAX_LEN {
+            warn!("stringifyPortFlags: output for flags {:#x} truncated at {} bytes", port_flags, len);
+            break len;
+        }
+        output_buffer = vec![0u8; output_buffer.len() * 2];
+    };
+    unsafe { jni_release_string_utf_chars(env, separator, separator_str) };
 
-    // Find null terminator
-    let len = output_buffer.iter().position(|&c| c == 0).unwrap_or(output_buffer.len());
-    let result = std::str::from_utf8(&output_buffer[..len]).unwrap_or("");
+    let result = String::from_utf8_lossy(&output_buffer[..len]);
 
-    let c_str = CString::new(result).unwrap_or_default();
+    let c_str = CString::new(result.as_ref()).unwrap_or_default();
     unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
 }
 
+/// "flag:portName" entries (e.g. "256:UDP 47998") for each port flag set in
+/// `port_flags`, in flag order. `lookup` maps a port flag index to its
+/// IPPROTO_* protocol and port number.
+fn port_info_entries(port_flags: u32, lookup: impl Fn(c_int) -> (c_int, u16)) -> Vec<String> {
+    (0..u32::BITS as c_int)
+        .filter(|&index| port_flags & (1 << index) != 0)
+        .map(|index| {
+            let (protocol, port) = lookup(index);
+            let protocol = if protocol == libc::IPPROTO_TCP { "TCP" } else { "UDP" };
+            format!("{}:{} {}", 1u32 << index, protocol, port)
+        })
+        .collect()
+}
+
+/// Ports a termination error code suggests may be blocked, as "flag:portName"
+/// entries (MoonBridge.nativeGetTerminationPortInfo). Built in Rust from the
+/// per-index port lookups rather than the fixed-buffer stringify path.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetTerminationPortInfo(
+    env: JNIEnv,
+    _clazz: JClass,
+    error_code: JInt,
+) -> JObject {
+    let port_flags = unsafe { LiGetPortFlagsFromTerminationErrorCode(error_code) } as u32;
+    let entries = port_info_entries(port_flags, |index| unsafe {
+        (LiGetProtocolFromPortFlagIndex(index), LiGetPortFromPortFlagIndex(index))
+    });
+    jni_helpers::create_string_array(env, &entries)
+}
+
 /// Get estimated RTT info
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getEstimatedRttInfo(
@@ -605,6 +943,85 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getEstimatedRttInfo
     ((rtt as u64) << 32) as i64 | (variance as i64)
 }
 
+/// Get the packet size the current (or most recent) connection was started with
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetPacketSize(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    active_packet_size()
+}
+
+/// Set the packet size for the next connection (0 clears it); false if out of range
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeSetPacketSize(
+    _env: JNIEnv,
+    _clazz: JClass,
+    packet_size: JInt,
+) -> JBoolean {
+    if set_next_packet_size(packet_size) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// Get opus packet loss concealment statistics and the active decode mode
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetAudioConcealmentStats(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JLong {
+    let (plc_frames, consecutive) = crate::opus::concealment_stats();
+    let plc_frames = plc_frames.min(u32::MAX as u64);
+    let consecutive = consecutive.min(0x00FF_FFFF) as u64;
+    let mode = (crate::opus::active_decode_mode() as u64 & 0xFF) << 24;
+
+    ((plc_frames << 32) | mode | consecutive) as i64
+}
+
+/// Select the Opus decode mode for the next audio stream
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeSetOpusDecodeMode(
+    _env: JNIEnv,
+    _clazz: JClass,
+    mode: JInt,
+) {
+    crate::opus::set_decode_mode(mode);
+}
+
+/// Get the MAKE_AUDIO_CONFIGURATION() values the Opus decoder can handle
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetSupportedAudioConfigs(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> jni_helpers::JIntArray {
+    jni_helpers::create_int_array(env, &crate::opus::supported_audio_configurations())
+}
+
+/// Get the audio configuration the current (or most recent) connection was started with
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeGetAudioConfig(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JInt {
+    crate::opus::active_audio_configuration()
+}
+
+/// Set the audio configuration for the next connection (0 clears it); false if unsupported
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_nativeSetPreferredAudioConfig(
+    _env: JNIEnv,
+    _clazz: JClass,
+    audio_configuration: JInt,
+) -> JBoolean {
+    if crate::opus::set_preferred_audio_configuration(audio_configuration) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
 /// Get launch URL query parameters
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getLaunchUrlQueryParameters(
@@ -619,6 +1036,30 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_getLaunchUrlQueryPa
     unsafe { jni_new_string_utf(env, params) }
 }
 
+// ============================================================================
+// Connection guard
+// ============================================================================
+
+/// Returned by startConnection while another connection is starting or active
+pub const ML_ERROR_CONNECTION_IN_PROGRESS: JInt = -200;
+
+/// Set while a connection is being started or is active. moonlight-common-c keeps
+/// one set of callbacks and stream state, so a second startConnection (a double
+/// tap, a reconnect racing a connect) must not run underneath the first. Cleared
+/// by stopConnection, or when the start fails.
+static CONNECTION_ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Claim the connection; false if another connection already holds it.
+fn try_begin_connection() -> bool {
+    CONNECTION_ACTIVE
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+}
+
+/// Release the connection so the next startConnection can run.
+fn end_connection() {
+    CONNECTION_ACTIVE.store(false, Ordering::Release);
+}
 
 /// Start connection
 #[no_mangle]
@@ -648,6 +1089,11 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_startConnection(
 ) -> JInt {
     info!("startConnection called: {}x{} @ {}fps, bitrate={}, disable_encryption={}", width, height, fps, bitrate, disable_encryption != 0);
 
+    if !try_begin_connection() {
+        warn!("startConnection rejected: another connection is already starting or active");
+        return ML_ERROR_CONNECTION_IN_PROGRESS;
+    }
+
     // Get string parameters
     let address_str = unsafe { jni_get_string_utf_chars(env, address) };
     let app_version_str = unsafe { jni_get_string_utf_chars(env, app_version) };
@@ -703,9 +1149,9 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_startConnection(
         height,
         fps,
         bitrate,
-        packetSize: packet_size,
+        packetSize: resolve_packet_size(packet_size),
         streamingRemotely: streaming_remotely,
-        audioConfiguration: audio_configuration,
+        audioConfiguration: crate::opus::resolve_audio_configuration(audio_configuration),
         supportedVideoFormats: supported_video_formats,
         clientRefreshRateX100: client_refresh_rate_x100,
         colorSpace: color_space,
@@ -714,6 +1160,11 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_startConnection(
         remoteInputAesKey: aes_key,
         remoteInputAesIv: aes_iv,
     };
+    crate::callbacks::set_requested_stream_config(&stream_config);
+    crate::callbacks::start_connection_timings(
+        crate::wireguard::wg_is_tunnel_active(),
+        crate::wireguard::wg_is_data_flowing(),
+    );
 
     info!("Creating callbacks...");
 
@@ -778,6 +1229,10 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_startConnection(
     };
 
     info!("LiStartConnection returned: {}", ret);
+    if ret != 0 {
+        // A failed start cleans up after itself; no stopConnection will follow
+        end_connection();
+    }
 
     // Release strings
     unsafe {
@@ -1125,9 +1580,9 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgStartTunnel(
     let tunnel_addr_str = unsafe { CStr::from_ptr(tunnel_str) }.to_string_lossy().to_string();
     unsafe { jni_release_string_utf_chars(env, tunnel_addr, tunnel_str) };
 
-    // Parse addresses
-    let tunnel_ip: std::net::IpAddr = match tunnel_addr_str.parse() {
-        Ok(ip) => ip,
+    // Parse addresses (comma-separated for a dual-stack tunnel)
+    let tunnel_ips = match crate::wireguard_config::parse_tunnel_addresses(&tunnel_addr_str) {
+        Ok(ips) => ips,
         Err(e) => {
             error!("wgStartTunnel: invalid tunnel address '{}': {}", tunnel_addr_str, e);
             return -4;
@@ -1143,8 +1598,10 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgStartTunnel(
         peer_public_key: pub_key,
         preshared_key: psk,
         endpoint: endpoint_str,
-        tunnel_address: tunnel_ip,
+        tunnel_addresses: tunnel_ips,
         mtu: mtu as u16,
+        handshake_retry: crate::wireguard::wg_handshake_retry_schedule(),
+        ..Default::default()
     };
 
     match crate::wireguard::wg_start_tunnel(config) {
@@ -1206,7 +1663,7 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgEnableDirectRouti
     let addr = unsafe { CStr::from_ptr(addr_str) }.to_string_lossy().to_string();
     unsafe { jni_release_string_utf_chars(env, server_addr, addr_str) };
 
-    let server_ip: std::net::Ipv4Addr = match addr.parse() {
+    let server_ip: std::net::IpAddr = match addr.parse() {
         Ok(ip) => ip,
         Err(e) => {
             error!("wgEnableDirectRouting: invalid address '{}': {}", addr, e);
@@ -1226,6 +1683,41 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgEnableDirectRouti
     }
 }
 
+/// Set extra destinations routed through the WireGuard tunnel besides the server IP.
+/// JNI interface: MoonBridge.wgSetRoutedNetworks(String networks)
+///
+/// Arguments:
+///   networks: Comma-separated CIDRs or addresses (e.g., "10.0.0.254/32,fd00::/64").
+///             Empty string restores routing of the server IP only.
+/// Returns: true on success, false if any entry is malformed (nothing is changed)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetRoutedNetworks(
+    env: JNIEnv,
+    _clazz: JClass,
+    networks: JString,
+) -> JBoolean {
+    let networks_str = unsafe { jni_get_string_utf_chars(env, networks) };
+    if networks_str.is_null() {
+        return JNI_FALSE;
+    }
+    let list = unsafe { CStr::from_ptr(networks_str) }.to_string_lossy().to_string();
+    unsafe { jni_release_string_utf_chars(env, networks, networks_str) };
+
+    let mut parsed = Vec::new();
+    for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        match crate::platform_sockets::RoutedNetwork::parse(entry) {
+            Some(net) => parsed.push(net),
+            None => {
+                error!("wgSetRoutedNetworks: invalid network '{}'", entry);
+                return JNI_FALSE;
+            }
+        }
+    }
+
+    crate::platform_sockets::set_wg_routed_networks(parsed);
+    JNI_TRUE
+}
+
 /// Rebind the WireGuard endpoint socket after a network change (WiFi ↔ mobile).
 /// Creates a new UDP socket on the current default network and re-initiates handshake.
 /// JNI interface: MoonBridge.wgRebindEndpoint()
@@ -1270,45 +1762,310 @@ pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgNotifyDeviceWake(
     crate::wireguard::wg_notify_device_wake();
 }
 
-// ============================================================================
-// WireGuardManager JNI Functions
-// ============================================================================
+/// Enable or disable reconnect-preserving port reservations for zero-copy sockets.
+/// A closed socket's channel stays registered for `ttlMs` so a quick re-bind on
+/// the same local port reattaches without losing packets.
+/// JNI interface: MoonBridge.wgSetPortReservation(boolean enabled, int ttlMs)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetPortReservation(
+    _env: JNIEnv,
+    _clazz: JClass,
+    enabled: JBoolean,
+    ttl_ms: JInt,
+) {
+    crate::platform_sockets::set_port_reservation(enabled != JNI_FALSE, ttl_ms.max(0) as u64);
+}
 
-/// Start WireGuard tunnel (WireGuardManager.nativeStartTunnel)
+/// Enable or disable sticky local ports for the video and audio stream sockets,
+/// optionally seeding the ports (0 keeps the remembered one).
+/// JNI interface: MoonBridge.wgSetStickyLocalPorts(boolean enabled, int videoPort, int audioPort)
 #[no_mangle]
-pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeStartTunnel(
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetStickyLocalPorts(
+    _env: JNIEnv,
+    _clazz: JClass,
+    enabled: JBoolean,
+    video_port: JInt,
+    audio_port: JInt,
+) {
+    crate::platform_sockets::set_sticky_local_ports(
+        enabled != JNI_FALSE,
+        video_port.clamp(0, u16::MAX as JInt) as u16,
+        audio_port.clamp(0, u16::MAX as JInt) as u16,
+    );
+}
+
+/// Get the remembered stream socket ports as [videoPort, audioPort] (0 where unknown).
+/// JNI interface: MoonBridge.wgGetStickyLocalPorts()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetStickyLocalPorts(
     env: JNIEnv,
     _clazz: JClass,
-    private_key: JByteArray,
-    peer_public_key: JByteArray,
-    preshared_key: JByteArray,
-    endpoint: JString,
-    tunnel_address: JString,
-    mtu: JInt,
-) -> JBoolean {
-    // Get private key bytes
-    let private_key_bytes = match jni_helpers::get_byte_array(env, private_key) {
-        Some(bytes) if bytes.len() == 32 => {
-            let mut arr = [0u8; 32];
-            arr.copy_from_slice(&bytes);
-            arr
-        }
-        _ => {
-            error!("nativeStartTunnel: invalid private key");
-            return JNI_FALSE;
-        }
-    };
+) -> jni_helpers::JIntArray {
+    let (video_port, audio_port) = crate::platform_sockets::sticky_local_ports();
+    jni_helpers::create_int_array(env, &[video_port as JInt, audio_port as JInt])
+}
 
-    // Get peer public key bytes
-    let peer_public_key_bytes = match jni_helpers::get_byte_array(env, peer_public_key) {
-        Some(bytes) if bytes.len() == 32 => {
-            let mut arr = [0u8; 32];
-            arr.copy_from_slice(&bytes);
-            arr
-        }
-        _ => {
-            error!("nativeStartTunnel: invalid peer public key");
-            return JNI_FALSE;
+/// Get inner IP traffic through the WireGuard tunnel since it was last started as
+/// {txBytes, rxBytes, txPackets, rxPackets}. Still valid after the tunnel stopped.
+/// JNI interface: MoonBridge.wgGetTunnelStats()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetTunnelStats(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> jni_helpers::JLongArray {
+    let traffic = crate::wireguard::wg_tunnel_traffic();
+    jni_helpers::create_long_array(
+        env,
+        &[
+            traffic.tx_bytes as JLong,
+            traffic.rx_bytes as JLong,
+            traffic.tx_packets as JLong,
+            traffic.rx_packets as JLong,
+        ],
+    )
+}
+
+/// Get zero-copy channel occupancy for the UDP socket bound to `localPort` as
+/// "len=N high_water=N capacity=N recv_timeouts=N oversized_drops=N", or null if there is none.
+/// JNI interface: MoonBridge.wgGetUdpChannelStats(int localPort)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetUdpChannelStats(
+    env: JNIEnv,
+    _clazz: JClass,
+    local_port: JInt,
+) -> JString {
+    let port = match u16::try_from(local_port) {
+        Ok(port) => port,
+        Err(_) => return ptr::null_mut(),
+    };
+    match crate::platform_sockets::udp_channel_stats(port) {
+        Some(stats) => {
+            let text = format!(
+                "len={} high_water={} capacity={} recv_timeouts={} oversized_drops={} server_quiet={}",
+                stats.len, stats.high_water, stats.capacity, stats.recv_timeouts, stats.oversized_drops,
+                stats.server_quiet as u8
+            );
+            let c_str = CString::new(text).unwrap_or_default();
+            unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Set the silence after which a stream socket's server counts as quiet (0 disables).
+/// JNI interface: MoonBridge.wgSetServerQuietWindow(int windowMs)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetServerQuietWindow(
+    _env: JNIEnv,
+    _clazz: JClass,
+    window_ms: JInt,
+) {
+    crate::platform_sockets::set_server_quiet_window_ms(window_ms.max(0) as u64);
+}
+
+/// Check if the server stopped sending to the stream socket bound to localPort.
+/// JNI interface: MoonBridge.wgIsUdpServerQuiet(int localPort)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgIsUdpServerQuiet(
+    _env: JNIEnv,
+    _clazz: JClass,
+    local_port: JInt,
+) -> JBoolean {
+    let quiet = u16::try_from(local_port)
+        .ok()
+        .and_then(crate::platform_sockets::udp_server_quiet)
+        .unwrap_or(false);
+    if quiet { JNI_TRUE } else { JNI_FALSE }
+}
+
+/// Pace delivery on the stream socket receiving from remotePort (0 disables).
+/// JNI interface: MoonBridge.wgSetRecvPacing(int remotePort, int maxDelayUs)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetRecvPacing(
+    _env: JNIEnv,
+    _clazz: JClass,
+    remote_port: JInt,
+    max_delay_us: JInt,
+) {
+    crate::platform_sockets::set_recv_pacing(
+        remote_port.clamp(0, u16::MAX as JInt) as u16,
+        max_delay_us.max(0) as u64,
+    );
+}
+
+/// Get frame-interval jitter of a paced stream socket as
+/// "intervals=N arrival_jitter_us=N release_jitter_us=N queued=N dropped=N", or null.
+/// JNI interface: MoonBridge.wgGetRecvPacingStats(int localPort)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetRecvPacingStats(
+    env: JNIEnv,
+    _clazz: JClass,
+    local_port: JInt,
+) -> JString {
+    let stats = u16::try_from(local_port)
+        .ok()
+        .and_then(crate::platform_sockets::udp_pacing_stats);
+    match stats {
+        Some(stats) => {
+            let text = format!(
+                "intervals={} arrival_jitter_us={:.0} release_jitter_us={:.0} queued={} dropped={}",
+                stats.intervals, stats.arrival_jitter_us, stats.release_jitter_us, stats.queued,
+                stats.dropped
+            );
+            let c_str = CString::new(text).unwrap_or_default();
+            unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Mirror tunneled UDP packets to 127.0.0.1:port for analysis (0 disables).
+/// JNI interface: MoonBridge.wgSetTapPort(int port, boolean includeInbound)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetTapPort(
+    _env: JNIEnv,
+    _clazz: JClass,
+    port: JInt,
+    include_inbound: JBoolean,
+) -> JBoolean {
+    let port = match u16::try_from(port) {
+        Ok(port) => port,
+        Err(_) => return JNI_FALSE,
+    };
+    match crate::platform_sockets::set_wg_tap_port(port, include_inbound != JNI_FALSE) {
+        Ok(()) => JNI_TRUE,
+        Err(e) => {
+            warn!("Failed to set WG tap port {}: {}", port, e);
+            JNI_FALSE
+        }
+    }
+}
+
+/// Get how packets from a server port are delivered, as a WG_PORT_MODE_* value.
+/// JNI interface: MoonBridge.wgGetPortMode(int port)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetPortMode(
+    _env: JNIEnv,
+    _clazz: JClass,
+    port: JInt,
+) -> JInt {
+    match u16::try_from(port) {
+        Ok(port) => crate::platform_sockets::get_wg_port_mode(port) as JInt,
+        Err(_) => crate::platform_sockets::WgPortMode::Unrouted as JInt,
+    }
+}
+
+/// Set the destination port (control/input) whose packets get send priority over
+/// bulk traffic on the tunnel lock. 0 disables prioritization.
+/// JNI interface: MoonBridge.wgSetPriorityPort(int port)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetPriorityPort(
+    _env: JNIEnv,
+    _clazz: JClass,
+    port: JInt,
+) {
+    crate::platform_sockets::set_priority_port(port.clamp(0, u16::MAX as JInt) as u16);
+}
+
+/// Set how many packets are buffered per server port before a socket registers for
+/// it (0 restores the default).
+/// JNI interface: MoonBridge.wgSetMaxPendingPackets(int maxPackets)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetMaxPendingPackets(
+    _env: JNIEnv,
+    _clazz: JClass,
+    max_packets: JInt,
+) {
+    crate::platform_sockets::set_max_pending_packets_per_port(max_packets.max(0) as usize);
+}
+
+/// Set how long a server port buffers packets with no socket registered before
+/// they are injected into a bound socket (0 = keep buffering).
+/// JNI interface: MoonBridge.wgSetPendingFallback(int windowMs)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetPendingFallback(
+    _env: JNIEnv,
+    _clazz: JClass,
+    window_ms: JInt,
+) {
+    crate::platform_sockets::set_pending_fallback_ms(window_ms.max(0) as u64);
+}
+
+/// Set how long a packet waits for room in a full zero-copy channel before being
+/// dropped (0 = drop immediately).
+/// JNI interface: MoonBridge.wgSetChannelFullWaitUs(int waitUs)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgSetChannelFullWaitUs(
+    _env: JNIEnv,
+    _clazz: JClass,
+    wait_us: JInt,
+) {
+    crate::platform_sockets::set_channel_full_wait_us(wait_us.max(0) as u64);
+}
+
+/// Get full-channel outcomes as "waited_delivered=N dropped=N".
+/// JNI interface: MoonBridge.wgGetChannelFullStats()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgGetChannelFullStats(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let (delivered, dropped) = crate::platform_sockets::channel_full_stats();
+    let text = format!("waited_delivered={} dropped={}", delivered, dropped);
+    let c_str = CString::new(text).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
+/// Get the worst-case tunnel lock wait of high-priority sends in microseconds,
+/// resetting it for the next measurement window.
+/// JNI interface: MoonBridge.wgTakePrioritySendMaxWaitUs()
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_nvstream_jni_MoonBridge_wgTakePrioritySendMaxWaitUs(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JLong {
+    crate::wireguard::wg_take_priority_send_max_wait_us() as JLong
+}
+
+// ============================================================================
+// WireGuardManager JNI Functions
+// ============================================================================
+
+/// Start WireGuard tunnel (WireGuardManager.nativeStartTunnel)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeStartTunnel(
+    env: JNIEnv,
+    _clazz: JClass,
+    private_key: JByteArray,
+    peer_public_key: JByteArray,
+    preshared_key: JByteArray,
+    endpoint: JString,
+    tunnel_address: JString,
+    mtu: JInt,
+) -> JBoolean {
+    // Get private key bytes
+    let private_key_bytes = match jni_helpers::get_byte_array(env, private_key) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut arr = [0u8; 32];
+            arr.copy_from_slice(&bytes);
+            arr
+        }
+        _ => {
+            error!("nativeStartTunnel: invalid private key");
+            return JNI_FALSE;
+        }
+    };
+
+    // Get peer public key bytes
+    let peer_public_key_bytes = match jni_helpers::get_byte_array(env, peer_public_key) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut arr = [0u8; 32];
+            arr.copy_from_slice(&bytes);
+            arr
+        }
+        _ => {
+            error!("nativeStartTunnel: invalid peer public key");
+            return JNI_FALSE;
         }
     };
 
@@ -1351,9 +2108,9 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeSt
     }
     info!("nativeStartTunnel: endpoint '{}' will be resolved dynamically on each connection", endpoint_str);
 
-    // Parse tunnel address
-    let tunnel_ip: std::net::IpAddr = match tunnel_addr_str.parse() {
-        Ok(ip) => ip,
+    // Parse tunnel address (comma-separated for a dual-stack tunnel)
+    let tunnel_ips = match crate::wireguard_config::parse_tunnel_addresses(&tunnel_addr_str) {
+        Ok(ips) => ips,
         Err(e) => {
             error!("nativeStartTunnel: invalid tunnel address '{}': {}", tunnel_addr_str, e);
             return JNI_FALSE;
@@ -1366,8 +2123,10 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeSt
         peer_public_key: peer_public_key_bytes,
         preshared_key: psk_bytes,
         endpoint: endpoint_str,
-        tunnel_address: tunnel_ip,
+        tunnel_addresses: tunnel_ips,
         mtu: mtu as u16,
+        handshake_retry: crate::wireguard::wg_handshake_retry_schedule(),
+        ..Default::default()
     };
 
     // Start tunnel
@@ -1406,6 +2165,347 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeIs
     }
 }
 
+/// Forward a tunnel state change to WireGuardManager.bridgeWgStateChanged(int).
+/// Called from the tunnel timer thread or from the thread that stops the tunnel.
+fn dispatch_wg_state_changed(state: crate::wireguard::WgTunnelState) {
+    let env = match get_jni_env() {
+        Some(e) => e,
+        None => return,
+    };
+
+    let class = jni_helpers::get_wg_manager_class();
+    let method = jni_helpers::get_wg_state_changed_method();
+    if !class.is_null() && !method.is_null() {
+        let args = [jni_helpers::JValue::int(state as JInt)];
+        jni_helpers::call_static_void_method_on_class(env, class, method, &args);
+        // Tunnel threads report every state change; keep this one attached
+        jni_helpers::describe_and_clear_exception(env);
+    }
+}
+
+/// Enable or disable tunnel state change callbacks (WireGuardManager.nativeSetWgStateListener)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeSetWgStateListener(
+    env: JNIEnv,
+    clazz: JClass,
+    enabled: JBoolean,
+) {
+    // Detach the listener before touching the class reference it dispatches through
+    crate::wireguard::wg_set_state_listener(None);
+
+    if enabled != JNI_FALSE {
+        let method = jni_helpers::jni_get_static_method_id(
+            env,
+            clazz,
+            b"bridgeWgStateChanged\0".as_ptr() as *const c_char,
+            b"(I)V\0".as_ptr() as *const c_char,
+        );
+        if method.is_null() {
+            jni_helpers::check_exception(env);
+            error!("nativeSetWgStateListener: bridgeWgStateChanged(I)V not found");
+            return;
+        }
+        jni_helpers::set_wg_state_changed_method(method);
+
+        if jni_helpers::get_wg_manager_class().is_null() {
+            let global_class = jni_helpers::new_global_ref(env, clazz);
+            jni_helpers::swap_wg_manager_class(global_class);
+        }
+
+        crate::wireguard::wg_set_state_listener(Some(dispatch_wg_state_changed));
+        info!("WireGuard state listener registered");
+    } else {
+        info!("WireGuard state listener cleared");
+    }
+}
+
+/// Abort a tunnel start waiting on its handshake (WireGuardManager.nativeWgCancelConnect)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgCancelConnect(
+    _env: JNIEnv,
+    _clazz: JClass,
+) {
+    crate::wireguard::wg_cancel_handshake();
+}
+
+/// Quiesce the tunnel threads while backgrounded (WireGuardManager.nativeWgPause)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgPause(
+    _env: JNIEnv,
+    _clazz: JClass,
+) {
+    crate::wireguard::wg_pause();
+}
+
+/// Resume the tunnel threads after backgrounding (WireGuardManager.nativeWgResume)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgResume(
+    _env: JNIEnv,
+    _clazz: JClass,
+) {
+    crate::wireguard::wg_resume();
+}
+
+/// Set the handshake escalation threshold (WireGuardManager.nativeWgSetHandshakeEscalationThreshold)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetHandshakeEscalationThreshold(
+    _env: JNIEnv,
+    _clazz: JClass,
+    threshold: JInt,
+) {
+    crate::wireguard::wg_set_handshake_escalation_threshold(threshold.max(0) as u32);
+}
+
+/// Set the handshake retries per source port (WireGuardManager.nativeWgSetSourcePortRotationRetries)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetSourcePortRotationRetries(
+    _env: JNIEnv,
+    _clazz: JClass,
+    retries: JInt,
+) {
+    crate::wireguard::wg_set_source_port_rotation_retries(retries.max(0) as u32);
+}
+
+/// Set how many packets are held during the initial handshake (WireGuardManager.nativeWgSetEarlySendQueue)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetEarlySendQueue(
+    _env: JNIEnv,
+    _clazz: JClass,
+    max_packets: JInt,
+) {
+    crate::wireguard::wg_set_early_send_queue(max_packets.max(0) as usize);
+}
+
+/// Set the initial handshake retry schedule (WireGuardManager.nativeWgSetHandshakeRetrySchedule)
+/// Returns: true on success, false if the schedule is invalid
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetHandshakeRetrySchedule(
+    _env: JNIEnv,
+    _clazz: JClass,
+    initial_ms: JInt,
+    multiplier: JFloat,
+    max_ms: JInt,
+) -> JBoolean {
+    let schedule = crate::wireguard::HandshakeRetrySchedule {
+        initial: std::time::Duration::from_millis(initial_ms.max(0) as u64),
+        multiplier,
+        max: std::time::Duration::from_millis(max_ms.max(0) as u64),
+    };
+    match crate::wireguard::wg_set_handshake_retry_schedule(schedule) {
+        Ok(()) => JNI_TRUE,
+        Err(e) => {
+            error!("nativeWgSetHandshakeRetrySchedule: {}", e);
+            JNI_FALSE
+        }
+    }
+}
+
+/// Set the proactive rekey lead time (WireGuardManager.nativeWgSetRekeyLeadTime)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetRekeyLeadTime(
+    _env: JNIEnv,
+    _clazz: JClass,
+    seconds: JInt,
+) {
+    crate::wireguard::wg_set_proactive_rekey_lead(seconds.max(0) as u64);
+}
+
+/// Set how long a completed handshake may go without data before the no-data
+/// diagnostic fires (WireGuardManager.nativeWgSetNoDataWindow)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetNoDataWindow(
+    _env: JNIEnv,
+    _clazz: JClass,
+    seconds: JInt,
+) {
+    crate::wireguard::wg_set_no_data_window(seconds.max(0) as u64);
+}
+
+/// Set how long the receiver thread may go silent before it is restarted
+/// (WireGuardManager.nativeWgSetReceiverStallWindow)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetReceiverStallWindow(
+    _env: JNIEnv,
+    _clazz: JClass,
+    window_ms: JInt,
+) {
+    crate::wireguard::wg_set_receiver_stall_window(window_ms.max(0) as u64);
+}
+
+/// Receiver thread stalls detected since the tunnel started
+/// (WireGuardManager.nativeWgGetReceiverStallCount)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgGetReceiverStallCount(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JLong {
+    crate::wireguard::wg_receiver_stall_count() as JLong
+}
+
+/// Whether the tunnel handshake completed but no data has arrived
+/// (WireGuardManager.nativeWgIsNoDataSuspected)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgIsNoDataSuspected(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JBoolean {
+    if crate::wireguard::wg_is_no_data_suspected() {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// Set the consecutive-EPERM automatic rebind threshold
+/// (WireGuardManager.nativeWgSetEpermRebindThreshold)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgSetEpermRebindThreshold(
+    _env: JNIEnv,
+    _clazz: JClass,
+    threshold: JInt,
+) {
+    crate::wireguard::wg_set_eperm_rebind_threshold(threshold.max(0) as u32);
+}
+
+/// Ping the host through the tunnel with small and full-MTU packets
+/// (WireGuardManager.nativeWgProbeMtu). Returns an MtuProbeResult value.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgProbeMtu(
+    env: JNIEnv,
+    _clazz: JClass,
+    server_address: JString,
+    timeout_ms: JInt,
+) -> JInt {
+    let target = match jni_helpers::get_string(env, server_address)
+        .and_then(|address| address.parse::<std::net::Ipv4Addr>().ok())
+    {
+        Some(target) => target,
+        None => {
+            error!("nativeWgProbeMtu: invalid IPv4 server address");
+            return crate::wireguard::MtuProbeResult::Inconclusive as JInt;
+        }
+    };
+    let timeout = std::time::Duration::from_millis(timeout_ms.max(0) as u64);
+    crate::wireguard::wg_probe_mtu(target, timeout) as JInt
+}
+
+/// Whether the last MTU probe suggested an MTU issue (WireGuardManager.nativeWgIsMtuIssueSuspected)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgIsMtuIssueSuspected(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JBoolean {
+    if crate::wireguard::wg_is_mtu_issue_suspected() {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// Get endpoint receive queue statistics as "high_water=N capacity=N over_threshold=N"
+/// (WireGuardManager.nativeWgGetRxQueueStats)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgGetRxQueueStats(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let (high_water, capacity, over_threshold) = crate::wireguard::wg_rx_queue_stats();
+    let text = format!("high_water={} capacity={} over_threshold={}", high_water, capacity, over_threshold);
+    let c_str = CString::new(text).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
+/// Get non-secret metadata about the current session as "session=N keys_current=0|1
+/// tx_bytes=N rx_bytes=N since_handshake_ms=N" (-1 before the first handshake), or
+/// null if no tunnel is running (WireGuardManager.nativeWgDebugSessionInfo).
+/// Debug builds only: release libraries don't export this symbol.
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgDebugSessionInfo(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let info = match crate::wireguard::wg_debug_session_info() {
+        Some(info) => info,
+        None => return ptr::null_mut(),
+    };
+    let text = format!(
+        "session={} keys_current={} tx_bytes={} rx_bytes={} since_handshake_ms={}",
+        info.session_number,
+        info.keys_current as u8,
+        info.tx_bytes,
+        info.rx_bytes,
+        info.since_handshake.map_or(-1, |age| age.as_millis() as i64),
+    );
+    let c_str = CString::new(text).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
+/// Simulate a network change: rebind the endpoint socket and re-handshake
+/// (WireGuardManager.nativeWgDebugSimulateNetworkChange). Debug builds only.
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgDebugSimulateNetworkChange(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JBoolean {
+    match crate::wireguard::wg_debug_simulate_network_change() {
+        Ok(generation) => {
+            info!("nativeWgDebugSimulateNetworkChange: now on socket generation {}", generation);
+            JNI_TRUE
+        }
+        Err(e) => {
+            error!("nativeWgDebugSimulateNetworkChange: {}", e);
+            JNI_FALSE
+        }
+    }
+}
+
+/// Simulate the endpoint's DNS record changing to `newIp`
+/// (WireGuardManager.nativeWgDebugSimulateEndpointIpChange). Debug builds only.
+#[cfg(debug_assertions)]
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgDebugSimulateEndpointIpChange(
+    env: JNIEnv,
+    _clazz: JClass,
+    new_ip: JString,
+) -> JBoolean {
+    let ip: std::net::IpAddr = match jni_helpers::get_string(env, new_ip).and_then(|s| s.parse().ok()) {
+        Some(ip) => ip,
+        None => {
+            error!("nativeWgDebugSimulateEndpointIpChange: invalid address");
+            return JNI_FALSE;
+        }
+    };
+    match crate::wireguard::wg_debug_simulate_endpoint_ip_change(ip) {
+        Ok(()) => JNI_TRUE,
+        Err(e) => {
+            error!("nativeWgDebugSimulateEndpointIpChange: {}", e);
+            JNI_FALSE
+        }
+    }
+}
+
+/// Get tunnel reconnect statistics (WireGuardManager.nativeGetTunnelStats)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeGetTunnelStats(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let c_str = CString::new(crate::wireguard::wg_get_tunnel_stats()).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
+/// Snapshot of all WG-tracked sockets and port mappings (WireGuardManager.nativeWgDumpSockets)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgDumpSockets(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let c_str = CString::new(crate::platform_sockets::wg_debug_dump_sockets()).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
 /// Generate a new WireGuard private key (WireGuardManager.nativeGeneratePrivateKey)
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeGeneratePrivateKey(
@@ -1444,6 +2544,36 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeDe
     jni_helpers::create_byte_array(env, &public_key)
 }
 
+/// Resolve the endpoint ahead of tunnel start (WireGuardManager.nativeWgResolveEndpoint).
+/// The result is cached so the following nativeStartTunnel doesn't block on DNS.
+/// Returns the resolved address as a string, or null if resolution failed.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeWgResolveEndpoint(
+    env: JNIEnv,
+    _clazz: JClass,
+    endpoint: JString,
+) -> JString {
+    let endpoint_str = match jni_helpers::get_string(env, endpoint) {
+        Some(s) => s,
+        None => {
+            error!("nativeWgResolveEndpoint: invalid endpoint");
+            return ptr::null_mut();
+        }
+    };
+
+    match crate::wireguard_config::warm_resolve_endpoint(&endpoint_str) {
+        Ok(addr) => {
+            info!("nativeWgResolveEndpoint: '{}' -> {}", endpoint_str, addr);
+            let c_str = CString::new(addr.to_string()).unwrap_or_default();
+            unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+        }
+        Err(e) => {
+            error!("nativeWgResolveEndpoint: {}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
 // ============================================================================
 // WireGuard Direct HTTP JNI Functions
 // ============================================================================
@@ -1546,9 +2676,9 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHt
     }
     info!("nativeHttpSetConfig: endpoint '{}' will be resolved dynamically on each connection", endpoint_str);
 
-    // Parse tunnel address (supports IPv4 and IPv6)
-    let tunnel_ip: std::net::IpAddr = match tunnel_addr_str.parse() {
-        Ok(ip) => ip,
+    // Parse tunnel addresses (supports IPv4 and IPv6, comma-separated for dual-stack)
+    let tunnel_ips = match crate::wireguard_config::parse_tunnel_addresses(&tunnel_addr_str) {
+        Ok(ips) => ips,
         Err(e) => {
             error!("nativeHttpSetConfig: invalid tunnel address '{}': {}", tunnel_addr_str, e);
             return JNI_FALSE;
@@ -1564,6 +2694,15 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHt
         }
     };
 
+    // The HTTP client talks to one server, so it uses the tunnel address of its family
+    let tunnel_ip = match crate::wireguard_config::tunnel_address_for(&tunnel_ips, server_ip) {
+        Some(ip) => ip,
+        None => {
+            error!("nativeHttpSetConfig: no tunnel address in '{}' matches server {}", tunnel_addr_str, server_ip);
+            return JNI_FALSE;
+        }
+    };
+
     // Build HTTP config - endpoint stored as string for DDNS support
     let config = crate::wg_http::WgHttpConfig {
         private_key: private_key_bytes,
@@ -1603,6 +2742,118 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHt
     }
 }
 
+/// Apply a new MTU to WireGuard HTTP connections (WireGuardManager.nativeHttpSetMtu)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpSetMtu(
+    _env: JNIEnv,
+    _clazz: JClass,
+    mtu: JInt,
+) {
+    if !(576..=65535).contains(&mtu) {
+        error!("nativeHttpSetMtu: invalid MTU {}", mtu);
+        return;
+    }
+    crate::wg_http::wg_http_set_mtu(mtu as u16);
+}
+
+/// Create the shared HTTP proxy tunnel in the background (WireGuardManager.nativeHttpPrewarm)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpPrewarm(
+    _env: JNIEnv,
+    _clazz: JClass,
+) -> JBoolean {
+    if crate::wg_http::wg_http_prewarm_proxy() {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// Configure TCP keepalive for WireGuard HTTP connections
+/// (WireGuardManager.nativeHttpSetTcpKeepalive). idleSec <= 0 disables keepalive.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpSetTcpKeepalive(
+    _env: JNIEnv,
+    _clazz: JClass,
+    idle_sec: JInt,
+    interval_sec: JInt,
+    max_probes: JInt,
+) {
+    let keepalive = if idle_sec > 0 {
+        Some(crate::tun_stack::TcpKeepalive {
+            idle: std::time::Duration::from_secs(idle_sec as u64),
+            interval: std::time::Duration::from_secs(interval_sec.max(1) as u64),
+            max_probes: max_probes.max(1) as u32,
+        })
+    } else {
+        None
+    };
+    crate::wg_http::wg_http_set_tcp_keepalive(keepalive);
+}
+
+/// Configure SYN retransmission for WireGuard TCP connects
+/// (WireGuardManager.nativeHttpSetConnectRetry). Zero restores the defaults.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpSetConnectRetry(
+    _env: JNIEnv,
+    _clazz: JClass,
+    initial_ms: JInt,
+    max_ms: JInt,
+) {
+    crate::wg_socket::wg_socket_set_connect_retry(initial_ms.max(0) as u64, max_ms.max(0) as u64);
+}
+
+/// Configure how many times a WireGuard TCP connect is attempted
+/// (WireGuardManager.nativeHttpSetConnectAttempts). Zero restores the default.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpSetConnectAttempts(
+    _env: JNIEnv,
+    _clazz: JClass,
+    attempts: JInt,
+) {
+    crate::wg_socket::wg_socket_set_connect_attempts(attempts.max(0) as u32);
+}
+
+/// Cap concurrent WireGuard TCP connections
+/// (WireGuardManager.nativeHttpSetMaxConnections). Zero restores the default.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpSetMaxConnections(
+    _env: JNIEnv,
+    _clazz: JClass,
+    max_connections: JInt,
+) {
+    crate::wg_socket::wg_socket_set_max_connections(max_connections.max(0) as usize);
+}
+
+/// Describe active WireGuard TCP proxy connections for diagnostics
+/// (WireGuardManager.nativeHttpListProxies).
+/// First line is the shared tunnel state and `connections=active/max`, followed
+/// by one `target_port local_port running|connecting` line per connection.
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WireGuardManager_nativeHttpListProxies(
+    env: JNIEnv,
+    _clazz: JClass,
+) -> JString {
+    let (active, max) = crate::wg_socket::wg_socket_connection_usage();
+    let mut out = format!(
+        "shared_tunnel={} connections={}/{}",
+        if crate::wg_http::wg_http_shared_proxy_active() { "active" } else { "inactive" },
+        active,
+        max
+    );
+    for (target_port, local_port, running) in crate::wg_http::wg_http_list_proxies() {
+        out.push_str(&format!(
+            "\n{} {} {}",
+            target_port,
+            local_port,
+            if running { "running" } else { "connecting" }
+        ));
+    }
+
+    let c_str = CString::new(out).unwrap_or_default();
+    unsafe { jni_new_string_utf(env, c_str.as_ptr()) }
+}
+
 // ============================================================================
 // WgSocket JNI Functions (for direct TCP socket access through WireGuard)
 // ============================================================================
@@ -1632,6 +2883,33 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeConnect(
     crate::wg_socket::wg_socket_connect(&host_str, port as u16, timeout_ms as u32) as JLong
 }
 
+/// Create a TCP connection whose connect and reads all share one hard deadline
+/// (WgSocket.nativeConnectWithDeadline)
+/// Parameters:
+///   host: Target host IP in the tunnel (e.g., "10.0.0.1")
+///   port: Target port
+///   deadlineMs: Time from now by which the connect and every read must finish
+/// Returns: Native handle (>0) on success, 0 on failure
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeConnectWithDeadline(
+    env: JNIEnv,
+    _clazz: JClass,
+    host: JString,
+    port: JInt,
+    deadline_ms: JInt,
+) -> JLong {
+    let host_str = match jni_helpers::get_string(env, host) {
+        Some(s) => s,
+        None => {
+            error!("WgSocket.nativeConnectWithDeadline: invalid host string");
+            return 0;
+        }
+    };
+
+    let deadline = std::time::Duration::from_millis(deadline_ms.max(0) as u64);
+    crate::wg_socket::wg_socket_connect_with_deadline(&host_str, port as u16, deadline) as JLong
+}
+
 /// Get the local port allocated for this connection (WgSocket.nativeGetLocalPort)
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeGetLocalPort(
@@ -1649,7 +2927,8 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeGetLocalPo
 ///   offset: Offset in buffer
 ///   length: Maximum bytes to receive
 ///   timeoutMs: Read timeout in milliseconds (0 = default timeout)
-/// Returns: Bytes received (>0), 0 on EOF, -1 on error, -2 on timeout
+/// Returns: Bytes received (>0), 0 on EOF, -1 on error, -2 on timeout,
+///   -4 if the tunnel restarted since the connection was opened
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeRecv(
     env: JNIEnv,
@@ -1679,13 +2958,44 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeRecv(
     result
 }
 
+/// Receive already-available data without blocking (WgSocket.nativeRecvAvailable)
+/// Parameters:
+///   handle: Native connection handle
+///   buffer: Buffer to receive into
+///   offset: Offset in buffer
+///   maxLen: Maximum bytes to receive
+/// Returns: Bytes received (>0), 0 if none available, -1 on error, -3 on EOF,
+///   -4 if the tunnel restarted since the connection was opened
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeRecvAvailable(
+    env: JNIEnv,
+    _clazz: JClass,
+    handle: JLong,
+    buffer: JByteArray,
+    offset: JInt,
+    max_len: JInt,
+) -> JInt {
+    if buffer.is_null() || max_len <= 0 {
+        error!("WgSocket.nativeRecvAvailable: invalid buffer");
+        return -1;
+    }
+
+    let mut recv_buf = vec![0u8; max_len as usize];
+    let result = crate::wg_socket::wg_socket_recv_available(handle as u64, &mut recv_buf);
+    if result > 0 {
+        jni_helpers::set_byte_array_region(env, buffer, offset, result, recv_buf.as_ptr() as *const i8);
+    }
+
+    result
+}
+
 /// Send data through the connection (WgSocket.nativeSend)
 /// Parameters:
 ///   handle: Native connection handle
 ///   buffer: Data to send
 ///   offset: Offset in buffer
 ///   length: Number of bytes to send
-/// Returns: Bytes sent (>0) on success, negative on error
+/// Returns: Bytes sent (>0) on success, negative on error (-4 if the tunnel restarted)
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeSend(
     env: JNIEnv,
@@ -1712,14 +3022,160 @@ pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeSend(
     crate::wg_socket::wg_socket_send(handle as u64, &data)
 }
 
+/// Send several buffers through the connection in one call (WgSocket.nativeSendv)
+/// Parameters:
+///   handle: Native connection handle
+///   buffers: Data to send, written contiguously in array order (null entries are skipped)
+/// Returns: Total bytes sent on success, negative on error (-4 if the tunnel restarted)
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeSendv(
+    env: JNIEnv,
+    _clazz: JClass,
+    handle: JLong,
+    buffers: JObject,
+) -> JInt {
+    if buffers.is_null() {
+        error!("WgSocket.nativeSendv: invalid buffers");
+        return -1;
+    }
+
+    // Copy every buffer out of Java, releasing each local ref as we go
+    let count = jni_helpers::get_array_length(env, buffers);
+    let mut data = Vec::with_capacity(count.max(0) as usize);
+    for i in 0..count {
+        let buffer = jni_helpers::get_object_array_element(env, buffers, i);
+        if buffer.is_null() {
+            continue;
+        }
+        let bytes = jni_helpers::get_byte_array(env, buffer);
+        jni_helpers::delete_local_ref(env, buffer);
+        match bytes {
+            Some(b) => data.push(b),
+            None => {
+                error!("WgSocket.nativeSendv: failed to get buffer {} data", i);
+                return -1;
+            }
+        }
+    }
+
+    let slices: Vec<&[u8]> = data.iter().map(|b| b.as_slice()).collect();
+    crate::wg_socket::wg_socket_sendv(handle as u64, &slices)
+}
+
+/// Half-close the connection (WgSocket.nativeShutdownOutput)
+/// Returns: 0 on success, -1 on error, -4 if the tunnel restarted
+#[no_mangle]
+pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeShutdownOutput(
+    _env: JNIEnv,
+    _clazz: JClass,
+    handle: JLong,
+) -> JInt {
+    crate::wg_socket::wg_socket_shutdown_write(handle as u64)
+}
+
 /// Close the connection (WgSocket.nativeClose)
+/// Returns: 0 on success, -1 for an unknown handle, -4 if the tunnel restarted
 #[no_mangle]
 pub extern "C" fn Java_com_limelight_binding_wireguard_WgSocket_nativeClose(
     _env: JNIEnv,
     _clazz: JClass,
     handle: JLong,
-) {
-    crate::wg_socket::wg_socket_close(handle as u64);
+) -> JInt {
+    crate::wg_socket::wg_socket_close(handle as u64)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_port_info_entries() {
+        let lookup = |index: c_int| match index {
+            0 => (libc::IPPROTO_TCP, 47984),
+            8 => (libc::IPPROTO_UDP, 47998),
+            _ => (libc::IPPROTO_UDP, 0),
+        };
+        assert!(port_info_entries(0, lookup).is_empty());
+        assert_eq!(port_info_entries(0x101, lookup), vec!["1:TCP 47984", "256:UDP 47998"]);
+        // Every flag set still yields one entry per flag
+        assert_eq!(port_info_entries(u32::MAX, lookup).len(), 32);
+    }
+
+    #[test]
+    fn test_high_res_mouse_move() {
+        let unit = MOUSE_MOVE_HIGH_RES_UNITS;
+        let mut remainder = (0, 0);
+
+        // Quarter pixels add up to a whole one
+        for _ in 0..3 {
+            assert_eq!(accumulate_high_res_move(&mut remainder, unit / 4, -unit / 4), (0, 0));
+        }
+        assert_eq!(accumulate_high_res_move(&mut remainder, unit / 4, -unit / 4), (1, -1));
+        assert_eq!(remainder, (0, 0));
+
+        // Fractions of a larger move are carried, and reversing cancels them
+        assert_eq!(accumulate_high_res_move(&mut remainder, unit * 5 / 2, 0), (2, 0));
+        assert_eq!(accumulate_high_res_move(&mut remainder, -unit / 2, 0), (0, 0));
+        assert_eq!(remainder, (0, 0));
+
+        // Whole pixels are split into short-sized steps
+        assert!(mouse_move_steps(0, 0).is_empty());
+        assert_eq!(mouse_move_steps(3, -4), vec![(3, -4)]);
+        assert_eq!(mouse_move_steps(40_000, -5), vec![(i16::MAX, -5), (7_233, 0)]);
+    }
+
+    #[test]
+    fn test_concurrent_start_rejected() {
+        end_connection();
+
+        // Racing starts: exactly one gets the connection
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(4));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    try_begin_connection()
+                })
+            })
+            .collect();
+        let winners = handles.into_iter().map(|h| h.join().unwrap()).filter(|&won| won).count();
+        assert_eq!(winners, 1);
+
+        // A later start is still rejected until the connection is stopped
+        assert!(!try_begin_connection());
+        end_connection();
+        assert!(try_begin_connection());
+        end_connection();
+    }
 
+    #[test]
+    fn test_stun_wan_addr_is_network_order() {
+        // s_addr as the STUN code stores it: first octet first in memory
+        let wan_addr = u32::from_ne_bytes([203, 0, 113, 7]);
+        assert_eq!(format_stun_wan_addr(wan_addr), "203.0.113.7");
+        assert_eq!(format_stun_wan_addr(u32::from_be(0xC0A8_0001)), "192.168.0.1");
+    }
+
+    #[test]
+    fn test_utf8_chunks_short_text_single_chunk() {
+        let chunks = split_utf8_chunks(b"hunter2", UTF8_TEXT_MAX_CHUNK_BYTES);
+        assert_eq!(chunks, vec![&b"hunter2"[..]]);
+    }
+
+    #[test]
+    fn test_utf8_chunks_do_not_split_codepoints() {
+        // Mix of 1, 2, 3 and 4 byte sequences, well over one chunk
+        let text = "a\u{e9}\u{4e2d}\u{1f600}".repeat(20);
+        let chunks = split_utf8_chunks(text.as_bytes(), UTF8_TEXT_MAX_CHUNK_BYTES);
+
+        assert!(chunks.len() > 1);
+        let mut rejoined = String::new();
+        for chunk in &chunks {
+            assert!(chunk.len() <= UTF8_TEXT_MAX_CHUNK_BYTES);
+            // Every chunk must be valid UTF-8 on its own
+            rejoined.push_str(std::str::from_utf8(chunk).expect("chunk split a codepoint"));
+        }
+        assert_eq!(rejoined, text);
+    }
+}
@@ -7,7 +7,7 @@
 
 use libc::{c_char, c_void};
 use std::ptr;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
 
 // JNI types
 pub type JNIEnv = *mut c_void;
@@ -16,6 +16,8 @@ pub type JMethodID = *mut c_void;
 pub type JObject = *mut c_void;
 pub type JByteArray = *mut c_void;
 pub type JShortArray = *mut c_void;
+pub type JIntArray = *mut c_void;
+pub type JLongArray = *mut c_void;
 pub type JBoolean = u8;
 pub type JByte = i8;
 pub type JShort = i16;
@@ -31,6 +33,7 @@ pub const JNI_ABORT: JInt = 2;
 // Global JavaVM and class references
 static JAVA_VM: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static GLOBAL_BRIDGE_CLASS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static GLOBAL_WG_MANAGER_CLASS: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 
 // Global method IDs
 static DR_SETUP_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
@@ -38,6 +41,7 @@ static DR_START_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static DR_STOP_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static DR_CLEANUP_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static DR_SUBMIT_DECODE_UNIT_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static DR_DECODER_OVERLOADED_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static AR_INIT_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static AR_START_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static AR_STOP_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
@@ -54,6 +58,10 @@ static CL_SET_HDR_MODE_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut(
 static CL_RUMBLE_TRIGGERS_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static CL_SET_MOTION_EVENT_STATE_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 static CL_SET_CONTROLLER_LED_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static CL_TUNNEL_DATA_FLOWING_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static CL_TUNNEL_NO_DATA_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static CL_VIDEO_FORMAT_NEGOTIATED_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
+static WG_STATE_CHANGED_METHOD: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
 
 // Global buffer references
 static DECODED_FRAME_BUFFER: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut());
@@ -63,6 +71,7 @@ static DECODED_AUDIO_BUFFER: AtomicPtr<c_void> = AtomicPtr::new(ptr::null_mut())
 const JNI_GET_VERSION: usize = 4;
 const JNI_FIND_CLASS: usize = 6;
 const JNI_EXCEPTION_OCCURRED: usize = 15;
+const JNI_EXCEPTION_DESCRIBE: usize = 16;
 const JNI_EXCEPTION_CLEAR: usize = 17;
 const JNI_NEW_GLOBAL_REF: usize = 21;
 const JNI_DELETE_GLOBAL_REF: usize = 22;
@@ -70,13 +79,21 @@ const JNI_DELETE_LOCAL_REF: usize = 23;
 const JNI_GET_STATIC_METHOD_ID: usize = 113;
 const JNI_CALL_STATIC_INT_METHOD_A: usize = 131;
 const JNI_CALL_STATIC_VOID_METHOD_A: usize = 143;
+const JNI_NEW_STRING_UTF: usize = 167;
 const JNI_GET_ARRAY_LENGTH: usize = 171;
+const JNI_NEW_OBJECT_ARRAY: usize = 172;
+const JNI_GET_OBJECT_ARRAY_ELEMENT: usize = 173;
+const JNI_SET_OBJECT_ARRAY_ELEMENT: usize = 174;
 const JNI_NEW_BYTE_ARRAY: usize = 176;
 const JNI_NEW_SHORT_ARRAY: usize = 178;
+const JNI_NEW_INT_ARRAY: usize = 179;
+const JNI_NEW_LONG_ARRAY: usize = 180;
 const JNI_GET_SHORT_ARRAY_ELEMENTS: usize = 186;
 const JNI_RELEASE_SHORT_ARRAY_ELEMENTS: usize = 194;
 const JNI_SET_BYTE_ARRAY_REGION: usize = 208;
 const JNI_SET_SHORT_ARRAY_REGION: usize = 210;
+const JNI_SET_INT_ARRAY_REGION: usize = 211;
+const JNI_SET_LONG_ARRAY_REGION: usize = 212;
 const JNI_GET_JAVA_VM: usize = 219;
 const JNI_GET_PRIMITIVE_ARRAY_CRITICAL: usize = 222;
 const JNI_RELEASE_PRIMITIVE_ARRAY_CRITICAL: usize = 223;
@@ -166,32 +183,84 @@ pub fn get_bridge_class() -> JClass {
     GLOBAL_BRIDGE_CLASS.load(Ordering::Acquire)
 }
 
+/// Store global WireGuardManager class reference, returning the previous one
+#[inline]
+pub fn swap_wg_manager_class(class: JClass) -> JClass {
+    GLOBAL_WG_MANAGER_CLASS.swap(class, Ordering::AcqRel)
+}
+
+/// Get global WireGuardManager class reference
+#[inline]
+pub fn get_wg_manager_class() -> JClass {
+    GLOBAL_WG_MANAGER_CLASS.load(Ordering::Acquire)
+}
+
+/// Number of AttachCurrentThread calls made by get_thread_env (diagnostics)
+static THREAD_ATTACH_CALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Detaches the thread on exit if get_thread_env attached it.
+/// Threads that were already attached (Java threads) are left alone.
+struct ThreadAttachGuard {
+    vm: std::cell::Cell<JavaVM>,
+}
+
+impl Drop for ThreadAttachGuard {
+    fn drop(&mut self) {
+        let vm = self.vm.replace(ptr::null_mut());
+        if vm.is_null() {
+            return;
+        }
+        unsafe {
+            type DetachFn = extern "C" fn(JavaVM) -> JInt;
+            let detach: DetachFn = get_jvm_fn(vm, JVM_DETACH_CURRENT_THREAD);
+            detach(vm);
+        }
+    }
+}
+
 // Thread-local cache for JNIEnv to avoid repeated JVM calls
 // This is safe because JNIEnv is thread-specific and doesn't change
-// within the same thread
+// within the same thread.
+//
+// Lifecycle: the first get_thread_env on a thread fetches (or attaches) the env
+// and caches it; every later call on that thread returns the cached env without
+// touching the JVM. A native thread attached here stays attached until it exits,
+// when ATTACH_GUARD's destructor detaches it. detach_current_thread detaches early
+// (e.g. after a Java exception) and clears both.
 thread_local! {
     static CACHED_ENV: std::cell::Cell<JNIEnv> = const { std::cell::Cell::new(std::ptr::null_mut()) };
+    static ATTACH_GUARD: ThreadAttachGuard = const {
+        ThreadAttachGuard { vm: std::cell::Cell::new(std::ptr::null_mut()) }
+    };
 }
 
 /// Get JNIEnv for current thread, using thread-local cache when possible
 /// This is optimized for the hot path where the thread is already attached
 #[inline]
 pub fn get_thread_env() -> Option<JNIEnv> {
+    get_thread_env_for(get_java_vm())
+}
+
+#[inline]
+fn get_thread_env_for(vm: JavaVM) -> Option<JNIEnv> {
     // Fast path: check thread-local cache first
     let cached = CACHED_ENV.with(|c| c.get());
     if !cached.is_null() {
-        // Verify the cached env is still valid by checking if we're still attached
-        // This is a lightweight check
         return Some(cached);
     }
 
-    get_thread_env_slow()
+    get_thread_env_slow(vm)
+}
+
+/// Number of times a thread had to be attached to the JVM.
+/// Stays flat while callbacks run on already-attached threads.
+pub fn thread_attach_count() -> u64 {
+    THREAD_ATTACH_CALLS.load(Ordering::Relaxed)
 }
 
 /// Slow path for get_thread_env - attaches thread if necessary
 #[cold]
-fn get_thread_env_slow() -> Option<JNIEnv> {
-    let vm = get_java_vm();
+fn get_thread_env_slow(vm: JavaVM) -> Option<JNIEnv> {
     if vm.is_null() {
         return None;
     }
@@ -212,10 +281,12 @@ fn get_thread_env_slow() -> Option<JNIEnv> {
         // Attach current thread
         type AttachFn = extern "C" fn(JavaVM, *mut JNIEnv, *mut c_void) -> JInt;
         let attach: AttachFn = get_jvm_fn(vm, JVM_ATTACH_CURRENT_THREAD);
+        THREAD_ATTACH_CALLS.fetch_add(1, Ordering::Relaxed);
         let result = attach(vm, &mut env, ptr::null_mut());
         if result == JNI_OK {
-            // Cache the env for this thread
+            // Cache the env for this thread and detach when it exits
             CACHED_ENV.with(|c| c.set(env));
+            ATTACH_GUARD.with(|g| g.vm.set(vm));
             Some(env)
         } else {
             None
@@ -225,8 +296,9 @@ fn get_thread_env_slow() -> Option<JNIEnv> {
 
 /// Detach current thread from JVM
 pub fn detach_current_thread() {
-    // Clear the thread-local cache
+    // Clear the thread-local cache; the exit-time detach is no longer needed
     CACHED_ENV.with(|c| c.set(ptr::null_mut()));
+    let _ = ATTACH_GUARD.try_with(|g| g.vm.set(ptr::null_mut()));
 
     let vm = get_java_vm();
     if vm.is_null() {
@@ -263,6 +335,32 @@ pub fn check_exception(env: JNIEnv) -> bool {
     }
 }
 
+/// Log and clear a pending exception, leaving the thread attached. For upcalls
+/// from long-lived Rust threads that make further upcalls, where a listener's
+/// exception should be reported rather than end the thread's JNI session.
+/// Returns true if there was one.
+pub fn describe_and_clear_exception(env: JNIEnv) -> bool {
+    if env.is_null() {
+        return false;
+    }
+
+    unsafe {
+        type ExceptionOccurredFn = extern "C" fn(JNIEnv) -> JObject;
+        type ExceptionDescribeFn = extern "C" fn(JNIEnv);
+        type ExceptionClearFn = extern "C" fn(JNIEnv);
+
+        let exception_occurred: ExceptionOccurredFn = get_jni_fn(env, JNI_EXCEPTION_OCCURRED);
+        if exception_occurred(env).is_null() {
+            return false;
+        }
+        let exception_describe: ExceptionDescribeFn = get_jni_fn(env, JNI_EXCEPTION_DESCRIBE);
+        exception_describe(env);
+        let exception_clear: ExceptionClearFn = get_jni_fn(env, JNI_EXCEPTION_CLEAR);
+        exception_clear(env);
+        true
+    }
+}
+
 // Method ID setters and getters
 macro_rules! define_method_id_accessors {
     ($set_name:ident, $get_name:ident, $static_var:ident) => {
@@ -281,6 +379,7 @@ define_method_id_accessors!(set_dr_start_method, get_dr_start_method, DR_START_M
 define_method_id_accessors!(set_dr_stop_method, get_dr_stop_method, DR_STOP_METHOD);
 define_method_id_accessors!(set_dr_cleanup_method, get_dr_cleanup_method, DR_CLEANUP_METHOD);
 define_method_id_accessors!(set_dr_submit_decode_unit_method, get_dr_submit_decode_unit_method, DR_SUBMIT_DECODE_UNIT_METHOD);
+define_method_id_accessors!(set_dr_decoder_overloaded_method, get_dr_decoder_overloaded_method, DR_DECODER_OVERLOADED_METHOD);
 define_method_id_accessors!(set_ar_init_method, get_ar_init_method, AR_INIT_METHOD);
 define_method_id_accessors!(set_ar_start_method, get_ar_start_method, AR_START_METHOD);
 define_method_id_accessors!(set_ar_stop_method, get_ar_stop_method, AR_STOP_METHOD);
@@ -297,6 +396,10 @@ define_method_id_accessors!(set_cl_set_hdr_mode_method, get_cl_set_hdr_mode_meth
 define_method_id_accessors!(set_cl_rumble_triggers_method, get_cl_rumble_triggers_method, CL_RUMBLE_TRIGGERS_METHOD);
 define_method_id_accessors!(set_cl_set_motion_event_state_method, get_cl_set_motion_event_state_method, CL_SET_MOTION_EVENT_STATE_METHOD);
 define_method_id_accessors!(set_cl_set_controller_led_method, get_cl_set_controller_led_method, CL_SET_CONTROLLER_LED_METHOD);
+define_method_id_accessors!(set_cl_tunnel_data_flowing_method, get_cl_tunnel_data_flowing_method, CL_TUNNEL_DATA_FLOWING_METHOD);
+define_method_id_accessors!(set_cl_tunnel_no_data_method, get_cl_tunnel_no_data_method, CL_TUNNEL_NO_DATA_METHOD);
+define_method_id_accessors!(set_cl_video_format_negotiated_method, get_cl_video_format_negotiated_method, CL_VIDEO_FORMAT_NEGOTIATED_METHOD);
+define_method_id_accessors!(set_wg_state_changed_method, get_wg_state_changed_method, WG_STATE_CHANGED_METHOD);
 
 // Buffer management
 pub fn set_decoded_frame_buffer(buffer: JByteArray) {
@@ -362,6 +465,11 @@ pub fn init_method_ids(env: JNIEnv, clazz: JClass) {
         b"bridgeDrSubmitDecodeUnit\0".as_ptr() as *const c_char,
         b"([BIIIICJJ)I\0".as_ptr() as *const c_char
     ));
+    set_dr_decoder_overloaded_method(jni_get_static_method_id(
+        env, clazz,
+        b"bridgeDrDecoderOverloaded\0".as_ptr() as *const c_char,
+        b"(II)V\0".as_ptr() as *const c_char
+    ));
 
     // Audio renderer callbacks
     set_ar_init_method(jni_get_static_method_id(
@@ -394,17 +502,17 @@ pub fn init_method_ids(env: JNIEnv, clazz: JClass) {
     set_cl_stage_starting_method(jni_get_static_method_id(
         env, clazz,
         b"bridgeClStageStarting\0".as_ptr() as *const c_char,
-        b"(I)V\0".as_ptr() as *const c_char
+        b"(II)V\0".as_ptr() as *const c_char
     ));
     set_cl_stage_complete_method(jni_get_static_method_id(
         env, clazz,
         b"bridgeClStageComplete\0".as_ptr() as *const c_char,
-        b"(I)V\0".as_ptr() as *const c_char
+        b"(II)V\0".as_ptr() as *const c_char
     ));
     set_cl_stage_failed_method(jni_get_static_method_id(
         env, clazz,
         b"bridgeClStageFailed\0".as_ptr() as *const c_char,
-        b"(II)V\0".as_ptr() as *const c_char
+        b"(III)V\0".as_ptr() as *const c_char
     ));
     set_cl_connection_started_method(jni_get_static_method_id(
         env, clazz,
@@ -446,6 +554,21 @@ pub fn init_method_ids(env: JNIEnv, clazz: JClass) {
         b"bridgeClSetControllerLED\0".as_ptr() as *const c_char,
         b"(SBBB)V\0".as_ptr() as *const c_char
     ));
+    set_cl_tunnel_data_flowing_method(jni_get_static_method_id(
+        env, clazz,
+        b"bridgeClTunnelDataFlowing\0".as_ptr() as *const c_char,
+        b"()V\0".as_ptr() as *const c_char
+    ));
+    set_cl_tunnel_no_data_method(jni_get_static_method_id(
+        env, clazz,
+        b"bridgeClTunnelNoData\0".as_ptr() as *const c_char,
+        b"(I)V\0".as_ptr() as *const c_char
+    ));
+    set_cl_video_format_negotiated_method(jni_get_static_method_id(
+        env, clazz,
+        b"bridgeClVideoFormatNegotiated\0".as_ptr() as *const c_char,
+        b"(IIII)V\0".as_ptr() as *const c_char
+    ));
 
     // Create global reference for bridge class
     let global_class = new_global_ref(env, clazz);
@@ -470,6 +593,19 @@ pub fn call_static_void_method(env: JNIEnv, method: JMethodID, args: &[JValue])
     }
 }
 
+/// Call static void method on an explicit class with arguments
+pub fn call_static_void_method_on_class(env: JNIEnv, class: JClass, method: JMethodID, args: &[JValue]) {
+    if env.is_null() || class.is_null() || method.is_null() {
+        return;
+    }
+
+    unsafe {
+        type CallStaticVoidMethodAFn = extern "C" fn(JNIEnv, JClass, JMethodID, *const JValue);
+        let call_static_void_method_a: CallStaticVoidMethodAFn = get_jni_fn(env, JNI_CALL_STATIC_VOID_METHOD_A);
+        call_static_void_method_a(env, class, method, args.as_ptr());
+    }
+}
+
 /// Call static int method with arguments
 pub fn call_static_int_method(env: JNIEnv, method: JMethodID, args: &[JValue]) -> JInt {
     if env.is_null() || method.is_null() {
@@ -554,6 +690,19 @@ pub fn get_array_length(env: JNIEnv, array: JObject) -> JInt {
     }
 }
 
+/// Get an element of an object array (returns a local reference)
+pub fn get_object_array_element(env: JNIEnv, array: JObject, index: JInt) -> JObject {
+    if env.is_null() || array.is_null() {
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        type GetObjectArrayElementFn = extern "C" fn(JNIEnv, JObject, JInt) -> JObject;
+        let get_object_array_element: GetObjectArrayElementFn = get_jni_fn(env, JNI_GET_OBJECT_ARRAY_ELEMENT);
+        get_object_array_element(env, array, index)
+    }
+}
+
 /// Create new global reference
 pub fn new_global_ref(env: JNIEnv, obj: JObject) -> JObject {
     if env.is_null() || obj.is_null() {
@@ -743,6 +892,90 @@ pub fn create_byte_array(env: JNIEnv, data: &[u8]) -> JByteArray {
     array
 }
 
+/// Create a new int array from a slice
+pub fn create_int_array(env: JNIEnv, data: &[JInt]) -> JIntArray {
+    if env.is_null() {
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        type NewIntArrayFn = extern "C" fn(JNIEnv, JInt) -> JIntArray;
+        let new_int_array: NewIntArrayFn = get_jni_fn(env, JNI_NEW_INT_ARRAY);
+        let array = new_int_array(env, data.len() as JInt);
+        if array.is_null() {
+            return ptr::null_mut();
+        }
+
+        type SetIntArrayRegionFn = extern "C" fn(JNIEnv, JIntArray, JInt, JInt, *const JInt);
+        let set_int_array_region: SetIntArrayRegionFn = get_jni_fn(env, JNI_SET_INT_ARRAY_REGION);
+        set_int_array_region(env, array, 0, data.len() as JInt, data.as_ptr());
+        array
+    }
+}
+
+/// Create a new long array from a slice
+pub fn create_long_array(env: JNIEnv, data: &[JLong]) -> JLongArray {
+    if env.is_null() {
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        type NewLongArrayFn = extern "C" fn(JNIEnv, JInt) -> JLongArray;
+        let new_long_array: NewLongArrayFn = get_jni_fn(env, JNI_NEW_LONG_ARRAY);
+        let array = new_long_array(env, data.len() as JInt);
+        if array.is_null() {
+            return ptr::null_mut();
+        }
+
+        type SetLongArrayRegionFn = extern "C" fn(JNIEnv, JLongArray, JInt, JInt, *const JLong);
+        let set_long_array_region: SetLongArrayRegionFn = get_jni_fn(env, JNI_SET_LONG_ARRAY_REGION);
+        set_long_array_region(env, array, 0, data.len() as JInt, data.as_ptr());
+        array
+    }
+}
+
+/// Create a new String[] from a slice. Returns null (with a pending Java
+/// exception) if an allocation fails.
+pub fn create_string_array(env: JNIEnv, items: &[String]) -> JObject {
+    if env.is_null() {
+        return ptr::null_mut();
+    }
+
+    unsafe {
+        type FindClassFn = extern "C" fn(JNIEnv, *const c_char) -> JClass;
+        type NewObjectArrayFn = extern "C" fn(JNIEnv, JInt, JClass, JObject) -> JObject;
+        type NewStringUtfFn = extern "C" fn(JNIEnv, *const c_char) -> JObject;
+        type SetObjectArrayElementFn = extern "C" fn(JNIEnv, JObject, JInt, JObject);
+
+        let find_class: FindClassFn = get_jni_fn(env, JNI_FIND_CLASS);
+        let string_class = find_class(env, b"java/lang/String\0".as_ptr() as *const c_char);
+        if string_class.is_null() {
+            return ptr::null_mut();
+        }
+
+        let new_object_array: NewObjectArrayFn = get_jni_fn(env, JNI_NEW_OBJECT_ARRAY);
+        let array = new_object_array(env, items.len() as JInt, string_class, ptr::null_mut());
+        delete_local_ref(env, string_class);
+        if array.is_null() {
+            return ptr::null_mut();
+        }
+
+        let new_string_utf: NewStringUtfFn = get_jni_fn(env, JNI_NEW_STRING_UTF);
+        let set_object_array_element: SetObjectArrayElementFn = get_jni_fn(env, JNI_SET_OBJECT_ARRAY_ELEMENT);
+        for (i, item) in items.iter().enumerate() {
+            let c_item = std::ffi::CString::new(item.as_str()).unwrap_or_default();
+            let string = new_string_utf(env, c_item.as_ptr());
+            if string.is_null() {
+                delete_local_ref(env, array);
+                return ptr::null_mut();
+            }
+            set_object_array_element(env, array, i as JInt, string);
+            delete_local_ref(env, string);
+        }
+        array
+    }
+}
+
 /// Get a String from JNI JString
 pub fn get_string(env: JNIEnv, jstring: *mut c_void) -> Option<String> {
     if env.is_null() || jstring.is_null() {
@@ -768,3 +1001,59 @@ pub fn get_string(env: JNIEnv, jstring: *mut c_void) -> Option<String> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+
+    static FAKE_ATTACHES: AtomicU32 = AtomicU32::new(0);
+    static FAKE_DETACHES: AtomicU32 = AtomicU32::new(0);
+    static FAKE_ENV: u8 = 0;
+
+    extern "C" fn fake_get_env(_vm: JavaVM, _env: *mut JNIEnv, _version: JInt) -> JInt {
+        -2 // JNI_EDETACHED
+    }
+
+    extern "C" fn fake_attach(_vm: JavaVM, env: *mut JNIEnv, _args: *mut c_void) -> JInt {
+        FAKE_ATTACHES.fetch_add(1, Ordering::SeqCst);
+        unsafe { *env = &FAKE_ENV as *const u8 as JNIEnv };
+        JNI_OK
+    }
+
+    extern "C" fn fake_detach(_vm: JavaVM) -> JInt {
+        FAKE_DETACHES.fetch_add(1, Ordering::SeqCst);
+        JNI_OK
+    }
+
+    /// A JavaVM whose invoke interface only implements GetEnv/Attach/Detach
+    fn fake_vm() -> JavaVM {
+        let mut table = [ptr::null_mut::<c_void>(); 7];
+        table[JVM_ATTACH_CURRENT_THREAD] = fake_attach as *mut c_void;
+        table[JVM_DETACH_CURRENT_THREAD] = fake_detach as *mut c_void;
+        table[JVM_GET_ENV] = fake_get_env as *mut c_void;
+        let table: &'static mut [*mut c_void; 7] = Box::leak(Box::new(table));
+        let vm: &'static mut *mut *mut c_void = Box::leak(Box::new(table.as_mut_ptr()));
+        vm as *mut *mut *mut c_void as JavaVM
+    }
+
+    #[test]
+    fn test_env_attached_once_per_thread() {
+        let vm = fake_vm() as usize;
+        let attach_calls_before = thread_attach_count();
+
+        std::thread::spawn(move || {
+            // High-rate callback path: one attach, then cache hits
+            for _ in 0..10_000 {
+                assert!(get_thread_env_for(vm as JavaVM).is_some());
+            }
+            assert_eq!(FAKE_ATTACHES.load(Ordering::SeqCst), 1);
+            assert_eq!(FAKE_DETACHES.load(Ordering::SeqCst), 0);
+        })
+        .join()
+        .unwrap();
+
+        // Detached by the thread-local destructor when the thread exited
+        assert_eq!(FAKE_DETACHES.load(Ordering::SeqCst), 1);
+        assert!(thread_attach_count() > attach_calls_before);
+    }
+}
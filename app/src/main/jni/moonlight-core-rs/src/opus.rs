@@ -1,8 +1,13 @@
 //! Opus decoder FFI bindings
 //!
-//! This module provides FFI declarations for the Opus multistream decoder.
+//! This module provides FFI declarations for the Opus multistream decoder,
+//! plus helpers for handling surround (5.1/7.1) multistream output, the
+//! decode mode, and packet loss concealment statistics.
 
+use crate::ffi::{AUDIO_CONFIGURATION_51_SURROUND, AUDIO_CONFIGURATION_71_SURROUND, AUDIO_CONFIGURATION_STEREO};
 use libc::{c_int, c_uchar};
+use log::{info, warn};
+use std::sync::atomic::{AtomicI32, AtomicU32, AtomicU64, Ordering};
 
 /// Opus multistream decoder opaque type
 #[repr(C)]
@@ -17,6 +22,8 @@ pub const OPUS_SET_GAIN: c_int = 4034;
 /// Get the duration of the last decoded packet in samples
 #[allow(dead_code)]
 pub const OPUS_GET_LAST_PACKET_DURATION: c_int = 4039;
+/// Reset the decoder state as if freshly created
+pub const OPUS_RESET_STATE: c_int = 4028;
 
 // External C functions from libopus
 #[link(name = "opus")]
@@ -49,3 +56,402 @@ extern "C" {
     pub fn opus_multistream_decoder_ctl(st: *mut OpusMSDecoder, request: c_int, ...) -> c_int;
 }
 
+
+// ============================================================================
+// Decode mode
+// ============================================================================
+
+// libopus is the only decoder and always runs in software; the mode picks how
+// much of it is used. Values match MoonBridge.OPUS_DECODE_MODE_*.
+
+/// Full path: in-band FEC recovery of lost packets, then PLC.
+/// Falls back to OPUS_DECODE_MODE_BASIC if decoding keeps failing.
+pub const OPUS_DECODE_MODE_AUTO: i32 = 0;
+/// Minimal path: decode received packets and use PLC for losses, no FEC
+pub const OPUS_DECODE_MODE_BASIC: i32 = 1;
+
+/// Consecutive decode errors in OPUS_DECODE_MODE_AUTO before falling back
+pub const DECODE_ERROR_FALLBACK_THRESHOLD: u32 = 3;
+
+/// Mode requested by Java, applied on the next audio init
+static REQUESTED_DECODE_MODE: AtomicI32 = AtomicI32::new(OPUS_DECODE_MODE_AUTO);
+
+/// Mode used by the active audio stream
+static ACTIVE_DECODE_MODE: AtomicI32 = AtomicI32::new(OPUS_DECODE_MODE_AUTO);
+
+/// Decode errors since the last successful decode
+static CONSECUTIVE_DECODE_ERRORS: AtomicU32 = AtomicU32::new(0);
+
+/// Request a decode mode for the next audio stream. Returns false for unknown modes.
+pub fn set_decode_mode(mode: i32) -> bool {
+    if mode != OPUS_DECODE_MODE_AUTO && mode != OPUS_DECODE_MODE_BASIC {
+        warn!("Ignoring unknown Opus decode mode {}", mode);
+        return false;
+    }
+    REQUESTED_DECODE_MODE.store(mode, Ordering::Relaxed);
+    true
+}
+
+/// Apply the requested decode mode (called when the audio decoder is created)
+pub fn start_decode_mode() -> i32 {
+    let mode = REQUESTED_DECODE_MODE.load(Ordering::Relaxed);
+    ACTIVE_DECODE_MODE.store(mode, Ordering::Relaxed);
+    CONSECUTIVE_DECODE_ERRORS.store(0, Ordering::Relaxed);
+    mode
+}
+
+/// Decode mode used by the active audio stream
+pub fn active_decode_mode() -> i32 {
+    ACTIVE_DECODE_MODE.load(Ordering::Relaxed)
+}
+
+/// Record the outcome of a decode call.
+/// Returns true when this error switched the stream to OPUS_DECODE_MODE_BASIC;
+/// the caller should then reset the decoder state.
+pub fn record_decode_result(success: bool) -> bool {
+    if success {
+        CONSECUTIVE_DECODE_ERRORS.store(0, Ordering::Relaxed);
+        return false;
+    }
+
+    let errors = CONSECUTIVE_DECODE_ERRORS.fetch_add(1, Ordering::Relaxed) + 1;
+    if errors >= DECODE_ERROR_FALLBACK_THRESHOLD
+        && ACTIVE_DECODE_MODE
+            .compare_exchange(OPUS_DECODE_MODE_AUTO, OPUS_DECODE_MODE_BASIC, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        CONSECUTIVE_DECODE_ERRORS.store(0, Ordering::Relaxed);
+        warn!("Opus decode failed {} times in a row, falling back to basic decode (no FEC)", errors);
+        return true;
+    }
+    false
+}
+
+// ============================================================================
+// Audio configurations
+// ============================================================================
+
+/// A channel layout that can be requested from the host, with the Opus
+/// multistream layout the host uses for it (used to probe the decoder)
+struct AudioLayout {
+    configuration: c_int,
+    channels: c_int,
+    streams: c_int,
+    coupled_streams: c_int,
+    mapping: &'static [u8],
+}
+
+/// Layouts from fewest to most channels
+const AUDIO_LAYOUTS: [AudioLayout; 3] = [
+    AudioLayout {
+        configuration: AUDIO_CONFIGURATION_STEREO,
+        channels: 2,
+        streams: 1,
+        coupled_streams: 1,
+        mapping: &[0, 1],
+    },
+    AudioLayout {
+        configuration: AUDIO_CONFIGURATION_51_SURROUND,
+        channels: 6,
+        streams: 4,
+        coupled_streams: 2,
+        mapping: &[0, 4, 1, 5, 2, 3],
+    },
+    AudioLayout {
+        configuration: AUDIO_CONFIGURATION_71_SURROUND,
+        channels: 8,
+        streams: 5,
+        coupled_streams: 3,
+        mapping: &[0, 4, 1, 5, 2, 3, 6, 7],
+    },
+];
+
+/// Audio configuration requested by Java for the next connection (0 = use the
+/// value passed to startConnection). Consumed by the connection start that uses it.
+static PREFERRED_AUDIO_CONFIGURATION: AtomicI32 = AtomicI32::new(0);
+
+/// Audio configuration the current (or most recent) connection was started with (0 = none yet)
+static ACTIVE_AUDIO_CONFIGURATION: AtomicI32 = AtomicI32::new(0);
+
+/// Get the MAKE_AUDIO_CONFIGURATION() values the decoder can handle, found by
+/// creating a 48 kHz multistream decoder for each layout
+pub fn supported_audio_configurations() -> Vec<c_int> {
+    AUDIO_LAYOUTS
+        .iter()
+        .filter(|layout| {
+            let mut error: c_int = 0;
+            let decoder = unsafe {
+                opus_multistream_decoder_create(
+                    48000,
+                    layout.channels,
+                    layout.streams,
+                    layout.coupled_streams,
+                    layout.mapping.as_ptr(),
+                    &mut error,
+                )
+            };
+            if decoder.is_null() {
+                warn!("Opus decoder can't handle {}-channel audio: error={}", layout.channels, error);
+                return false;
+            }
+            unsafe { opus_multistream_decoder_destroy(decoder) };
+            true
+        })
+        .map(|layout| layout.configuration)
+        .collect()
+}
+
+/// Request an audio configuration for the next connection, or 0 to clear it.
+/// Returns false if the decoder can't handle the configuration.
+pub fn set_preferred_audio_configuration(configuration: c_int) -> bool {
+    if configuration != 0 && !supported_audio_configurations().contains(&configuration) {
+        warn!("Rejected unsupported audio configuration {:#x}", configuration);
+        return false;
+    }
+    PREFERRED_AUDIO_CONFIGURATION.store(configuration, Ordering::Relaxed);
+    true
+}
+
+/// Pick the audio configuration for a connection being started: a pending
+/// set_preferred_audio_configuration() value wins over `requested` (and is used up)
+pub fn resolve_audio_configuration(requested: c_int) -> c_int {
+    let preferred = PREFERRED_AUDIO_CONFIGURATION.swap(0, Ordering::Relaxed);
+    let configuration = if preferred == 0 { requested } else { preferred };
+    if configuration != requested {
+        info!("Audio configuration changed from {:#x} to preferred {:#x}", requested, configuration);
+    }
+    ACTIVE_AUDIO_CONFIGURATION.store(configuration, Ordering::Relaxed);
+    configuration
+}
+
+/// Audio configuration the current (or most recent) connection was started with
+pub fn active_audio_configuration() -> c_int {
+    ACTIVE_AUDIO_CONFIGURATION.load(Ordering::Relaxed)
+}
+
+// ============================================================================
+// Packet loss concealment statistics
+// ============================================================================
+
+/// Number of frames synthesized by the decoder's packet loss concealment
+static PLC_FRAMES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of PLC frames since the last frame decoded from real data (packet or FEC)
+static CONSECUTIVE_PLC_FRAMES: AtomicU32 = AtomicU32::new(0);
+
+/// Record that the decoder fell back to packet loss concealment for a frame
+pub fn record_concealed_frame() {
+    PLC_FRAMES.fetch_add(1, Ordering::Relaxed);
+    CONSECUTIVE_PLC_FRAMES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record a frame decoded from packet data (normal decode or FEC recovery)
+pub fn record_decoded_frame() {
+    CONSECUTIVE_PLC_FRAMES.store(0, Ordering::Relaxed);
+}
+
+/// Reset the concealment statistics (called when the audio renderer starts)
+pub fn reset_concealment_stats() {
+    PLC_FRAMES.store(0, Ordering::Relaxed);
+    CONSECUTIVE_PLC_FRAMES.store(0, Ordering::Relaxed);
+}
+
+/// Get (total PLC frames, current run of consecutive PLC frames)
+pub fn concealment_stats() -> (u64, u32) {
+    (
+        PLC_FRAMES.load(Ordering::Relaxed),
+        CONSECUTIVE_PLC_FRAMES.load(Ordering::Relaxed),
+    )
+}
+
+// ============================================================================
+// Surround downmix
+// ============================================================================
+
+/// Downmix coefficient for center and surround channels (-3 dB)
+const DOWNMIX_CENTER_GAIN: f32 = 0.7071;
+
+/// Downmix coefficient for the LFE channel (-6 dB)
+const DOWNMIX_LFE_GAIN: f32 = 0.5;
+
+/// Downmix interleaved surround PCM to interleaved stereo.
+///
+/// Input channels follow the GameStream order after the Opus mapping table is
+/// applied: FL, FR, FC, LFE, BL, BR, SL, SR. Mono input is duplicated to both
+/// sides and stereo input is copied as-is. The output is normalized so a full
+/// scale signal on every channel does not clip.
+///
+/// Returns the number of stereo frames written to `output`.
+pub fn downmix_to_stereo(input: &[i16], channels: usize, output: &mut [i16]) -> usize {
+    if channels == 0 {
+        return 0;
+    }
+
+    let frames = std::cmp::min(input.len() / channels, output.len() / 2);
+
+    match channels {
+        1 => {
+            for i in 0..frames {
+                output[i * 2] = input[i];
+                output[i * 2 + 1] = input[i];
+            }
+        }
+        2 => {
+            output[..frames * 2].copy_from_slice(&input[..frames * 2]);
+        }
+        _ => {
+            // Total gain applied to one side, used to normalize the mix
+            let side_gain = 1.0
+                + if channels > 2 { DOWNMIX_CENTER_GAIN } else { 0.0 }
+                + if channels > 3 { DOWNMIX_LFE_GAIN } else { 0.0 }
+                + if channels > 4 { DOWNMIX_CENTER_GAIN } else { 0.0 }
+                + if channels > 6 { DOWNMIX_CENTER_GAIN } else { 0.0 };
+            let norm = 1.0 / side_gain;
+
+            for i in 0..frames {
+                let frame = &input[i * channels..(i + 1) * channels];
+                let ch = |idx: usize| -> f32 { frame.get(idx).copied().unwrap_or(0) as f32 };
+
+                let center = ch(2) * DOWNMIX_CENTER_GAIN + ch(3) * DOWNMIX_LFE_GAIN;
+                let left = ch(0) + center + ch(4) * DOWNMIX_CENTER_GAIN + ch(6) * DOWNMIX_CENTER_GAIN;
+                let right = ch(1) + center + ch(5) * DOWNMIX_CENTER_GAIN + ch(7) * DOWNMIX_CENTER_GAIN;
+
+                output[i * 2] = (left * norm).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                output[i * 2 + 1] = (right * norm).round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+            }
+        }
+    }
+
+    frames
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn test_concealment_stats() {
+        reset_concealment_stats();
+        record_concealed_frame();
+        record_concealed_frame();
+        assert_eq!(concealment_stats(), (2, 2));
+
+        // A real frame ends the concealment run but keeps the total
+        record_decoded_frame();
+        record_concealed_frame();
+        assert_eq!(concealment_stats(), (3, 1));
+
+        reset_concealment_stats();
+        assert_eq!(concealment_stats(), (0, 0));
+    }
+
+    #[test]
+    fn test_decode_error_falls_back_to_basic() {
+        assert!(!set_decode_mode(7));
+        assert!(set_decode_mode(OPUS_DECODE_MODE_AUTO));
+        assert_eq!(start_decode_mode(), OPUS_DECODE_MODE_AUTO);
+
+        // Isolated errors don't trigger the fallback
+        for _ in 0..DECODE_ERROR_FALLBACK_THRESHOLD - 1 {
+            assert!(!record_decode_result(false));
+        }
+        assert!(!record_decode_result(true));
+        assert_eq!(active_decode_mode(), OPUS_DECODE_MODE_AUTO);
+
+        // A run of errors falls back exactly once
+        for _ in 0..DECODE_ERROR_FALLBACK_THRESHOLD - 1 {
+            assert!(!record_decode_result(false));
+        }
+        assert!(record_decode_result(false));
+        assert_eq!(active_decode_mode(), OPUS_DECODE_MODE_BASIC);
+        for _ in 0..DECODE_ERROR_FALLBACK_THRESHOLD {
+            assert!(!record_decode_result(false));
+        }
+
+        // The next stream starts in the requested mode again
+        assert_eq!(start_decode_mode(), OPUS_DECODE_MODE_AUTO);
+    }
+
+    #[test]
+    fn test_supported_audio_configurations() {
+        assert_eq!(
+            supported_audio_configurations(),
+            vec![AUDIO_CONFIGURATION_STEREO, AUDIO_CONFIGURATION_51_SURROUND, AUDIO_CONFIGURATION_71_SURROUND]
+        );
+
+        assert!(!set_preferred_audio_configuration(0x1234));
+        assert_eq!(resolve_audio_configuration(AUDIO_CONFIGURATION_STEREO), AUDIO_CONFIGURATION_STEREO);
+
+        assert!(set_preferred_audio_configuration(AUDIO_CONFIGURATION_51_SURROUND));
+        assert_eq!(resolve_audio_configuration(AUDIO_CONFIGURATION_STEREO), AUDIO_CONFIGURATION_51_SURROUND);
+        assert_eq!(active_audio_configuration(), AUDIO_CONFIGURATION_51_SURROUND);
+        // Used up by that connection
+        assert_eq!(resolve_audio_configuration(AUDIO_CONFIGURATION_STEREO), AUDIO_CONFIGURATION_STEREO);
+        assert_eq!(active_audio_configuration(), AUDIO_CONFIGURATION_STEREO);
+
+        assert!(set_preferred_audio_configuration(AUDIO_CONFIGURATION_51_SURROUND));
+        assert!(set_preferred_audio_configuration(0));
+        assert_eq!(resolve_audio_configuration(AUDIO_CONFIGURATION_71_SURROUND), AUDIO_CONFIGURATION_71_SURROUND);
+    }
+
+    #[test]
+    fn test_downmix_stereo_passthrough() {
+        let input = [100i16, -100, 200, -200];
+        let mut output = [0i16; 4];
+        assert_eq!(downmix_to_stereo(&input, 2, &mut output), 2);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn test_downmix_51_no_clipping() {
+        // One frame of full-scale 5.1 audio on every channel
+        let input = [i16::MAX; 6];
+        let mut output = [0i16; 2];
+        assert_eq!(downmix_to_stereo(&input, 6, &mut output), 1);
+        assert!(output[0] > i16::MAX - 2);
+        assert_eq!(output[0], output[1]);
+    }
+
+    #[test]
+    fn test_downmix_71_front_left_only() {
+        let mut input = [0i16; 8];
+        input[0] = 10000;
+        let mut output = [0i16; 2];
+        downmix_to_stereo(&input, 8, &mut output);
+        assert!(output[0] > 0);
+        assert_eq!(output[1], 0);
+    }
+
+    #[test]
+    fn test_decode_51_multistream_packet() {
+        // 5.1 layout as sent by GameStream hosts: 4 streams, 2 of them coupled
+        let mapping: [u8; 6] = [0, 4, 1, 5, 2, 3];
+        let mut error: c_int = 0;
+        let decoder = unsafe {
+            opus_multistream_decoder_create(48000, 6, 4, 2, mapping.as_ptr(), &mut error)
+        };
+        assert!(!decoder.is_null());
+        assert_eq!(error, 0);
+
+        // Multistream packet with 4 empty 20ms CELT frames. All but the last
+        // stream use self-delimited framing (TOC byte followed by a length byte).
+        let packet: [u8; 7] = [0xF8, 0x00, 0xF8, 0x00, 0xF8, 0x00, 0xF8];
+        let mut pcm = vec![0i16; 960 * 6];
+        let decoded = unsafe {
+            opus_multistream_decode(decoder, packet.as_ptr(), packet.len() as c_int, pcm.as_mut_ptr(), 960, 0)
+        };
+        assert_eq!(decoded, 960);
+
+        let mut stereo = vec![0i16; 960 * 2];
+        assert_eq!(downmix_to_stereo(&pcm, 6, &mut stereo), 960);
+
+        // PLC path with no packet must also produce a full frame
+        let plc = unsafe {
+            opus_multistream_decode(decoder, ptr::null(), 0, pcm.as_mut_ptr(), 960, 0)
+        };
+        assert_eq!(plc, 960);
+
+        unsafe { opus_multistream_decoder_destroy(decoder) };
+    }
+}
@@ -10,10 +10,15 @@
 //! - Thread-safe with parking_lot::Mutex
 //! - Outgoing packets queued for the caller to send through WireGuard
 //! - Incoming data delivered to application via mpsc channels
+//!
+//! Each in-order segment is sent on the channel as soon as it arrives and
+//! wg_socket_recv() hands it to the Java reader without waiting for more, so the
+//! PSH flag needs no handling: there is no relay socket in between to flush.
 
-use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::RangeInclusive;
 use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
 use std::sync::mpsc;
 use std::time::{Duration, Instant};
@@ -22,6 +27,53 @@ use etherparse::{IpNumber, Ipv4Header, Ipv6Header, TcpHeader};
 use log::{info, warn};
 use parking_lot::{Condvar, Mutex};
 
+/// MSS used when the tunnel MTU is unknown (WireGuard default MTU 1420 minus
+/// IP/TCP headers, with some margin)
+pub const DEFAULT_TCP_MSS: u16 = 1360;
+
+/// Local ports handed out to new connections unless configured otherwise
+pub const DEFAULT_EPHEMERAL_PORT_RANGE: RangeInclusive<u16> = 49152..=65000;
+
+/// Smallest MSS we will advertise or segment by (RFC 879 default MSS)
+const MIN_TCP_MSS: u16 = 536;
+
+/// IPv4 header (20) + TCP header (20) without options
+const TCP_IPV4_HEADER_OVERHEAD: u16 = 40;
+
+/// IPv6 header (40) + TCP header (20) without options
+const TCP_IPV6_HEADER_OVERHEAD: u16 = 60;
+
+/// Derive the TCP MSS for a tunnel MTU so full-sized segments fit in one
+/// tunnel packet without fragmentation.
+pub fn mss_for_mtu(mtu: u16, local_ip: IpAddr) -> u16 {
+    let overhead = match local_ip {
+        IpAddr::V4(_) => TCP_IPV4_HEADER_OVERHEAD,
+        IpAddr::V6(_) => TCP_IPV6_HEADER_OVERHEAD,
+    };
+    mtu.saturating_sub(overhead).max(MIN_TCP_MSS)
+}
+
+/// TCP keepalive settings for idle established connections
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpKeepalive {
+    /// Idle time before the first probe is sent
+    pub idle: Duration,
+    /// Time to wait for an answer before the next probe
+    pub interval: Duration,
+    /// Unanswered probes after which the connection is declared dead
+    pub max_probes: u32,
+}
+
+impl TcpKeepalive {
+    /// Conservative defaults: first probe after a minute of silence, dead after
+    /// four unanswered probes (~2 minutes), well before the stale-connection sweep.
+    pub const DEFAULT: TcpKeepalive = TcpKeepalive {
+        idle: Duration::from_secs(60),
+        interval: Duration::from_secs(15),
+        max_probes: 4,
+    };
+}
+
 /// TCP connection state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TcpState {
@@ -30,6 +82,8 @@ pub enum TcpState {
     Established,
     FinWait1,
     FinWait2,
+    /// Both sides sent FIN before seeing the other's (simultaneous close)
+    Closing,
     CloseWait,
     LastAck,
     TimeWait,
@@ -53,11 +107,113 @@ struct RetransmitSegment {
     retransmit_count: u32,
 }
 
+impl RetransmitSegment {
+    /// Sequence number just past this segment; a FIN occupies one
+    fn end_seq(&self) -> u32 {
+        let fin = u32::from((self.flags & TcpFlags::FIN) != 0);
+        self.seq.wrapping_add(self.data.len() as u32).wrapping_add(fin)
+    }
+}
+
 /// TCP window scale shift count for our receive window.
 /// With shift=7, effective window = 65535 * 128 = ~8MB, supporting
 /// high throughput even at moderate latencies (e.g., 100Mbps @ 80ms RTT).
 const TCP_WINDOW_SCALE_SHIFT: u8 = 7;
 
+/// TCP option kind for Window Scale (RFC 7323)
+const TCP_OPTION_WINDOW_SCALE: u8 = 3;
+
+/// Largest shift count a peer may use (RFC 7323 section 2.3)
+const TCP_MAX_WINDOW_SCALE: u8 = 14;
+
+/// Capacity of each connection's channel to the application. The receive
+/// window advertises this many full-sized segments.
+const TCP_APP_CHANNEL_SEGMENTS: usize = 2048;
+
+/// How long sent data may go without any ACK progress before an established
+/// connection is declared stalled. A little past the point where the
+/// retransmission schedule (8 attempts, RTO capped at 8s) has given up.
+const TCP_STALL_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// How long the peer may keep its receive window closed on data we are waiting
+/// to send. It answers our window probes meanwhile, so this is longer than
+/// TCP_STALL_TIMEOUT, but a reader that never drains its buffer still ends up
+/// with the connection closed.
+const TCP_ZERO_WINDOW_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// TCP option kind for Timestamps (RFC 7323)
+const TCP_OPTION_TIMESTAMPS: u8 = 8;
+
+/// NOP, NOP, then the 10-byte Timestamps option, as sent on every segment
+/// once negotiated. Subtracted from the MSS when segmenting data.
+const TCP_TIMESTAMPS_OPTION_LEN: u16 = 12;
+
+/// RTO before any RTT has been measured, and after an ACK when timestamps
+/// were not negotiated
+const TCP_INITIAL_RTO: Duration = Duration::from_millis(500);
+
+/// Lower bound for an RTO derived from measured RTT
+const TCP_MIN_RTO: Duration = Duration::from_millis(200);
+
+/// Upper bound for the RTO, including exponential backoff
+const TCP_MAX_RTO: Duration = Duration::from_secs(8);
+
+/// Find the option `kind` with total length `len` in raw TCP options and
+/// return its data (without the kind and length bytes)
+fn find_tcp_option(options: &[u8], kind: u8, len: usize) -> Option<&[u8]> {
+    let mut i = 0;
+    while i < options.len() {
+        match options[i] {
+            0 => return None, // End of option list
+            1 => i += 1,      // NOP
+            k => {
+                let l = *options.get(i + 1)? as usize;
+                if l < 2 || i + l > options.len() {
+                    return None;
+                }
+                if k == kind && l == len {
+                    return Some(&options[i + 2..i + l]);
+                }
+                i += l;
+            }
+        }
+    }
+    None
+}
+
+/// Find the Timestamps option in raw TCP options. Returns (TSval, TSecr).
+fn parse_timestamps_option(options: &[u8]) -> Option<(u32, u32)> {
+    let data = find_tcp_option(options, TCP_OPTION_TIMESTAMPS, 10)?;
+    let tsval = u32::from_be_bytes(data[..4].try_into().ok()?);
+    let tsecr = u32::from_be_bytes(data[4..].try_into().ok()?);
+    Some((tsval, tsecr))
+}
+
+/// Find the Window Scale option in raw TCP options. Returns the shift count,
+/// clamped to 14 as RFC 7323 requires.
+fn parse_window_scale_option(options: &[u8]) -> Option<u8> {
+    find_tcp_option(options, TCP_OPTION_WINDOW_SCALE, 3).map(|data| data[0].min(TCP_MAX_WINDOW_SCALE))
+}
+
+/// Payload bytes per data segment for an MSS of `mss`, leaving room for the
+/// Timestamps option when it is sent
+fn segment_payload_size(mss: u16, timestamps: bool) -> usize {
+    let mss = if timestamps {
+        mss.saturating_sub(TCP_TIMESTAMPS_OPTION_LEN).max(MIN_TCP_MSS)
+    } else {
+        mss
+    };
+    mss as usize
+}
+
+/// Window field for a segment advertising `rcv_window` bytes. Scaled by our
+/// shift once scaling has been negotiated; the window in a SYN is never
+/// scaled (RFC 7323 section 2.2).
+fn advertised_window(rcv_window: u32, scaled: bool, syn: bool) -> u16 {
+    let shift = if scaled && !syn { TCP_WINDOW_SCALE_SHIFT } else { 0 };
+    (rcv_window >> shift).min(u16::MAX as u32) as u16
+}
+
 /// TCP control block - tracks per-connection state
 struct TcpControlBlock {
     state: TcpState,
@@ -85,8 +241,177 @@ struct TcpControlBlock {
     pending_fin_seq: Option<u32>,
     /// Retransmission queue: segments sent but not yet acknowledged
     retransmit_queue: VecDeque<RetransmitSegment>,
+    /// Data accepted from the application that doesn't fit in the peer's
+    /// window yet; sent as ACKs open the window (see take_sendable)
+    unsent: VecDeque<u8>,
+    /// Our FIN, waiting for `unsent` to drain before it can go out
+    fin_queued: bool,
+    /// When the last zero-window probe was sent
+    last_window_probe_at: Option<Instant>,
     /// Current retransmission timeout (adaptive, starts at 500ms)
     rto: Duration,
+    /// Whether both sides sent Window Scale, so our advertised window is
+    /// shifted by TCP_WINDOW_SCALE_SHIFT
+    window_scaling: bool,
+    /// Peer's Window Scale shift, applied to the window of every non-SYN
+    /// segment it sends (0 unless negotiated)
+    peer_window_shift: u8,
+    /// Peer's receive window in bytes, from the latest segment it sent
+    snd_wnd: u32,
+    /// Most recent TSval from the peer, echoed as TSecr.
+    /// None when the peer did not negotiate timestamps in its SYN-ACK.
+    ts_recent: Option<u32>,
+    /// Smoothed RTT from timestamp echoes (RFC 6298), None until the first sample
+    srtt: Option<Duration>,
+    /// RTT variation from timestamp echoes
+    rttvar: Duration,
+    /// Keepalive probes sent since the peer was last heard from
+    keepalive_probes_sent: u32,
+    /// When the last keepalive probe was sent
+    last_keepalive_at: Option<Instant>,
+    /// When snd_una last advanced, or outbound data was first queued
+    last_ack_progress: Instant,
+    /// Closed because sent data stopped being acknowledged (see check_stalled_connections)
+    stalled: bool,
+}
+
+/// Per-connection options every non-SYN segment carries, read from the TCB by
+/// callers that already hold the connection lock
+#[derive(Debug, Clone, Copy, Default)]
+struct SegmentOptions {
+    /// Peer TSval to echo as TSecr (None: timestamps not negotiated)
+    ts_recent: Option<u32>,
+    /// Whether our advertised window is scaled
+    window_scaling: bool,
+}
+
+impl TcpControlBlock {
+    fn segment_options(&self) -> SegmentOptions {
+        SegmentOptions { ts_recent: self.ts_recent, window_scaling: self.window_scaling }
+    }
+
+    /// Fold an RTT measurement into srtt/rttvar (RFC 6298 section 2)
+    fn update_rtt(&mut self, sample: Duration) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(sample);
+                self.rttvar = sample / 2;
+            }
+            Some(srtt) => {
+                let delta = if srtt > sample { srtt - sample } else { sample - srtt };
+                self.rttvar = (self.rttvar * 3 + delta) / 4;
+                self.srtt = Some((srtt * 7 + sample) / 8);
+            }
+        }
+    }
+
+    /// Whether sent or queued data (our FIN included) is waiting on the peer
+    fn has_outbound(&self) -> bool {
+        !self.retransmit_queue.is_empty() || !self.unsent.is_empty() || self.fin_queued
+    }
+
+    /// Move as much unsent data as the peer's window leaves room for into
+    /// segments of up to `mss` bytes, followed by a queued FIN once nothing is
+    /// left. The segments are queued for retransmission and returned as
+    /// (seq, data, flags) for the caller to send outside the lock.
+    fn take_sendable(&mut self, mss: usize, now: Instant) -> Vec<(u32, Vec<u8>, u8)> {
+        let mut segments = Vec::new();
+        if !matches!(
+            self.state,
+            TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::LastAck
+        ) {
+            return segments;
+        }
+        loop {
+            let in_flight = self.local_seq.wrapping_sub(self.snd_una);
+            let usable = self.snd_wnd.saturating_sub(in_flight) as usize;
+            let len = self.unsent.len().min(mss).min(usable);
+            if len == 0 {
+                break;
+            }
+            let data: Vec<u8> = self.unsent.drain(..len).collect();
+            // Last segment of what the application handed us: set PSH
+            let flags = if self.unsent.is_empty() {
+                TcpFlags::ACK | TcpFlags::PSH
+            } else {
+                TcpFlags::ACK
+            };
+            segments.push(self.queue_segment(data, flags, now));
+            self.local_seq = self.local_seq.wrapping_add(len as u32);
+            self.last_window_probe_at = None;
+        }
+        if self.fin_queued && self.unsent.is_empty() {
+            // Queued like data so a lost FIN is retransmitted until acknowledged
+            self.fin_queued = false;
+            segments.push(self.queue_segment(Vec::new(), TcpFlags::FIN | TcpFlags::ACK, now));
+        }
+        segments
+    }
+
+    /// Add a segment starting at local_seq to the retransmit queue
+    fn queue_segment(&mut self, data: Vec<u8>, flags: u8, now: Instant) -> (u32, Vec<u8>, u8) {
+        let seq = self.local_seq;
+        self.retransmit_queue.push_back(RetransmitSegment {
+            seq,
+            data: data.clone(),
+            flags,
+            sent_at: now,
+            retransmit_count: 0,
+        });
+        (seq, data, flags)
+    }
+
+    /// Take an in-order payload that arrives after our FIN has gone out, so a
+    /// half-closed connection keeps relaying the peer's data. Out-of-order
+    /// segments are dropped here and left to the peer to retransmit.
+    fn take_half_closed_payload(&mut self, seq: u32, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload.is_empty() || seq != self.local_ack {
+            return None;
+        }
+        self.local_ack = self.local_ack.wrapping_add(payload.len() as u32);
+        Some(payload.to_vec())
+    }
+
+    /// Process the ACK number of an incoming segment: advance snd_una and drop
+    /// fully acknowledged segments (our FIN included) from the retransmit queue.
+    /// `rtt_sample` is the timestamp echo's RTT, if any.
+    fn process_ack(&mut self, ack_num: u32, rtt_sample: Option<Duration>) {
+        // Only advance if ACK is within valid range
+        let ack_advance = ack_num.wrapping_sub(self.snd_una) as i32;
+        if ack_advance <= 0 {
+            return;
+        }
+        self.snd_una = ack_num;
+        self.last_ack_progress = Instant::now();
+        // The echoed TSval is an RTT sample even for retransmitted data
+        // (RFC 7323 section 4.1)
+        if self.ts_recent.is_some() {
+            if let Some(rtt) = rtt_sample {
+                self.update_rtt(rtt);
+            }
+        }
+        // Remove fully acknowledged segments from retransmit queue
+        while let Some(front) = self.retransmit_queue.front() {
+            // If snd_una >= seg_end, this segment is fully ACKed
+            if front.end_seq().wrapping_sub(self.snd_una) as i32 <= 0 {
+                self.retransmit_queue.pop_front();
+            } else {
+                break;
+            }
+        }
+        // Reset RTO on successful ACK
+        self.rto = self.base_rto();
+    }
+
+    /// RTO to use when nothing is being backed off: derived from the smoothed
+    /// RTT once measured, TCP_INITIAL_RTO otherwise
+    fn base_rto(&self) -> Duration {
+        match self.srtt {
+            Some(srtt) => (srtt + (self.rttvar * 4).max(Duration::from_millis(1)))
+                .clamp(TCP_MIN_RTO, TCP_MAX_RTO),
+            None => TCP_INITIAL_RTO,
+        }
+    }
 }
 
 /// Action to perform after processing a TCP packet (outside the lock)
@@ -121,9 +446,47 @@ enum TcpPacketAction {
     ConnectionReset,
     /// Signal EOF to the application (e.g., on RST or unexpected close)
     SignalEof { tx: mpsc::SyncSender<Vec<u8>> },
+    /// Segment for a connection we don't know: answer with RST
+    SendReset { seq: u32, ack: u32, flags: u8 },
     None,
 }
 
+/// Action for a non-FIN segment while our side is closed for writing:
+/// relay in-order data to the application, ignore pure ACKs
+fn half_closed_data_action(tcb: &mut TcpControlBlock, seq: u32, payload: &[u8]) -> TcpPacketAction {
+    match tcb.take_half_closed_payload(seq, payload) {
+        Some(data) => TcpPacketAction::SendData {
+            seq: tcb.local_seq,
+            ack: tcb.local_ack,
+            data,
+            tx: tcb.tx_to_app.clone(),
+        },
+        None if !payload.is_empty() => TcpPacketAction::SendAck {
+            seq: tcb.local_seq,
+            ack: tcb.local_ack,
+        },
+        None => TcpPacketAction::None,
+    }
+}
+
+/// Action for the peer's FIN while our side is closed for writing: ACK it,
+/// relay any final payload and signal EOF to the application
+fn half_closed_fin_action(tcb: &TcpControlBlock, data: Option<Vec<u8>>) -> TcpPacketAction {
+    match data {
+        Some(data) => TcpPacketAction::SendDataThenFinAck {
+            seq: tcb.local_seq,
+            ack: tcb.local_ack,
+            data_segments: vec![data],
+            tx: tcb.tx_to_app.clone(),
+        },
+        None => TcpPacketAction::SendFinAck {
+            seq: tcb.local_seq,
+            ack: tcb.local_ack,
+            tx: tcb.tx_to_app.clone(),
+        },
+    }
+}
+
 /// TCP flags constants
 struct TcpFlags;
 
@@ -145,7 +508,10 @@ impl TcpFlags {
 pub struct VirtualStack {
     local_ip: IpAddr,
     tcp_connections: Mutex<HashMap<TcpConnectionId, TcpControlBlock>>,
+    /// Next local port to try; always inside `port_range`
     next_local_port: AtomicU16,
+    /// Local ports handed out to new connections
+    port_range: Mutex<RangeInclusive<u16>>,
     next_seq: AtomicU32,
     /// Queued outgoing IP packets (to be sent through WireGuard)
     outgoing_packets: Mutex<Vec<Vec<u8>>>,
@@ -153,6 +519,13 @@ pub struct VirtualStack {
     state_change_condvar: Condvar,
     /// Mutex used with the condvar (parking_lot Condvar works with its own Mutex)
     state_change_mutex: Mutex<()>,
+    /// MSS advertised in SYN and used to segment outgoing data.
+    /// Atomic so a probed MTU can be applied while connections are open.
+    mss: AtomicU16,
+    /// Keepalive settings for idle connections (None, the default, disables probing)
+    keepalive: Mutex<Option<TcpKeepalive>>,
+    /// Origin of the millisecond clock sent as TSval
+    ts_epoch: Instant,
 }
 
 impl VirtualStack {
@@ -161,14 +534,96 @@ impl VirtualStack {
         Self {
             local_ip: local_ip.into(),
             tcp_connections: Mutex::new(HashMap::new()),
-            next_local_port: AtomicU16::new(49152),
+            next_local_port: AtomicU16::new(*DEFAULT_EPHEMERAL_PORT_RANGE.start()),
+            port_range: Mutex::new(DEFAULT_EPHEMERAL_PORT_RANGE),
             next_seq: AtomicU32::new(1_000_000),
             outgoing_packets: Mutex::new(Vec::new()),
             state_change_condvar: Condvar::new(),
             state_change_mutex: Mutex::new(()),
+            mss: AtomicU16::new(DEFAULT_TCP_MSS),
+            keepalive: Mutex::new(None),
+            ts_epoch: Instant::now(),
+        }
+    }
+
+    /// Derive the MSS from the tunnel MTU instead of using `DEFAULT_TCP_MSS`
+    pub fn with_mtu(self, mtu: u16) -> Self {
+        self.set_mtu(mtu);
+        self
+    }
+
+    /// Apply a new tunnel MTU (e.g. a lower value found by MTU probing).
+    ///
+    /// Only affects segments built after the call: packets already queued for
+    /// sending and segments awaiting retransmission are left untouched, and open
+    /// connections keep the MSS they advertised in their SYN.
+    pub fn set_mtu(&self, mtu: u16) {
+        let mss = mss_for_mtu(mtu, self.local_ip);
+        let old = self.mss.swap(mss, Ordering::Relaxed);
+        if old != mss {
+            info!("VirtualStack MSS changed {} -> {} (MTU {})", old, mss, mtu);
         }
     }
 
+    /// MSS advertised in SYN and used to segment outgoing data
+    pub fn mss(&self) -> u16 {
+        self.mss.load(Ordering::Relaxed)
+    }
+
+    /// Current TSval: milliseconds since the stack was created, starting at 1
+    /// so an echoed value is never mistaken for "no echo" (TSecr 0)
+    fn ts_now(&self) -> u32 {
+        (self.ts_epoch.elapsed().as_millis() as u32).wrapping_add(1)
+    }
+
+    /// RTT sample from a TSecr we sent as TSval, if it is plausible
+    fn rtt_from_echo(&self, tsecr: u32) -> Option<Duration> {
+        if tsecr == 0 {
+            return None;
+        }
+        let elapsed = self.ts_now().wrapping_sub(tsecr) as i32;
+        if elapsed < 0 {
+            return None;
+        }
+        Some(Duration::from_millis(elapsed as u64))
+    }
+
+    /// Smoothed RTT measured from timestamp echoes, if timestamps were
+    /// negotiated and at least one sample has been taken
+    pub fn tcp_srtt(&self, conn_id: &TcpConnectionId) -> Option<Duration> {
+        self.tcp_connections.lock().get(conn_id).and_then(|tcb| tcb.srtt)
+    }
+
+    /// Use the given local port range instead of `DEFAULT_EPHEMERAL_PORT_RANGE`
+    pub fn with_port_range(self, range: RangeInclusive<u16>) -> Self {
+        self.set_port_range(range);
+        self
+    }
+
+    /// Change the local port range for new connections. Open connections keep
+    /// their ports. An empty range or one including port 0 is ignored.
+    pub fn set_port_range(&self, range: RangeInclusive<u16>) {
+        if range.is_empty() || *range.start() == 0 {
+            warn!("VirtualStack: ignoring invalid port range {:?}", range);
+            return;
+        }
+        self.next_local_port.store(*range.start(), Ordering::Relaxed);
+        info!("VirtualStack port range: {:?}", range);
+        *self.port_range.lock() = range;
+    }
+
+    /// Probe idle connections with the given keepalive settings (off by default)
+    pub fn with_keepalive(self, keepalive: Option<TcpKeepalive>) -> Self {
+        self.set_keepalive(keepalive);
+        self
+    }
+
+    /// Change keepalive settings (None disables probing). Applies to open connections.
+    pub fn set_keepalive(&self, keepalive: Option<TcpKeepalive>) {
+        *self.keepalive.lock() = keepalive;
+        info!("VirtualStack keepalive: {:?}", keepalive);
+    }
+
     /// Wait for a TCP connection state change with timeout.
     /// Returns true if notified, false if timed out.
     pub fn wait_for_state_change(&self, timeout: Duration) -> bool {
@@ -182,11 +637,31 @@ impl VirtualStack {
         self.state_change_condvar.notify_all();
     }
 
-    fn allocate_port(&self) -> u16 {
-        let port = self.next_local_port.fetch_add(1, Ordering::Relaxed);
-        if port >= 65000 {
-            self.next_local_port.store(49152, Ordering::Relaxed);
+    /// Pick the next local port in the range that no tracked connection uses,
+    /// including ones lingering in TimeWait: reusing such a port would let the
+    /// new connection's SYN-ACK match the stale control block. Called with the
+    /// connection table locked so the port can't be taken before it's inserted.
+    fn allocate_port(&self, conns: &HashMap<TcpConnectionId, TcpControlBlock>) -> u16 {
+        let range = self.port_range.lock().clone();
+        let (start, end) = (*range.start(), *range.end());
+        let in_use: HashSet<u16> = conns.keys().map(|id| id.local_port).collect();
+
+        let mut port = self.next_local_port.load(Ordering::Relaxed);
+        for _ in 0..=(end - start) {
+            if !range.contains(&port) {
+                port = start;
+            }
+            let candidate = port;
+            port = if candidate == end { start } else { candidate + 1 };
+            if !in_use.contains(&candidate) {
+                self.next_local_port.store(port, Ordering::Relaxed);
+                return candidate;
+            }
         }
+
+        // Every port in the range is tracked; fall back to plain rotation
+        warn!("VirtualStack: all {} local ports in use, reusing {}", in_use.len(), port);
+        self.next_local_port.store(if port == end { start } else { port + 1 }, Ordering::Relaxed);
         port
     }
 
@@ -208,19 +683,11 @@ impl VirtualStack {
         remote_port: u16,
     ) -> (TcpConnectionId, mpsc::Receiver<Vec<u8>>) {
         let remote_addr = remote_addr.into();
-        let local_port = self.allocate_port();
         let initial_seq = self.generate_initial_seq();
 
-        let conn_id = TcpConnectionId {
-            local_addr: self.local_ip,
-            local_port,
-            remote_addr,
-            remote_port,
-        };
-
         // Larger channel buffer to support TCP window scaling (up to ~8MB window).
-        // With 2048 entries * ~1360 bytes MSS = ~2.8MB effective buffer.
-        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(2048);
+        // With 2048 entries * ~1360 bytes MSS (default) = ~2.8MB effective buffer.
+        let (tx, rx) = mpsc::sync_channel::<Vec<u8>>(TCP_APP_CHANNEL_SEGMENTS);
 
         let now = Instant::now();
         let tcb = TcpControlBlock {
@@ -237,16 +704,36 @@ impl VirtualStack {
             reorder_buffer_bytes: 0,
             pending_fin_seq: None,
             retransmit_queue: VecDeque::new(),
-            rto: Duration::from_millis(500),
+            unsent: VecDeque::new(),
+            fin_queued: false,
+            last_window_probe_at: None,
+            rto: TCP_INITIAL_RTO,
+            window_scaling: false,
+            peer_window_shift: 0,
+            snd_wnd: 0,
+            ts_recent: None,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            keepalive_probes_sent: 0,
+            last_keepalive_at: None,
+            last_ack_progress: now,
+            stalled: false,
         };
 
-        {
+        let conn_id = {
             let mut conns = self.tcp_connections.lock();
+            let conn_id = TcpConnectionId {
+                local_addr: self.local_ip,
+                local_port: self.allocate_port(&conns),
+                remote_addr,
+                remote_port,
+            };
             conns.insert(conn_id, tcb);
-        }
+            conn_id
+        };
 
         // Send SYN
-        self.send_tcp_packet(&conn_id, initial_seq, 0, TcpFlags::SYN, &[]);
+        self.send_tcp_packet(&conn_id, initial_seq, 0, TcpFlags::SYN, &[], SegmentOptions::default());
 
         info!(
             "Initiated TCP connection to {}:{}",
@@ -256,14 +743,26 @@ impl VirtualStack {
         (conn_id, rx)
     }
 
-    /// Send data on an established TCP connection
+    /// Send data on an established TCP connection. Only as much as the peer's
+    /// window allows goes out right away; the rest is queued and sent as ACKs
+    /// open the window.
     pub fn tcp_send(&self, conn_id: &TcpConnectionId, data: &[u8]) -> io::Result<()> {
-        let (mut seq, ack) = {
+        // Segment data by the MSS derived from the tunnel MTU (read once so a
+        // concurrent set_mtu can't split one send into mixed segment sizes)
+        let mss = self.mss();
+        let now = Instant::now();
+        let (segments, ack, opts) = {
             let mut conns = self.tcp_connections.lock();
             let tcb = conns.get_mut(conn_id).ok_or_else(|| {
                 io::Error::new(io::ErrorKind::NotConnected, "Connection not found")
             })?;
 
+            if tcb.stalled {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Connection stalled: sent data was never acknowledged",
+                ));
+            }
             if tcb.state != TcpState::Established && tcb.state != TcpState::CloseWait {
                 return Err(io::Error::new(
                     io::ErrorKind::NotConnected,
@@ -271,74 +770,96 @@ impl VirtualStack {
                 ));
             }
 
-            tcb.last_activity = Instant::now();
-            let seq = tcb.local_seq;
-            tcb.local_seq = tcb.local_seq.wrapping_add(data.len() as u32);
-            (seq, tcb.local_ack)
+            tcb.last_activity = now;
+            if !tcb.has_outbound() {
+                tcb.last_ack_progress = now;
+            }
+            tcb.unsent.extend(data);
+            let segments = tcb.take_sendable(segment_payload_size(mss, tcb.ts_recent.is_some()), now);
+            (segments, tcb.local_ack, tcb.segment_options())
         };
 
-        // Segment data by a conservative MSS (1360 bytes for WG tunnel)
-        // MTU 1420 - IP header 20 - TCP header 20 - some margin = 1360
-        let mss = 1360usize;
-        let now = Instant::now();
-        for chunk in data.chunks(mss) {
-            let flags = if chunk.as_ptr() as usize + chunk.len()
-                == data.as_ptr() as usize + data.len()
-            {
-                // Last (or only) segment: set PSH
-                TcpFlags::ACK | TcpFlags::PSH
-            } else {
-                TcpFlags::ACK
-            };
-            self.send_tcp_packet(conn_id, seq, ack, flags, chunk);
-
-            // Store segment for potential retransmission
-            {
-                let mut conns = self.tcp_connections.lock();
-                if let Some(tcb) = conns.get_mut(conn_id) {
-                    tcb.retransmit_queue.push_back(RetransmitSegment {
-                        seq,
-                        data: chunk.to_vec(),
-                        flags,
-                        sent_at: now,
-                        retransmit_count: 0,
-                    });
-                }
-            }
+        self.send_segments(conn_id, &segments, ack, opts);
+        Ok(())
+    }
 
-            seq = seq.wrapping_add(chunk.len() as u32);
+    /// Send segments returned by take_sendable
+    fn send_segments(&self, conn_id: &TcpConnectionId, segments: &[(u32, Vec<u8>, u8)], ack: u32, opts: SegmentOptions) {
+        for (seq, data, flags) in segments {
+            self.send_tcp_packet(conn_id, *seq, ack, *flags, data, opts);
         }
+    }
 
-        Ok(())
+    /// Send several buffers as one contiguous stream write (scatter-gather).
+    /// Small buffers are coalesced into full-sized segments instead of one
+    /// segment per buffer. Returns the total number of bytes queued.
+    pub fn tcp_sendv(&self, conn_id: &TcpConnectionId, buffers: &[&[u8]]) -> io::Result<usize> {
+        if let [single] = buffers {
+            self.tcp_send(conn_id, single)?;
+            return Ok(single.len());
+        }
+
+        let total: usize = buffers.iter().map(|b| b.len()).sum();
+        if total == 0 {
+            return Ok(0);
+        }
+        let mut data = Vec::with_capacity(total);
+        for buffer in buffers {
+            data.extend_from_slice(buffer);
+        }
+        self.tcp_send(conn_id, &data)?;
+        Ok(total)
     }
 
     /// Close a TCP connection gracefully
     pub fn tcp_close(&self, conn_id: &TcpConnectionId) -> io::Result<()> {
-        let (seq, ack) = {
+        self.send_fin(conn_id, true)
+    }
+
+    /// Half-close a TCP connection: send our FIN but keep delivering the peer's
+    /// data to the receive channel until the peer FINs too (EOF) or the
+    /// connection goes stale. Unacknowledged data stays queued.
+    pub fn tcp_shutdown_write(&self, conn_id: &TcpConnectionId) -> io::Result<()> {
+        self.send_fin(conn_id, false)
+    }
+
+    fn send_fin(&self, conn_id: &TcpConnectionId, discard_unacked: bool) -> io::Result<()> {
+        let mss = self.mss();
+        let now = Instant::now();
+        let (segments, ack, opts) = {
             let mut conns = self.tcp_connections.lock();
             if let Some(tcb) = conns.get_mut(conn_id) {
-                // Clear retransmit queue on close - no point retransmitting
-                tcb.retransmit_queue.clear();
+                if discard_unacked {
+                    // Clear retransmit queue on close - no point retransmitting,
+                    // or sending what the peer's window held back
+                    tcb.retransmit_queue.clear();
+                    tcb.unsent.clear();
+                }
                 match tcb.state {
                     TcpState::Established => {
                         // Active close: we initiate FIN
                         tcb.state = TcpState::FinWait1;
-                        (tcb.local_seq, tcb.local_ack)
                     }
                     TcpState::CloseWait => {
                         // Passive close: server already FIN'd, now we FIN too
                         // Next state is LastAck (waiting for ACK of our FIN)
                         tcb.state = TcpState::LastAck;
-                        (tcb.local_seq, tcb.local_ack)
                     }
                     _ => return Ok(()),
                 }
+                // The FIN follows any data still waiting for the peer's window
+                if !tcb.has_outbound() {
+                    tcb.last_ack_progress = now;
+                }
+                tcb.fin_queued = true;
+                let segments = tcb.take_sendable(segment_payload_size(mss, tcb.ts_recent.is_some()), now);
+                (segments, tcb.local_ack, tcb.segment_options())
             } else {
                 return Ok(());
             }
         };
 
-        self.send_tcp_packet(conn_id, seq, ack, TcpFlags::FIN | TcpFlags::ACK, &[]);
+        self.send_segments(conn_id, &segments, ack, opts);
         Ok(())
     }
 
@@ -380,26 +901,53 @@ impl VirtualStack {
         };
 
         if let Some(seq) = initial_seq {
-            self.send_tcp_packet(conn_id, seq, 0, TcpFlags::SYN, &[]);
+            self.send_tcp_packet(conn_id, seq, 0, TcpFlags::SYN, &[], SegmentOptions::default());
             true
         } else {
             false
         }
     }
 
-    /// Check all connections for segments that need retransmission.
-    /// Returns the number of segments retransmitted.
+    /// Check all connections for segments that need retransmission, and probe
+    /// peers whose zero window is holding back queued data.
+    /// Returns the number of segments retransmitted or probes sent.
     pub fn check_retransmissions(&self) -> usize {
         let now = Instant::now();
         let max_retransmits: u32 = 8;
-        let max_rto = Duration::from_secs(8);
 
         // Collect segments that need retransmission (under lock)
-        let mut to_retransmit: Vec<(TcpConnectionId, u32, Vec<u8>, u8, u32)> = Vec::new();
+        let mut to_retransmit: Vec<(TcpConnectionId, u32, Vec<u8>, u8, u32, SegmentOptions)> = Vec::new();
         {
             let mut conns = self.tcp_connections.lock();
             for (conn_id, tcb) in conns.iter_mut() {
-                if tcb.state != TcpState::Established && tcb.state != TcpState::CloseWait {
+                // Covers our FIN too, until the peer acknowledges it
+                if !matches!(
+                    tcb.state,
+                    TcpState::Established
+                        | TcpState::CloseWait
+                        | TcpState::FinWait1
+                        | TcpState::Closing
+                        | TcpState::LastAck
+                ) {
+                    continue;
+                }
+                if tcb.retransmit_queue.is_empty() && !tcb.unsent.is_empty() && tcb.snd_wnd == 0 {
+                    // Persist timer: a zero-length segment one byte behind snd.nxt
+                    // makes the peer ACK with its current window, so a lost window
+                    // update can't leave the data queued forever
+                    let last = tcb.last_window_probe_at.unwrap_or(tcb.last_ack_progress);
+                    if now.duration_since(last) >= tcb.rto {
+                        to_retransmit.push((
+                            *conn_id,
+                            tcb.local_seq.wrapping_sub(1),
+                            Vec::new(),
+                            TcpFlags::ACK,
+                            tcb.local_ack,
+                            tcb.segment_options(),
+                        ));
+                        tcb.last_window_probe_at = Some(now);
+                        tcb.rto = (tcb.rto * 2).min(TCP_MAX_RTO);
+                    }
                     continue;
                 }
                 for seg in tcb.retransmit_queue.iter_mut() {
@@ -415,11 +963,12 @@ impl VirtualStack {
                             seg.data.clone(),
                             seg.flags,
                             tcb.local_ack,
+                            SegmentOptions { ts_recent: tcb.ts_recent, window_scaling: tcb.window_scaling },
                         ));
                         seg.retransmit_count += 1;
                         seg.sent_at = now;
                         // Exponential backoff for RTO
-                        tcb.rto = (tcb.rto * 2).min(max_rto);
+                        tcb.rto = (tcb.rto * 2).min(TCP_MAX_RTO);
                     }
                     // Only retransmit the first unACKed segment per connection (go-back-N style)
                     break;
@@ -429,8 +978,114 @@ impl VirtualStack {
 
         // Send retransmit packets outside the lock
         let count = to_retransmit.len();
-        for (conn_id, seq, data, flags, ack) in to_retransmit {
-            self.send_tcp_packet(&conn_id, seq, ack, flags, &data);
+        for (conn_id, seq, data, flags, ack, opts) in to_retransmit {
+            self.send_tcp_packet(&conn_id, seq, ack, flags, &data, opts);
+        }
+        count
+    }
+
+    /// Send keepalive probes on idle established connections and close the ones
+    /// whose probes went unanswered. Dead connections are marked Closed and the
+    /// application sees EOF. Returns the number of probes sent.
+    pub fn check_keepalives(&self) -> usize {
+        let keepalive = match *self.keepalive.lock() {
+            Some(keepalive) => keepalive,
+            None => return 0,
+        };
+        let now = Instant::now();
+
+        let mut probes: Vec<(TcpConnectionId, u32, u32, SegmentOptions)> = Vec::new();
+        let mut dead: Vec<mpsc::SyncSender<Vec<u8>>> = Vec::new();
+        {
+            let mut conns = self.tcp_connections.lock();
+            for (conn_id, tcb) in conns.iter_mut() {
+                // Outbound data is covered by retransmission and window probes
+                if tcb.state != TcpState::Established || tcb.has_outbound() {
+                    continue;
+                }
+
+                let due = match tcb.last_keepalive_at {
+                    Some(sent_at) if tcb.keepalive_probes_sent > 0 => {
+                        now.duration_since(sent_at) >= keepalive.interval
+                    }
+                    _ => now.duration_since(tcb.last_activity) >= keepalive.idle,
+                };
+                if !due {
+                    continue;
+                }
+
+                if tcb.keepalive_probes_sent >= keepalive.max_probes {
+                    warn!("TCP keepalive: {}:{} idle, unanswered after {} probes, closing",
+                          conn_id.remote_addr, conn_id.remote_port, tcb.keepalive_probes_sent);
+                    tcb.state = TcpState::Closed;
+                    tcb.last_activity = now;
+                    dead.push(tcb.tx_to_app.clone());
+                    continue;
+                }
+
+                // Zero-length segment one byte behind snd.nxt forces the peer to ACK
+                tcb.keepalive_probes_sent += 1;
+                tcb.last_keepalive_at = Some(now);
+                probes.push((*conn_id, tcb.local_seq.wrapping_sub(1), tcb.local_ack, tcb.segment_options()));
+            }
+        }
+
+        // Send probes and signal EOF outside the lock
+        let count = probes.len();
+        for (conn_id, seq, ack, opts) in probes {
+            self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
+        }
+        if !dead.is_empty() {
+            for tx in dead {
+                let _ = tx.send(Vec::new());
+            }
+            self.notify_state_change();
+        }
+        count
+    }
+
+    /// Close established connections whose outbound data has gone
+    /// TCP_STALL_TIMEOUT without any ACK progress (TCP_ZERO_WINDOW_TIMEOUT while
+    /// the peer's window is closed): the peer is still "connected" but nothing
+    /// moves. Unlike an idle connection (see check_keepalives), there is
+    /// outbound data waiting. Later sends fail with TimedOut and the
+    /// application sees EOF. Returns the number of connections closed.
+    pub fn check_stalled_connections(&self) -> usize {
+        let now = Instant::now();
+        let mut stalled: Vec<mpsc::SyncSender<Vec<u8>>> = Vec::new();
+        {
+            let mut conns = self.tcp_connections.lock();
+            for (conn_id, tcb) in conns.iter_mut() {
+                if tcb.state != TcpState::Established || !tcb.has_outbound() {
+                    continue;
+                }
+                // A peer advertising a zero window is applying flow control and
+                // answers our window probes, so it gets longer, but not forever
+                let timeout = if tcb.snd_wnd == 0 { TCP_ZERO_WINDOW_TIMEOUT } else { TCP_STALL_TIMEOUT };
+                if now.duration_since(tcb.last_ack_progress) < timeout {
+                    continue;
+                }
+
+                let unacked: usize = tcb.retransmit_queue.iter().map(|seg| seg.data.len()).sum();
+                warn!("TCP connection {}:{} stalled: {} bytes unacknowledged, {} unsent (window {}) for {}s, closing",
+                      conn_id.remote_addr, conn_id.remote_port, unacked, tcb.unsent.len(), tcb.snd_wnd,
+                      now.duration_since(tcb.last_ack_progress).as_secs());
+                tcb.state = TcpState::Closed;
+                tcb.stalled = true;
+                tcb.last_activity = now;
+                tcb.retransmit_queue.clear();
+                tcb.unsent.clear();
+                stalled.push(tcb.tx_to_app.clone());
+            }
+        }
+
+        // Signal EOF outside the lock
+        let count = stalled.len();
+        if count > 0 {
+            for tx in stalled {
+                let _ = tx.send(Vec::new());
+            }
+            self.notify_state_change();
         }
         count
     }
@@ -505,16 +1160,61 @@ impl VirtualStack {
             src_ip, tcp_header.source_port, dst_ip, tcp_header.destination_port,
             tcp_header.syn, tcp_header.ack, tcp_header.fin, tcp_header.rst);
 
+        let timestamps = parse_timestamps_option(tcp_header.options.as_slice());
+        let rtt_sample = timestamps.and_then(|(_, tsecr)| self.rtt_from_echo(tsecr));
+
+        let mss = self.mss();
+
         // Process packet while holding lock, determine action to take
-        let action = {
+        // (and the options our reply carries, as of after this packet),
+        // plus any queued data the packet's ACK or window update lets out
+        let (action, opts, sendable, sendable_ack) = {
             let mut conns = self.tcp_connections.lock();
 
             if let Some(tcb) = conns.get_mut(&conn_id) {
                 info!("process_tcp_packet: found connection, state={:?}", tcb.state);
-                match tcb.state {
+
+                // Remember the peer's TSval for echoing, only from segments at or
+                // before our ACK point so an old duplicate can't move it backwards
+                // (RFC 7323 section 4.3)
+                if let (Some(recent), Some((tsval, _))) = (tcb.ts_recent, timestamps) {
+                    if tcp_header.sequence_number.wrapping_sub(tcb.local_ack) as i32 <= 0
+                        && tsval.wrapping_sub(recent) as i32 >= 0
+                    {
+                        tcb.ts_recent = Some(tsval);
+                    }
+                }
+
+                // The window in a SYN is never scaled (RFC 7323 section 2.2);
+                // the SYN-ACK's is picked up below
+                if tcp_header.ack && !tcp_header.syn {
+                    tcb.snd_wnd = (tcp_header.window_size as u32) << tcb.peer_window_shift;
+                }
+
+                let action = match tcb.state {
                     TcpState::SynSent => {
                         if tcp_header.syn && tcp_header.ack {
-                            // SYN-ACK received - complete handshake
+                            // SYN-ACK received - complete handshake.
+                            // Timestamps and window scaling are used only if the
+                            // peer echoed the option.
+                            if let Some(peer_shift) =
+                                parse_window_scale_option(tcp_header.options.as_slice())
+                            {
+                                info!(
+                                    "Window scaling negotiated: local shift {}, peer shift {}",
+                                    TCP_WINDOW_SCALE_SHIFT, peer_shift
+                                );
+                                tcb.window_scaling = true;
+                                tcb.peer_window_shift = peer_shift;
+                            }
+                            tcb.snd_wnd = tcp_header.window_size as u32;
+                            if let Some((tsval, tsecr)) = timestamps {
+                                tcb.ts_recent = Some(tsval);
+                                if let Some(rtt) = self.rtt_from_echo(tsecr) {
+                                    tcb.update_rtt(rtt);
+                                    tcb.rto = tcb.base_rto();
+                                }
+                            }
                             tcb.local_ack = tcp_header.sequence_number.wrapping_add(1);
                             tcb.local_seq = tcp_header.acknowledgment_number;
                             tcb.snd_una = tcp_header.acknowledgment_number;
@@ -535,27 +1235,12 @@ impl VirtualStack {
                     }
                     TcpState::Established => {
                         tcb.last_activity = Instant::now();
+                        // Any segment from the peer answers outstanding keepalive probes
+                        tcb.keepalive_probes_sent = 0;
 
                         // Process ACK number - advance snd_una and clear retransmit buffer
                         if tcp_header.ack {
-                            let ack_num = tcp_header.acknowledgment_number;
-                            // Only advance if ACK is within valid range
-                            let ack_advance = ack_num.wrapping_sub(tcb.snd_una) as i32;
-                            if ack_advance > 0 {
-                                tcb.snd_una = ack_num;
-                                // Remove fully acknowledged segments from retransmit queue
-                                while let Some(front) = tcb.retransmit_queue.front() {
-                                    let seg_end = front.seq.wrapping_add(front.data.len() as u32);
-                                    // If snd_una >= seg_end, this segment is fully ACKed
-                                    if seg_end.wrapping_sub(tcb.snd_una) as i32 <= 0 {
-                                        tcb.retransmit_queue.pop_front();
-                                    } else {
-                                        break;
-                                    }
-                                }
-                                // Reset RTO on successful ACK
-                                tcb.rto = Duration::from_millis(500);
-                            }
+                            tcb.process_ack(tcp_header.acknowledgment_number, rtt_sample);
                         }
 
                         if tcp_header.rst {
@@ -741,39 +1426,77 @@ impl VirtualStack {
                     }
                     TcpState::FinWait1 => {
                         tcb.last_activity = Instant::now();
+                        // Our FIN was sent with sequence number local_seq
+                        let acks_our_fin = tcp_header.ack
+                            && tcp_header.acknowledgment_number == tcb.local_seq.wrapping_add(1);
+                        if tcp_header.ack {
+                            tcb.process_ack(tcp_header.acknowledgment_number, rtt_sample);
+                        }
                         if tcp_header.rst {
                             tcb.state = TcpState::Closed;
-                            TcpPacketAction::None
-                        } else if tcp_header.fin && tcp_header.ack {
-                            tcb.state = TcpState::TimeWait;
+                            TcpPacketAction::SignalEof { tx: tcb.tx_to_app.clone() }
+                        } else if tcp_header.fin {
+                            let data = tcb.take_half_closed_payload(
+                                tcp_header.sequence_number,
+                                tcp_payload,
+                            );
+                            // FIN+ACK of our FIN completes the close; a FIN that doesn't
+                            // cover ours means the peer closed simultaneously
+                            tcb.state = if acks_our_fin {
+                                TcpState::TimeWait
+                            } else {
+                                TcpState::Closing
+                            };
                             // Account for any data payload + the FIN sequence number
                             tcb.local_ack = tcp_header
                                 .sequence_number
                                 .wrapping_add(tcp_payload.len() as u32)
                                 .wrapping_add(1);
-                            TcpPacketAction::SendAck {
-                                seq: tcb.local_seq,
-                                ack: tcb.local_ack,
-                            }
-                        } else if tcp_header.ack {
-                            tcb.state = TcpState::FinWait2;
-                            TcpPacketAction::None
+                            half_closed_fin_action(tcb, data)
                         } else {
-                            TcpPacketAction::None
+                            // Only the ACK of our FIN moves on; an ACK of earlier
+                            // data leaves the FIN outstanding
+                            if acks_our_fin {
+                                tcb.state = TcpState::FinWait2;
+                            }
+                            half_closed_data_action(tcb, tcp_header.sequence_number, tcp_payload)
                         }
                     }
                     TcpState::FinWait2 => {
                         tcb.last_activity = Instant::now();
                         if tcp_header.rst {
                             tcb.state = TcpState::Closed;
-                            TcpPacketAction::None
+                            TcpPacketAction::SignalEof { tx: tcb.tx_to_app.clone() }
                         } else if tcp_header.fin {
+                            let data = tcb.take_half_closed_payload(
+                                tcp_header.sequence_number,
+                                tcp_payload,
+                            );
                             tcb.state = TcpState::TimeWait;
                             // Account for any data payload + the FIN sequence number
                             tcb.local_ack = tcp_header
                                 .sequence_number
                                 .wrapping_add(tcp_payload.len() as u32)
                                 .wrapping_add(1);
+                            half_closed_fin_action(tcb, data)
+                        } else {
+                            half_closed_data_action(tcb, tcp_header.sequence_number, tcp_payload)
+                        }
+                    }
+                    TcpState::Closing => {
+                        tcb.last_activity = Instant::now();
+                        if tcp_header.rst {
+                            tcb.state = TcpState::Closed;
+                            TcpPacketAction::None
+                        } else if tcp_header.ack
+                            && tcp_header.acknowledgment_number == tcb.local_seq.wrapping_add(1)
+                        {
+                            // Peer acknowledged our FIN
+                            tcb.process_ack(tcp_header.acknowledgment_number, rtt_sample);
+                            tcb.state = TcpState::TimeWait;
+                            TcpPacketAction::None
+                        } else if tcp_header.fin {
+                            // Peer retransmitted its FIN; our ACK was lost
                             TcpPacketAction::SendAck {
                                 seq: tcb.local_seq,
                                 ack: tcb.local_ack,
@@ -786,6 +1509,9 @@ impl VirtualStack {
                         tcb.last_activity = Instant::now();
                         if tcp_header.rst {
                             tcb.state = TcpState::Closed;
+                        } else if tcp_header.ack {
+                            // Data we still send after the peer's FIN gets acknowledged
+                            tcb.process_ack(tcp_header.acknowledgment_number, rtt_sample);
                         }
                         // In CloseWait, we haven't sent our FIN yet, just waiting for app to close
                         TcpPacketAction::None
@@ -794,6 +1520,11 @@ impl VirtualStack {
                         tcb.last_activity = Instant::now();
                         // Waiting for final ACK of our FIN
                         if tcp_header.ack {
+                            tcb.process_ack(tcp_header.acknowledgment_number, rtt_sample);
+                        }
+                        if tcp_header.ack
+                            && tcp_header.acknowledgment_number == tcb.local_seq.wrapping_add(1)
+                        {
                             tcb.state = TcpState::Closed;
                             tcb.last_activity = Instant::now(); // Reset for grace period
                         }
@@ -812,57 +1543,49 @@ impl VirtualStack {
                         }
                     }
                     _ => TcpPacketAction::None,
-                }
+                };
+                let sendable = tcb.take_sendable(segment_payload_size(mss, tcb.ts_recent.is_some()), Instant::now());
+                (action, tcb.segment_options(), sendable, tcb.local_ack)
             } else {
                 warn!("process_tcp_packet: no connection found for {}:{} -> {}:{}",
                       src_ip, tcp_header.source_port, dst_ip, tcp_header.destination_port);
-                if !tcp_header.rst {
+                let action = if tcp_header.rst {
+                    TcpPacketAction::None
+                } else if tcp_header.ack {
                     // Send RST to inform remote side this connection doesn't exist.
                     // This stops retransmissions and cleans up server-side state.
-                    let orphan_id = TcpConnectionId {
-                        local_addr: dst_ip,
-                        local_port: tcp_header.destination_port,
-                        remote_addr: src_ip,
-                        remote_port: tcp_header.source_port,
-                    };
-                    if tcp_header.ack {
-                        // If incoming has ACK, use its ack number as our seq
-                        self.send_tcp_packet(
-                            &orphan_id,
-                            tcp_header.acknowledgment_number,
-                            0,
-                            TcpFlags::RST,
-                            &[],
-                        );
-                    } else {
-                        // Otherwise, send RST+ACK
-                        let ack_num = tcp_header
-                            .sequence_number
-                            .wrapping_add(tcp_payload.len() as u32)
-                            .wrapping_add(
-                                if tcp_header.syn || tcp_header.fin { 1 } else { 0 },
-                            );
-                        self.send_tcp_packet(
-                            &orphan_id,
-                            0,
-                            ack_num,
-                            TcpFlags::RST | TcpFlags::ACK,
-                            &[],
+                    // If incoming has ACK, use its ack number as our seq
+                    TcpPacketAction::SendReset {
+                        seq: tcp_header.acknowledgment_number,
+                        ack: 0,
+                        flags: TcpFlags::RST,
+                    }
+                } else {
+                    // Otherwise, send RST+ACK
+                    let ack_num = tcp_header
+                        .sequence_number
+                        .wrapping_add(tcp_payload.len() as u32)
+                        .wrapping_add(
+                            if tcp_header.syn || tcp_header.fin { 1 } else { 0 },
                         );
+                    TcpPacketAction::SendReset {
+                        seq: 0,
+                        ack: ack_num,
+                        flags: TcpFlags::RST | TcpFlags::ACK,
                     }
-                }
-                TcpPacketAction::None
+                };
+                (action, SegmentOptions::default(), Vec::new(), 0)
             }
         };
 
         // Execute action with lock released
         match action {
             TcpPacketAction::SendAck { seq, ack } => {
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
             }
             TcpPacketAction::SendFinAck { seq, ack, tx } => {
                 // ACK the FIN from remote
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
                 // Signal EOF to the application so recv() returns immediately.
                 // Stay in CloseWait - our FIN will be sent when the app calls tcp_close.
                 // This supports half-close: the app can still send data before closing.
@@ -870,7 +1593,7 @@ impl VirtualStack {
             }
             TcpPacketAction::SendData { seq, ack, data, tx } => {
                 // ACK the data
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
                 // Forward data to application
                 if tx.send(data).is_err() {
                     warn!("TCP data channel disconnected for {:?}", conn_id);
@@ -884,7 +1607,7 @@ impl VirtualStack {
             }
             TcpPacketAction::SendMultipleData { seq, ack, data_segments, tx } => {
                 // ACK all the data
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
                 // Forward all segments to application in order
                 for data in data_segments {
                     if tx.send(data).is_err() {
@@ -900,7 +1623,7 @@ impl VirtualStack {
             }
             TcpPacketAction::SendDataThenFinAck { seq, ack, data_segments, tx } => {
                 // ACK all the data + FIN from remote
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
                 // Forward all segments to application in order
                 for data in data_segments {
                     if tx.send(data).is_err() {
@@ -915,7 +1638,7 @@ impl VirtualStack {
             }
             TcpPacketAction::BufferedOutOfOrder { seq, ack } => {
                 // Send duplicate ACK to indicate gap (triggers fast retransmit on sender)
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
             }
             TcpPacketAction::SignalEof { tx } => {
                 // Signal EOF to the application (connection was reset)
@@ -923,7 +1646,7 @@ impl VirtualStack {
             }
             TcpPacketAction::ConnectionEstablished { seq, ack } => {
                 // Send ACK to complete 3-way handshake
-                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[]);
+                self.send_tcp_packet(&conn_id, seq, ack, TcpFlags::ACK, &[], opts);
                 info!(
                     "TCP connection established to {}:{}",
                     conn_id.remote_addr, conn_id.remote_port
@@ -935,8 +1658,12 @@ impl VirtualStack {
                 // Notify waiters that connection was reset
                 self.notify_state_change();
             }
+            TcpPacketAction::SendReset { seq, ack, flags } => {
+                self.send_tcp_packet(&conn_id, seq, ack, flags, &[], opts);
+            }
             TcpPacketAction::None => {}
         }
+        self.send_segments(&conn_id, &sendable, sendable_ack, opts);
     }
 
     /// Build and queue a TCP packet for sending (supports IPv4 and IPv6)
@@ -947,12 +1674,14 @@ impl VirtualStack {
         ack: u32,
         flags: u8,
         payload: &[u8],
+        opts: SegmentOptions,
     ) {
+        let rcv_window = self.mss() as u32 * TCP_APP_CHANNEL_SEGMENTS as u32;
         let mut tcp_header = TcpHeader::new(
             conn_id.local_port,
             conn_id.remote_port,
             seq,
-            65535, // window size (with WS=7, effective = 65535 * 128 = ~8MB)
+            advertised_window(rcv_window, false, true),
         );
         tcp_header.acknowledgment_number = ack;
         tcp_header.syn = (flags & TcpFlags::SYN) != 0;
@@ -961,17 +1690,36 @@ impl VirtualStack {
         tcp_header.rst = (flags & TcpFlags::RST) != 0;
         tcp_header.psh = (flags & TcpFlags::PSH) != 0;
 
-        // Add TCP options for SYN packets: MSS + Window Scale
+        // Add TCP options for SYN packets: MSS + Window Scale + Timestamps
+        // (TSecr is 0 until we have heard from the peer)
+        let tsval = self.ts_now().to_be_bytes();
         if tcp_header.syn {
-            let mss: u16 = 1360;
-            let options: [u8; 8] = [
+            let mss = self.mss();
+            let options: [u8; 20] = [
                 2, 4, (mss >> 8) as u8, (mss & 0xff) as u8,
                 1,
-                3, 3, TCP_WINDOW_SCALE_SHIFT,
+                TCP_OPTION_WINDOW_SCALE, 3, TCP_WINDOW_SCALE_SHIFT,
+                1, 1,
+                TCP_OPTION_TIMESTAMPS, 10, tsval[0], tsval[1], tsval[2], tsval[3], 0, 0, 0, 0,
             ];
             if let Err(e) = tcp_header.set_options_raw(&options) {
                 warn!("Failed to set TCP SYN options: {:?}", e);
             }
+        } else if !tcp_header.rst {
+            // Once negotiated, every other segment carries Timestamps echoing the
+            // peer and a scaled window
+            tcp_header.window_size = advertised_window(rcv_window, opts.window_scaling, false);
+            if let Some(recent) = opts.ts_recent {
+                let tsecr = recent.to_be_bytes();
+                let options: [u8; TCP_TIMESTAMPS_OPTION_LEN as usize] = [
+                    1, 1,
+                    TCP_OPTION_TIMESTAMPS, 10, tsval[0], tsval[1], tsval[2], tsval[3],
+                    tsecr[0], tsecr[1], tsecr[2], tsecr[3],
+                ];
+                if let Err(e) = tcp_header.set_options_raw(&options) {
+                    warn!("Failed to set TCP timestamp option: {:?}", e);
+                }
+            }
         }
 
         let ip_payload_len = tcp_header.header_len() as usize + payload.len();
@@ -1083,7 +1831,11 @@ impl VirtualStack {
                 // Give Closed connections a brief grace period for any in-flight packets
                 TcpState::Closed => now.duration_since(tcb.last_activity).as_secs() > 5,
                 TcpState::SynSent => now.duration_since(tcb.created_at).as_secs() > 30,
-                TcpState::FinWait1 | TcpState::FinWait2 | TcpState::CloseWait | TcpState::LastAck => {
+                TcpState::FinWait1
+                | TcpState::FinWait2
+                | TcpState::Closing
+                | TcpState::CloseWait
+                | TcpState::LastAck => {
                     now.duration_since(tcb.last_activity).as_secs() > 120
                 }
                 TcpState::Established => {
@@ -1105,4 +1857,606 @@ impl VirtualStack {
     pub fn connection_count(&self) -> usize {
         self.tcp_connections.lock().len()
     }
+
+    /// Get number of TCP connections whose FIN is still waiting for the peer's ACK.
+    /// Open connections don't count: nothing has asked them to close, so waiting
+    /// on them would only run out a shutdown's timeout.
+    pub fn draining_connection_count(&self) -> usize {
+        self.tcp_connections
+            .lock()
+            .values()
+            .filter(|tcb| {
+                matches!(
+                    tcb.state,
+                    TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck
+                )
+            })
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mss_derived_from_mtu() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).with_mtu(1280);
+        assert_eq!(stack.mss(), 1240);
+
+        let stack = VirtualStack::new(Ipv6Addr::LOCALHOST).with_mtu(1280);
+        assert_eq!(stack.mss(), 1220);
+
+        assert_eq!(VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).mss(), DEFAULT_TCP_MSS);
+        assert_eq!(mss_for_mtu(0, IpAddr::V4(Ipv4Addr::UNSPECIFIED)), MIN_TCP_MSS);
+    }
+
+    #[test]
+    fn test_set_mtu_keeps_queued_packets() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).with_mtu(1420);
+        stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+
+        stack.set_mtu(1280);
+        assert_eq!(stack.mss(), 1240);
+
+        // The SYN queued before the change still advertises the old MSS
+        let queued = stack.take_outgoing_packets();
+        assert_eq!(queued.len(), 1);
+        let (_, tcp) = Ipv4Header::from_slice(&queued[0]).unwrap();
+        let (syn, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(&syn.options.as_slice()[..4], &[2, 4, (1380u16 >> 8) as u8, (1380u16 & 0xff) as u8]);
+    }
+
+    /// Build a SYN-ACK from the peer answering the SYN in `syn_packet` (IPv4).
+    fn syn_ack_for(syn_packet: &[u8], server_isn: u32) -> Vec<u8> {
+        syn_ack_with_options(syn_packet, server_isn, &[])
+    }
+
+    /// Like `syn_ack_for`, with raw TCP options on the SYN-ACK
+    fn syn_ack_with_options(syn_packet: &[u8], server_isn: u32, options: &[u8]) -> Vec<u8> {
+        let (syn_ip, syn_tcp) = Ipv4Header::from_slice(syn_packet).unwrap();
+        let (syn, _) = TcpHeader::from_slice(syn_tcp).unwrap();
+        assert!(syn.syn && !syn.ack);
+
+        let mut tcp = TcpHeader::new(syn.destination_port, syn.source_port, server_isn, 65535);
+        tcp.syn = true;
+        tcp.ack = true;
+        tcp.acknowledgment_number = syn.sequence_number.wrapping_add(1);
+        tcp.set_options_raw(options).unwrap();
+        let ip = Ipv4Header::new(
+            tcp.header_len() as u16,
+            64,
+            IpNumber::TCP,
+            syn_ip.destination,
+            syn_ip.source,
+        )
+        .unwrap();
+        tcp.checksum = tcp.calc_checksum_ipv4(&ip, &[]).unwrap();
+
+        let mut packet = Vec::new();
+        ip.write(&mut packet).unwrap();
+        tcp.write(&mut packet).unwrap();
+        packet
+    }
+
+    /// Build a segment from the peer on the connection `local_packet` was sent on (IPv4).
+    fn peer_segment(local_packet: &[u8], seq: u32, ack: Option<u32>, fin: bool) -> Vec<u8> {
+        peer_data_segment(local_packet, seq, ack, fin, &[])
+    }
+
+    /// Like `peer_segment`, carrying `payload`
+    fn peer_data_segment(
+        local_packet: &[u8],
+        seq: u32,
+        ack: Option<u32>,
+        fin: bool,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        peer_segment_with_window(local_packet, seq, ack, fin, payload, 65535)
+    }
+
+    /// Like `peer_data_segment`, advertising `window`
+    fn peer_segment_with_window(
+        local_packet: &[u8],
+        seq: u32,
+        ack: Option<u32>,
+        fin: bool,
+        payload: &[u8],
+        window: u16,
+    ) -> Vec<u8> {
+        let (local_ip, local_tcp) = Ipv4Header::from_slice(local_packet).unwrap();
+        let (local, _) = TcpHeader::from_slice(local_tcp).unwrap();
+
+        let mut tcp = TcpHeader::new(local.destination_port, local.source_port, seq, window);
+        tcp.fin = fin;
+        if let Some(ack) = ack {
+            tcp.ack = true;
+            tcp.acknowledgment_number = ack;
+        }
+        let ip = Ipv4Header::new(
+            (tcp.header_len() as usize + payload.len()) as u16,
+            64,
+            IpNumber::TCP,
+            local_ip.destination,
+            local_ip.source,
+        )
+        .unwrap();
+        tcp.checksum = tcp.calc_checksum_ipv4(&ip, payload).unwrap();
+
+        let mut packet = Vec::new();
+        ip.write(&mut packet).unwrap();
+        tcp.write(&mut packet).unwrap();
+        packet.extend_from_slice(payload);
+        packet
+    }
+
+    #[test]
+    fn test_timestamps_negotiated_and_echoed() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+
+        // SYN offers Timestamps with TSecr 0
+        let (_, tcp) = Ipv4Header::from_slice(&syn).unwrap();
+        let (syn_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        let (syn_tsval, syn_tsecr) = parse_timestamps_option(syn_tcp.options.as_slice()).unwrap();
+        assert_ne!(syn_tsval, 0);
+        assert_eq!(syn_tsecr, 0);
+
+        // Peer accepts, echoing our TSval
+        let server_tsval: u32 = 0x1122_3344;
+        let mut options = vec![1, 1, TCP_OPTION_TIMESTAMPS, 10];
+        options.extend_from_slice(&server_tsval.to_be_bytes());
+        options.extend_from_slice(&syn_tsval.to_be_bytes());
+        stack.process_incoming_packet(&syn_ack_with_options(&syn, 5000, &options));
+        assert!(stack.tcp_srtt(&conn_id).is_some());
+
+        // The handshake ACK carries NOP NOP TS with TSecr = the peer's TSval
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        let opts = ack_tcp.options.as_slice();
+        assert_eq!(opts.len(), TCP_TIMESTAMPS_OPTION_LEN as usize);
+        assert_eq!(&opts[..4], &[1, 1, TCP_OPTION_TIMESTAMPS, 10]);
+        assert_eq!(&opts[8..], &server_tsval.to_be_bytes());
+        assert!(u32::from_be_bytes(opts[4..8].try_into().unwrap()).wrapping_sub(syn_tsval) as i32 >= 0);
+
+        // Data segments leave room for the option within the MSS
+        let data = vec![0u8; stack.mss() as usize];
+        stack.tcp_send(&conn_id, &data).unwrap();
+        assert_eq!(stack.take_outgoing_packets().len(), 2);
+    }
+
+    #[test]
+    fn test_timestamps_not_sent_unless_negotiated() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert!(ack_tcp.options.as_slice().is_empty());
+        assert_eq!(stack.tcp_srtt(&conn_id), None);
+    }
+
+    #[test]
+    fn test_window_scale_negotiated_and_applied() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let rcv_window = stack.mss() as u32 * TCP_APP_CHANNEL_SEGMENTS as u32;
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+
+        // SYN offers our shift with an unscaled window
+        let (_, tcp) = Ipv4Header::from_slice(&syn).unwrap();
+        let (syn_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(parse_window_scale_option(syn_tcp.options.as_slice()), Some(TCP_WINDOW_SCALE_SHIFT));
+        assert_eq!(syn_tcp.window_size, 65535);
+
+        // Peer answers with its own shift; our window is now scaled
+        let options = [1, TCP_OPTION_WINDOW_SCALE, 3, 8];
+        stack.process_incoming_packet(&syn_ack_with_options(&syn, 5000, &options));
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(ack_tcp.window_size as u32, rcv_window >> TCP_WINDOW_SCALE_SHIFT);
+
+        // The SYN-ACK's window is taken as is, later ones with the peer's shift
+        let snd_wnd = |stack: &VirtualStack| stack.tcp_connections.lock().get(&conn_id).unwrap().snd_wnd;
+        assert_eq!(snd_wnd(&stack), 65535);
+        stack.process_incoming_packet(&peer_segment(&ack, 5001, Some(ack_tcp.sequence_number), false));
+        assert_eq!(snd_wnd(&stack), 65535 << 8);
+    }
+
+    #[test]
+    fn test_window_not_scaled_unless_negotiated() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (_conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_tcp, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(ack_tcp.window_size, 65535);
+
+        assert_eq!(parse_window_scale_option(&[TCP_OPTION_WINDOW_SCALE, 3, 20]), Some(TCP_MAX_WINDOW_SCALE));
+    }
+
+    #[test]
+    fn test_rto_follows_smoothed_rtt() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let mut conns = stack.tcp_connections.lock();
+        let tcb = conns.get_mut(&conn_id).unwrap();
+        assert_eq!(tcb.base_rto(), TCP_INITIAL_RTO);
+
+        tcb.update_rtt(Duration::from_millis(100));
+        assert_eq!(tcb.srtt, Some(Duration::from_millis(100)));
+        assert_eq!(tcb.base_rto(), Duration::from_millis(300));
+
+        // Steady low RTT converges toward the floor
+        for _ in 0..50 {
+            tcb.update_rtt(Duration::from_millis(10));
+        }
+        assert_eq!(tcb.base_rto(), TCP_MIN_RTO);
+    }
+
+    #[test]
+    fn test_binary_payload_delivered_byte_exact() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        let ack = stack.take_outgoing_packets().remove(0);
+        assert!(stack.is_tcp_established(&conn_id));
+
+        // PNG signature followed by every byte value, including invalid UTF-8
+        let mut blob = vec![0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+        blob.extend(0..=255u8);
+
+        let (_, local_tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (local, _) = TcpHeader::from_slice(local_tcp).unwrap();
+        stack.process_incoming_packet(&peer_data_segment(&ack, 5001, Some(local.sequence_number), false, &blob));
+        assert_eq!(rx.try_recv().unwrap(), blob);
+    }
+
+    #[test]
+    fn test_port_allocation_skips_time_wait() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).with_port_range(50000..=50001);
+        let remote = Ipv4Addr::new(10, 0, 0, 1);
+
+        let (lingering, _rx_a) = stack.tcp_connect(remote, 47984);
+        assert_eq!(lingering.local_port, 50000);
+        stack.tcp_connections.lock().get_mut(&lingering).unwrap().state = TcpState::TimeWait;
+
+        let (closed, _rx_b) = stack.tcp_connect(remote, 47984);
+        assert_eq!(closed.local_port, 50001);
+        stack.tcp_connections.lock().remove(&closed);
+
+        // The allocator wraps to 50000, which is still held by the TimeWait entry
+        let (fresh, _rx_c) = stack.tcp_connect(remote, 47984);
+        assert_eq!(fresh.local_port, 50001);
+        assert_eq!(stack.get_tcp_state(&lingering), Some(TcpState::TimeWait));
+    }
+
+    #[test]
+    fn test_half_close_relays_server_data_until_fin() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+
+        stack.tcp_shutdown_write(&conn_id).unwrap();
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::FinWait1));
+        let our_fin = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&our_fin).unwrap();
+        let (fin_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert!(fin_header.fin);
+        let fin_acked = Some(fin_header.sequence_number.wrapping_add(1));
+
+        // The server acknowledges our FIN along with more response data
+        stack.process_incoming_packet(&peer_data_segment(&our_fin, 5001, fin_acked, false, b"part one;"));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::FinWait2));
+        assert_eq!(rx.try_recv().unwrap(), b"part one;");
+
+        // ...keeps sending while we are half-closed
+        stack.process_incoming_packet(&peer_data_segment(&our_fin, 5010, fin_acked, false, b"part two;"));
+        assert_eq!(rx.try_recv().unwrap(), b"part two;");
+        let ack = stack.take_outgoing_packets().pop().unwrap();
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(ack_header.acknowledgment_number, 5019);
+
+        // ...and finally FINs with its last bytes, which ends the stream
+        stack.process_incoming_packet(&peer_data_segment(&our_fin, 5019, fin_acked, true, b"end"));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::TimeWait));
+        assert_eq!(rx.try_recv().unwrap(), b"end");
+        assert!(rx.try_recv().unwrap().is_empty());
+        let ack = stack.take_outgoing_packets().pop().unwrap();
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert_eq!(ack_header.acknowledgment_number, 5023);
+    }
+
+    #[test]
+    fn test_lost_fin_is_retransmitted() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+        assert_eq!(stack.draining_connection_count(), 0);
+
+        // Our FIN is lost on the way
+        stack.tcp_close(&conn_id).unwrap();
+        assert_eq!(stack.draining_connection_count(), 1);
+        let our_fin = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&our_fin).unwrap();
+        let (fin_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        let our_fin_seq = fin_header.sequence_number;
+
+        // An ACK that doesn't cover the FIN leaves it outstanding
+        stack.process_incoming_packet(&peer_segment(&our_fin, 5001, Some(our_fin_seq), false));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::FinWait1));
+
+        stack.tcp_connections.lock().get_mut(&conn_id).unwrap().rto = Duration::ZERO;
+        assert_eq!(stack.check_retransmissions(), 1);
+        let resent = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&resent).unwrap();
+        let (resent_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert!(resent_header.fin);
+        assert_eq!(resent_header.sequence_number, our_fin_seq);
+
+        // Once acknowledged, the FIN is no longer retransmitted
+        stack.process_incoming_packet(&peer_segment(&our_fin, 5001, Some(our_fin_seq.wrapping_add(1)), false));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::FinWait2));
+        assert_eq!(stack.draining_connection_count(), 0);
+        stack.tcp_connections.lock().get_mut(&conn_id).unwrap().rto = Duration::ZERO;
+        assert_eq!(stack.check_retransmissions(), 0);
+    }
+
+    #[test]
+    fn test_simultaneous_close() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+
+        stack.tcp_close(&conn_id).unwrap();
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::FinWait1));
+        let our_fin = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&our_fin).unwrap();
+        let (fin_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert!(fin_header.fin);
+        let our_fin_seq = fin_header.sequence_number;
+
+        // The peer's FIN crossed ours: it doesn't acknowledge our FIN yet
+        stack.process_incoming_packet(&peer_segment(&our_fin, 5001, Some(our_fin_seq), true));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Closing));
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (_, tcp) = Ipv4Header::from_slice(&ack).unwrap();
+        let (ack_header, _) = TcpHeader::from_slice(tcp).unwrap();
+        assert!(ack_header.ack && !ack_header.fin);
+        assert_eq!(ack_header.acknowledgment_number, 5002);
+
+        // A retransmitted FIN is re-acknowledged without leaving Closing
+        stack.process_incoming_packet(&peer_segment(&our_fin, 5001, Some(our_fin_seq), true));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Closing));
+        assert_eq!(stack.take_outgoing_packets().len(), 1);
+
+        // The ACK of our FIN completes the close
+        stack.process_incoming_packet(&peer_segment(&our_fin, 5002, Some(our_fin_seq.wrapping_add(1)), false));
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::TimeWait));
+        assert!(stack.take_outgoing_packets().is_empty());
+    }
+
+    #[test]
+    fn test_keepalive_unanswered_closes_connection() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).with_keepalive(Some(TcpKeepalive {
+            idle: Duration::ZERO,
+            interval: Duration::ZERO,
+            max_probes: 2,
+        }));
+        let (conn_id, rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        assert!(stack.is_tcp_established(&conn_id));
+        stack.take_outgoing_packets();
+
+        // Probes carry no data and sit one byte behind snd.nxt (the SYN's sequence number)
+        let (_, syn_tcp) = Ipv4Header::from_slice(&syn).unwrap();
+        let (syn_header, _) = TcpHeader::from_slice(syn_tcp).unwrap();
+        for _ in 0..2 {
+            assert_eq!(stack.check_keepalives(), 1);
+            let probe = stack.take_outgoing_packets().remove(0);
+            let (_, tcp) = Ipv4Header::from_slice(&probe).unwrap();
+            let (header, payload) = TcpHeader::from_slice(tcp).unwrap();
+            assert!(header.ack && !header.syn && !header.fin);
+            assert!(payload.is_empty());
+            assert_eq!(header.sequence_number, syn_header.sequence_number);
+            assert!(stack.is_tcp_established(&conn_id));
+        }
+
+        // No answer to either probe: the connection is declared dead
+        assert_eq!(stack.check_keepalives(), 0);
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Closed));
+        assert_eq!(rx.try_recv().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_keepalive_off_by_default() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+        {
+            let mut conns = stack.tcp_connections.lock();
+            let tcb = conns.get_mut(&conn_id).unwrap();
+            tcb.last_activity = Instant::now().checked_sub(TcpKeepalive::DEFAULT.idle).unwrap();
+        }
+
+        assert_eq!(stack.check_keepalives(), 0);
+        assert!(stack.take_outgoing_packets().is_empty());
+        assert!(stack.is_tcp_established(&conn_id));
+    }
+
+    #[test]
+    fn test_unacked_data_stall_closes_connection() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+
+        // Data in flight but within the window: not stalled
+        stack.tcp_send(&conn_id, b"GET /applist HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(stack.check_stalled_connections(), 0);
+
+        // No ACK progress for the whole window
+        {
+            let mut conns = stack.tcp_connections.lock();
+            let tcb = conns.get_mut(&conn_id).unwrap();
+            tcb.last_ack_progress = Instant::now().checked_sub(TCP_STALL_TIMEOUT).unwrap();
+        }
+        assert_eq!(stack.check_stalled_connections(), 1);
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Closed));
+        assert_eq!(rx.try_recv().unwrap(), Vec::<u8>::new());
+
+        // Further sends report the stall rather than a generic state error
+        let err = stack.tcp_send(&conn_id, b"more").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(stack.check_stalled_connections(), 0);
+    }
+
+    /// Sequence number and payload of an outgoing segment
+    fn segment_payload(packet: &[u8]) -> (u32, &[u8]) {
+        let (_, tcp) = Ipv4Header::from_slice(packet).unwrap();
+        let (header, payload) = TcpHeader::from_slice(tcp).unwrap();
+        (header.sequence_number, payload)
+    }
+
+    #[test]
+    fn test_send_limited_to_peer_window() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        let ack = stack.take_outgoing_packets().remove(0);
+        let (isn, _) = segment_payload(&ack);
+
+        // Only the SYN-ACK's 65535-byte window goes out; the rest waits
+        let data: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+        stack.tcp_send(&conn_id, &data).unwrap();
+        let mut sent: Vec<u8> = Vec::new();
+        for packet in stack.take_outgoing_packets() {
+            sent.extend_from_slice(segment_payload(&packet).1);
+        }
+        assert_eq!(sent.len(), 65535);
+
+        // Peer takes it all but closes its window: nothing more is sent
+        let acked = isn.wrapping_add(65535);
+        stack.process_incoming_packet(&peer_segment_with_window(&ack, 5001, Some(acked), false, &[], 0));
+        assert!(stack.take_outgoing_packets().is_empty());
+        assert_eq!(stack.check_retransmissions(), 0);
+
+        // After an RTO the persist timer probes one byte behind snd.nxt
+        {
+            let mut conns = stack.tcp_connections.lock();
+            let tcb = conns.get_mut(&conn_id).unwrap();
+            tcb.last_ack_progress = Instant::now().checked_sub(tcb.rto).unwrap();
+        }
+        assert_eq!(stack.check_retransmissions(), 1);
+        let probe = stack.take_outgoing_packets();
+        assert_eq!(probe.len(), 1);
+        assert_eq!(segment_payload(&probe[0]), (acked.wrapping_sub(1), &[][..]));
+
+        // The window reopens and the rest follows in order
+        stack.process_incoming_packet(&peer_segment_with_window(&ack, 5001, Some(acked), false, &[], 65535));
+        for packet in stack.take_outgoing_packets() {
+            sent.extend_from_slice(segment_payload(&packet).1);
+        }
+        assert_eq!(sent, data);
+    }
+
+    #[test]
+    fn test_zero_window_stall_is_capped() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+        stack.tcp_send(&conn_id, b"GET /applist HTTP/1.1\r\n\r\n").unwrap();
+
+        // A closed window gets longer than TCP_STALL_TIMEOUT to reopen...
+        let set_last_progress = |ago: Duration| {
+            let mut conns = stack.tcp_connections.lock();
+            let tcb = conns.get_mut(&conn_id).unwrap();
+            tcb.snd_wnd = 0;
+            tcb.last_ack_progress = Instant::now().checked_sub(ago).unwrap();
+        };
+        set_last_progress(TCP_STALL_TIMEOUT);
+        assert_eq!(stack.check_stalled_connections(), 0);
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Established));
+
+        // ...but not forever
+        set_last_progress(TCP_ZERO_WINDOW_TIMEOUT);
+        assert_eq!(stack.check_stalled_connections(), 1);
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::Closed));
+        assert_eq!(rx.try_recv().unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn test_tcp_sendv_keeps_buffer_order() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2)).with_mtu(576);
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+        let syn = stack.take_outgoing_packets().remove(0);
+        stack.process_incoming_packet(&syn_ack_for(&syn, 5000));
+        stack.take_outgoing_packets();
+
+        // Many small buffers plus one larger than the MSS
+        let large: Vec<u8> = (0..1000u32).map(|i| i as u8).collect();
+        let buffers: Vec<&[u8]> = vec![b"PUT /cert ", b"HTTP/1.1\r\n", b"\r\n", &large, b"end"];
+        let expected: Vec<u8> = buffers.concat();
+        assert_eq!(stack.tcp_sendv(&conn_id, &buffers).unwrap(), expected.len());
+
+        // Segments carry the concatenation in order, coalesced up to the MSS
+        let segments = stack.take_outgoing_packets();
+        assert_eq!(segments.len(), expected.len().div_ceil(stack.mss() as usize));
+        let mut received = Vec::new();
+        for packet in &segments {
+            let (_, tcp) = Ipv4Header::from_slice(packet).unwrap();
+            let (_, payload) = TcpHeader::from_slice(tcp).unwrap();
+            received.extend_from_slice(payload);
+        }
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    fn test_lost_syn_retry_establishes_connection() {
+        let stack = VirtualStack::new(Ipv4Addr::new(10, 0, 0, 2));
+        let (conn_id, _rx) = stack.tcp_connect(Ipv4Addr::new(10, 0, 0, 1), 47984);
+
+        // First SYN is lost in transit
+        let lost = stack.take_outgoing_packets();
+        assert_eq!(lost.len(), 1);
+        assert_eq!(stack.get_tcp_state(&conn_id), Some(TcpState::SynSent));
+
+        // Retry re-issues the SYN with the original sequence number
+        assert!(stack.resend_syn_if_pending(&conn_id));
+        let retried = stack.take_outgoing_packets();
+        assert_eq!(retried, lost);
+
+        // Peer answers the retried SYN
+        stack.process_incoming_packet(&syn_ack_for(&retried[0], 5000));
+        assert!(stack.is_tcp_established(&conn_id));
+
+        // Nothing left to retry once established
+        stack.take_outgoing_packets();
+        assert!(!stack.resend_syn_if_pending(&conn_id));
+    }
 }
@@ -6,6 +6,13 @@
 //! - Global configuration management (GLOBAL_HTTP_CONFIG)
 //!
 //! HTTP requests go through OkHttp + WgSocket -> wg_socket.rs -> SharedTcpProxy
+//!
+//! There is no buffering HTTP client here: response bodies are streamed. Each TCP
+//! segment reaches Java through WgSocket.read() as it arrives, and OkHttp decodes
+//! chunked transfer-encoding incrementally (e.g. box art via ResponseBody.byteStream()).
+//! Unread data is bounded by the per-connection channel in tun_stack.rs.
+//! Payload bytes are copied into the Java byte[] as received and never decoded
+//! as text on this side, so binary bodies such as PNG box art arrive intact.
 
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
@@ -20,12 +27,36 @@ use parking_lot::Mutex;
 use boringtun::noise::{Tunn, TunnResult};
 use x25519_dalek::{PublicKey, StaticSecret};
 
-use crate::tun_stack::VirtualStack;
+use crate::tun_stack::{TcpKeepalive, VirtualStack};
 
 /// Maximum packet size for WireGuard
 const MAX_PACKET_SIZE: usize = 65535;
 
+/// Longest the shared proxy's handshake waits when the caller has no earlier deadline
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+/// How long to wait for a handshake response before re-sending the initiation
+const HANDSHAKE_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Pause before retrying after the endpoint refused the handshake
+const HANDSHAKE_REFUSED_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Time left before `deadline`, or None once it has passed
+pub(crate) fn time_left(deadline: Instant) -> Option<Duration> {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    (!remaining.is_zero()).then_some(remaining)
+}
+
+fn handshake_timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "WireGuard handshake timed out")
+}
+
 /// WireGuard tunnel configuration
+///
+/// Only carries the tunnel itself: connections opened through it are plain TCP
+/// byte streams. TLS for GameStream HTTPS is terminated in Java (NvHTTP over
+/// WgSocketFactory), whose trust manager pins the paired server certificate, so
+/// TLS versions and certificate pinning are configured there, not here.
 #[derive(Clone)]
 pub struct WgHttpConfig {
     pub private_key: [u8; 32],
@@ -43,6 +74,10 @@ pub struct WgHttpConfig {
 fn resolve_endpoint_all(endpoint: &str) -> io::Result<Vec<SocketAddr>> {
     use std::net::ToSocketAddrs;
 
+    if let Some(scoped) = crate::wireguard_config::parse_scoped_endpoint(endpoint) {
+        return scoped.map(|addr| vec![addr]);
+    }
+
     let mut addrs: Vec<SocketAddr> = endpoint.to_socket_addrs()
         .map_err(|e| io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -142,8 +177,9 @@ fn create_tunnel(config: &WgHttpConfig) -> io::Result<(Box<Tunn>, UdpSocket, Soc
     )))
 }
 
-/// Perform WireGuard handshake with proper continuation and logging
-fn do_handshake(tunnel: &mut Tunn, socket: &UdpSocket) -> io::Result<()> {
+/// Perform WireGuard handshake with proper continuation and logging.
+/// Fails with TimedOut as soon as `deadline` passes, including during retries.
+fn do_handshake(tunnel: &mut Tunn, socket: &UdpSocket, deadline: Instant) -> io::Result<()> {
     let mut buf = vec![0u8; MAX_PACKET_SIZE];
 
     // Initiate handshake with retry for connection refused
@@ -164,7 +200,8 @@ fn do_handshake(tunnel: &mut Tunn, socket: &UdpSocket) -> io::Result<()> {
                             ));
                         }
                         warn!("WG handshake init: connection refused, retry {}/{}", init_retries, MAX_INIT_RETRIES);
-                        std::thread::sleep(Duration::from_millis(500));
+                        let remaining = time_left(deadline).ok_or_else(handshake_timed_out)?;
+                        std::thread::sleep(remaining.min(HANDSHAKE_REFUSED_BACKOFF));
                         continue;
                     }
                     Err(e) => return Err(e),
@@ -184,12 +221,11 @@ fn do_handshake(tunnel: &mut Tunn, socket: &UdpSocket) -> io::Result<()> {
     }
 
     // Wait for response
-    socket.set_read_timeout(Some(Duration::from_secs(2)))?;
     let mut recv_buf = vec![0u8; MAX_PACKET_SIZE];
     let mut dec_buf = vec![0u8; MAX_PACKET_SIZE];
 
-    let start = Instant::now();
-    while start.elapsed() < Duration::from_secs(10) {
+    while let Some(remaining) = time_left(deadline) {
+        socket.set_read_timeout(Some(remaining.min(HANDSHAKE_RETRY_INTERVAL)))?;
         match socket.recv(&mut recv_buf) {
             Ok(n) => {
                 match tunnel.decapsulate(None, &recv_buf[..n], &mut dec_buf) {
@@ -256,17 +292,16 @@ fn do_handshake(tunnel: &mut Tunn, socket: &UdpSocket) -> io::Result<()> {
                     _ => {}
                 }
                 // Small delay before retry to avoid hammering
-                std::thread::sleep(Duration::from_millis(500));
+                if let Some(remaining) = time_left(deadline) {
+                    std::thread::sleep(remaining.min(HANDSHAKE_REFUSED_BACKOFF));
+                }
                 continue;
             }
             Err(e) => return Err(e),
         }
     }
 
-    Err(io::Error::new(
-        io::ErrorKind::TimedOut,
-        "WireGuard handshake timed out",
-    ))
+    Err(handshake_timed_out())
 }
 
 // ============================================================================
@@ -301,11 +336,116 @@ pub fn wg_http_clear_config() {
     *GLOBAL_HTTP_CONFIG.lock() = None;
 }
 
+/// Apply a new tunnel MTU to the HTTP path (e.g. after MTU probing).
+/// Updates the stored config and the running shared proxy's virtual stack, so
+/// in-flight connections segment subsequent sends by the new MSS.
+pub fn wg_http_set_mtu(mtu: u16) {
+    if let Some(ref mut config) = *GLOBAL_HTTP_CONFIG.lock() {
+        config.mtu = mtu;
+    }
+    let proxy = SHARED_TCP_PROXY.lock().clone();
+    if let Some(proxy) = proxy {
+        proxy.virtual_stack.set_mtu(mtu);
+    }
+}
+
+/// Keepalive settings for connections on the shared proxy (see wg_http_set_tcp_keepalive).
+/// On by default here; VirtualStack itself only probes when given settings.
+static TCP_KEEPALIVE: Mutex<Option<TcpKeepalive>> = Mutex::new(Some(TcpKeepalive::DEFAULT));
+
+/// Configure TCP keepalive probing for proxied connections (None disables it).
+/// Applies to the running shared proxy and to proxies created later.
+pub fn wg_http_set_tcp_keepalive(keepalive: Option<TcpKeepalive>) {
+    *TCP_KEEPALIVE.lock() = keepalive;
+    let proxy = SHARED_TCP_PROXY.lock().clone();
+    if let Some(proxy) = proxy {
+        proxy.virtual_stack.set_keepalive(keepalive);
+    }
+}
+
 /// Check if WireGuard HTTP client is configured
 pub fn wg_http_is_configured() -> bool {
     GLOBAL_HTTP_CONFIG.lock().is_some()
 }
 
+/// Whether a background prewarm of the shared proxy is running
+static PREWARM_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Create the shared proxy and complete its handshake in the background, so the
+/// first proxied connection doesn't pay for it. Call after wg_http_set_config.
+///
+/// Idempotent: does nothing if the proxy is already running or being prewarmed.
+/// Also a no-op while the streaming tunnel is active, since the proxy then routes
+/// through it and has no handshake of its own. Returns true if a prewarm started.
+pub fn wg_http_prewarm_proxy() -> bool {
+    if crate::wireguard::wg_is_tunnel_active() {
+        debug!("Shared proxy prewarm skipped: streaming tunnel active");
+        return false;
+    }
+    if wg_http_shared_proxy_active() {
+        return false;
+    }
+    let config = match GLOBAL_HTTP_CONFIG.lock().clone() {
+        Some(config) => config,
+        None => {
+            warn!("Shared proxy prewarm skipped: HTTP config not set");
+            return false;
+        }
+    };
+    if PREWARM_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+        return false;
+    }
+
+    let spawned = thread::Builder::new()
+        .name("wg-tcp-proxy-prewarm".into())
+        .spawn(move || {
+            let started = Instant::now();
+            // A request arriving meanwhile waits on the same lock instead of
+            // starting a second handshake
+            match get_or_create_shared_proxy(&config) {
+                Ok(_) => info!("Shared proxy prewarmed in {}ms", started.elapsed().as_millis()),
+                Err(e) => warn!("Shared proxy prewarm failed: {}", e),
+            }
+            PREWARM_IN_PROGRESS.store(false, Ordering::Release);
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to spawn shared proxy prewarm thread: {}", e);
+        PREWARM_IN_PROGRESS.store(false, Ordering::Release);
+        return false;
+    }
+    true
+}
+
+/// Check whether the shared WG TCP proxy tunnel exists and is running.
+/// Unlike get_or_create_shared_proxy, this never creates the tunnel.
+pub fn wg_http_shared_proxy_active() -> bool {
+    SHARED_TCP_PROXY
+        .lock()
+        .as_ref()
+        .map_or(false, |p| p.running.load(Ordering::Relaxed))
+}
+
+/// List the TCP connections proxied through the shared tunnel, for diagnostics.
+/// Returns `(target_port, local_port, established)` per open WgSocket connection.
+/// Each global lock is held only long enough to snapshot its contents.
+pub fn wg_http_list_proxies() -> Vec<(u16, u16, bool)> {
+    let conn_ids = crate::wg_socket::wg_socket_connection_ids();
+    if conn_ids.is_empty() {
+        return Vec::new();
+    }
+
+    let proxy = SHARED_TCP_PROXY.lock().clone();
+    conn_ids
+        .iter()
+        .map(|id| {
+            let established = proxy
+                .as_ref()
+                .map_or(false, |p| p.virtual_stack.is_tcp_established(id));
+            (id.remote_port, id.local_port, established)
+        })
+        .collect()
+}
+
 /// Inject a received IP packet into the HTTP shared proxy's virtual stack.
 /// This is called by the streaming tunnel when it receives TCP packets.
 /// Cached Arc to avoid locking SHARED_TCP_PROXY on every injected packet.
@@ -337,6 +477,7 @@ pub fn wg_http_inject_packet(packet: &[u8]) {
     };
     proxy.virtual_stack.process_incoming_packet(packet);
     proxy.flush_outgoing();
+    crate::wg_socket::wg_socket_notify_event();
 }
 
 // ============================================================================
@@ -356,6 +497,10 @@ const DDNS_RERESOLVE_TIMEOUT_SECS: u64 = 135;
 /// instead of every loop iteration or waiting the full DDNS_RERESOLVE_TIMEOUT_SECS.
 const DDNS_RETRY_INTERVAL_SECS: u64 = 30;
 
+/// Upper bound on how long stop_shared_proxy waits for connections to drain and
+/// for the receiver/timer threads to exit. Kept short so shutdown stays responsive.
+const PROXY_STOP_TIMEOUT_MS: u64 = 500;
+
 /// Shared WireGuard tunnel and virtual TCP stack for all TCP proxy connections.
 /// Using a single tunnel avoids WG peer endpoint conflicts when multiple
 /// connections use the same key pair.
@@ -376,20 +521,26 @@ pub struct SharedTcpProxy {
     receiver_ready: AtomicBool,
     /// Last successful handshake timestamp
     last_handshake: Mutex<Instant>,
+    /// Whether endpoint_socket/tunnel talk to the real endpoint. False while the
+    /// proxy was created on top of the streaming tunnel (dummy socket).
+    own_endpoint_ready: AtomicBool,
     /// Condvar to wake receiver thread when packets are injected
     inject_notify: std::sync::Condvar,
     /// Mutex used with inject_notify
     inject_mutex: std::sync::Mutex<bool>,
+    /// Receiver and timer thread handles, joined on stop
+    threads: Mutex<Vec<thread::JoinHandle<()>>>,
 }
 
 /// Global shared TCP proxy (single WG tunnel for all connections)
 pub static SHARED_TCP_PROXY: Mutex<Option<Arc<SharedTcpProxy>>> = Mutex::new(None);
 
 impl SharedTcpProxy {
-    /// Create a new shared proxy with WG tunnel and handshake.
+    /// Create a new shared proxy with WG tunnel and handshake, which must
+    /// complete before `handshake_deadline`.
     /// If streaming tunnel is active, skip creating our own WG session -
     /// packets will be routed through the streaming tunnel instead.
-    fn new(config: &WgHttpConfig) -> io::Result<Arc<Self>> {
+    fn new(config: &WgHttpConfig, handshake_deadline: Instant) -> io::Result<Arc<Self>> {
         let streaming_active = crate::wireguard::wg_is_tunnel_active();
 
         // Only create our own tunnel if streaming is not active
@@ -419,7 +570,7 @@ impl SharedTcpProxy {
             info!("Initial endpoint resolution: '{}' -> {}", config.endpoint, endpoint_addr);
 
             // Perform handshake before wrapping in Mutex
-            do_handshake(&mut tun, &sock)?;
+            do_handshake(&mut tun, &sock, handshake_deadline)?;
             info!("Shared WG tunnel handshake completed");
             
             // Flush timer events after handshake
@@ -450,29 +601,35 @@ impl SharedTcpProxy {
             endpoint_socket: Mutex::new(endpoint_socket),
             endpoint_addr: Mutex::new(endpoint_addr),
             config: config.clone(),
-            virtual_stack: VirtualStack::new(tunnel_ipv4),
+            virtual_stack: VirtualStack::new(tunnel_ipv4)
+                .with_mtu(config.mtu)
+                .with_keepalive(*TCP_KEEPALIVE.lock()),
             running: Arc::new(AtomicBool::new(true)),
             receiver_ready: AtomicBool::new(false),
             last_handshake: Mutex::new(Instant::now()),
+            own_endpoint_ready: AtomicBool::new(!streaming_active),
             inject_notify: std::sync::Condvar::new(),
             inject_mutex: std::sync::Mutex::new(false),
+            threads: Mutex::new(Vec::with_capacity(2)),
         });
 
         // Start packet receiver thread
         let proxy_rx = proxy.clone();
-        thread::Builder::new()
+        let rx_handle = thread::Builder::new()
             .name("wg-tcp-proxy-rx".into())
             .spawn(move || {
                 Self::receiver_loop(proxy_rx);
             })?;
+        proxy.threads.lock().push(rx_handle);
 
         // Start timer thread
         let proxy_timer = proxy.clone();
-        thread::Builder::new()
+        let timer_handle = thread::Builder::new()
             .name("wg-tcp-proxy-timer".into())
             .spawn(move || {
                 Self::timer_loop(proxy_timer);
             })?;
+        proxy.threads.lock().push(timer_handle);
 
         // Wait for receiver thread to be ready (up to 500ms)
         let start = Instant::now();
@@ -489,6 +646,7 @@ impl SharedTcpProxy {
     /// Send queued outgoing IP packets through the WG tunnel.
     /// If the streaming tunnel is active, route through it instead to avoid two WG sessions.
     /// Uses batch send for streaming tunnel path to minimize lock contention.
+    /// If the streaming tunnel turns out to be down, the packets go through our own tunnel.
     pub fn flush_outgoing(&self) {
         let packets = self.virtual_stack.take_outgoing_packets();
         if packets.is_empty() {
@@ -498,47 +656,87 @@ impl SharedTcpProxy {
         // Check if we should route through streaming tunnel
         if crate::wireguard::wg_is_tunnel_active() {
             // Batch send through streaming tunnel (single lock acquisition)
-            if let Err(e) = crate::wireguard::wg_send_ip_packets_batch(&packets) {
-                warn!("WG TCP proxy: batch send via streaming tunnel failed: {}", e);
+            match crate::wireguard::wg_send_ip_packets_batch(&packets) {
+                Ok(()) => return,
+                Err(ref e) if crate::wireguard::wg_is_tunnel_down(e) => {
+                    // Streaming stopped between the check and the send
+                    info!("WG TCP proxy: streaming tunnel went down, sending via own tunnel");
+                }
+                Err(e) => {
+                    warn!("WG TCP proxy: batch send via streaming tunnel failed: {}", e);
+                    return;
+                }
             }
-        } else {
-            // Use our own tunnel
-            let mut tunnel = self.tunnel.lock();
-            let endpoint_socket = self.endpoint_socket.lock();
-            let mut buf = vec![0u8; MAX_PACKET_SIZE + 200];
-            let mut timer_flushed = false;
-
-            for packet in &packets {
-                match tunnel.encapsulate(packet, &mut buf) {
-                    TunnResult::WriteToNetwork(data) => {
-                        if let Err(e) = endpoint_socket.send(data) {
-                            warn!("WG TCP proxy: send failed: {}", e);
-                        }
+        }
+
+        if let Err(e) = self.ensure_own_endpoint() {
+            warn!("WG TCP proxy: own tunnel unavailable, dropping {} packets: {}", packets.len(), e);
+            return;
+        }
+        self.send_via_own_tunnel(&packets);
+    }
+
+    /// Connect our own tunnel to the real endpoint if the proxy was created on
+    /// top of the streaming tunnel (which has since stopped). The first
+    /// encapsulate starts the handshake; the timer thread completes it.
+    fn ensure_own_endpoint(&self) -> io::Result<()> {
+        if self.own_endpoint_ready.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let mut endpoint_addr = self.endpoint_addr.lock();
+        // Re-check under the lock: another thread may have connected already
+        if self.own_endpoint_ready.load(Ordering::Acquire) {
+            return Ok(());
+        }
+
+        let (_, socket, addr) = create_tunnel(&self.config)?;
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        *self.endpoint_socket.lock() = socket;
+        *endpoint_addr = addr;
+        *self.last_handshake.lock() = Instant::now();
+        self.own_endpoint_ready.store(true, Ordering::Release);
+        info!("WG TCP proxy: connected own tunnel to {} (streaming tunnel down)", addr);
+        Ok(())
+    }
+
+    /// Encapsulate and send packets through the proxy's own WG session.
+    fn send_via_own_tunnel(&self, packets: &[Vec<u8>]) {
+        let mut tunnel = self.tunnel.lock();
+        let endpoint_socket = self.endpoint_socket.lock();
+        let mut buf = vec![0u8; MAX_PACKET_SIZE + 200];
+        let mut timer_flushed = false;
+
+        for packet in packets {
+            match tunnel.encapsulate(packet, &mut buf) {
+                TunnResult::WriteToNetwork(data) => {
+                    if let Err(e) = endpoint_socket.send(data) {
+                        warn!("WG TCP proxy: send failed: {}", e);
                     }
-                    TunnResult::Done => {
-                        // Flush timers once to advance tunnel state, then retry
-                        if !timer_flushed {
-                            timer_flushed = true;
-                            loop {
-                                match tunnel.update_timers(&mut buf) {
-                                    TunnResult::WriteToNetwork(data) => {
-                                        endpoint_socket.send(data).ok();
-                                    }
-                                    _ => break,
+                }
+                TunnResult::Done => {
+                    // Flush timers once to advance tunnel state, then retry
+                    if !timer_flushed {
+                        timer_flushed = true;
+                        loop {
+                            match tunnel.update_timers(&mut buf) {
+                                TunnResult::WriteToNetwork(data) => {
+                                    endpoint_socket.send(data).ok();
                                 }
+                                _ => break,
                             }
-                            if let TunnResult::WriteToNetwork(data) = tunnel.encapsulate(packet, &mut buf) {
-                                if let Err(e) = endpoint_socket.send(data) {
-                                    warn!("WG TCP proxy: send failed (retry): {}", e);
-                                }
+                        }
+                        if let TunnResult::WriteToNetwork(data) = tunnel.encapsulate(packet, &mut buf) {
+                            if let Err(e) = endpoint_socket.send(data) {
+                                warn!("WG TCP proxy: send failed (retry): {}", e);
                             }
                         }
                     }
-                    TunnResult::Err(e) => {
-                        warn!("WG TCP proxy: encapsulate error: {:?}", e);
-                    }
-                    _ => {}
                 }
+                TunnResult::Err(e) => {
+                    warn!("WG TCP proxy: encapsulate error: {:?}", e);
+                }
+                _ => {}
             }
         }
     }
@@ -618,9 +816,13 @@ impl SharedTcpProxy {
                     }
 
                     // Process IP packets through virtual stack (tunnel lock released)
+                    let delivered = !ip_packets.is_empty();
                     for packet in ip_packets {
                         proxy.virtual_stack.process_incoming_packet(&packet);
                     }
+                    if delivered {
+                        crate::wg_socket::wg_socket_notify_event();
+                    }
 
                     // Flush any outgoing packets generated by processing (e.g., ACKs)
                     proxy.flush_outgoing();
@@ -668,6 +870,7 @@ impl SharedTcpProxy {
             let mut endpoint_socket = self.endpoint_socket.lock();
             *endpoint_socket = new_socket;
             *current_addr = new_addr;
+            self.own_endpoint_ready.store(true, Ordering::Release);
 
             info!("DDNS: reconnected to new endpoint {}", new_addr);
         } else {
@@ -690,7 +893,16 @@ impl SharedTcpProxy {
         let mut was_sleeping = false;
 
         while proxy.running.load(Ordering::Relaxed) {
-            thread::sleep(Duration::from_secs(1));
+            // Sleep in short slices so stop() is noticed without waiting a full second
+            for _ in 0..10 {
+                if !proxy.running.load(Ordering::Relaxed) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(100));
+            }
+            if !proxy.running.load(Ordering::Relaxed) {
+                break;
+            }
 
             // Skip WG timer updates when streaming tunnel is active
             // (streaming tunnel handles its own timers, we just handle connection cleanup)
@@ -813,9 +1025,12 @@ impl SharedTcpProxy {
                 }
             }
 
-            // Check for TCP data retransmissions every second
+            // Check for TCP data retransmissions, stalled transfers and
+            // idle-connection keepalives every second
             let retransmitted = proxy.virtual_stack.check_retransmissions();
-            if retransmitted > 0 {
+            proxy.virtual_stack.check_stalled_connections();
+            let probes = proxy.virtual_stack.check_keepalives();
+            if retransmitted > 0 || probes > 0 {
                 proxy.flush_outgoing();
             }
         }
@@ -826,13 +1041,60 @@ impl SharedTcpProxy {
         // Wake receiver thread if blocked on inject_notify
         self.inject_notify.notify_all();
     }
+
+    /// Wait until every FIN we sent has been acknowledged, or until `deadline`.
+    /// Background threads keep running meanwhile so ACKs are still processed.
+    fn wait_for_drain(&self, deadline: Instant) -> bool {
+        loop {
+            self.flush_outgoing();
+            if self.virtual_stack.draining_connection_count() == 0 {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Join the receiver/timer threads, giving up on any still running at `deadline`.
+    /// Returns true if all threads exited.
+    fn join_threads(&self, deadline: Instant) -> bool {
+        let handles: Vec<thread::JoinHandle<()>> = self.threads.lock().drain(..).collect();
+        let mut all_joined = true;
+        for handle in handles {
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            if handle.is_finished() {
+                handle.join().ok();
+            } else {
+                // Detach: the thread exits on its own once it observes running == false
+                all_joined = false;
+            }
+        }
+        all_joined
+    }
 }
 
 /// Get or create the shared WG tunnel for TCP proxying.
 /// When streaming tunnel is active, the shared proxy routes through it instead of
 /// creating its own WG session.
 pub fn get_or_create_shared_proxy(config: &WgHttpConfig) -> io::Result<Arc<SharedTcpProxy>> {
-    let mut shared = SHARED_TCP_PROXY.lock();
+    get_or_create_shared_proxy_before(config, Instant::now() + Duration::from_secs(HANDSHAKE_TIMEOUT_SECS))
+}
+
+/// Like get_or_create_shared_proxy, but fails with TimedOut once `deadline`
+/// passes, whether waiting for another caller's handshake or doing its own
+/// (which never runs longer than HANDSHAKE_TIMEOUT_SECS either).
+pub fn get_or_create_shared_proxy_before(
+    config: &WgHttpConfig,
+    deadline: Instant,
+) -> io::Result<Arc<SharedTcpProxy>> {
+    // Another caller may hold the lock for a whole handshake
+    let mut shared = SHARED_TCP_PROXY.try_lock_until(deadline).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::TimedOut, "Timed out waiting for the shared WG tunnel")
+    })?;
     if let Some(ref proxy) = *shared {
         if proxy.running.load(Ordering::Relaxed) {
             return Ok(proxy.clone());
@@ -840,22 +1102,102 @@ pub fn get_or_create_shared_proxy(config: &WgHttpConfig) -> io::Result<Arc<Share
     }
 
     info!("Creating shared WG tunnel for TCP proxy");
-    let proxy = SharedTcpProxy::new(config)?;
+    let deadline = deadline.min(Instant::now() + Duration::from_secs(HANDSHAKE_TIMEOUT_SECS));
+    let proxy = SharedTcpProxy::new(config, deadline)?;
     *shared = Some(proxy.clone());
     Ok(proxy)
 }
 
 /// Stop the shared WireGuard tunnel.
 /// Called when WireGuard is disabled or when the streaming tunnel starts.
+///
+/// Waits up to PROXY_STOP_TIMEOUT_MS for in-flight FINs to be acknowledged and for
+/// the background threads to exit, so a rapid stop/start does not race the old
+/// tunnel against the one created by get_or_create_shared_proxy.
 pub fn stop_shared_proxy() {
+    let deadline = Instant::now() + Duration::from_millis(PROXY_STOP_TIMEOUT_MS);
+
+    // Let active connections drain while the proxy is still running.
+    // Clone the Arc so the global lock isn't held while waiting on the peer.
+    let draining = SHARED_TCP_PROXY.lock().clone();
+    if let Some(proxy) = draining {
+        if !proxy.wait_for_drain(deadline) {
+            warn!("Shared WG TCP proxy: {} connection(s) still draining at stop",
+                  proxy.virtual_stack.draining_connection_count());
+        }
+    }
+
     // Clear inject cache first
     *INJECT_PROXY_CACHE.lock() = None;
 
+    // Hold the global lock until the threads exit so a concurrent
+    // get_or_create_shared_proxy can't start a new tunnel alongside them.
     let mut shared = SHARED_TCP_PROXY.lock();
-    if let Some(ref proxy) = *shared {
+    if let Some(proxy) = shared.take() {
         proxy.stop();
-        info!("Stopped shared WG TCP proxy tunnel");
+        if proxy.join_threads(deadline) {
+            info!("Stopped shared WG TCP proxy tunnel");
+        } else {
+            warn!("Stopped shared WG TCP proxy tunnel (threads still exiting after {}ms)",
+                  PROXY_STOP_TIMEOUT_MS);
+        }
     }
-    *shared = None;
+    drop(shared);
+    // Sockets on the stopped tunnel now read as hung up
+    crate::wg_socket::wg_socket_invalidate_handles();
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake_honors_deadline() {
+        // An endpoint that never answers
+        let endpoint = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(endpoint.local_addr().unwrap()).unwrap();
+
+        let peer = StaticSecret::from([7u8; 32]);
+        let mut tunnel = Tunn::new(StaticSecret::from([9u8; 32]), PublicKey::from(&peer), None, None, 0, None);
+
+        // Fails at the deadline rather than after the full retry schedule
+        let start = Instant::now();
+        let err = do_handshake(&mut tunnel, &socket, start + Duration::from_millis(300)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert!(start.elapsed() < HANDSHAKE_RETRY_INTERVAL);
+
+        assert_eq!(time_left(start), None);
+        assert!(time_left(Instant::now() + Duration::from_secs(5)).is_some());
+    }
+
+    #[test]
+    fn test_concurrent_deadline_connects() {
+        // An endpoint that never answers, so the first caller sits in its handshake
+        let endpoint = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = WgHttpConfig {
+            private_key: [9u8; 32],
+            peer_public_key: PublicKey::from(&StaticSecret::from([7u8; 32])).to_bytes(),
+            preshared_key: None,
+            endpoint: endpoint.local_addr().unwrap().to_string(),
+            tunnel_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)),
+            server_ip: IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+            mtu: 1420,
+        };
+
+        let first_config = config.clone();
+        let first = std::thread::spawn(move || {
+            get_or_create_shared_proxy_before(&first_config, Instant::now() + Duration::from_millis(1500)).err()
+        });
+        std::thread::sleep(Duration::from_millis(100));
+
+        // The second caller gives up at its own deadline instead of waiting out the first handshake
+        let start = Instant::now();
+        let err = get_or_create_shared_proxy_before(&config, start + Duration::from_millis(200)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_millis(1000));
+
+        assert_eq!(first.join().unwrap().map(|e| e.kind()), Some(io::ErrorKind::TimedOut));
+    }
+}
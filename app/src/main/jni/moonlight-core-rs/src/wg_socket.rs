@@ -8,30 +8,160 @@
 //! Java OkHttp                           Rust WireGuard tunnel
 //!   WgSocket.connect() ---JNI---> wg_socket_connect() ---> VirtualStack.tcp_connect()
 //!   WgSocket.read()    ---JNI---> wg_socket_recv()    ---> channel.recv()
+//!   WgSocket.readAvailable() -JNI-> wg_socket_recv_available() -> channel.try_recv()
 //!   WgSocket.write()   ---JNI---> wg_socket_send()    ---> VirtualStack.tcp_send()
+//!   WgSocket.writev()  ---JNI---> wg_socket_sendv()   ---> VirtualStack.tcp_sendv()
 //!   WgSocket.close()   ---JNI---> wg_socket_close()   ---> VirtualStack.tcp_close()
 //! ```
 //!
+//! Each handle is stamped with the tunnel generation it was opened on. The
+//! generation moves on whenever the connections' tunnel goes away: the shared proxy
+//! stopping, the HTTP config being cleared, or the streaming tunnel restarting.
+//! Handles from an older generation stay in the map until Java touches them, and
+//! operations on them return WG_SOCKET_TUNNEL_RESTARTED so the Java side
+//! reconnects instead of treating it as a data error.
+//!
+//! Blocking bounds: a connect waits for the shared tunnel's handshake (up to 10s)
+//! and the TCP handshake within its timeout, and each recv waits up to its own
+//! timeout (5 minutes by default), so one HTTP request can block its thread far
+//! longer than a UI can wait. wg_socket_connect_with_deadline sets one hard
+//! deadline for the connect and every read on the connection instead.
+//!
 //! IMPORTANT: The global SOCKET_CONNECTIONS lock is only held briefly for map lookups.
 //! Blocking I/O (recv_timeout) is done on Arc-wrapped per-connection state, outside the
 //! global lock, to avoid deadlocking OkHttp's concurrent read/write threads.
 
 use std::collections::HashMap;
+use std::io;
 use std::net::IpAddr;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc::{Receiver, RecvTimeoutError};
 use std::sync::Arc;
+use std::thread;
 use std::time::{Duration, Instant};
 
 use log::{debug, error, info, warn};
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
 use crate::tun_stack::{TcpConnectionId, TcpState};
-use crate::wg_http::{get_or_create_shared_proxy, GLOBAL_HTTP_CONFIG};
+use crate::wg_http::{
+    get_or_create_shared_proxy, get_or_create_shared_proxy_before, time_left, GLOBAL_HTTP_CONFIG,
+};
+
+/// Returned by recv, send, shutdown and close when the tunnel the handle was opened
+/// on has since been stopped or restarted. The connection no longer exists; open a
+/// new one.
+pub const WG_SOCKET_TUNNEL_RESTARTED: i32 = -4;
 
 /// Handle counter for socket connections
 static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(1);
 
+/// Generation of the tunnel new connections are opened on (see wg_socket_invalidate_handles)
+static TUNNEL_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+/// Default delay before the first SYN retransmission while connecting
+const DEFAULT_SYN_RETRY_INITIAL_MS: u64 = 500;
+
+/// Default cap for the exponential SYN retransmission backoff
+const DEFAULT_SYN_RETRY_MAX_MS: u64 = 4000;
+
+/// Delay before the first SYN retransmission (see wg_socket_set_connect_retry)
+static SYN_RETRY_INITIAL_MS: AtomicU64 = AtomicU64::new(DEFAULT_SYN_RETRY_INITIAL_MS);
+
+/// Cap for the SYN retransmission backoff (see wg_socket_set_connect_retry)
+static SYN_RETRY_MAX_MS: AtomicU64 = AtomicU64::new(DEFAULT_SYN_RETRY_MAX_MS);
+
+/// Configure SYN retransmission while connecting.
+///
+/// If the connection isn't established within `initial_ms`, the SYN is re-issued,
+/// doubling the interval up to `max_ms`, until the overall connect timeout. A short
+/// initial interval recovers quickly from a single lost SYN on lossy (mobile) links.
+/// Zero values restore the defaults.
+pub fn wg_socket_set_connect_retry(initial_ms: u64, max_ms: u64) {
+    let initial_ms = if initial_ms == 0 { DEFAULT_SYN_RETRY_INITIAL_MS } else { initial_ms };
+    let max_ms = if max_ms == 0 { DEFAULT_SYN_RETRY_MAX_MS } else { max_ms.max(initial_ms) };
+    SYN_RETRY_INITIAL_MS.store(initial_ms, Ordering::Relaxed);
+    SYN_RETRY_MAX_MS.store(max_ms, Ordering::Relaxed);
+    info!("wg_socket: SYN retry initial={}ms max={}ms", initial_ms, max_ms);
+}
+
+/// Default number of connect attempts (first attempt plus retries)
+const DEFAULT_CONNECT_ATTEMPTS: u32 = 3;
+
+/// Delay before the second connect attempt; doubles for each further attempt
+const CONNECT_RETRY_BACKOFF_MS: u64 = 250;
+
+/// Connect attempts made by wg_socket_connect (see wg_socket_set_connect_attempts)
+static CONNECT_ATTEMPTS: AtomicU32 = AtomicU32::new(DEFAULT_CONNECT_ATTEMPTS);
+
+/// Configure how many times wg_socket_connect tries before giving up.
+///
+/// Transient failures (tunnel handshake timeout, connection refused while the
+/// host is waking up) are retried with exponential backoff, all within the
+/// caller's connect timeout. Other errors fail immediately. 1 disables retries;
+/// 0 restores the default.
+pub fn wg_socket_set_connect_attempts(attempts: u32) {
+    let attempts = if attempts == 0 { DEFAULT_CONNECT_ATTEMPTS } else { attempts };
+    CONNECT_ATTEMPTS.store(attempts, Ordering::Relaxed);
+    info!("wg_socket: connect attempts={}", attempts);
+}
+
+/// Default cap on concurrent connections, counting connects still in progress.
+/// Generous for OkHttp's pool; it only guards against runaway reconnect loops.
+const DEFAULT_MAX_CONNECTIONS: usize = 128;
+
+/// Cap on concurrent connections (see wg_socket_set_max_connections)
+static MAX_CONNECTIONS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_CONNECTIONS);
+
+/// Connections holding a ConnectionSlot: connects in progress plus open handles
+static ACTIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Cap how many connections may be open or connecting through the shared tunnel
+/// at once. Connects beyond the cap fail immediately with a warning, which
+/// bounds the virtual stack's per-connection state on low-end devices.
+/// Takes effect for the next connect; 0 restores the default.
+pub fn wg_socket_set_max_connections(max: usize) {
+    let max = if max == 0 { DEFAULT_MAX_CONNECTIONS } else { max };
+    MAX_CONNECTIONS.store(max, Ordering::Relaxed);
+    info!("wg_socket: max connections={}", max);
+}
+
+/// Connections open or connecting, and the cap: (active, max)
+pub fn wg_socket_connection_usage() -> (usize, usize) {
+    (ACTIVE_CONNECTIONS.load(Ordering::Acquire), MAX_CONNECTIONS.load(Ordering::Relaxed))
+}
+
+/// One unit of the connection cap, released on drop. Held by a connect in
+/// progress and then by its WgSocketConnection, so the count goes down however
+/// the connection ends: failed connect, close or close_all.
+struct ConnectionSlot {
+    counter: &'static AtomicUsize,
+}
+
+impl ConnectionSlot {
+    fn try_acquire() -> Option<Self> {
+        Self::try_acquire_from(&ACTIVE_CONNECTIONS, MAX_CONNECTIONS.load(Ordering::Relaxed))
+    }
+
+    fn try_acquire_from(counter: &'static AtomicUsize, max: usize) -> Option<Self> {
+        counter
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1))
+            .ok()
+            .map(|_| ConnectionSlot { counter })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.counter.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Whether a failed connect attempt is worth repeating
+fn is_retryable_connect_error(kind: io::ErrorKind) -> bool {
+    matches!(kind, io::ErrorKind::TimedOut | io::ErrorKind::ConnectionRefused)
+}
+
 /// Per-connection receive buffer (protected by its own mutex, independent of global map)
 struct RecvBuffer {
     data: Vec<u8>,
@@ -44,17 +174,56 @@ struct RecvBuffer {
 /// Fields wrapped in Arc so they can be used outside the global map lock.
 struct WgSocketConnection {
     conn_id: TcpConnectionId,
+    /// Tunnel generation the connection was opened on
+    generation: u64,
     /// Receiver channel - wrapped in Arc<Mutex> so recv can block without holding global lock
     receiver: Arc<Mutex<Receiver<Vec<u8>>>>,
     /// Per-connection recv buffer - wrapped in Arc<Mutex> for the same reason
     recv_buf: Arc<Mutex<RecvBuffer>>,
     _created_at: Instant,
+    /// Hard deadline for reads (see wg_socket_connect_with_deadline), None for none
+    deadline: Option<Instant>,
+    /// Counts this connection against MAX_CONNECTIONS until it is removed from the
+    /// map or invalidated
+    slot: Option<ConnectionSlot>,
 }
 
 /// Global map of socket handles to connections.
 /// IMPORTANT: This lock must only be held briefly for map lookups, never during blocking I/O.
 static SOCKET_CONNECTIONS: Mutex<Option<HashMap<u64, WgSocketConnection>>> = Mutex::new(None);
 
+/// Counts events that may have made a socket readable or hung up: data or EOF
+/// arriving from the tunnel, a close, or the tunnel going away. Pollers remember
+/// the count before checking their sockets and wait for it to change.
+static SOCKET_EVENT_SEQ: Mutex<u64> = Mutex::new(0);
+
+/// Signalled whenever SOCKET_EVENT_SEQ is bumped
+static SOCKET_EVENT_CV: Condvar = Condvar::new();
+
+/// Wake pollers waiting in wg_socket_wait_event
+pub fn wg_socket_notify_event() {
+    *SOCKET_EVENT_SEQ.lock() += 1;
+    SOCKET_EVENT_CV.notify_all();
+}
+
+/// Current event count, to pass to wg_socket_wait_event after checking sockets
+pub fn wg_socket_event_seq() -> u64 {
+    *SOCKET_EVENT_SEQ.lock()
+}
+
+/// Block until an event newer than `seen` arrives or `timeout` elapses.
+/// Returns true if an event arrived.
+pub fn wg_socket_wait_event(seen: u64, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let mut seq = SOCKET_EVENT_SEQ.lock();
+    while *seq == seen {
+        if SOCKET_EVENT_CV.wait_until(&mut seq, deadline).timed_out() {
+            return *seq != seen;
+        }
+    }
+    true
+}
+
 /// Initialize the socket connections map if needed
 fn ensure_connections_map() {
     let mut map = SOCKET_CONNECTIONS.lock();
@@ -63,28 +232,74 @@ fn ensure_connections_map() {
     }
 }
 
+/// A connection's fields, cloned out of the global map so they can be used
+/// without holding its lock
+struct ConnectionArcs {
+    conn_id: TcpConnectionId,
+    generation: u64,
+    receiver: Arc<Mutex<Receiver<Vec<u8>>>>,
+    recv_buf: Arc<Mutex<RecvBuffer>>,
+    deadline: Option<Instant>,
+}
+
 /// Look up a connection and clone its Arc-wrapped fields for use outside the lock.
-fn get_connection_arcs(handle: u64) -> Option<(TcpConnectionId, Arc<Mutex<Receiver<Vec<u8>>>>, Arc<Mutex<RecvBuffer>>)> {
+fn get_connection_arcs(handle: u64) -> Option<ConnectionArcs> {
     let map = SOCKET_CONNECTIONS.lock();
     let connections = map.as_ref()?;
     let conn = connections.get(&handle)?;
-    Some((conn.conn_id, conn.receiver.clone(), conn.recv_buf.clone()))
+    Some(ConnectionArcs {
+        conn_id: conn.conn_id,
+        generation: conn.generation,
+        receiver: conn.receiver.clone(),
+        recv_buf: conn.recv_buf.clone(),
+        deadline: conn.deadline,
+    })
+}
+
+/// Whether the tunnel a connection was opened on has gone away since
+fn tunnel_restarted(generation: u64) -> bool {
+    TUNNEL_GENERATION.load(Ordering::Acquire) != generation
+}
+
+/// Mark every open handle as belonging to a tunnel that is gone. Called when the
+/// shared proxy stops or the streaming tunnel restarts. The handles stay
+/// registered, so Java's next call on each returns WG_SOCKET_TUNNEL_RESTARTED
+/// rather than "unknown handle", but no longer count against the connection cap.
+pub fn wg_socket_invalidate_handles() {
+    let generation = TUNNEL_GENERATION.fetch_add(1, Ordering::AcqRel) + 1;
+    let invalidated = {
+        let mut map = SOCKET_CONNECTIONS.lock();
+        map.as_mut().map_or(0, |connections| {
+            connections.values_mut().filter_map(|conn| conn.slot.take()).count()
+        })
+    };
+    if invalidated > 0 {
+        info!("wg_socket: tunnel generation {}, {} open handle(s) invalidated", generation, invalidated);
+    }
+    // Pollers must see the stale handles hang up
+    wg_socket_notify_event();
 }
 
 /// Create a TCP connection through WireGuard VirtualStack.
+/// Transient failures are retried (see wg_socket_set_connect_attempts) as long
+/// as `timeout_ms` allows; the timeout also bounds the tunnel handshake.
 /// Returns a handle (>0) on success, 0 on failure.
 pub fn wg_socket_connect(host: &str, port: u16, timeout_ms: u32) -> u64 {
     info!("wg_socket_connect: {}:{} (timeout={}ms)", host, port, timeout_ms);
+    connect(host, port, Instant::now() + Duration::from_millis(timeout_ms as u64), false)
+}
 
-    // Get config
-    let config = match GLOBAL_HTTP_CONFIG.lock().clone() {
-        Some(c) => c,
-        None => {
-            error!("wg_socket_connect: WireGuard HTTP not configured");
-            return 0;
-        }
-    };
+/// Create a TCP connection whose tunnel handshake, TCP connect and every later
+/// read must finish within `deadline` from now. Once it passes, reads return the
+/// timeout code (-2) immediately instead of waiting out their own timeout, which
+/// gives the calling thread a predictable worst case. Returns a handle (>0) on
+/// success, 0 on failure (including running out of time).
+pub fn wg_socket_connect_with_deadline(host: &str, port: u16, deadline: Duration) -> u64 {
+    info!("wg_socket_connect: {}:{} (deadline={:?})", host, port, deadline);
+    connect(host, port, Instant::now() + deadline, true)
+}
 
+fn connect(host: &str, port: u16, deadline: Instant, keep_deadline: bool) -> u64 {
     // Parse host as IP address (IPv4 or IPv6)
     let target_ip: IpAddr = match host.parse() {
         Ok(ip) => ip,
@@ -94,15 +309,80 @@ pub fn wg_socket_connect(host: &str, port: u16, timeout_ms: u32) -> u64 {
         }
     };
 
-    // Get the shared proxy (handles WG tunnel creation/reuse)
-    let proxy = match get_or_create_shared_proxy(&config) {
-        Ok(p) => p,
-        Err(e) => {
-            error!("wg_socket_connect: failed to get shared proxy: {}", e);
+    let slot = match ConnectionSlot::try_acquire() {
+        Some(slot) => slot,
+        None => {
+            let (active, max) = wg_socket_connection_usage();
+            warn!("wg_socket_connect: {}:{} rejected, {} connections already open or connecting (max {})",
+                  target_ip, port, active, max);
             return 0;
         }
     };
 
+    let attempts = CONNECT_ATTEMPTS.load(Ordering::Relaxed).max(1);
+    let mut backoff = Duration::from_millis(CONNECT_RETRY_BACKOFF_MS);
+    let mut attempt = 1;
+    // Taken before connecting, so a restart during the connect leaves the handle stale
+    let generation = TUNNEL_GENERATION.load(Ordering::Acquire);
+
+    let (conn_id, rx) = loop {
+        match connect_once(target_ip, port, deadline) {
+            Ok(established) => break established,
+            Err(e) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if attempt >= attempts || !is_retryable_connect_error(e.kind()) || remaining <= backoff {
+                    error!("wg_socket_connect: {}:{} failed after {} attempt(s): {}",
+                           target_ip, port, attempt, e);
+                    return 0;
+                }
+                warn!("wg_socket_connect: attempt {}/{} to {}:{} failed: {}, retrying in {:?}",
+                      attempt, attempts, target_ip, port, e, backoff);
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    };
+
+    // Connection established - create handle
+    let handle = HANDLE_COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let connection = WgSocketConnection {
+        conn_id,
+        generation,
+        receiver: Arc::new(Mutex::new(rx)),
+        recv_buf: Arc::new(Mutex::new(RecvBuffer {
+            data: Vec::new(),
+            pos: 0,
+            eof: false,
+        })),
+        _created_at: Instant::now(),
+        deadline: keep_deadline.then_some(deadline),
+        slot: Some(slot),
+    };
+
+    ensure_connections_map();
+    SOCKET_CONNECTIONS.lock().as_mut().unwrap().insert(handle, connection);
+
+    info!("wg_socket_connect: established connection to {}:{}, handle={}", target_ip, port, handle);
+    handle
+}
+
+/// One connect attempt: get the shared tunnel and complete the TCP handshake
+/// before `deadline`. Errors carry the kind used to decide on a retry.
+fn connect_once(
+    target_ip: IpAddr,
+    port: u16,
+    deadline: Instant,
+) -> io::Result<(TcpConnectionId, Receiver<Vec<u8>>)> {
+    // Get config
+    let config = GLOBAL_HTTP_CONFIG.lock().clone().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotConnected, "WireGuard HTTP not configured")
+    })?;
+
+    // Get the shared proxy (handles WG tunnel creation/reuse)
+    let proxy = get_or_create_shared_proxy_before(&config, deadline)?;
+
     // Initiate TCP connection through virtual stack
     let (conn_id, rx) = proxy.virtual_stack.tcp_connect(target_ip, port);
 
@@ -110,21 +390,20 @@ pub fn wg_socket_connect(host: &str, port: u16, timeout_ms: u32) -> u64 {
     proxy.flush_outgoing();
 
     // Wait for connection establishment with SYN retransmission
-    let connect_timeout = Duration::from_millis(timeout_ms as u64);
     let start = Instant::now();
-    
-    // SYN retransmission with exponential backoff: 500ms, 1s, 2s, 4s...
-    let mut next_syn_retry = start + Duration::from_millis(500);
-    let mut syn_retry_interval = Duration::from_millis(500);
-    let max_syn_retry_interval = Duration::from_secs(4);
+
+    // SYN retransmission with exponential backoff (default: 500ms, 1s, 2s, 4s...)
+    let mut syn_retry_interval = Duration::from_millis(SYN_RETRY_INITIAL_MS.load(Ordering::Relaxed));
+    let max_syn_retry_interval = Duration::from_millis(SYN_RETRY_MAX_MS.load(Ordering::Relaxed));
+    let mut next_syn_retry = start + syn_retry_interval;
 
     while !proxy.virtual_stack.is_tcp_established(&conn_id) {
-        let remaining = connect_timeout.saturating_sub(start.elapsed());
+        let remaining = deadline.saturating_duration_since(Instant::now());
         if remaining.is_zero() {
             let state = proxy.virtual_stack.get_tcp_state(&conn_id);
             warn!("wg_socket_connect: timeout after {:?}, state: {:?}", start.elapsed(), state);
             proxy.virtual_stack.remove_tcp_connection(&conn_id);
-            return 0;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "TCP connect timed out"));
         }
 
         // Check for connection reset/refused
@@ -132,7 +411,7 @@ pub fn wg_socket_connect(host: &str, port: u16, timeout_ms: u32) -> u64 {
             Some(TcpState::Closed) | None => {
                 warn!("wg_socket_connect: connection to {}:{} refused", target_ip, port);
                 proxy.virtual_stack.remove_tcp_connection(&conn_id);
-                return 0;
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "TCP connection refused"));
             }
             _ => {}
         }
@@ -153,25 +432,7 @@ pub fn wg_socket_connect(host: &str, port: u16, timeout_ms: u32) -> u64 {
         proxy.virtual_stack.wait_for_state_change(wait_time);
     }
 
-    // Connection established - create handle
-    let handle = HANDLE_COUNTER.fetch_add(1, Ordering::Relaxed);
-
-    let connection = WgSocketConnection {
-        conn_id,
-        receiver: Arc::new(Mutex::new(rx)),
-        recv_buf: Arc::new(Mutex::new(RecvBuffer {
-            data: Vec::new(),
-            pos: 0,
-            eof: false,
-        })),
-        _created_at: Instant::now(),
-    };
-
-    ensure_connections_map();
-    SOCKET_CONNECTIONS.lock().as_mut().unwrap().insert(handle, connection);
-
-    info!("wg_socket_connect: established connection to {}:{}, handle={}", target_ip, port, handle);
-    handle
+    Ok((conn_id, rx))
 }
 
 /// Get the local port allocated for this connection
@@ -186,7 +447,9 @@ pub fn wg_socket_get_local_port(handle: u64) -> u16 {
 }
 
 /// Receive data from a connection.
-/// Returns bytes read, 0 on EOF, -1 on error, -2 on timeout.
+/// Returns bytes read, 0 on EOF, -1 on error, -2 on timeout, or
+/// WG_SOCKET_TUNNEL_RESTARTED once buffered data is drained from a connection
+/// whose tunnel is gone.
 ///
 /// CRITICAL: This function must NOT hold the global SOCKET_CONNECTIONS lock while blocking
 /// on recv_timeout(), because OkHttp reads and writes on separate threads and both need
@@ -194,13 +457,14 @@ pub fn wg_socket_get_local_port(handle: u64) -> u16 {
 /// then block only on the per-connection mutex.
 pub fn wg_socket_recv(handle: u64, buffer: &mut [u8], timeout_ms: u32) -> i32 {
     // Step 1: Briefly lock global map to get Arc refs, then release
-    let (receiver_arc, recv_buf_arc) = match get_connection_arcs(handle) {
-        Some((_conn_id, rx, buf)) => (rx, buf),
-        None => {
-            error!("wg_socket_recv: invalid handle {}", handle);
-            return -1;
-        }
-    };
+    let ConnectionArcs { generation, receiver: receiver_arc, recv_buf: recv_buf_arc, deadline, .. } =
+        match get_connection_arcs(handle) {
+            Some(arcs) => arcs,
+            None => {
+                error!("wg_socket_recv: invalid handle {}", handle);
+                return -1;
+            }
+        };
     // Global lock is now released.
 
     // Step 2: Lock only the per-connection recv buffer
@@ -227,15 +491,30 @@ pub fn wg_socket_recv(handle: u64, buffer: &mut [u8], timeout_ms: u32) -> i32 {
         return 0; // EOF
     }
 
+    // Nothing will ever arrive on a connection whose tunnel is gone
+    if tunnel_restarted(generation) {
+        warn!("wg_socket_recv: tunnel restarted since handle {} was opened", handle);
+        return WG_SOCKET_TUNNEL_RESTARTED;
+    }
+
     // Step 3: Lock per-connection receiver and block on channel recv
     // (recv_buf is still held, which is fine - only one reader at a time)
     let receiver = receiver_arc.lock();
 
-    let timeout = if timeout_ms > 0 {
+    let mut timeout = if timeout_ms > 0 {
         Duration::from_millis(timeout_ms as u64)
     } else {
         Duration::from_secs(300) // Default 5 min timeout
     };
+    if let Some(deadline) = deadline {
+        match time_left(deadline) {
+            Some(remaining) => timeout = timeout.min(remaining),
+            None => {
+                warn!("wg_socket_recv: deadline passed for handle {}", handle);
+                return -2;
+            }
+        }
+    }
 
     match receiver.recv_timeout(timeout) {
         Ok(data) => {
@@ -259,6 +538,10 @@ pub fn wg_socket_recv(handle: u64, buffer: &mut [u8], timeout_ms: u32) -> i32 {
             -2 // Timeout error code
         }
         Err(RecvTimeoutError::Disconnected) => {
+            if tunnel_restarted(generation) {
+                warn!("wg_socket_recv: tunnel restarted while reading handle {}", handle);
+                return WG_SOCKET_TUNNEL_RESTARTED;
+            }
             // Mark EOF so subsequent calls return immediately without logging again
             recv_buf.eof = true;
             debug!("wg_socket_recv: channel disconnected for handle {}", handle);
@@ -267,78 +550,222 @@ pub fn wg_socket_recv(handle: u64, buffer: &mut [u8], timeout_ms: u32) -> i32 {
     }
 }
 
+/// Receive whatever data is already available, without blocking.
+/// Returns bytes read, 0 if nothing is available, -1 on error, -3 on EOF, or
+/// WG_SOCKET_TUNNEL_RESTARTED once drained if the connection's tunnel is gone.
+///
+/// Drains the partial-read buffer and then every queued segment that fits in
+/// `buffer`. If another thread is blocked in wg_socket_recv on the same
+/// connection, returns 0 rather than waiting for it.
+pub fn wg_socket_recv_available(handle: u64, buffer: &mut [u8]) -> i32 {
+    let ConnectionArcs { generation, receiver: receiver_arc, recv_buf: recv_buf_arc, .. } =
+        match get_connection_arcs(handle) {
+            Some(arcs) => arcs,
+            None => {
+                error!("wg_socket_recv_available: invalid handle {}", handle);
+                return -1;
+            }
+        };
+
+    // Same lock order as wg_socket_recv: recv_buf, then receiver
+    let mut recv_buf = match recv_buf_arc.try_lock() {
+        Some(buf) => buf,
+        None => return 0,
+    };
+    let mut copied = 0;
+    let mut disconnected = false;
+
+    if recv_buf.pos < recv_buf.data.len() {
+        let available = recv_buf.data.len() - recv_buf.pos;
+        copied = std::cmp::min(available, buffer.len());
+        buffer[..copied].copy_from_slice(&recv_buf.data[recv_buf.pos..recv_buf.pos + copied]);
+        recv_buf.pos += copied;
+        if recv_buf.pos < recv_buf.data.len() {
+            return copied as i32; // Caller's buffer is full
+        }
+        recv_buf.data.clear();
+        recv_buf.pos = 0;
+    }
+
+    if !recv_buf.eof {
+        if let Some(receiver) = receiver_arc.try_lock() {
+            while copied < buffer.len() {
+                match receiver.try_recv() {
+                    Ok(data) if data.is_empty() => {
+                        recv_buf.eof = true;
+                        break;
+                    }
+                    Ok(data) => {
+                        let to_copy = std::cmp::min(data.len(), buffer.len() - copied);
+                        buffer[copied..copied + to_copy].copy_from_slice(&data[..to_copy]);
+                        copied += to_copy;
+                        if to_copy < data.len() {
+                            recv_buf.data = data[to_copy..].to_vec();
+                            recv_buf.pos = 0;
+                        }
+                    }
+                    Err(std::sync::mpsc::TryRecvError::Empty) => break,
+                    Err(std::sync::mpsc::TryRecvError::Disconnected) => {
+                        disconnected = true;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    if disconnected {
+        if tunnel_restarted(generation) {
+            if copied == 0 {
+                return WG_SOCKET_TUNNEL_RESTARTED;
+            }
+        } else {
+            recv_buf.eof = true;
+        }
+    }
+    if copied == 0 && recv_buf.eof {
+        return -3; // EOF
+    }
+    copied as i32
+}
+
 /// Send data through a connection.
-/// Returns bytes sent, or negative on error.
+/// Returns bytes sent, -1 on error, or WG_SOCKET_TUNNEL_RESTARTED.
 pub fn wg_socket_send(handle: u64, data: &[u8]) -> i32 {
+    send_buffers("wg_socket_send", handle, &[data])
+}
+
+/// Send several buffers back-to-back on a connection in one call (scatter-gather).
+/// The buffers are written contiguously, in order, into the virtual stack.
+/// Returns the total number of bytes sent, -1 on error, or WG_SOCKET_TUNNEL_RESTARTED.
+pub fn wg_socket_sendv(handle: u64, buffers: &[&[u8]]) -> i32 {
+    send_buffers("wg_socket_sendv", handle, buffers)
+}
+
+fn send_buffers(caller: &str, handle: u64, buffers: &[&[u8]]) -> i32 {
+    let total: usize = buffers.iter().map(|b| b.len()).sum();
+    if total > i32::MAX as usize {
+        error!("{}: {} bytes exceeds the maximum send size", caller, total);
+        return -1;
+    }
+
     // Get config for proxy access
     let config = match GLOBAL_HTTP_CONFIG.lock().clone() {
         Some(c) => c,
         None => {
-            error!("wg_socket_send: WireGuard HTTP not configured");
+            error!("{}: WireGuard HTTP not configured", caller);
             return -1;
         }
     };
 
     // Briefly lock global map to get conn_id, then release
-    let conn_id = match get_connection_arcs(handle) {
-        Some((id, _, _)) => id,
+    let (conn_id, generation) = match get_connection_arcs(handle) {
+        Some(arcs) => (arcs.conn_id, arcs.generation),
         None => {
-            error!("wg_socket_send: invalid handle {}", handle);
+            error!("{}: invalid handle {}", caller, handle);
             return -1;
         }
     };
+    // Checked before getting the proxy, which would otherwise start a new tunnel
+    if tunnel_restarted(generation) {
+        warn!("{}: tunnel restarted since handle {} was opened", caller, handle);
+        return WG_SOCKET_TUNNEL_RESTARTED;
+    }
 
     // Get shared proxy and send data (no global lock held)
     let proxy = match get_or_create_shared_proxy(&config) {
         Ok(p) => p,
         Err(e) => {
-            error!("wg_socket_send: failed to get shared proxy: {}", e);
+            error!("{}: failed to get shared proxy: {}", caller, e);
             return -1;
         }
     };
 
     // Send through virtual stack
-    if let Err(e) = proxy.virtual_stack.tcp_send(&conn_id, data) {
-        error!("wg_socket_send: tcp_send failed: {}", e);
+    if let Err(e) = proxy.virtual_stack.tcp_sendv(&conn_id, buffers) {
+        error!("{}: tcp_send failed: {}", caller, e);
         return -1;
     }
 
     // Flush outgoing packets
     proxy.flush_outgoing();
 
-    data.len() as i32
+    total as i32
 }
 
-/// Close a connection
-pub fn wg_socket_close(handle: u64) {
-    info!("wg_socket_close: handle={}", handle);
-
-    // Get config for proxy access
+/// Shut down the sending side of a connection (half-close).
+/// Our FIN goes out, but the handle stays open so reads keep relaying the
+/// server's remaining data until it closes its side (EOF) or times out.
+/// Returns 0 on success, -1 on error, or WG_SOCKET_TUNNEL_RESTARTED.
+pub fn wg_socket_shutdown_write(handle: u64) -> i32 {
     let config = match GLOBAL_HTTP_CONFIG.lock().clone() {
         Some(c) => c,
         None => {
-            // Just remove the connection entry
-            let mut map = SOCKET_CONNECTIONS.lock();
-            if let Some(ref mut connections) = *map {
-                connections.remove(&handle);
-            }
-            return;
+            error!("wg_socket_shutdown_write: WireGuard HTTP not configured");
+            return -1;
+        }
+    };
+
+    let (conn_id, generation) = match get_connection_arcs(handle) {
+        Some(arcs) => (arcs.conn_id, arcs.generation),
+        None => {
+            error!("wg_socket_shutdown_write: invalid handle {}", handle);
+            return -1;
+        }
+    };
+    if tunnel_restarted(generation) {
+        warn!("wg_socket_shutdown_write: tunnel restarted since handle {} was opened", handle);
+        return WG_SOCKET_TUNNEL_RESTARTED;
+    }
+
+    let proxy = match get_or_create_shared_proxy(&config) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("wg_socket_shutdown_write: failed to get shared proxy: {}", e);
+            return -1;
         }
     };
 
+    if let Err(e) = proxy.virtual_stack.tcp_shutdown_write(&conn_id) {
+        error!("wg_socket_shutdown_write: {}", e);
+        return -1;
+    }
+    proxy.flush_outgoing();
+    0
+}
+
+/// Close a connection.
+/// Returns 0 on success, -1 for an unknown handle, or WG_SOCKET_TUNNEL_RESTARTED
+/// if the connection's tunnel was already gone (the handle is released either way).
+pub fn wg_socket_close(handle: u64) -> i32 {
+    info!("wg_socket_close: handle={}", handle);
+
     // Get connection ID and remove from map
-    let conn_id = {
+    let (conn_id, generation) = {
         let mut map = SOCKET_CONNECTIONS.lock();
         let connections = match *map {
             Some(ref mut c) => c,
-            None => return,
+            None => return -1,
         };
         match connections.remove(&handle) {
-            Some(conn) => conn.conn_id,
-            None => return,
+            Some(conn) => (conn.conn_id, conn.generation),
+            None => return -1,
         }
     };
     // Global lock released here; the removed connection's Arcs will drop when we leave scope
+    wg_socket_notify_event();
+
+    // Nothing to tear down on a stack that no longer exists
+    if tunnel_restarted(generation) {
+        debug!("wg_socket_close: tunnel restarted since handle {} was opened", handle);
+        return WG_SOCKET_TUNNEL_RESTARTED;
+    }
+
+    // Get config for proxy access
+    let config = match GLOBAL_HTTP_CONFIG.lock().clone() {
+        Some(c) => c,
+        None => return 0,
+    };
 
     // Gracefully close the TCP connection.
     // Don't remove from virtual stack - let TCP teardown complete properly.
@@ -348,30 +775,52 @@ pub fn wg_socket_close(handle: u64) {
         proxy.virtual_stack.tcp_close(&conn_id).ok();
         proxy.flush_outgoing();
     }
+    0
 }
 
-/// Close all socket connections (cleanup)
+/// Close all socket connections (cleanup before the tunnel goes away).
+/// Each connection gets a FIN, and its handle is invalidated rather than
+/// released, so Java sees WG_SOCKET_TUNNEL_RESTARTED on its next call.
 pub fn wg_socket_close_all() {
     info!("wg_socket_close_all");
-    
-    let handles: Vec<u64> = {
-        let map = SOCKET_CONNECTIONS.lock();
-        match *map {
-            Some(ref connections) => connections.keys().cloned().collect(),
-            None => return,
+
+    let conn_ids = wg_socket_connection_ids();
+    wg_socket_invalidate_handles();
+    if conn_ids.is_empty() {
+        return;
+    }
+
+    // Only a running proxy has anything to close; don't start one for this
+    let proxy = crate::wg_http::SHARED_TCP_PROXY.lock().clone();
+    if let Some(proxy) = proxy {
+        for conn_id in &conn_ids {
+            proxy.virtual_stack.tcp_close(conn_id).ok();
         }
-    };
+        proxy.flush_outgoing();
+    }
+}
 
-    for handle in handles {
-        wg_socket_close(handle);
+/// Snapshot the connection IDs of all current (not invalidated) socket handles
+/// (global lock held briefly).
+pub fn wg_socket_connection_ids() -> Vec<TcpConnectionId> {
+    let generation = TUNNEL_GENERATION.load(Ordering::Acquire);
+    let map = SOCKET_CONNECTIONS.lock();
+    match *map {
+        Some(ref connections) => connections
+            .values()
+            .filter(|c| c.generation == generation)
+            .map(|c| c.conn_id)
+            .collect(),
+        None => Vec::new(),
     }
 }
 
-/// Get the number of active socket connections
+/// Get the number of active (not invalidated) socket connections
 pub fn wg_socket_connection_count() -> usize {
+    let generation = TUNNEL_GENERATION.load(Ordering::Acquire);
     let map = SOCKET_CONNECTIONS.lock();
     match *map {
-        Some(ref connections) => connections.len(),
+        Some(ref connections) => connections.values().filter(|c| c.generation == generation).count(),
         None => 0,
     }
 }
@@ -381,7 +830,7 @@ pub fn wg_socket_connection_count() -> usize {
 pub fn wg_socket_has_data(handle: u64) -> bool {
     // Get Arc refs without holding the global lock
     let (receiver_arc, recv_buf_arc) = match get_connection_arcs(handle) {
-        Some((_conn_id, rx, buf)) => (rx, buf),
+        Some(arcs) => (arcs.receiver, arcs.recv_buf),
         None => return false,
     };
 
@@ -417,3 +866,175 @@ pub fn wg_socket_has_data(handle: u64) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Serializes tests that register handles, since invalidating handles
+    /// affects every registered one
+    static SOCKET_MAP_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Register a connection under `handle` whose data the test feeds through
+    /// the returned sender
+    fn insert_test_connection(
+        handle: u64,
+        local_port: u16,
+        generation: u64,
+    ) -> std::sync::mpsc::SyncSender<Vec<u8>> {
+        let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<u8>>(8);
+        ensure_connections_map();
+        SOCKET_CONNECTIONS.lock().as_mut().unwrap().insert(handle, WgSocketConnection {
+            conn_id: TcpConnectionId {
+                local_addr: IpAddr::from([10, 0, 0, 2]),
+                local_port,
+                remote_addr: IpAddr::from([10, 0, 0, 1]),
+                remote_port: 47984,
+            },
+            generation,
+            receiver: Arc::new(Mutex::new(rx)),
+            recv_buf: Arc::new(Mutex::new(RecvBuffer { data: Vec::new(), pos: 0, eof: false })),
+            _created_at: Instant::now(),
+            deadline: None,
+            slot: ConnectionSlot::try_acquire(),
+        });
+        tx
+    }
+
+    #[test]
+    fn test_connect_retry_classification() {
+        assert!(is_retryable_connect_error(io::ErrorKind::TimedOut));
+        assert!(is_retryable_connect_error(io::ErrorKind::ConnectionRefused));
+        assert!(!is_retryable_connect_error(io::ErrorKind::NotConnected));
+        assert!(!is_retryable_connect_error(io::ErrorKind::Unsupported));
+        assert!(!is_retryable_connect_error(io::ErrorKind::InvalidInput));
+
+        // Not configured: fails on the first attempt instead of retrying
+        let start = Instant::now();
+        assert_eq!(wg_socket_connect("10.0.0.1", 47984, 10_000), 0);
+        assert!(start.elapsed() < Duration::from_millis(CONNECT_RETRY_BACKOFF_MS));
+
+        wg_socket_set_connect_attempts(0);
+        assert_eq!(CONNECT_ATTEMPTS.load(Ordering::Relaxed), DEFAULT_CONNECT_ATTEMPTS);
+    }
+
+    #[test]
+    fn test_connection_slots_are_capped_and_released() {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let first = ConnectionSlot::try_acquire_from(&COUNTER, 2).unwrap();
+        let second = ConnectionSlot::try_acquire_from(&COUNTER, 2).unwrap();
+        assert!(ConnectionSlot::try_acquire_from(&COUNTER, 2).is_none());
+        assert_eq!(COUNTER.load(Ordering::Acquire), 2);
+
+        // A failed connect or a closed connection gives its slot back
+        drop(first);
+        let third = ConnectionSlot::try_acquire_from(&COUNTER, 2).unwrap();
+        drop(second);
+        drop(third);
+        assert_eq!(COUNTER.load(Ordering::Acquire), 0);
+
+        wg_socket_set_max_connections(0);
+        assert_eq!(MAX_CONNECTIONS.load(Ordering::Relaxed), DEFAULT_MAX_CONNECTIONS);
+    }
+
+    #[test]
+    fn test_recv_available_drains_without_blocking() {
+        let _map = SOCKET_MAP_TEST_LOCK.lock();
+        let handle = u64::MAX - 1;
+        let tx = insert_test_connection(handle, 50000, TUNNEL_GENERATION.load(Ordering::Acquire));
+
+        let mut buf = [0u8; 8];
+        let start = Instant::now();
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), 0);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        // Two queued segments drain into one call; the overflow is kept
+        tx.send(b"hello".to_vec()).unwrap();
+        tx.send(b"world".to_vec()).unwrap();
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), 8);
+        assert_eq!(&buf, b"hellowor");
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), 2);
+        assert_eq!(&buf[..2], b"ld");
+
+        // EOF is distinct from "nothing yet"
+        tx.send(Vec::new()).unwrap();
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), -3);
+        assert_eq!(wg_socket_recv(handle, &mut buf, 10), 0);
+
+        SOCKET_CONNECTIONS.lock().as_mut().unwrap().remove(&handle);
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), -1);
+    }
+
+    #[test]
+    fn test_recv_times_out_once_deadline_passes() {
+        let _map = SOCKET_MAP_TEST_LOCK.lock();
+        let handle = u64::MAX - 3;
+        let tx = insert_test_connection(handle, 50002, TUNNEL_GENERATION.load(Ordering::Acquire));
+        SOCKET_CONNECTIONS.lock().as_mut().unwrap().get_mut(&handle).unwrap().deadline =
+            Some(Instant::now() - Duration::from_millis(1));
+
+        // Buffered data is still handed out, but nothing more is waited for,
+        // however long the read timeout
+        tx.send(b"late".to_vec()).unwrap();
+        assert!(wg_socket_has_data(handle));
+        let mut buf = [0u8; 8];
+        assert_eq!(wg_socket_recv(handle, &mut buf, 10_000), 4);
+        let start = Instant::now();
+        assert_eq!(wg_socket_recv(handle, &mut buf, 10_000), -2);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        SOCKET_CONNECTIONS.lock().as_mut().unwrap().remove(&handle);
+    }
+
+    #[test]
+    fn test_event_wakes_waiter() {
+        // Nothing happening: the wait runs out (unless another test notifies)
+        let seen = wg_socket_event_seq();
+        let start = Instant::now();
+        if !wg_socket_wait_event(seen, Duration::from_millis(20)) {
+            assert!(start.elapsed() >= Duration::from_millis(20));
+        }
+
+        // A notification wakes the waiter well before its timeout
+        let seen = wg_socket_event_seq();
+        let notifier = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            wg_socket_notify_event();
+        });
+        let start = Instant::now();
+        assert!(wg_socket_wait_event(seen, Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        notifier.join().unwrap();
+
+        // An event that already happened returns immediately
+        assert!(wg_socket_wait_event(seen, Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_stale_generation_reports_tunnel_restart() {
+        let _map = SOCKET_MAP_TEST_LOCK.lock();
+        let handle = u64::MAX - 2;
+        let tx = insert_test_connection(handle, 50001, TUNNEL_GENERATION.load(Ordering::Acquire));
+        assert_eq!(wg_socket_connection_count(), 1);
+
+        // Clearing the HTTP config takes the tunnel down with it (no streaming
+        // tunnel is running in tests)
+        crate::wg_http::wg_http_clear_config();
+        assert_eq!(wg_socket_connection_count(), 0);
+
+        // Data that arrived before the restart is still delivered
+        tx.send(b"data".to_vec()).unwrap();
+        drop(tx);
+        let mut buf = [0u8; 8];
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), 4);
+
+        // Then the dropped stack shows up as a restart, not as EOF
+        assert_eq!(wg_socket_recv_available(handle, &mut buf), WG_SOCKET_TUNNEL_RESTARTED);
+        assert_eq!(wg_socket_recv(handle, &mut buf, 10), WG_SOCKET_TUNNEL_RESTARTED);
+
+        // Close releases the handle and says so
+        assert_eq!(wg_socket_close(handle), WG_SOCKET_TUNNEL_RESTARTED);
+        assert_eq!(wg_socket_close(handle), -1);
+    }
+}
@@ -14,18 +14,19 @@
 use std::cell::RefCell;
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
 use boringtun::noise::{Tunn, TunnResult};
+use boringtun::noise::rate_limiter::RateLimiter;
 use x25519_dalek::{PublicKey, StaticSecret};
 use log::{debug, error, info, warn};
-use parking_lot::Mutex;
+use parking_lot::{Condvar, Mutex};
 
 // Re-export configuration from dedicated module
-pub use crate::wireguard_config::WireGuardConfig;
+pub use crate::wireguard_config::{HandshakeRetrySchedule, WireGuardConfig};
 
 /// Maximum size of a UDP packet
 const MAX_UDP_PACKET_SIZE: usize = 65535;
@@ -41,14 +42,24 @@ const DDNS_RERESOLVE_TIMEOUT_SECS: u64 = 135;
 /// instead of waiting the full DDNS_RERESOLVE_TIMEOUT_SECS.
 const DDNS_RETRY_INTERVAL_SECS: u64 = 30;
 
-/// Return the unspecified bind address matching the address family of `addr`.
-/// IPv4 endpoints bind to `0.0.0.0:0`, IPv6 endpoints bind to `[::]:0`.
-fn bind_addr_for(addr: &SocketAddr) -> &'static str {
-    match addr {
-        SocketAddr::V4(_) => "0.0.0.0:0",
-        SocketAddr::V6(_) => "[::]:0",
-    }
-}
+/// How long wg_start_tunnel waits for the initial handshake. The configured
+/// HandshakeRetrySchedule only re-sends the initiation within this window.
+const INITIAL_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// How often a paused receiver thread checks for resume or shutdown
+const PAUSED_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Upper bound on how long stop() waits for the receiver and timer threads to exit.
+/// Covers one timer tick (250ms) and one paused poll.
+const TUNNEL_STOP_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// WireGuard's REKEY_AFTER_TIME: the session age at which the initiator starts
+/// a new handshake (same value boringtun uses internally)
+const REKEY_AFTER_TIME: Duration = Duration::from_secs(120);
+
+/// A pause longer than this re-initiates the handshake on resume. Past the
+/// persistent keepalive interval the NAT mapping towards the peer may be gone.
+const PAUSE_REHANDSHAKE_AFTER: Duration = Duration::from_secs(25);
 
 
 /// State of the WireGuard tunnel
@@ -63,6 +74,9 @@ struct TunnelState {
     handshake_completed: AtomicBool,
     /// Last successful handshake/packet timestamp for DDNS re-resolution
     last_handshake: Instant,
+    /// When a packet was last received from the peer. Unlike last_handshake it
+    /// is never reset by the timer, so it proves the path is actually alive.
+    last_received: Instant,
     /// Incremented each time endpoint_socket is replaced (e.g. DDNS re-resolution).
     /// Used by the receiver thread and send cache to detect stale socket clones.
     socket_generation: u64,
@@ -73,6 +87,13 @@ pub struct WireGuardTunnel {
     config: WireGuardConfig,
     state: Arc<Mutex<TunnelState>>,
     running: Arc<AtomicBool>,
+    /// Handshake rate limiter shared with boringtun (reset by the timer thread)
+    rate_limiter: Arc<RateLimiter>,
+    /// Heartbeat of the receiver thread, watched by the timer thread
+    receiver_liveness: Arc<ReceiverLiveness>,
+    /// Receiver and timer thread handles, joined on stop. Shared with the timer
+    /// thread, which starts a replacement receiver after a stall.
+    threads: Arc<Mutex<Vec<thread::JoinHandle<()>>>>,
 }
 
 impl WireGuardTunnel {
@@ -80,10 +101,20 @@ impl WireGuardTunnel {
     pub fn new(config: WireGuardConfig) -> io::Result<Self> {
         info!("Creating WireGuard tunnel to endpoint: {}", config.endpoint);
 
+        config.validate()?;
+
         // Create the private/public key pair
         let private_key = StaticSecret::from(config.private_key);
         let peer_public_key = PublicKey::from(config.peer_public_key);
 
+        // Rate limit handshake processing so a flood of spoofed/garbage handshake
+        // packets can't peg the receiver thread. Transport data is not counted.
+        let rate_limiter = Arc::new(RateLimiter::new(
+            &PublicKey::from(&private_key),
+            config.handshake_rate_limit,
+        ));
+        info!("WireGuard handshake rate limit: {} packets/s", config.handshake_rate_limit);
+
         // Create the boringtun tunnel
         let tunnel = Box::new(Tunn::new(
             private_key,
@@ -91,7 +122,7 @@ impl WireGuardTunnel {
             config.preshared_key,
             None,
             0, // index
-            None, // rate limiter
+            Some(rate_limiter.clone()),
         ));
 
         // Resolve endpoint dynamically for DDNS support
@@ -99,7 +130,7 @@ impl WireGuardTunnel {
         info!("Resolved endpoint '{}' -> {}", config.endpoint, endpoint_addr);
 
         // Create UDP socket to the WireGuard endpoint (address family must match)
-        let endpoint_socket = UdpSocket::bind(bind_addr_for(&endpoint_addr))?;
+        let endpoint_socket = UdpSocket::bind(config.bind_addr_for(&endpoint_addr)?)?;
         endpoint_socket.connect(endpoint_addr)?;
         endpoint_socket.set_nonblocking(false)?;
 
@@ -119,6 +150,7 @@ impl WireGuardTunnel {
             resolved_endpoint: endpoint_addr,
             handshake_completed: AtomicBool::new(false),
             last_handshake: Instant::now(),
+            last_received: Instant::now(),
             socket_generation: 0,
         }));
 
@@ -128,6 +160,9 @@ impl WireGuardTunnel {
             config,
             state,
             running,
+            rate_limiter,
+            receiver_liveness: Arc::new(ReceiverLiveness::new()),
+            threads: Arc::new(Mutex::new(Vec::with_capacity(2))),
         })
     }
 
@@ -146,37 +181,110 @@ impl WireGuardTunnel {
 
         // Start the endpoint receiver thread - reads from the real WireGuard endpoint
         // and decapsulates packets, forwarding via zero-copy channels
-        let state = self.state.clone();
-        let running = self.running.clone();
-
-        thread::Builder::new()
-            .name("wg-endpoint-rx".into())
-            .spawn(move || {
-                Self::endpoint_receiver_loop(state, running);
-            })?;
+        Self::spawn_receiver(&self.state, &self.running, &self.receiver_liveness, &self.threads)?;
 
         // Start the timer thread for handshake retransmission and DDNS re-resolution
         let state = self.state.clone();
         let running = self.running.clone();
         let config = self.config.clone();
+        let rate_limiter = self.rate_limiter.clone();
+        let receiver_liveness = self.receiver_liveness.clone();
+        let threads = self.threads.clone();
 
-        thread::Builder::new()
+        let timer_handle = thread::Builder::new()
             .name("wg-timer".into())
             .spawn(move || {
-                Self::timer_loop(state, running, config);
+                Self::timer_loop(state, running, config, rate_limiter, receiver_liveness, threads);
             })?;
+        self.threads.lock().push(timer_handle);
 
         info!("WireGuard tunnel started");
         Ok(())
     }
 
-    /// Stop the WireGuard tunnel.
+    /// Stop the WireGuard tunnel and wait (up to TUNNEL_STOP_TIMEOUT) for the
+    /// receiver and timer threads to exit, so that when this returns no tunnel
+    /// thread is still delivering packets or touching shared state.
     pub fn stop(&self) {
         // Only log and act if actually running (avoids double-stop from Drop)
         if self.running.swap(false, Ordering::Release) {
             info!("Stopping WireGuard tunnel...");
-            info!("WireGuard tunnel stopped");
+            if self.join_threads(Instant::now() + TUNNEL_STOP_TIMEOUT) {
+                info!("WireGuard tunnel stopped");
+            } else {
+                warn!("WireGuard tunnel stopped (threads still exiting after {:?})", TUNNEL_STOP_TIMEOUT);
+            }
+        }
+    }
+
+    /// Join the receiver/timer threads, giving up on any still running at `deadline`.
+    /// Returns true if all threads exited.
+    fn join_threads(&self, deadline: Instant) -> bool {
+        let handles: Vec<thread::JoinHandle<()>> = self.threads.lock().drain(..).collect();
+        let current = thread::current().id();
+        let mut all_joined = true;
+        for handle in handles {
+            if handle.thread().id() == current {
+                // Stopped from one of its own threads; it exits after returning
+                continue;
+            }
+            while !handle.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(5));
+            }
+            if handle.is_finished() {
+                handle.join().ok();
+            } else {
+                // Detach: the thread exits on its own once it observes running == false
+                all_joined = false;
+            }
+        }
+        all_joined
+    }
+
+    /// Replace the endpoint socket with a fresh one connected to the same endpoint
+    /// and re-initiate the handshake on it. With `force_resend`, an initiation goes
+    /// out even while one is already pending (boringtun otherwise waits for its own
+    /// retry timer). Returns a clone of the new socket for the send cache, which the
+    /// caller must update after releasing the state lock.
+    fn replace_endpoint_socket(
+        st: &mut TunnelState,
+        config: &WireGuardConfig,
+        force_resend: bool,
+    ) -> io::Result<UdpSocket> {
+        let endpoint_addr = st.resolved_endpoint;
+
+        let new_socket = UdpSocket::bind(config.bind_addr_for(&endpoint_addr)?)?;
+        new_socket.connect(endpoint_addr)?;
+        new_socket.set_nonblocking(false)?;
+        new_socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+        Self::set_socket_buffer_sizes(&new_socket);
+
+        // Clone for send cache update (before moving into state)
+        let new_send_socket = new_socket.try_clone()?;
+
+        // Replace socket in tunnel state
+        st.endpoint_socket = new_socket;
+        st.socket_generation += 1;
+        CONSECUTIVE_SEND_EPERMS.store(0, Ordering::Relaxed);
+
+        // Re-initiate handshake on the new socket
+        let mut dst_buf = vec![0u8; WG_BUFFER_SIZE];
+        match st.tunnel.format_handshake_initiation(&mut dst_buf, force_resend) {
+            TunnResult::WriteToNetwork(data) => {
+                match st.endpoint_socket.send(data) {
+                    Ok(sent) => {
+                        HANDSHAKE_TX_BYTES.fetch_add(sent as u64, Ordering::Relaxed);
+                        info!("Rebind: sent handshake initiation on new socket (gen={})", st.socket_generation);
+                    }
+                    Err(e) => warn!("Rebind: failed to send handshake initiation: {}", e),
+                }
+            }
+            _ => {}
         }
+
+        // Reset last_handshake so the timer thread doesn't immediately try DDNS re-resolution
+        st.last_handshake = Instant::now();
+        Ok(new_send_socket)
     }
 
     /// Set large send/receive buffer sizes on a UDP socket for streaming throughput.
@@ -237,16 +345,22 @@ impl WireGuardTunnel {
 
     /// Wait for the handshake to complete, with a timeout.
     ///
-    /// Actively re-initiates the handshake with exponential backoff to handle
-    /// packet loss on unreliable networks (mobile, WiFi).
+    /// Actively re-initiates the handshake following the configured
+    /// HandshakeRetrySchedule to handle packet loss on unreliable networks
+    /// (mobile, WiFi). No retry is sent once `timeout` has passed.
     pub fn wait_for_handshake(&self, timeout: Duration) -> bool {
+        let schedule = self.config.handshake_retry;
         let start = Instant::now();
-        let mut next_retry = start + Duration::from_millis(1000);
-        let mut retry_interval = Duration::from_millis(1000);
-        let max_retry_interval = Duration::from_secs(4);
+        let mut retry_interval = schedule.initial;
+        let mut next_retry = start + retry_interval;
         let mut retry_count = 0u32;
 
         while start.elapsed() < timeout {
+            if HANDSHAKE_CANCELLED.load(Ordering::Acquire) {
+                info!("WireGuard handshake cancelled after {:?} ({} retries)",
+                      start.elapsed(), retry_count);
+                return false;
+            }
             if self.is_ready() {
                 if retry_count > 0 {
                     info!("WireGuard handshake completed after {} retries ({:?})",
@@ -264,10 +378,13 @@ impl WireGuardTunnel {
                 retry_count += 1;
                 info!("Re-initiating WireGuard handshake (attempt {}, {:?} elapsed)",
                       retry_count, start.elapsed());
-                if let Err(e) = self.initiate_handshake() {
-                    warn!("Handshake re-initiation failed: {}", e);
+                let rotated = source_port_rotation_due(retry_count) && self.rotate_source_port();
+                if !rotated {
+                    if let Err(e) = self.initiate_handshake() {
+                        warn!("Handshake re-initiation failed: {}", e);
+                    }
                 }
-                retry_interval = (retry_interval * 2).min(max_retry_interval);
+                retry_interval = schedule.next_interval(retry_interval);
                 next_retry = now + retry_interval;
             }
 
@@ -276,9 +393,64 @@ impl WireGuardTunnel {
 
         warn!("WireGuard handshake timed out after {:?} ({} retries)",
               start.elapsed(), retry_count);
+        {
+            let st = self.state.lock();
+            error!("{}", failure_diagnostics("handshake timeout", &st, &self.config.endpoint, retry_count));
+        }
+        false
+    }
+
+    /// Probe the tunnel path end to end: force a handshake initiation, which the
+    /// peer always answers, and wait up to `timeout` for any packet from it.
+    /// The current session stays in use until the new handshake completes.
+    fn probe(state: &Mutex<TunnelState>, timeout: Duration) -> bool {
+        let sent_at = Instant::now();
+        {
+            let mut st = state.lock();
+            let mut dst_buf = vec![0u8; WG_BUFFER_SIZE];
+            match st.tunnel.format_handshake_initiation(&mut dst_buf, true) {
+                TunnResult::WriteToNetwork(data) => {
+                    if let Err(e) = st.endpoint_socket.send(data) {
+                        warn!("Tunnel probe: failed to send handshake: {}", e);
+                        return false;
+                    }
+                }
+                _ => return false,
+            }
+        }
+
+        while sent_at.elapsed() < timeout {
+            if state.lock().last_received > sent_at {
+                info!("Tunnel probe: peer answered in {:?}", sent_at.elapsed());
+                return true;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        warn!("Tunnel probe: no answer from peer within {:?}", timeout);
         false
     }
 
+    /// Move the endpoint socket to a fresh ephemeral source port and send the
+    /// handshake initiation from it, in case a NAT or firewall is blocking the
+    /// old port. Returns false if the socket couldn't be replaced.
+    fn rotate_source_port(&self) -> bool {
+        let mut st = self.state.lock();
+        let old_port = st.endpoint_socket.local_addr().map(|addr| addr.port()).unwrap_or(0);
+        // The handshake being retried is still pending, so force the initiation
+        // out now rather than on boringtun's next retry
+        match Self::replace_endpoint_socket(&mut st, &self.config, true) {
+            Ok(_) => {
+                let new_port = st.endpoint_socket.local_addr().map(|addr| addr.port()).unwrap_or(0);
+                info!("WireGuard handshake unanswered, moved source port {} -> {}", old_port, new_port);
+                true
+            }
+            Err(e) => {
+                warn!("Failed to rotate WireGuard source port: {}", e);
+                false
+            }
+        }
+    }
+
     /// Initiate the WireGuard handshake
     fn initiate_handshake(&self) -> io::Result<()> {
         let mut state = self.state.lock();
@@ -287,7 +459,8 @@ impl WireGuardTunnel {
         match state.tunnel.format_handshake_initiation(&mut dst_buf, false) {
             TunnResult::WriteToNetwork(data) => {
                 info!("Sending WireGuard handshake initiation ({} bytes)", data.len());
-                state.endpoint_socket.send(data)?;
+                let sent = state.endpoint_socket.send(data)?;
+                HANDSHAKE_TX_BYTES.fetch_add(sent as u64, Ordering::Relaxed);
             }
             TunnResult::Err(e) => {
                 error!("Failed to create handshake initiation: {:?}", e);
@@ -303,10 +476,35 @@ impl WireGuardTunnel {
 
 
 
+    /// Start a receiver thread. Any receiver already running is retired: it exits
+    /// as soon as it notices, so a stuck one that wakes up later doesn't compete.
+    fn spawn_receiver(
+        state: &Arc<Mutex<TunnelState>>,
+        running: &Arc<AtomicBool>,
+        liveness: &Arc<ReceiverLiveness>,
+        threads: &Mutex<Vec<thread::JoinHandle<()>>>,
+    ) -> io::Result<()> {
+        let id = liveness.id.fetch_add(1, Ordering::AcqRel) + 1;
+        liveness.beat();
+
+        let state = state.clone();
+        let running = running.clone();
+        let receiver_liveness = liveness.clone();
+        let rx_handle = thread::Builder::new()
+            .name("wg-endpoint-rx".into())
+            .spawn(move || {
+                Self::endpoint_receiver_loop(state, running, receiver_liveness, id);
+            })?;
+        threads.lock().push(rx_handle);
+        Ok(())
+    }
+
     /// Background thread: receives packets from the WireGuard endpoint and decapsulates them
     fn endpoint_receiver_loop(
         state: Arc<Mutex<TunnelState>>,
         running: Arc<AtomicBool>,
+        liveness: Arc<ReceiverLiveness>,
+        id: u64,
     ) {
         // CRITICAL PERFORMANCE FIX: Clone socket for receiving so we don't hold
         // the tunnel state lock during blocking recv(). Previously, the lock was
@@ -328,6 +526,19 @@ impl WireGuardTunnel {
         info!("WireGuard endpoint receiver started");
 
         while running.load(Ordering::Relaxed) {
+            if liveness.id.load(Ordering::Relaxed) != id {
+                warn!("WireGuard endpoint receiver replaced after a stall, exiting");
+                return;
+            }
+            liveness.beat();
+
+            if wg_is_paused() {
+                // Backgrounded: stop reading so the thread sleeps instead of waking
+                // on every packet. Arrivals queue in the kernel buffer until resume.
+                thread::sleep(PAUSED_POLL_INTERVAL);
+                continue;
+            }
+
             // Read WITHOUT holding tunnel lock - allows concurrent sends
             let n = match recv_socket.recv(&mut recv_buf) {
                 Ok(n) => n,
@@ -364,19 +575,45 @@ impl WireGuardTunnel {
                 }
             };
 
-            // Lock briefly for decapsulate only (fast crypto operation, ~microseconds)
+            // Lock briefly for decapsulate only (fast crypto operation, ~microseconds).
+            // Let a pending high-priority send (input/control) take the lock first.
+            yield_to_priority_senders();
             let mut st = state.lock();
 
             // Update last handshake time on any received packet
             st.last_handshake = Instant::now();
+            st.last_received = st.last_handshake;
+            let session_established = st.handshake_completed.load(Ordering::Relaxed);
+            if !session_established {
+                HANDSHAKE_RX_BYTES.fetch_add(n as u64, Ordering::Relaxed);
+            }
 
-            let result = st.tunnel.decapsulate(None, &recv_buf[..n], &mut dec_buf);
+            // Pass the endpoint address so cookie replies (sent while the rate
+            // limiter is under load) are bound to the peer's source IP
+            let src_ip = st.resolved_endpoint.ip();
+            let msg_type = classify_wg_message(&recv_buf[..n]);
+            let result = st.tunnel.decapsulate(Some(src_ip), &recv_buf[..n], &mut dec_buf);
 
             match result {
                 TunnResult::WriteToNetwork(data) => {
-                    // This is typically a handshake response
-                    if let Err(e) = st.endpoint_socket.send(data) {
-                        error!("Failed to send WireGuard response: {}", e);
+                    // Either our reply to a peer-initiated handshake (e.g. the server
+                    // restarted or re-keyed first), or the keepalive that confirms a
+                    // response to our own initiation
+                    match msg_type {
+                        WgMessageType::HandshakeInitiation => {
+                            info!("WireGuard handshake initiation received from peer, sending response");
+                        }
+                        WgMessageType::HandshakeResponse => {
+                            info!("WireGuard handshake response received");
+                        }
+                        _ => {}
+                    }
+                    match st.endpoint_socket.send(data) {
+                        Ok(sent) if !session_established => {
+                            HANDSHAKE_TX_BYTES.fetch_add(sent as u64, Ordering::Relaxed);
+                        }
+                        Ok(_) => {}
+                        Err(e) => error!("Failed to send WireGuard response: {}", e),
                     }
 
                     // Check if there's more data to process (for handshake completion)
@@ -391,12 +628,14 @@ impl WireGuardTunnel {
                             if !st.handshake_completed.load(Ordering::Relaxed) {
                                 st.handshake_completed.store(true, Ordering::Release);
                                 info!("WireGuard handshake completed!");
+                                crate::callbacks::record_handshake_complete();
                             }
                         }
                         TunnResult::Done => {
                             if !st.handshake_completed.load(Ordering::Relaxed) {
                                 st.handshake_completed.store(true, Ordering::Release);
                                 info!("WireGuard handshake completed!");
+                                crate::callbacks::record_handshake_complete();
                             }
                         }
                         _ => {}
@@ -407,12 +646,26 @@ impl WireGuardTunnel {
                     if !st.handshake_completed.load(Ordering::Relaxed) {
                         st.handshake_completed.store(true, Ordering::Release);
                         info!("WireGuard handshake completed (first data packet)!");
+                        crate::callbacks::record_handshake_complete();
                     }
                     drop(st); // Release lock before forwarding
+                    record_tunnel_rx(data.len());
+
+                    if take_first_data_packet() {
+                        info!("WireGuard data plane confirmed: first packet decapsulated");
+                        crate::callbacks::record_first_data_packet();
+                        if NO_DATA_SUSPECTED.swap(false, Ordering::AcqRel) {
+                            info!("WireGuard data arrived after the no-data diagnostic fired");
+                        }
+                        crate::callbacks::bridge_cl_tunnel_data_flowing();
+                    }
 
                     // Determine IP version and extract protocol
                     if data.len() >= 20 {
                         let ip_version = (data[0] >> 4) & 0x0F;
+                        // The protocol byte sits at a fixed offset even when the IPv4 header
+                        // carries options; both the UDP parser and the virtual stack
+                        // (via etherparse) locate the transport header from the IHL.
                         let protocol = match ip_version {
                             4 => data[9],     // IPv4: protocol at offset 9
                             6 if data.len() >= 40 => data[6], // IPv6: next header at offset 6
@@ -424,6 +677,7 @@ impl WireGuardTunnel {
                             crate::wg_http::wg_http_inject_packet(data);
                         } else if protocol == 17 {
                             // UDP packet - deliver via zero-copy channel
+                            crate::platform_sockets::tap_inbound_packet(data);
                             if let Some((src_port, _dst_port, payload)) = parse_udp_from_ip_packet(data) {
                                 // Try zero-copy delivery via platform_sockets channel
                                 if crate::platform_sockets::try_push_udp_data(src_port, payload) {
@@ -438,6 +692,9 @@ impl WireGuardTunnel {
                                     crate::platform_sockets::buffer_pending_udp_data(src_port, payload);
                                 }
                             }
+                        } else if protocol == 1 && ip_version == 4 {
+                            // ICMP - only echo replies to our MTU probe are of interest
+                            record_mtu_probe_reply(data);
                         }
                     }
                 }
@@ -446,6 +703,8 @@ impl WireGuardTunnel {
                 }
                 TunnResult::Err(e) => {
                     warn!("WireGuard decapsulation error: {:?}", e);
+                    *LAST_DECAPSULATE_ERROR.lock() = Some(format!("{:?}", e));
+                    record_tunnel_error(format!("decapsulate: {:?}", e));
                 }
             }
         }
@@ -454,27 +713,130 @@ impl WireGuardTunnel {
     }
 
     /// Background thread: periodic timer for DDNS re-resolution and handshake maintenance
-    fn timer_loop(state: Arc<Mutex<TunnelState>>, running: Arc<AtomicBool>, config: WireGuardConfig) {
+    fn timer_loop(
+        state: Arc<Mutex<TunnelState>>,
+        running: Arc<AtomicBool>,
+        config: WireGuardConfig,
+        rate_limiter: Arc<RateLimiter>,
+        receiver_liveness: Arc<ReceiverLiveness>,
+        threads: Arc<Mutex<Vec<thread::JoinHandle<()>>>>,
+    ) {
         let mut dst_buf = vec![0u8; WG_BUFFER_SIZE];
         let mut handshake_retry_count = 0u32;
+        // Whether the tunnel has completed a handshake at least once, used to
+        // distinguish the initial Connecting state from Reconnecting.
+        let mut ever_connected = false;
         // Track last DNS resolution attempt to implement retry backoff
         let mut last_ddns_attempt = Instant::now();
         // Track previous sleep state to detect wake transitions
         let mut was_sleeping = false;
+        // When the tunnel was paused, to detect resume and measure the pause
+        let mut paused_since: Option<Instant> = None;
+        // Last automatic rebind after persistent EPERM, to space them out
+        let mut last_eperm_rebind: Option<Instant> = None;
+        // Since when a completed handshake has been waiting for its first data
+        // packet, and whether the no-data diagnostic already fired for that wait
+        let mut data_wait_since: Option<Instant> = None;
+        let mut no_data_reported = false;
+        // When the previous tick ran; a late tick means this thread was frozen too
+        // (doze), so the receiver's silence proves nothing
+        let mut last_tick = Instant::now();
 
         info!("WireGuard timer thread started");
 
         while running.load(Ordering::Relaxed) {
             thread::sleep(Duration::from_millis(250));
+            let tick_gap = last_tick.elapsed();
+            last_tick = Instant::now();
+
+            // Reset the handshake rate limiter counter (no-op until a full period has elapsed)
+            rate_limiter.reset_count();
+
+            // While paused, skip keepalives, DDNS and state reports entirely
+            if wg_is_paused() {
+                paused_since.get_or_insert_with(Instant::now);
+                continue;
+            }
+            let resumed_after = paused_since.take().map(|since| since.elapsed());
+            if resumed_after.is_some() {
+                // Time spent paused doesn't count against the data window
+                data_wait_since = None;
+            }
 
             // Track whether we need to update the send cache after releasing the state lock.
             // This avoids a lock ordering deadlock: send path holds WG_SEND_CACHE then state,
             // so we must NOT hold state while locking WG_SEND_CACHE.
             let mut new_send_socket: Option<UdpSocket> = None;
+            // Snapshot of tunnel health taken under the state lock; the state listener
+            // is notified after the lock is released.
+            let handshake_completed;
+            let mut ddns_reresolved = false;
+            let mut restart_receiver = false;
 
             {
                 let mut st = state.lock();
 
+                sample_rx_queue(&st.endpoint_socket);
+                #[cfg(debug_assertions)]
+                observe_session(&st.tunnel);
+
+                // Persistent EPERM means the socket is stuck on a network that went
+                // away; only a new socket recovers it
+                if eperm_rebind_due()
+                    && last_eperm_rebind.map_or(true, |at| at.elapsed() >= EPERM_REBIND_MIN_INTERVAL)
+                {
+                    last_eperm_rebind = Some(Instant::now());
+                    let eperms = CONSECUTIVE_SEND_EPERMS.swap(0, Ordering::Relaxed);
+                    warn!("{} consecutive endpoint sends failed with EPERM, rebinding endpoint socket", eperms);
+                    match Self::replace_endpoint_socket(&mut st, &config, false) {
+                        Ok(send_socket) => {
+                            new_send_socket = Some(send_socket);
+                            STAT_ENDPOINT_REBINDS.fetch_add(1, Ordering::Relaxed);
+                            DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+                        }
+                        Err(e) => {
+                            warn!("Automatic endpoint rebind failed: {}", e);
+                            record_tunnel_error(format!("auto rebind: {}", e));
+                        }
+                    }
+                }
+
+                // A receiver that stopped going round its loop delivers nothing while
+                // the tunnel still looks up: give it a fresh socket and a new thread
+                let silent_for = receiver_liveness.silent_for();
+                if receiver_stalled(silent_for, tick_gap) {
+                    let stalls = STAT_RECEIVER_STALLS.fetch_add(1, Ordering::Relaxed) + 1;
+                    error!(
+                        "WireGuard receiver thread unresponsive for {}ms (stall #{}), restarting it",
+                        silent_for.as_millis(), stalls
+                    );
+                    record_tunnel_error(format!("receiver stalled for {}ms", silent_for.as_millis()));
+                    match Self::replace_endpoint_socket(&mut st, &config, false) {
+                        Ok(send_socket) => {
+                            new_send_socket = Some(send_socket);
+                            DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+                        }
+                        Err(e) => warn!("Endpoint rebind after receiver stall failed: {}", e),
+                    }
+                    restart_receiver = true;
+                }
+
+                if let Some(paused_for) = resumed_after {
+                    // The pause must not count towards the DDNS timeout
+                    st.last_handshake = Instant::now();
+                    if pause_needs_rehandshake(paused_for) {
+                        info!("Resumed after {}s pause, re-initiating handshake", paused_for.as_secs());
+                        match st.tunnel.format_handshake_initiation(&mut dst_buf, false) {
+                            TunnResult::WriteToNetwork(data) => {
+                                if let Err(e) = st.endpoint_socket.send(data) {
+                                    warn!("Failed to send handshake after resume: {}", e);
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+
                 // Check for DDNS re-resolution (same as WireGuard's reresolve-dns.sh)
                 // If no successful packet in DDNS_RERESOLVE_TIMEOUT_SECS, re-resolve DNS.
                 // Use a separate retry interval to avoid waiting the full timeout on failure
@@ -496,15 +858,22 @@ impl WireGuardTunnel {
                     // Reset last_handshake to exclude sleep duration from the elapsed count
                     st.last_handshake = Instant::now();
                     true
+                } else if debug_ddns_forced() {
+                    info!("DDNS: simulated endpoint change, triggering re-resolution");
+                    true
                 } else {
                     last_handshake_elapsed > Duration::from_secs(DDNS_RERESOLVE_TIMEOUT_SECS)
                         && last_ddns_attempt.elapsed() > Duration::from_secs(DDNS_RETRY_INTERVAL_SECS)
                 };
                 if should_check_ddns {
                     last_ddns_attempt = Instant::now();
+                    ddns_reresolved = true;
+                    STAT_DDNS_RERESOLUTIONS.fetch_add(1, Ordering::Relaxed);
                     info!("DDNS: no handshake for {} seconds, re-resolving endpoint",
                           last_handshake_elapsed.as_secs());
 
+                    // A warm-resolved entry may be the very address that stopped working
+                    crate::wireguard_config::forget_cached_endpoint(&config.endpoint);
                     match config.resolve_endpoint() {
                         Ok(new_addr) => {
                             if new_addr != st.resolved_endpoint {
@@ -512,10 +881,11 @@ impl WireGuardTunnel {
                                       config.endpoint, st.resolved_endpoint, new_addr);
 
                                 // Create new socket and connect to new address (address family must match)
-                                match UdpSocket::bind(bind_addr_for(&new_addr)) {
+                                match config.bind_addr_for(&new_addr).and_then(UdpSocket::bind) {
                                     Ok(new_socket) => {
                                         if let Err(e) = new_socket.connect(new_addr) {
                                             warn!("DDNS: failed to connect to new endpoint: {}", e);
+                                            record_tunnel_error(format!("DDNS connect {}: {}", new_addr, e));
                                         } else {
                                             new_socket.set_nonblocking(false).ok();
                                             new_socket.set_read_timeout(Some(Duration::from_millis(10))).ok();
@@ -540,6 +910,7 @@ impl WireGuardTunnel {
                                     }
                                     Err(e) => {
                                         warn!("DDNS: failed to create new socket: {}", e);
+                                        record_tunnel_error(format!("DDNS socket: {}", e));
                                     }
                                 }
                             } else {
@@ -565,6 +936,7 @@ impl WireGuardTunnel {
                         Err(e) => {
                             warn!("DDNS re-resolution failed (will retry in {}s): {}",
                                   DDNS_RETRY_INTERVAL_SECS, e);
+                            record_tunnel_error(format!("DDNS resolve: {}", e));
 
                             // DNS failed (possibly device just woke up), but the existing endpoint
                             // IP may still be valid — try handshake with current endpoint anyway
@@ -587,36 +959,57 @@ impl WireGuardTunnel {
                 loop {
                     match st.tunnel.update_timers(&mut dst_buf) {
                         TunnResult::WriteToNetwork(data) => {
-                            if let Err(e) = st.endpoint_socket.send(data) {
-                                // EPERM (os error 1) is common on Android when network state changes
-                                // Only log non-EPERM errors to reduce log spam
-                                if e.raw_os_error() != Some(1) {
+                            let result = st.endpoint_socket.send(data);
+                            record_endpoint_send(&result);
+                            if let Err(e) = result {
+                                // EPERM (os error 1) is common on Android when network state changes.
+                                // Only log non-EPERM errors to reduce log spam; a run of them
+                                // triggers an automatic rebind (see eperm_rebind_due)
+                                if e.raw_os_error() != Some(libc::EPERM) {
                                     debug!("Failed to send timer packet: {}", e);
                                 }
                             }
                         }
                         TunnResult::Err(e) => {
                             warn!("WireGuard timer error: {:?}", e);
+                            record_tunnel_error(format!("timer: {:?}", e));
 
                             // Check if this is a connection expired error
                             let error_str = format!("{:?}", e);
                             if error_str.contains("ConnectionExpired") {
                                 handshake_retry_count += 1;
+                                STAT_REHANDSHAKES.fetch_add(1, Ordering::Relaxed);
                                 warn!("Connection expired, re-initiating handshake (attempt {})",
                                       handshake_retry_count);
+                                if handshake_retry_count == 1 {
+                                    // New outage: count handshake traffic from here
+                                    HANDSHAKE_TX_BYTES.store(0, Ordering::Relaxed);
+                                    HANDSHAKE_RX_BYTES.store(0, Ordering::Relaxed);
+                                }
+                                if handshake_escalated(handshake_retry_count)
+                                    && !handshake_escalated(handshake_retry_count - 1)
+                                {
+                                    error!("WireGuard peer unreachable after {} handshake attempts, still retrying",
+                                           handshake_retry_count);
+                                    error!("{}", failure_diagnostics(
+                                        "peer unreachable", &st, &config.endpoint, handshake_retry_count));
+                                }
 
                                 // Mark handshake as not completed
                                 st.handshake_completed.store(false, Ordering::Release);
 
                                 // Always retry - WireGuard connections can recover after
                                 // network changes, temporary outages, or NAT rebinding.
-                                // A hard cap would permanently kill the tunnel.
+                                // A hard cap would permanently kill the tunnel; past the
+                                // escalation threshold the tunnel is only reported Unreachable.
                                 match st.tunnel.format_handshake_initiation(&mut dst_buf, false) {
                                     TunnResult::WriteToNetwork(data) => {
-                                        if let Err(e) = st.endpoint_socket.send(data) {
-                                            warn!("Failed to send handshake re-initiation: {}", e);
-                                        } else {
-                                            info!("Sent handshake re-initiation");
+                                        match st.endpoint_socket.send(data) {
+                                            Ok(sent) => {
+                                                HANDSHAKE_TX_BYTES.fetch_add(sent as u64, Ordering::Relaxed);
+                                                info!("Sent handshake re-initiation");
+                                            }
+                                            Err(e) => warn!("Failed to send handshake re-initiation: {}", e),
                                         }
                                     }
                                     _ => {}
@@ -629,24 +1022,82 @@ impl WireGuardTunnel {
                     }
                 }
 
-                // Reset retry count if handshake is completed
+                // Start the next handshake a little before REKEY_AFTER_TIME, while the
+                // current session is still valid, so traffic never waits on re-keying.
+                // Returns Done while a handshake is already in flight, so this sends
+                // one initiation per session.
                 if st.handshake_completed.load(Ordering::Acquire) {
+                    let session_age = st.tunnel.time_since_last_handshake();
+                    if session_age.is_some_and(proactive_rekey_due) {
+                        if let TunnResult::WriteToNetwork(data) =
+                            st.tunnel.format_handshake_initiation(&mut dst_buf, false)
+                        {
+                            if let Err(e) = st.endpoint_socket.send(data) {
+                                warn!("Failed to send proactive rekey handshake: {}", e);
+                            } else {
+                                info!("Proactive rekey: session age {}s, initiated handshake",
+                                      session_age.unwrap_or_default().as_secs());
+                            }
+                        }
+                    }
+                }
+
+                // Reset retry count if handshake is completed
+                handshake_completed = st.handshake_completed.load(Ordering::Acquire);
+                if handshake_completed {
                     handshake_retry_count = 0;
                 }
             } // state lock released here
 
+            // Notify the state listener outside the state lock. Stop is reported by
+            // wg_stop_tunnel, so a tunnel that is shutting down stays silent here.
+            if running.load(Ordering::Relaxed) {
+                let tunnel_state = if handshake_completed && !ddns_reresolved {
+                    ever_connected = true;
+                    WgTunnelState::Connected
+                } else if handshake_escalated(handshake_retry_count) {
+                    WgTunnelState::Unreachable
+                } else if ever_connected {
+                    WgTunnelState::Reconnecting
+                } else {
+                    WgTunnelState::Connecting
+                };
+                report_tunnel_state(tunnel_state);
+
+                if handshake_completed && !DATA_FLOWING_REPORTED.load(Ordering::Acquire) {
+                    let waited = data_wait_since.get_or_insert_with(Instant::now).elapsed();
+                    if !no_data_reported && no_data_overdue(waited) {
+                        no_data_reported = true;
+                        report_no_data(waited);
+                    }
+                } else {
+                    data_wait_since = None;
+                    no_data_reported = false;
+                }
+            }
+
+            if restart_receiver {
+                if let Err(e) = Self::spawn_receiver(&state, &running, &receiver_liveness, &threads) {
+                    error!("Failed to restart WireGuard receiver thread: {}", e);
+                    record_tunnel_error(format!("receiver restart: {}", e));
+                }
+            }
+
             // Update send cache OUTSIDE the state lock to avoid deadlock.
             // Lock ordering: send path holds WG_SEND_CACHE -> state,
             // so we must NOT hold state -> WG_SEND_CACHE.
             if let Some(new_sock) = new_send_socket {
                 let mut cache = WG_SEND_CACHE.lock();
                 if let Some(ref mut c) = *cache {
-                    c.send_socket = new_sock;
-                    info!("DDNS: updated send cache with new socket");
+                    c.replace_send_socket(new_sock);
+                    info!("Timer: updated send cache with new endpoint socket");
                 }
             }
         }
 
+        // The state listener may have attached this thread to the JVM
+        crate::jni_helpers::detach_current_thread();
+
         info!("WireGuard timer thread stopped");
     }
 }
@@ -661,26 +1112,38 @@ impl Drop for WireGuardTunnel {
 // IP/UDP packet construction helpers (IPv4 + IPv6, zero-alloc variants)
 // ============================================================================
 
-/// Build an IPv4 or IPv6 UDP packet into the provided buffer.
-/// Returns the number of bytes written. Zero-allocation hot path.
-pub fn build_udp_ip_packet_into(buf: &mut [u8], src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> usize {
+/// Build an IPv4 or IPv6 UDP packet into the provided buffer, with `ttl` as the
+/// IPv4 TTL or IPv6 hop limit and `traffic_class` as the IPv4 DSCP+ECN byte or
+/// IPv6 traffic class. Returns the number of bytes written.
+/// Zero-allocation hot path.
+pub fn build_udp_ip_packet_into(
+    buf: &mut [u8],
+    src: SocketAddr,
+    dst: SocketAddr,
+    payload: &[u8],
+    ttl: u8,
+    traffic_class: u8,
+) -> usize {
     match (src.ip(), dst.ip()) {
-        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => {
-            build_udp_ipv4_packet_into(buf, src_ip, src.port(), dst_ip, dst.port(), payload)
-        }
-        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => {
-            build_udp_ipv6_packet_into(buf, src_ip, src.port(), dst_ip, dst.port(), payload)
-        }
+        (IpAddr::V4(src_ip), IpAddr::V4(dst_ip)) => build_udp_ipv4_packet_into(
+            buf, src_ip, src.port(), dst_ip, dst.port(), payload, ttl, traffic_class,
+        ),
+        (IpAddr::V6(src_ip), IpAddr::V6(dst_ip)) => build_udp_ipv6_packet_into(
+            buf, src_ip, src.port(), dst_ip, dst.port(), payload, ttl, traffic_class,
+        ),
         _ => 0, // Mismatched address families
     }
 }
 
 /// Build an IPv4/UDP packet into buf. Returns total bytes written.
+#[allow(clippy::too_many_arguments)]
 fn build_udp_ipv4_packet_into(
     buf: &mut [u8],
     src_ip: Ipv4Addr, src_port: u16,
     dst_ip: Ipv4Addr, dst_port: u16,
     payload: &[u8],
+    ttl: u8,
+    tos: u8,
 ) -> usize {
     let udp_len = 8 + payload.len();
     let total_len = 20 + udp_len;
@@ -690,11 +1153,11 @@ fn build_udp_ipv4_packet_into(
 
     // IPv4 header (20 bytes)
     buf[0] = 0x45; // Version (4) + IHL (5)
-    buf[1] = 0x00; // DSCP + ECN
+    buf[1] = tos; // DSCP + ECN
     buf[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
     buf[4..6].copy_from_slice(&[0x00, 0x00]); // Identification
     buf[6..8].copy_from_slice(&[0x40, 0x00]); // Flags (DF)
-    buf[8] = 64; // TTL
+    buf[8] = ttl;
     buf[9] = 17; // Protocol (UDP)
     buf[10..12].copy_from_slice(&[0x00, 0x00]); // Checksum placeholder
     buf[12..16].copy_from_slice(&src_ip.octets());
@@ -718,11 +1181,14 @@ fn build_udp_ipv4_packet_into(
 }
 
 /// Build an IPv6/UDP packet into buf. Returns total bytes written.
+#[allow(clippy::too_many_arguments)]
 fn build_udp_ipv6_packet_into(
     buf: &mut [u8],
     src_ip: Ipv6Addr, src_port: u16,
     dst_ip: Ipv6Addr, dst_port: u16,
     payload: &[u8],
+    hop_limit: u8,
+    traffic_class: u8,
 ) -> usize {
     let udp_len = 8 + payload.len();
     let total_len = 40 + udp_len; // IPv6 header (40) + UDP
@@ -731,12 +1197,12 @@ fn build_udp_ipv6_packet_into(
     }
 
     // IPv6 header (40 bytes)
-    buf[0] = 0x60; // Version (6) + Traffic Class high nibble
-    buf[1] = 0x00; // Traffic Class low nibble + Flow Label high
+    buf[0] = 0x60 | (traffic_class >> 4); // Version (6) + Traffic Class high nibble
+    buf[1] = (traffic_class & 0x0F) << 4; // Traffic Class low nibble + Flow Label high
     buf[2..4].copy_from_slice(&[0x00, 0x00]); // Flow Label low
     buf[4..6].copy_from_slice(&(udp_len as u16).to_be_bytes()); // Payload length
     buf[6] = 17; // Next Header (UDP)
-    buf[7] = 64; // Hop Limit
+    buf[7] = hop_limit;
     buf[8..24].copy_from_slice(&src_ip.octets()); // Source
     buf[24..40].copy_from_slice(&dst_ip.octets()); // Destination
 
@@ -758,28 +1224,37 @@ fn build_udp_ipv6_packet_into(
     total_len
 }
 
-/// Allocating version for callers that need a Vec (backward compat)
+/// Allocating version for callers that need a Vec (backward compat).
+/// Uses the default TTL and Not-ECT.
 pub fn build_udp_ip_packet(src: SocketAddr, dst: SocketAddr, payload: &[u8]) -> Vec<u8> {
     let max_len = 40 + 8 + payload.len(); // IPv6 header max
     let mut buf = vec![0u8; max_len];
-    let len = build_udp_ip_packet_into(&mut buf, src, dst, payload);
+    let len = build_udp_ip_packet_into(
+        &mut buf, src, dst, payload, WireGuardConfig::DEFAULT_INNER_TTL, WireGuardConfig::ECN_NOT_ECT,
+    );
     buf.truncate(len);
     buf
 }
 
 /// Calculate an IPv4 header checksum
 fn ip_checksum(header: &[u8]) -> u16 {
+    internet_checksum(header, Some(10))
+}
+
+/// RFC 1071 Internet checksum of `data`, treating the 16-bit checksum field at
+/// `checksum_offset` (if any) as zero
+fn internet_checksum(data: &[u8], checksum_offset: Option<usize>) -> u16 {
     let mut sum: u32 = 0;
     let mut i = 0;
-    while i < header.len() {
-        if i == 10 {
+    while i < data.len() {
+        if Some(i) == checksum_offset {
             i += 2;
             continue;
         }
-        let word = if i + 1 < header.len() {
-            ((header[i] as u32) << 8) | (header[i + 1] as u32)
+        let word = if i + 1 < data.len() {
+            ((data[i] as u32) << 8) | (data[i + 1] as u32)
         } else {
-            (header[i] as u32) << 8
+            (data[i] as u32) << 8
         };
         sum += word;
         i += 2;
@@ -849,15 +1324,21 @@ fn parse_udp_from_ipv4(packet: &[u8]) -> Option<(u16, u16, &[u8])> {
     if packet.len() < 28 {
         return None;
     }
+    // The header may carry options (IHL > 5); the UDP header starts after them
     let ihl = (packet[0] & 0x0F) as usize * 4;
-    if packet[9] != 17 || packet.len() < ihl + 8 {
+    if ihl < 20 || packet[9] != 17 {
+        return None;
+    }
+    // Ignore any link padding past the IPv4 total length
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if total_len > packet.len() || total_len < ihl + 8 {
         return None;
     }
-    let udp = &packet[ihl..];
+    let udp = &packet[ihl..total_len];
     let src_port = u16::from_be_bytes([udp[0], udp[1]]);
     let dst_port = u16::from_be_bytes([udp[2], udp[3]]);
     let udp_len = u16::from_be_bytes([udp[4], udp[5]]) as usize;
-    if udp_len < 8 || ihl + udp_len > packet.len() {
+    if udp_len < 8 || udp_len > udp.len() {
         return None;
     }
     Some((src_port, dst_port, &udp[8..udp_len]))
@@ -881,6 +1362,35 @@ fn parse_udp_from_ipv6(packet: &[u8]) -> Option<(u16, u16, &[u8])> {
     Some((src_port, dst_port, &udp[8..udp_len]))
 }
 
+// ============================================================================
+// WireGuard message classification
+// ============================================================================
+
+/// Type of a WireGuard message received from the endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WgMessageType {
+    HandshakeInitiation,
+    HandshakeResponse,
+    CookieReply,
+    TransportData,
+    Unknown,
+}
+
+/// Classify a raw WireGuard packet by its message type and length
+/// (types 1-4 from the WireGuard protocol, little-endian u32 with reserved zero bytes).
+fn classify_wg_message(packet: &[u8]) -> WgMessageType {
+    if packet.len() < 4 {
+        return WgMessageType::Unknown;
+    }
+    match (u32::from_le_bytes([packet[0], packet[1], packet[2], packet[3]]), packet.len()) {
+        (1, 148) => WgMessageType::HandshakeInitiation,
+        (2, 92) => WgMessageType::HandshakeResponse,
+        (3, 64) => WgMessageType::CookieReply,
+        (4, len) if len >= 32 => WgMessageType::TransportData,
+        _ => WgMessageType::Unknown,
+    }
+}
+
 // ============================================================================
 // Device sleep/wake tracking for DDNS optimization
 // ============================================================================
@@ -911,109 +1421,1351 @@ pub fn wg_is_device_sleeping() -> bool {
 }
 
 // ============================================================================
-// Global WireGuard tunnel instance + performance-optimized send cache
+// Pause/resume while the app is backgrounded
 // ============================================================================
 
-static GLOBAL_TUNNEL: Mutex<Option<WireGuardTunnel>> = Mutex::new(None);
+/// Set while the app is backgrounded. The receiver thread stops reading and the
+/// timer thread skips keepalives and DDNS, but the tunnel (keys, sockets and the
+/// send cache) is left intact so resume needs no reconnect.
+static TUNNEL_PAUSED: AtomicBool = AtomicBool::new(false);
 
-/// Cached state for hot-path packet sending.
-/// Avoids double-lock on GLOBAL_TUNNEL and per-packet socket dup() syscall.
-struct WgSendCache {
-    state: Arc<Mutex<TunnelState>>,
-    send_socket: UdpSocket, // pre-cloned once
+/// Quiesce the tunnel threads. Sending still works while paused.
+pub fn wg_pause() {
+    if !TUNNEL_PAUSED.swap(true, Ordering::AcqRel) {
+        info!("WireGuard tunnel paused");
+    }
 }
-static WG_SEND_CACHE: Mutex<Option<WgSendCache>> = Mutex::new(None);
 
-// Thread-local encode buffer to avoid per-packet heap allocation (~65KB).
-thread_local! {
-    static ENCODE_BUF: RefCell<Vec<u8>> = RefCell::new(vec![0u8; WG_BUFFER_SIZE]);
+/// Resume the tunnel threads. The timer thread re-initiates the handshake if the
+/// pause lasted longer than PAUSE_REHANDSHAKE_AFTER.
+pub fn wg_resume() {
+    if TUNNEL_PAUSED.swap(false, Ordering::AcqRel) {
+        info!("WireGuard tunnel resumed");
+    }
 }
 
-/// Initialize and start the global WireGuard tunnel
-pub fn wg_start_tunnel(config: WireGuardConfig) -> io::Result<()> {
-    let mut global = GLOBAL_TUNNEL.lock();
-    
-    // Stop any existing tunnel
-    if let Some(ref tunnel) = *global {
-        tunnel.stop();
-    }
-    // Clear send cache
-    *WG_SEND_CACHE.lock() = None;
+/// Check whether the tunnel is currently paused.
+pub fn wg_is_paused() -> bool {
+    TUNNEL_PAUSED.load(Ordering::Acquire)
+}
 
-    let tunnel = WireGuardTunnel::new(config)?;
-    tunnel.start()?;
-    
-    // Wait for handshake with active retry (timeout allows ~4 retry attempts with backoff)
-    if !tunnel.wait_for_handshake(Duration::from_secs(15)) {
-        tunnel.stop();
-        return Err(io::Error::new(io::ErrorKind::TimedOut, "WireGuard handshake timed out"));
-    }
+/// Whether a pause of this length warrants a fresh handshake on resume
+fn pause_needs_rehandshake(paused_for: Duration) -> bool {
+    paused_for > PAUSE_REHANDSHAKE_AFTER
+}
 
-    // Populate send cache for hot-path
-    {
-        let state_arc = tunnel.state.clone();
-        let send_socket = {
-            let st = state_arc.lock();
-            st.endpoint_socket.try_clone()
-                .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Socket clone for cache: {}", e)))?
-        };
-        *WG_SEND_CACHE.lock() = Some(WgSendCache {
-            state: state_arc,
-            send_socket,
-        });
-    }
+// ============================================================================
+// Tunnel state change notifications
+// ============================================================================
+
+/// Tunnel state reported to the state listener.
+/// Values match the STATE_* constants in WireGuardManager.java.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum WgTunnelState {
+    Stopped = 0,
+    Connecting = 1,
+    Connected = 2,
+    Reconnecting = 3,
+    /// Consecutive handshake attempts expired without hearing from the peer.
+    /// The tunnel keeps retrying and returns to Connected if the peer answers.
+    Unreachable = 4,
+}
+
+/// Default number of consecutive expired handshake attempts before the tunnel
+/// is reported Unreachable. Each attempt lasts up to REKEY_ATTEMPT_TIME (90s).
+pub const DEFAULT_HANDSHAKE_ESCALATION_THRESHOLD: u32 = 3;
+
+/// Consecutive expired handshake attempts before reporting Unreachable (0 = never)
+static HANDSHAKE_ESCALATION_THRESHOLD: AtomicU32 =
+    AtomicU32::new(DEFAULT_HANDSHAKE_ESCALATION_THRESHOLD);
+
+/// Set how many consecutive expired handshake attempts (with no packet received
+/// from the peer in between) escalate the tunnel state to Unreachable.
+/// 0 disables escalation. Takes effect on the next timer tick.
+pub fn wg_set_handshake_escalation_threshold(threshold: u32) {
+    HANDSHAKE_ESCALATION_THRESHOLD.store(threshold, Ordering::Relaxed);
+    info!("WireGuard handshake escalation threshold set to {}", threshold);
+}
+
+/// Whether `retry_count` consecutive expired handshakes warrant reporting Unreachable
+fn handshake_escalated(retry_count: u32) -> bool {
+    let threshold = HANDSHAKE_ESCALATION_THRESHOLD.load(Ordering::Relaxed);
+    threshold != 0 && retry_count >= threshold
+}
+
+/// Default number of unanswered initial handshake retries before the endpoint
+/// socket moves to a new source port
+pub const DEFAULT_SOURCE_PORT_ROTATION_RETRIES: u32 = 3;
+
+/// Unanswered initial handshake retries per source port (0 = never rotate)
+static SOURCE_PORT_ROTATION_RETRIES: AtomicU32 = AtomicU32::new(DEFAULT_SOURCE_PORT_ROTATION_RETRIES);
+
+/// Set how many unanswered handshake retries wg_start_tunnel sends from one
+/// source port before rebinding the endpoint socket to a fresh ephemeral port.
+/// A new port gets a new NAT mapping, which rescues networks where the first
+/// one is blocked. 0 keeps the original port. Takes effect on the next start.
+pub fn wg_set_source_port_rotation_retries(retries: u32) {
+    SOURCE_PORT_ROTATION_RETRIES.store(retries, Ordering::Relaxed);
+    info!("WireGuard source port rotation after {} handshake retries", retries);
+}
+
+/// Whether handshake retry `retry_count` should go out from a new source port:
+/// every SOURCE_PORT_ROTATION_RETRIES retries, so each port gets the same chance
+fn source_port_rotation_due(retry_count: u32) -> bool {
+    let retries = SOURCE_PORT_ROTATION_RETRIES.load(Ordering::Relaxed);
+    retries != 0 && retry_count != 0 && retry_count % retries == 0
+}
+
+/// Default lead time before REKEY_AFTER_TIME at which a new handshake is started
+pub const DEFAULT_PROACTIVE_REKEY_LEAD_SECS: u64 = 15;
+
+/// Upper bound on the rekey lead time, so a session always lives a while before
+/// it is replaced
+const MAX_PROACTIVE_REKEY_LEAD_SECS: u64 = 60;
+
+/// Seconds before REKEY_AFTER_TIME to start re-keying (0 = leave it to boringtun)
+static PROACTIVE_REKEY_LEAD_SECS: AtomicU64 = AtomicU64::new(DEFAULT_PROACTIVE_REKEY_LEAD_SECS);
+
+/// Set how many seconds before the session's rekey-after time the timer thread
+/// starts a new handshake. 0 disables proactive re-keying; values above
+/// MAX_PROACTIVE_REKEY_LEAD_SECS are clamped. Takes effect on the next timer tick.
+pub fn wg_set_proactive_rekey_lead(secs: u64) {
+    let secs = secs.min(MAX_PROACTIVE_REKEY_LEAD_SECS);
+    PROACTIVE_REKEY_LEAD_SECS.store(secs, Ordering::Relaxed);
+    info!("WireGuard proactive rekey lead set to {}s", secs);
+}
 
-    *global = Some(tunnel);
+/// Whether a session this old should be re-keyed now
+fn proactive_rekey_due(session_age: Duration) -> bool {
+    let lead = PROACTIVE_REKEY_LEAD_SECS.load(Ordering::Relaxed);
+    lead != 0 && session_age + Duration::from_secs(lead) >= REKEY_AFTER_TIME
+}
+
+/// Retry schedule applied to tunnels started through JNI (see
+/// wg_set_handshake_retry_schedule)
+static HANDSHAKE_RETRY_SCHEDULE: Mutex<HandshakeRetrySchedule> =
+    Mutex::new(HandshakeRetrySchedule::DEFAULT);
+
+/// Set the handshake retry schedule for tunnels started after this call.
+/// Rejects schedules that don't pass HandshakeRetrySchedule::validate.
+pub fn wg_set_handshake_retry_schedule(schedule: HandshakeRetrySchedule) -> io::Result<()> {
+    schedule.validate()?;
+    *HANDSHAKE_RETRY_SCHEDULE.lock() = schedule;
+    info!("WireGuard handshake retry schedule set to {:?}", schedule);
     Ok(())
 }
 
-/// Stop the global WireGuard tunnel
-pub fn wg_stop_tunnel() {
-    // Disable zero-copy routing before stopping the tunnel
-    crate::platform_sockets::disable_wg_routing();
+/// Handshake retry schedule for the next tunnel started through JNI
+pub fn wg_handshake_retry_schedule() -> HandshakeRetrySchedule {
+    *HANDSHAKE_RETRY_SCHEDULE.lock()
+}
 
-    // Clear send cache first
-    *WG_SEND_CACHE.lock() = None;
+/// Set by wg_cancel_handshake to abort wait_for_handshake in wg_start_tunnel
+static HANDSHAKE_CANCELLED: AtomicBool = AtomicBool::new(false);
 
-    let mut global = GLOBAL_TUNNEL.lock();
-    if let Some(ref tunnel) = *global {
-        tunnel.stop();
+/// Abort a wg_start_tunnel that is waiting for the initial handshake.
+/// The start call returns an Interrupted error promptly and leaves no tunnel
+/// running. Has no effect on a tunnel that is already connected.
+pub fn wg_cancel_handshake() {
+    HANDSHAKE_CANCELLED.store(true, Ordering::Release);
+    info!("WireGuard handshake cancellation requested");
+}
+
+/// Listener invoked whenever the tunnel state changes.
+/// Set by Java via JNI: WireGuardManager.nativeSetWgStateListener().
+static STATE_LISTENER: Mutex<Option<fn(WgTunnelState)>> = Mutex::new(None);
+
+/// Last state passed to the listener, used to suppress duplicate notifications.
+static LAST_REPORTED_STATE: AtomicI32 = AtomicI32::new(WgTunnelState::Stopped as i32);
+
+/// Set (or clear with `None`) the tunnel state listener.
+pub fn wg_set_state_listener(listener: Option<fn(WgTunnelState)>) {
+    *STATE_LISTENER.lock() = listener;
+}
+
+/// Notify the state listener if `state` differs from the last reported state.
+/// The listener is called without holding any lock so it may call back into the tunnel.
+fn report_tunnel_state(state: WgTunnelState) {
+    if LAST_REPORTED_STATE.swap(state as i32, Ordering::AcqRel) == state as i32 {
+        return;
+    }
+
+    info!("WireGuard tunnel state changed: {:?}", state);
+    let listener = *STATE_LISTENER.lock();
+    if let Some(listener) = listener {
+        listener(state);
     }
-    *global = None;
 }
 
-/// Check if the WireGuard tunnel is active and ready
-pub fn wg_is_tunnel_active() -> bool {
-    let global = GLOBAL_TUNNEL.lock();
-    global.as_ref().map_or(false, |t| t.is_ready())
+/// Set once the first data packet has been decapsulated since the tunnel was
+/// started, its endpoint rebound, or direct routing enabled for a new
+/// connection. Cleared by wg_start_tunnel, rebind and wg_enable_direct_routing.
+static DATA_FLOWING_REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Returns true exactly once per start/rebind/connection: for the first data packet
+fn take_first_data_packet() -> bool {
+    !DATA_FLOWING_REPORTED.swap(true, Ordering::AcqRel)
 }
 
-/// Send an IP packet through the global WireGuard tunnel (hot path).
-///
-/// Performance: Uses cached `Arc<Mutex<TunnelState>>` and pre-cloned socket
-/// to avoid double-lock and per-packet `dup()` syscall. Uses thread-local
-/// encode buffer to avoid per-packet 65KB heap allocation.
-pub fn wg_send_ip_packet(packet: &[u8]) -> io::Result<()> {
-    let cache = WG_SEND_CACHE.lock();
-    let c = cache.as_ref().ok_or_else(|| {
-        io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active")
-    })?;
+/// Check whether the tunnel is up and has carried data since it was started,
+/// rebound, or routed for the current connection
+pub fn wg_is_data_flowing() -> bool {
+    wg_is_tunnel_active() && DATA_FLOWING_REPORTED.load(Ordering::Acquire)
+}
 
-    ENCODE_BUF.with(|buf_cell| {
-        let mut buf = buf_cell.borrow_mut();
-        // Encapsulate under tunnel state lock (fast crypto, ~microseconds)
-        // then send directly from the buffer - zero allocation hot path.
-        // The encrypted `data` slice borrows `buf` (not the lock), so we can
-        // send while still in the match arm without copying.
-        let mut st = c.state.lock();
-        match st.tunnel.encapsulate(packet, &mut buf) {
+// ============================================================================
+// Receiver liveness
+// ============================================================================
+
+// After some network changes a socket can end up in a state where the receiver
+// thread blocks for good. Nothing is delivered any more, yet the tunnel still
+// reports itself active. The receiver beats a heartbeat on every loop iteration
+// (at most every 10ms read timeout, or every PAUSED_POLL_INTERVAL while paused);
+// the timer thread restarts it on a fresh socket once it falls silent.
+
+/// Default time the receiver may go without a heartbeat before it counts as stuck
+pub const DEFAULT_RECEIVER_STALL_WINDOW_MS: u64 = 5000;
+
+/// Receiver silence (ms) that counts as a stall (0 = never restart it)
+static RECEIVER_STALL_WINDOW_MS: AtomicU64 = AtomicU64::new(DEFAULT_RECEIVER_STALL_WINDOW_MS);
+
+/// Receiver stalls detected since the tunnel started
+static STAT_RECEIVER_STALLS: AtomicU64 = AtomicU64::new(0);
+
+/// Heartbeat of a tunnel's receiver thread
+struct ReceiverLiveness {
+    /// Receiver thread that should be running; an older one exits when it sees a newer id
+    id: AtomicU64,
+    /// When the receiver last went round its loop, in ms since `epoch`
+    heartbeat_ms: AtomicU64,
+    epoch: Instant,
+}
+
+impl ReceiverLiveness {
+    fn new() -> Self {
+        Self { id: AtomicU64::new(0), heartbeat_ms: AtomicU64::new(0), epoch: Instant::now() }
+    }
+
+    /// Record that the receiver is alive (a relaxed store, cheap enough for every iteration)
+    #[inline]
+    fn beat(&self) {
+        self.heartbeat_ms.store(self.epoch.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Time since the last heartbeat
+    fn silent_for(&self) -> Duration {
+        let now_ms = self.epoch.elapsed().as_millis() as u64;
+        Duration::from_millis(now_ms.saturating_sub(self.heartbeat_ms.load(Ordering::Relaxed)))
+    }
+}
+
+/// Set how long the receiver thread may go without a heartbeat before the timer
+/// thread restarts it on a new endpoint socket. 0 disables the check.
+pub fn wg_set_receiver_stall_window(window_ms: u64) {
+    RECEIVER_STALL_WINDOW_MS.store(window_ms, Ordering::Relaxed);
+    info!("WireGuard receiver stall window set to {}ms", window_ms);
+}
+
+/// Number of receiver stalls detected (and recovered from) since the tunnel started
+pub fn wg_receiver_stall_count() -> u64 {
+    STAT_RECEIVER_STALLS.load(Ordering::Relaxed)
+}
+
+/// Whether a receiver silent for `silent_for` is stuck. Only trusted when the
+/// timer itself ran on time (`tick_gap` within the window): after the device
+/// froze both threads, the receiver just hasn't been scheduled yet.
+fn receiver_stalled(silent_for: Duration, tick_gap: Duration) -> bool {
+    let window = RECEIVER_STALL_WINDOW_MS.load(Ordering::Relaxed);
+    window != 0
+        && silent_for > Duration::from_millis(window)
+        && tick_gap < Duration::from_millis(window)
+}
+
+// ============================================================================
+// No-data watchdog
+// ============================================================================
+
+// A server whose AllowedIPs or inner routing doesn't match the client completes
+// handshakes normally but never sends data back. The tunnel looks active and the
+// user gets a black screen, indistinguishable from a host that isn't streaming.
+// The timer thread watches for a completed handshake that goes this long without
+// a single decapsulated data packet and reports it as its own diagnostic.
+
+/// Default time a completed handshake may go without a data packet
+pub const DEFAULT_NO_DATA_WINDOW_SECS: u64 = 30;
+
+/// Seconds to wait for the first data packet after a handshake (0 = never report)
+static NO_DATA_WINDOW_SECS: AtomicU64 = AtomicU64::new(DEFAULT_NO_DATA_WINDOW_SECS);
+
+/// Set when the watchdog fired and no data packet has arrived since.
+/// Cleared by wg_start_tunnel and by the first data packet.
+static NO_DATA_SUSPECTED: AtomicBool = AtomicBool::new(false);
+
+/// Set how long a completed handshake may go without any data packet from the
+/// tunnel before the no-data diagnostic fires. 0 disables it. Takes effect on
+/// the next timer tick.
+pub fn wg_set_no_data_window(secs: u64) {
+    NO_DATA_WINDOW_SECS.store(secs, Ordering::Relaxed);
+    info!("WireGuard no-data window set to {}s", secs);
+}
+
+/// Whether the tunnel completed its handshake but has carried no data for the
+/// whole no-data window
+pub fn wg_is_no_data_suspected() -> bool {
+    NO_DATA_SUSPECTED.load(Ordering::Acquire)
+}
+
+/// Whether waiting `waited` for the first data packet exceeds the window
+fn no_data_overdue(waited: Duration) -> bool {
+    let window = NO_DATA_WINDOW_SECS.load(Ordering::Relaxed);
+    window != 0 && waited >= Duration::from_secs(window)
+}
+
+/// Log and report a handshake that has carried no data for `waited`
+fn report_no_data(waited: Duration) {
+    warn!(
+        "WireGuard handshake completed but no data received for {}s: \
+         check the server's AllowedIPs and routing for this peer",
+        waited.as_secs()
+    );
+    NO_DATA_SUSPECTED.store(true, Ordering::Release);
+    record_tunnel_error(format!("no data for {}s after handshake", waited.as_secs()));
+    crate::callbacks::bridge_cl_tunnel_no_data(waited.as_secs().min(i32::MAX as u64) as i32);
+}
+
+// ============================================================================
+// MTU diagnostic
+// ============================================================================
+
+// A tunnel MTU that is too large in one direction lets handshakes, input and
+// audio through while full-size video packets vanish, which shows up as a black
+// screen with working audio. The probe pings the host through the tunnel with a
+// small and a full-MTU echo request: if only the small one comes back, large
+// packets are being lost somewhere on the path.
+
+/// Outcome of wg_probe_mtu. Values match WireGuardManager.MTU_PROBE_*.
+#[repr(i32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MtuProbeResult {
+    /// Both the small and the full-MTU echo came back
+    Ok = 0,
+    /// Only the small echo came back: full-size packets are lost on the path
+    PossibleMtuIssue = 1,
+    /// No echo came back at all (host doesn't answer pings, no IPv4 tunnel, or
+    /// no tunnel running), so nothing can be concluded
+    Inconclusive = 2,
+}
+
+/// Total size of the small probe packet, far below any usable tunnel MTU
+const MTU_PROBE_SMALL_SIZE: usize = 64;
+
+/// ICMP echo identifier of the probe, to tell its replies from other traffic
+const MTU_PROBE_ICMP_ID: u16 = 0x4d4c;
+
+/// Sequence number bit marking the full-MTU probe
+const MTU_PROBE_LARGE_SEQ: u16 = 0x8000;
+
+/// Rounds of probes sent within the timeout, to ride out a single lost packet
+const MTU_PROBE_ROUNDS: u32 = 3;
+
+/// Replies received for the running probe
+static MTU_PROBE_SMALL_REPLY: AtomicBool = AtomicBool::new(false);
+static MTU_PROBE_LARGE_REPLY: AtomicBool = AtomicBool::new(false);
+
+/// Set when the last probe saw small packets but not full-size ones.
+/// Cleared by wg_start_tunnel.
+static MTU_ISSUE_SUSPECTED: AtomicBool = AtomicBool::new(false);
+
+/// Build an IPv4 ICMP echo request of exactly `total_len` bytes
+fn build_icmp_echo_request(src: Ipv4Addr, dst: Ipv4Addr, seq: u16, total_len: usize, ttl: u8) -> Vec<u8> {
+    let total_len = total_len.clamp(20 + 8, u16::MAX as usize);
+    let mut packet = vec![0u8; total_len];
+
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&(total_len as u16).to_be_bytes());
+    packet[8] = ttl;
+    packet[9] = 1; // ICMP
+    packet[12..16].copy_from_slice(&src.octets());
+    packet[16..20].copy_from_slice(&dst.octets());
+    let checksum = ip_checksum(&packet[..20]);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+
+    let icmp = &mut packet[20..];
+    icmp[0] = 8; // Echo request
+    icmp[4..6].copy_from_slice(&MTU_PROBE_ICMP_ID.to_be_bytes());
+    icmp[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, byte) in icmp[8..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    let checksum = internet_checksum(icmp, Some(2));
+    icmp[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+/// Sequence number of an IPv4 echo reply to our probe, if `packet` is one
+fn parse_mtu_probe_reply(packet: &[u8]) -> Option<u16> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 || packet[9] != 1 {
+        return None;
+    }
+    let icmp = packet.get(((packet[0] & 0x0F) as usize) * 4..)?;
+    if icmp.len() < 8 || icmp[0] != 0 {
+        return None;
+    }
+    if u16::from_be_bytes([icmp[4], icmp[5]]) != MTU_PROBE_ICMP_ID {
+        return None;
+    }
+    Some(u16::from_be_bytes([icmp[6], icmp[7]]))
+}
+
+/// Record a decapsulated ICMP packet if it answers the MTU probe
+fn record_mtu_probe_reply(packet: &[u8]) {
+    if let Some(seq) = parse_mtu_probe_reply(packet) {
+        if seq & MTU_PROBE_LARGE_SEQ != 0 {
+            MTU_PROBE_LARGE_REPLY.store(true, Ordering::Release);
+        } else {
+            MTU_PROBE_SMALL_REPLY.store(true, Ordering::Release);
+        }
+    }
+}
+
+fn evaluate_mtu_probe(small_reply: bool, large_reply: bool) -> MtuProbeResult {
+    match (small_reply, large_reply) {
+        (_, true) => MtuProbeResult::Ok,
+        (true, false) => MtuProbeResult::PossibleMtuIssue,
+        (false, false) => MtuProbeResult::Inconclusive,
+    }
+}
+
+/// Ping `target` (the host's tunnel address) through the running tunnel with a
+/// small and a full-MTU echo request, waiting at most `timeout`. Logs a
+/// "possible MTU issue" warning and sets the flag read by
+/// wg_is_mtu_issue_suspected when only the small echo comes back.
+/// Only IPv4 tunnels are probed.
+pub fn wg_probe_mtu(target: Ipv4Addr, timeout: Duration) -> MtuProbeResult {
+    let (source, mtu, ttl) = match GLOBAL_TUNNEL.lock().as_ref() {
+        Some(tunnel) => match tunnel.config.tunnel_address_for(IpAddr::V4(target)) {
+            Some(IpAddr::V4(source)) => (source, tunnel.config.mtu as usize, tunnel.config.inner_ttl),
+            _ => {
+                info!("MTU probe skipped: the tunnel has no IPv4 address");
+                return MtuProbeResult::Inconclusive;
+            }
+        },
+        None => return MtuProbeResult::Inconclusive,
+    };
+
+    MTU_PROBE_SMALL_REPLY.store(false, Ordering::Release);
+    MTU_PROBE_LARGE_REPLY.store(false, Ordering::Release);
+
+    let round_timeout = timeout / MTU_PROBE_ROUNDS;
+    for round in 0..MTU_PROBE_ROUNDS as u16 {
+        let small = build_icmp_echo_request(source, target, round, MTU_PROBE_SMALL_SIZE, ttl);
+        let large = build_icmp_echo_request(source, target, MTU_PROBE_LARGE_SEQ | round, mtu, ttl);
+        if let Err(e) = wg_send_ip_packet(&small).and_then(|_| wg_send_ip_packet(&large)) {
+            warn!("MTU probe send failed: {}", e);
+            break;
+        }
+
+        let round_started = Instant::now();
+        while round_started.elapsed() < round_timeout
+            && !MTU_PROBE_LARGE_REPLY.load(Ordering::Acquire)
+        {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if MTU_PROBE_LARGE_REPLY.load(Ordering::Acquire) {
+            break;
+        }
+    }
+
+    let result = evaluate_mtu_probe(
+        MTU_PROBE_SMALL_REPLY.load(Ordering::Acquire),
+        MTU_PROBE_LARGE_REPLY.load(Ordering::Acquire),
+    );
+    match result {
+        MtuProbeResult::Ok => info!("MTU probe: {}-byte packets reach {} and back", mtu, target),
+        MtuProbeResult::PossibleMtuIssue => warn!(
+            "Possible MTU issue: {}-byte packets reach {} through the tunnel but {}-byte packets are lost; \
+             lower the tunnel MTU if video stays black",
+            MTU_PROBE_SMALL_SIZE, target, mtu
+        ),
+        MtuProbeResult::Inconclusive => info!("MTU probe: no echo reply from {}, result inconclusive", target),
+    }
+    MTU_ISSUE_SUSPECTED.store(result == MtuProbeResult::PossibleMtuIssue, Ordering::Release);
+    result
+}
+
+/// Whether the last MTU probe since the tunnel started suggested an MTU issue
+pub fn wg_is_mtu_issue_suspected() -> bool {
+    MTU_ISSUE_SUSPECTED.load(Ordering::Acquire)
+}
+
+// ============================================================================
+// Automatic rebind on persistent EPERM
+// ============================================================================
+
+/// Default number of consecutive endpoint sends failing with EPERM before the
+/// endpoint socket is rebound automatically
+pub const DEFAULT_EPERM_REBIND_THRESHOLD: u32 = 50;
+
+/// Minimum time between automatic rebinds, so a network that is still coming
+/// up isn't hit with a new socket every timer tick
+const EPERM_REBIND_MIN_INTERVAL: Duration = Duration::from_secs(5);
+
+static EPERM_REBIND_THRESHOLD: AtomicU32 = AtomicU32::new(DEFAULT_EPERM_REBIND_THRESHOLD);
+
+/// Endpoint sends that failed with EPERM since the last successful send
+static CONSECUTIVE_SEND_EPERMS: AtomicU32 = AtomicU32::new(0);
+
+/// Set how many consecutive EPERM send failures trigger an automatic endpoint
+/// rebind. 0 disables automatic rebinds.
+pub fn wg_set_eperm_rebind_threshold(threshold: u32) {
+    EPERM_REBIND_THRESHOLD.store(threshold, Ordering::Relaxed);
+    info!("WireGuard EPERM rebind threshold set to {}", threshold);
+}
+
+/// Track the outcome of a send on the endpoint socket. Any successful send
+/// ends the run, so isolated EPERMs during a network change never add up.
+fn record_endpoint_send<T>(result: &io::Result<T>) {
+    match result {
+        Ok(_) => {
+            if CONSECUTIVE_SEND_EPERMS.load(Ordering::Relaxed) != 0 {
+                CONSECUTIVE_SEND_EPERMS.store(0, Ordering::Relaxed);
+            }
+        }
+        Err(e) if e.raw_os_error() == Some(libc::EPERM) => {
+            CONSECUTIVE_SEND_EPERMS.fetch_add(1, Ordering::Relaxed);
+        }
+        Err(_) => {}
+    }
+}
+
+/// Whether sends have failed with EPERM often enough in a row to rebind
+fn eperm_rebind_due() -> bool {
+    let threshold = EPERM_REBIND_THRESHOLD.load(Ordering::Relaxed);
+    threshold != 0 && CONSECUTIVE_SEND_EPERMS.load(Ordering::Relaxed) >= threshold
+}
+
+// ============================================================================
+// Tunnel statistics
+// ============================================================================
+
+/// Handshakes re-initiated after the session expired
+static STAT_REHANDSHAKES: AtomicU64 = AtomicU64::new(0);
+
+/// DDNS re-resolutions of the endpoint hostname
+static STAT_DDNS_RERESOLUTIONS: AtomicU64 = AtomicU64::new(0);
+
+/// Successful endpoint socket rebinds (network changes)
+static STAT_ENDPOINT_REBINDS: AtomicU64 = AtomicU64::new(0);
+
+/// Traffic carried by the tunnel, counted as inner IP packets. Left in place by
+/// wg_stop_tunnel for a final snapshot; the next wg_start_tunnel resets them.
+static STAT_TX_BYTES: AtomicU64 = AtomicU64::new(0);
+static STAT_RX_BYTES: AtomicU64 = AtomicU64::new(0);
+static STAT_TX_PACKETS: AtomicU64 = AtomicU64::new(0);
+static STAT_RX_PACKETS: AtomicU64 = AtomicU64::new(0);
+
+/// Most recent tunnel error, for field debugging
+static LAST_TUNNEL_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+/// Handshake traffic on the endpoint socket since the tunnel started or the
+/// session last expired, for failure diagnostics
+static HANDSHAKE_TX_BYTES: AtomicU64 = AtomicU64::new(0);
+static HANDSHAKE_RX_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Most recent decapsulation error. Kept apart from LAST_TUNNEL_ERROR so that
+/// later errors of other kinds don't hide it.
+static LAST_DECAPSULATE_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+fn record_tunnel_error(error: String) {
+    *LAST_TUNNEL_ERROR.lock() = Some(error);
+}
+
+/// Count an inner packet of `len` bytes sent through the tunnel, if the send succeeded
+#[inline]
+fn record_tunnel_tx<T>(result: &io::Result<T>, len: usize) {
+    if result.is_ok() {
+        STAT_TX_BYTES.fetch_add(len as u64, Ordering::Relaxed);
+        STAT_TX_PACKETS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Count an inner packet of `len` bytes received through the tunnel
+#[inline]
+fn record_tunnel_rx(len: usize) {
+    STAT_RX_BYTES.fetch_add(len as u64, Ordering::Relaxed);
+    STAT_RX_PACKETS.fetch_add(1, Ordering::Relaxed);
+}
+
+fn reset_tunnel_stats() {
+    STAT_REHANDSHAKES.store(0, Ordering::Relaxed);
+    STAT_DDNS_RERESOLUTIONS.store(0, Ordering::Relaxed);
+    STAT_ENDPOINT_REBINDS.store(0, Ordering::Relaxed);
+    STAT_RECEIVER_STALLS.store(0, Ordering::Relaxed);
+    STAT_TX_BYTES.store(0, Ordering::Relaxed);
+    STAT_RX_BYTES.store(0, Ordering::Relaxed);
+    STAT_TX_PACKETS.store(0, Ordering::Relaxed);
+    STAT_RX_PACKETS.store(0, Ordering::Relaxed);
+    *LAST_TUNNEL_ERROR.lock() = None;
+    HANDSHAKE_TX_BYTES.store(0, Ordering::Relaxed);
+    HANDSHAKE_RX_BYTES.store(0, Ordering::Relaxed);
+    *LAST_DECAPSULATE_ERROR.lock() = None;
+    RX_QUEUE_HIGH_WATER.store(0, Ordering::Relaxed);
+    RX_QUEUE_CAPACITY.store(0, Ordering::Relaxed);
+    RX_QUEUE_OVER_THRESHOLD.store(0, Ordering::Relaxed);
+}
+
+/// One-line summary of a failed connection, logged once when the initial
+/// handshake times out or the peer is declared unreachable, so a user's logcat
+/// can be triaged without piecing together the debug lines leading up to it.
+fn failure_diagnostics(reason: &str, st: &TunnelState, endpoint: &str, retries: u32) -> String {
+    let local = st.endpoint_socket.local_addr()
+        .map(|addr| addr.to_string())
+        .unwrap_or_else(|_| "?".to_string());
+    format!(
+        "WireGuard diagnostics ({}): endpoint={} resolved={} local={} socket_gen={} retries={} \
+         handshake_tx_bytes={} handshake_rx_bytes={} last_rx_ms_ago={} last_decapsulate_error={}",
+        reason,
+        endpoint,
+        st.resolved_endpoint,
+        local,
+        st.socket_generation,
+        retries,
+        HANDSHAKE_TX_BYTES.load(Ordering::Relaxed),
+        HANDSHAKE_RX_BYTES.load(Ordering::Relaxed),
+        st.last_received.elapsed().as_millis(),
+        LAST_DECAPSULATE_ERROR.lock().as_deref().unwrap_or("none"),
+    )
+}
+
+/// Describe tunnel reconnect activity since the last wg_start_tunnel, one
+/// "key=value" per line: rehandshakes, ddns_reresolutions, endpoint_rebinds
+/// and last_error (empty if none).
+pub fn wg_get_tunnel_stats() -> String {
+    format!(
+        "rehandshakes={}\nddns_reresolutions={}\nendpoint_rebinds={}\nlast_error={}",
+        STAT_REHANDSHAKES.load(Ordering::Relaxed),
+        STAT_DDNS_RERESOLUTIONS.load(Ordering::Relaxed),
+        STAT_ENDPOINT_REBINDS.load(Ordering::Relaxed),
+        LAST_TUNNEL_ERROR.lock().as_deref().unwrap_or(""),
+    )
+}
+
+/// Traffic carried by the tunnel since the last wg_start_tunnel (see wg_tunnel_traffic)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TunnelTraffic {
+    /// Bytes of inner IP packets sent
+    pub tx_bytes: u64,
+    /// Bytes of inner IP packets received
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub rx_packets: u64,
+}
+
+/// Inner IP traffic through the tunnel since the last wg_start_tunnel. WireGuard
+/// and outer IP/UDP headers, handshakes and keepalives are not included. Still
+/// readable after wg_stop_tunnel.
+pub fn wg_tunnel_traffic() -> TunnelTraffic {
+    TunnelTraffic {
+        tx_bytes: STAT_TX_BYTES.load(Ordering::Relaxed),
+        rx_bytes: STAT_RX_BYTES.load(Ordering::Relaxed),
+        tx_packets: STAT_TX_PACKETS.load(Ordering::Relaxed),
+        rx_packets: STAT_RX_PACKETS.load(Ordering::Relaxed),
+    }
+}
+
+// ============================================================================
+// Endpoint receive queue
+// ============================================================================
+
+// The single receiver thread drains the endpoint socket. If it can't keep up,
+// datagrams pile up in the kernel receive buffer until the kernel starts
+// dropping them. The timer samples how full that buffer is.
+//
+// FIONREAD/SIOCINQ on a UDP socket only report the size of the next datagram,
+// so the fill level comes from SO_MEMINFO (Linux/Android), which reports the
+// memory charged to the receive queue against the SO_RCVBUF limit.
+
+/// Receive queue fill, in percent of the buffer, counted as close to overflowing
+const RX_QUEUE_HIGH_THRESHOLD_PERCENT: u64 = 75;
+
+/// Most bytes seen queued on the endpoint socket (-1 = not supported)
+static RX_QUEUE_HIGH_WATER: AtomicI64 = AtomicI64::new(0);
+
+/// Receive buffer limit at the last sample (-1 = not supported)
+static RX_QUEUE_CAPACITY: AtomicI64 = AtomicI64::new(0);
+
+/// Samples that found the queue above RX_QUEUE_HIGH_THRESHOLD_PERCENT
+static RX_QUEUE_OVER_THRESHOLD: AtomicU64 = AtomicU64::new(0);
+
+/// Bytes queued on a socket's receive buffer and the buffer's limit,
+/// or None if the platform can't report them
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn rx_queue_usage(socket: &UdpSocket) -> Option<(u32, u32)> {
+    use std::os::unix::io::AsRawFd;
+    // Not exported by every libc target; values from linux/sock_diag.h
+    const SO_MEMINFO: libc::c_int = 55;
+    const SK_MEMINFO_RMEM_ALLOC: usize = 0;
+    const SK_MEMINFO_RCVBUF: usize = 1;
+    const SK_MEMINFO_VARS: usize = 9;
+
+    let mut meminfo = [0u32; SK_MEMINFO_VARS];
+    let mut len = std::mem::size_of_val(&meminfo) as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_MEMINFO,
+            meminfo.as_mut_ptr() as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc != 0 || (len as usize) < (SK_MEMINFO_RCVBUF + 1) * 4 {
+        return None;
+    }
+    Some((meminfo[SK_MEMINFO_RMEM_ALLOC], meminfo[SK_MEMINFO_RCVBUF]))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn rx_queue_usage(_socket: &UdpSocket) -> Option<(u32, u32)> {
+    None
+}
+
+/// Fold one receive queue sample into the statistics
+fn record_rx_queue_sample(usage: Option<(u32, u32)>) {
+    let (queued, capacity) = match usage {
+        Some(usage) => usage,
+        None => {
+            RX_QUEUE_HIGH_WATER.store(-1, Ordering::Relaxed);
+            RX_QUEUE_CAPACITY.store(-1, Ordering::Relaxed);
+            return;
+        }
+    };
+    RX_QUEUE_HIGH_WATER.fetch_max(queued as i64, Ordering::Relaxed);
+    RX_QUEUE_CAPACITY.store(capacity as i64, Ordering::Relaxed);
+    if rx_queue_over_threshold(queued, capacity) {
+        let count = RX_QUEUE_OVER_THRESHOLD.fetch_add(1, Ordering::Relaxed) + 1;
+        debug!("WG endpoint receive queue at {}/{} bytes ({} times over threshold)", queued, capacity, count);
+    }
+}
+
+fn rx_queue_over_threshold(queued: u32, capacity: u32) -> bool {
+    capacity > 0 && queued as u64 * 100 > capacity as u64 * RX_QUEUE_HIGH_THRESHOLD_PERCENT
+}
+
+fn sample_rx_queue(socket: &UdpSocket) {
+    record_rx_queue_sample(rx_queue_usage(socket));
+}
+
+/// Endpoint receive queue statistics since the last wg_start_tunnel:
+/// (most bytes seen queued, receive buffer limit, samples over the threshold).
+/// The byte figures are -1 where the platform can't report them.
+pub fn wg_rx_queue_stats() -> (i64, i64, u64) {
+    (
+        RX_QUEUE_HIGH_WATER.load(Ordering::Relaxed),
+        RX_QUEUE_CAPACITY.load(Ordering::Relaxed),
+        RX_QUEUE_OVER_THRESHOLD.load(Ordering::Relaxed),
+    )
+}
+
+// ============================================================================
+// Session audit (debug builds only)
+// ============================================================================
+
+// Lets a reviewer confirm that handshakes produce fresh sessions without going
+// near key material. boringtun keeps session indices and nonce counters private,
+// so a session is identified by our own sequence number (advanced whenever the
+// handshake age drops) and its traffic by the bytes moved since it started.
+
+/// WireGuard's REJECT_AFTER_TIME: keys older than this are no longer used
+#[cfg(debug_assertions)]
+const REJECT_AFTER_TIME: Duration = Duration::from_secs(180);
+
+/// Non-secret metadata about the current session
+#[cfg(debug_assertions)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DebugSessionInfo {
+    /// Sessions established since the tunnel started (0 = no handshake yet)
+    pub session_number: u64,
+    /// Whether the session's keys are still within REJECT_AFTER_TIME
+    pub keys_current: bool,
+    /// Bytes sent with the current session's keys (approximate: sampled every timer tick)
+    pub tx_bytes: u64,
+    /// Bytes received with the current session's keys (approximate, as tx_bytes)
+    pub rx_bytes: u64,
+    /// Time since the handshake that established the session
+    pub since_handshake: Option<Duration>,
+}
+
+#[cfg(debug_assertions)]
+struct SessionTracker {
+    session_number: u64,
+    last_age: Option<Duration>,
+    tx_base: u64,
+    rx_base: u64,
+}
+
+#[cfg(debug_assertions)]
+impl SessionTracker {
+    const fn new() -> Self {
+        SessionTracker { session_number: 0, last_age: None, tx_base: 0, rx_base: 0 }
+    }
+
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feed one sample of boringtun's handshake age and byte totals. A younger
+    /// handshake than last time means a new session was established.
+    fn observe(&mut self, age: Option<Duration>, tx_total: u64, rx_total: u64) -> DebugSessionInfo {
+        if let Some(age) = age {
+            if self.last_age.map_or(true, |last| age < last) {
+                self.session_number += 1;
+                self.tx_base = tx_total;
+                self.rx_base = rx_total;
+            }
+        }
+        self.last_age = age;
+
+        DebugSessionInfo {
+            session_number: self.session_number,
+            keys_current: age.map_or(false, |age| age < REJECT_AFTER_TIME),
+            tx_bytes: tx_total.saturating_sub(self.tx_base),
+            rx_bytes: rx_total.saturating_sub(self.rx_base),
+            since_handshake: age,
+        }
+    }
+}
+
+#[cfg(debug_assertions)]
+static SESSION_TRACKER: Mutex<SessionTracker> = Mutex::new(SessionTracker::new());
+
+/// Sample the tunnel's session (called by the timer, so no rekey goes unnoticed)
+#[cfg(debug_assertions)]
+fn observe_session(tunnel: &Tunn) -> DebugSessionInfo {
+    let (age, tx_total, rx_total, _, _) = tunnel.stats();
+    SESSION_TRACKER.lock().observe(age, tx_total as u64, rx_total as u64)
+}
+
+/// Describe the current session without exposing any key material, or None if
+/// no tunnel is running. Only compiled into debug builds.
+#[cfg(debug_assertions)]
+pub fn wg_debug_session_info() -> Option<DebugSessionInfo> {
+    let state = GLOBAL_TUNNEL.lock().as_ref().map(|tunnel| tunnel.state.clone())?;
+    let st = state.lock();
+    Some(observe_session(&st.tunnel))
+}
+
+// ============================================================================
+// Simulated network changes (debug builds only)
+// ============================================================================
+
+// Rebinds and DDNS re-resolution only happen when the network or the endpoint's
+// DNS record really changes, which is hard to reproduce on demand. These hooks
+// drive the same recovery paths from tests and from a debug build's QA tools.
+
+/// Set by wg_debug_simulate_endpoint_ip_change so the timer thread re-resolves
+/// the endpoint on its next tick instead of waiting for the DDNS timeout
+#[cfg(debug_assertions)]
+static DEBUG_FORCE_DDNS: AtomicBool = AtomicBool::new(false);
+
+/// Whether a simulated endpoint change asked for DDNS re-resolution (consumes
+/// the request)
+#[cfg(debug_assertions)]
+fn debug_ddns_forced() -> bool {
+    DEBUG_FORCE_DDNS.swap(false, Ordering::AcqRel)
+}
+
+/// Release builds can't simulate endpoint changes
+#[cfg(not(debug_assertions))]
+fn debug_ddns_forced() -> bool {
+    false
+}
+
+/// Generation of the running tunnel's endpoint socket, or None if no tunnel is
+/// running. Only compiled into debug builds.
+#[cfg(debug_assertions)]
+pub fn wg_debug_socket_generation() -> Option<u64> {
+    let state = GLOBAL_TUNNEL.lock().as_ref().map(|tunnel| tunnel.state.clone())?;
+    let generation = state.lock().socket_generation;
+    Some(generation)
+}
+
+/// Act as if the device switched networks: rebind the endpoint socket and
+/// re-initiate the handshake, exactly as wg_rebind_endpoint does for a real
+/// network change. Returns the new socket generation. Only compiled into debug
+/// builds.
+#[cfg(debug_assertions)]
+pub fn wg_debug_simulate_network_change() -> io::Result<u64> {
+    info!("Debug: simulating a network change");
+    wg_rebind_endpoint()?;
+    wg_debug_socket_generation()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active"))
+}
+
+/// Act as if the endpoint's DNS record changed to `new_ip`: the next resolution
+/// of the endpoint returns it (keeping the configured port) and the timer thread
+/// re-resolves on its next tick, exercising the DDNS reconnect path. Only
+/// compiled into debug builds.
+#[cfg(debug_assertions)]
+pub fn wg_debug_simulate_endpoint_ip_change(new_ip: IpAddr) -> io::Result<()> {
+    let endpoint = GLOBAL_TUNNEL.lock().as_ref()
+        .map(|tunnel| tunnel.config.endpoint.clone())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active"))?;
+    info!("Debug: simulating endpoint '{}' moving to {}", endpoint, new_ip);
+    crate::wireguard_config::debug_override_next_resolution(&endpoint, new_ip);
+    DEBUG_FORCE_DDNS.store(true, Ordering::Release);
+    Ok(())
+}
+
+// ============================================================================
+// Send priority for latency-critical packets
+// ============================================================================
+
+/// Number of high-priority senders currently waiting for (or holding) the tunnel lock.
+/// Bulk senders and the receiver thread block while this is non-zero.
+static PRIORITY_SENDERS_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// Signalled (under PRIORITY_GATE) when the last pending high-priority sender finishes
+static PRIORITY_GATE: Mutex<()> = Mutex::new(());
+static PRIORITY_GATE_CLEARED: Condvar = Condvar::new();
+
+/// Upper bound on how long a bulk path waits for pending high-priority
+/// senders before taking the lock anyway, so bulk traffic can't be starved.
+const MAX_PRIORITY_WAIT: Duration = Duration::from_millis(2);
+
+/// Worst-case time (microseconds) a high-priority send waited for the tunnel lock
+static PRIORITY_SEND_MAX_WAIT_US: AtomicU64 = AtomicU64::new(0);
+
+/// Marks a high-priority send in flight for as long as it is alive.
+struct PrioritySendGuard {
+    started: Instant,
+}
+
+impl PrioritySendGuard {
+    fn new() -> Self {
+        PRIORITY_SENDERS_PENDING.fetch_add(1, Ordering::AcqRel);
+        PrioritySendGuard { started: Instant::now() }
+    }
+
+    /// Record how long this send waited to acquire the tunnel lock
+    fn record_lock_acquired(&self) {
+        let waited_us = self.started.elapsed().as_micros() as u64;
+        PRIORITY_SEND_MAX_WAIT_US.fetch_max(waited_us, Ordering::Relaxed);
+    }
+}
+
+impl Drop for PrioritySendGuard {
+    fn drop(&mut self) {
+        if PRIORITY_SENDERS_PENDING.fetch_sub(1, Ordering::AcqRel) == 1 {
+            // Taking the gate orders this wakeup after any waiter's check of the count
+            let _gate = PRIORITY_GATE.lock();
+            PRIORITY_GATE_CLEARED.notify_all();
+        }
+    }
+}
+
+/// Defer to pending high-priority senders before taking the tunnel lock.
+/// Returns whether the caller had to wait.
+fn yield_to_priority_senders() -> bool {
+    wait_for_priority_senders(MAX_PRIORITY_WAIT)
+}
+
+/// Block until no high-priority sender is pending, or `max_wait` has passed.
+/// Returns whether the caller had to wait.
+fn wait_for_priority_senders(max_wait: Duration) -> bool {
+    if PRIORITY_SENDERS_PENDING.load(Ordering::Acquire) == 0 {
+        return false;
+    }
+    let deadline = Instant::now() + max_wait;
+    let mut gate = PRIORITY_GATE.lock();
+    while PRIORITY_SENDERS_PENDING.load(Ordering::Acquire) > 0 {
+        if PRIORITY_GATE_CLEARED.wait_until(&mut gate, deadline).timed_out() {
+            break;
+        }
+    }
+    true
+}
+
+/// Worst-case tunnel lock wait of high-priority sends (microseconds) since the last call.
+/// Used to measure input latency under video load.
+pub fn wg_take_priority_send_max_wait_us() -> u64 {
+    PRIORITY_SEND_MAX_WAIT_US.swap(0, Ordering::Relaxed)
+}
+
+// ============================================================================
+// Global WireGuard tunnel instance + performance-optimized send cache
+// ============================================================================
+
+static GLOBAL_TUNNEL: Mutex<Option<WireGuardTunnel>> = Mutex::new(None);
+
+/// Serializes wg_start_tunnel and wg_stop_tunnel. Held across the thread joins
+/// instead of GLOBAL_TUNNEL, which tunnel threads lock themselves (e.g.
+/// wg_is_tunnel_active from the HTTP proxy's inject path).
+static TUNNEL_LIFECYCLE: Mutex<()> = Mutex::new(());
+
+/// Cached state for hot-path packet sending.
+/// Avoids double-lock on GLOBAL_TUNNEL and per-packet socket dup() syscall.
+struct WgSendCache {
+    state: Arc<Mutex<TunnelState>>,
+    send_socket: UdpSocket, // pre-cloned once
+    /// Whether send_socket is an IPv6 socket, which selects the DSCP control message
+    send_socket_ipv6: bool,
+}
+
+/// Send `data` on connected `socket` with its DSCP set to `dscp` for this packet
+/// only (an IP_TOS or IPV6_TCLASS control message). The socket's own TOS is left
+/// alone: it is shared with the receiver and timer threads, whose handshakes and
+/// keepalives must stay best effort. A `dscp` of 0 is a plain send.
+fn send_with_dscp(socket: &UdpSocket, data: &[u8], dscp: u8, ipv6: bool) -> io::Result<usize> {
+    use std::os::unix::io::AsRawFd;
+    if dscp == 0 {
+        return socket.send(data);
+    }
+    let tos: libc::c_int = (dscp as libc::c_int) << 2;
+    let (level, kind) = if ipv6 {
+        (libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+    } else {
+        (libc::IPPROTO_IP, libc::IP_TOS)
+    };
+
+    // u64 storage keeps the control buffer aligned for cmsghdr
+    let mut cmsg_buf = [0u64; 4];
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let rc = unsafe {
+        let space = libc::CMSG_SPACE(std::mem::size_of::<libc::c_int>() as u32) as usize;
+        let mut msg: libc::msghdr = std::mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = space as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = level;
+        (*cmsg).cmsg_type = kind;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<libc::c_int>() as u32) as _;
+        std::ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut libc::c_int, tos);
+        libc::sendmsg(socket.as_raw_fd(), &msg, 0)
+    };
+    if rc >= 0 {
+        Ok(rc as usize)
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+impl WgSendCache {
+    fn new(state: Arc<Mutex<TunnelState>>, send_socket: UdpSocket) -> Self {
+        let send_socket_ipv6 = matches!(send_socket.local_addr(), Ok(SocketAddr::V6(_)));
+        WgSendCache { state, send_socket, send_socket_ipv6 }
+    }
+
+    /// Send an encapsulated packet to the endpoint, marked with `dscp`
+    fn send(&self, data: &[u8], dscp: u8) -> io::Result<usize> {
+        send_with_dscp(&self.send_socket, data, dscp, self.send_socket_ipv6)
+    }
+
+    /// Swap in a new endpoint socket
+    fn replace_send_socket(&mut self, socket: UdpSocket) {
+        self.send_socket_ipv6 = matches!(socket.local_addr(), Ok(SocketAddr::V6(_)));
+        self.send_socket = socket;
+    }
+}
+static WG_SEND_CACHE: Mutex<Option<WgSendCache>> = Mutex::new(None);
+
+/// Most packets held while the initial handshake is in progress (0 = off, the default)
+static EARLY_SEND_QUEUE_LIMIT: AtomicUsize = AtomicUsize::new(0);
+
+/// Packets sent before the send cache exists; Some only while wg_start_tunnel
+/// waits for its handshake with early sends enabled.
+/// Lock order: WG_SEND_CACHE before EARLY_SEND_QUEUE.
+static EARLY_SEND_QUEUE: Mutex<Option<Vec<Vec<u8>>>> = Mutex::new(None);
+
+/// Let `wg_send_ip_packet` accept up to `max_packets` packets while the initial
+/// handshake is in progress instead of failing with NotConnected. They are sent
+/// the moment the session keys exist, which saves a handshake round trip for
+/// eager callers. Beyond the bound sends fail with WouldBlock; packets still held
+/// when the handshake fails are dropped. 0 disables it (the default).
+pub fn wg_set_early_send_queue(max_packets: usize) {
+    EARLY_SEND_QUEUE_LIMIT.store(max_packets, Ordering::Relaxed);
+    info!("WireGuard early send queue set to {} packets", max_packets);
+}
+
+fn open_early_send_queue() {
+    if EARLY_SEND_QUEUE_LIMIT.load(Ordering::Relaxed) > 0 {
+        *EARLY_SEND_QUEUE.lock() = Some(Vec::new());
+    }
+}
+
+/// Close the queue, returning how many held packets were dropped
+fn close_early_send_queue() -> usize {
+    EARLY_SEND_QUEUE.lock().take().map_or(0, |queue| queue.len())
+}
+
+/// Hold a packet for sending after the handshake. None if no queue is open.
+fn queue_early_packet(packet: &[u8]) -> Option<io::Result<()>> {
+    let mut queue = EARLY_SEND_QUEUE.lock();
+    let queue = queue.as_mut()?;
+    if queue.len() >= EARLY_SEND_QUEUE_LIMIT.load(Ordering::Relaxed) {
+        return Some(Err(io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "WireGuard handshake in progress (early send queue full)",
+        )));
+    }
+    queue.push(packet.to_vec());
+    Some(Ok(()))
+}
+
+// Thread-local encode buffer to avoid per-packet heap allocation (~65KB).
+thread_local! {
+    static ENCODE_BUF: RefCell<Vec<u8>> = RefCell::new(vec![0u8; WG_BUFFER_SIZE]);
+}
+
+/// Initialize and start the global WireGuard tunnel
+pub fn wg_start_tunnel(config: WireGuardConfig) -> io::Result<()> {
+    let _lifecycle = TUNNEL_LIFECYCLE.lock();
+
+    // Stop any existing tunnel, outside GLOBAL_TUNNEL so its threads can finish
+    let previous = GLOBAL_TUNNEL.lock().take();
+    if let Some(tunnel) = previous {
+        tunnel.stop();
+        // WgSocket connections routed through it are gone
+        crate::wg_socket::wg_socket_invalidate_handles();
+    }
+    // Clear send cache
+    *WG_SEND_CACHE.lock() = None;
+    close_early_send_queue();
+    reset_tunnel_stats();
+    #[cfg(debug_assertions)]
+    SESSION_TRACKER.lock().reset();
+    // A cancel aimed at an earlier attempt must not abort this one
+    HANDSHAKE_CANCELLED.store(false, Ordering::Release);
+    // Nor may a pause left over from an earlier session freeze this one
+    TUNNEL_PAUSED.store(false, Ordering::Release);
+    DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+    NO_DATA_SUSPECTED.store(false, Ordering::Release);
+    MTU_ISSUE_SUSPECTED.store(false, Ordering::Release);
+
+    report_tunnel_state(WgTunnelState::Connecting);
+    crate::callbacks::start_tunnel_timings();
+
+    let tunnel = match WireGuardTunnel::new(config) {
+        Ok(tunnel) => tunnel,
+        Err(e) => {
+            report_tunnel_state(WgTunnelState::Stopped);
+            return Err(e);
+        }
+    };
+    if let Err(e) = tunnel.start() {
+        report_tunnel_state(WgTunnelState::Stopped);
+        return Err(e);
+    }
+    
+    // Wait for handshake with active retry (the default schedule fits 4 retries)
+    open_early_send_queue();
+    if !tunnel.wait_for_handshake(INITIAL_HANDSHAKE_TIMEOUT) {
+        // Stopping ends the spawned threads; the send cache was cleared above
+        // and is only populated after a completed handshake
+        let dropped = close_early_send_queue();
+        if dropped > 0 {
+            warn!("Dropped {} packets queued during the failed handshake", dropped);
+        }
+        tunnel.stop();
+        report_tunnel_state(WgTunnelState::Stopped);
+        if HANDSHAKE_CANCELLED.swap(false, Ordering::AcqRel) {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "WireGuard handshake cancelled"));
+        }
+        record_tunnel_error("initial handshake timed out".to_string());
+        return Err(io::Error::new(io::ErrorKind::TimedOut, "WireGuard handshake timed out"));
+    }
+
+    // Populate send cache for hot-path
+    {
+        let state_arc = tunnel.state.clone();
+        let send_socket = {
+            let st = state_arc.lock();
+            st.endpoint_socket.try_clone()
+                .map_err(|e| {
+                    close_early_send_queue();
+                    io::Error::new(io::ErrorKind::Other, format!("Socket clone for cache: {}", e))
+                })?
+        };
+        let mut cache = WG_SEND_CACHE.lock();
+        let c = cache.insert(WgSendCache::new(state_arc, send_socket));
+        // Flush early packets before releasing the cache lock, so later sends
+        // can't overtake them
+        if let Some(early) = EARLY_SEND_QUEUE.lock().take() {
+            if !early.is_empty() {
+                info!("Sending {} packets queued during the handshake", early.len());
+                send_batch_with_cache(c, &early);
+            }
+        }
+    }
+
+    *GLOBAL_TUNNEL.lock() = Some(tunnel);
+    Ok(())
+}
+
+/// Stop the global WireGuard tunnel
+///
+/// Teardown order: the receiver and timer threads are joined first, then the
+/// send cache is cleared, then zero-copy routing is disabled. Invariant: tunnel
+/// threads must have exited before any state they use is cleared, so none of
+/// them is still pushing into channels, injecting into the HTTP proxy or
+/// swapping the send socket while it is torn down.
+pub fn wg_stop_tunnel() {
+    // Serialize with wg_start_tunnel. GLOBAL_TUNNEL itself is released before
+    // the join, since a thread being joined may need it to finish.
+    let _lifecycle = TUNNEL_LIFECYCLE.lock();
+
+    // 1. Quiesce: stop the tunnel and join its threads
+    let tunnel = GLOBAL_TUNNEL.lock().take();
+    if let Some(ref tunnel) = tunnel {
+        tunnel.stop();
+    }
+
+    // 2. Senders (streaming sockets, HTTP proxy) now see NotConnected
+    *WG_SEND_CACHE.lock() = None;
+    close_early_send_queue();
+
+    // 3. Nothing delivers into the zero-copy channels any more
+    crate::platform_sockets::disable_wg_routing();
+
+    drop(tunnel);
+
+    report_tunnel_state(WgTunnelState::Stopped);
+}
+
+/// Probe whether the peer is reachable through the tunnel, waiting up to
+/// `timeout` for an answer. Returns false if no tunnel is running.
+pub fn wg_probe_tunnel(timeout: Duration) -> bool {
+    // Don't hold GLOBAL_TUNNEL while waiting, so a stop isn't blocked by the probe
+    let state = match GLOBAL_TUNNEL.lock().as_ref() {
+        Some(tunnel) => tunnel.state.clone(),
+        None => return false,
+    };
+    WireGuardTunnel::probe(&state, timeout)
+}
+
+/// Check if the WireGuard tunnel is active and ready
+pub fn wg_is_tunnel_active() -> bool {
+    let global = GLOBAL_TUNNEL.lock();
+    global.as_ref().map_or(false, |t| t.is_ready())
+}
+
+/// RTP header space moonlight-common-c reserves per packet (MAX_RTP_HEADER_SIZE:
+/// the 12-byte fixed header plus room for an extension)
+pub const STREAM_RTP_HEADER_LEN: u16 = 16;
+
+/// Video packet header (NV_VIDEO_PACKET): stream packet index, frame index,
+/// flags and FEC info
+pub const STREAM_VIDEO_HEADER_LEN: u16 = 16;
+
+/// Video encryption header (ENC_VIDEO_HEADER) present when the host encrypts
+/// video: 12-byte IV, frame number and 16-byte GCM tag
+pub const STREAM_VIDEO_ENCRYPTION_HEADER_LEN: u16 = 32;
+
+/// Bytes a stream packet carries on top of its payload, assuming encrypted video
+pub const STREAM_PACKET_HEADER_OVERHEAD: u16 =
+    STREAM_RTP_HEADER_LEN + STREAM_VIDEO_HEADER_LEN + STREAM_VIDEO_ENCRYPTION_HEADER_LEN;
+
+/// Largest stream packet size (payload) that fits inside the tunnel's MTU without
+/// fragmenting the inner IP packet, or None unless the tunnel is up (see
+/// wg_is_tunnel_active).
+pub fn wg_max_stream_packet_size() -> Option<u16> {
+    let global = GLOBAL_TUNNEL.lock();
+    global.as_ref().filter(|t| t.is_ready()).map(|t| {
+        let header_len = t.config.inner_udp_header_len();
+        t.config.mtu.saturating_sub(header_len + STREAM_PACKET_HEADER_OVERHEAD)
+    })
+}
+
+/// WireGuard transport data header: message type (4), receiver index (4) and
+/// nonce counter (8)
+pub const WG_TRANSPORT_HEADER_LEN: u16 = 16;
+
+/// Poly1305 tag that ends every WireGuard transport message
+pub const WG_AUTH_TAG_LEN: u16 = 16;
+
+/// Bytes the tunnel adds to every UDP datagram sent through it: the inner IP +
+/// UDP headers, the WireGuard transport header and tag, and the outer UDP + IP
+/// headers to the endpoint. A preshared key only enters the handshake, so it
+/// doesn't change this. WireGuard also pads the inner packet to a multiple of
+/// 16 bytes, which adds up to 15 more bytes depending on the packet size.
+pub fn wg_overhead_bytes(inner_header_len: u16, outer_is_ipv6: bool) -> u16 {
+    let outer_header_len = if outer_is_ipv6 { 40 + 8 } else { 20 + 8 };
+    inner_header_len + WG_TRANSPORT_HEADER_LEN + WG_AUTH_TAG_LEN + outer_header_len
+}
+
+/// Per-datagram overhead (see wg_overhead_bytes) of the running tunnel, using
+/// the family of its current endpoint, or None if no tunnel is running.
+pub fn wg_tunnel_overhead_bytes() -> Option<u16> {
+    let global = GLOBAL_TUNNEL.lock();
+    global.as_ref().map(|t| {
+        let outer_is_ipv6 = t.state.lock().resolved_endpoint.is_ipv6();
+        wg_overhead_bytes(t.config.inner_udp_header_len(), outer_is_ipv6)
+    })
+}
+
+/// Send an IP packet through the global WireGuard tunnel (hot path).
+///
+/// Performance: Uses cached `Arc<Mutex<TunnelState>>` and pre-cloned socket
+/// to avoid double-lock and per-packet `dup()` syscall. Uses thread-local
+/// encode buffer to avoid per-packet 65KB heap allocation.
+pub fn wg_send_ip_packet(packet: &[u8]) -> io::Result<()> {
+    wg_send_ip_packet_with_priority(packet, false)
+}
+
+/// Send an IP packet through the global WireGuard tunnel, optionally as high priority.
+///
+/// High-priority sends (control/input) make bulk senders and the receiver thread
+/// defer briefly so the high-priority packet gets the tunnel lock next.
+pub fn wg_send_ip_packet_with_priority(packet: &[u8], high_priority: bool) -> io::Result<()> {
+    wg_send_ip_packet_with_dscp(packet, high_priority, 0)
+}
+
+/// Send an IP packet through the global WireGuard tunnel with the outer
+/// (endpoint) packet marked with `dscp`, so the inner stream's QoS class
+/// survives encapsulation. 0 sends best effort.
+pub fn wg_send_ip_packet_with_dscp(packet: &[u8], high_priority: bool, dscp: u8) -> io::Result<()> {
+    let priority = if high_priority {
+        Some(PrioritySendGuard::new())
+    } else {
+        yield_to_priority_senders();
+        None
+    };
+
+    let cache = WG_SEND_CACHE.lock();
+    let c = match cache.as_ref() {
+        Some(c) => c,
+        // Held for the handshake if an early send queue is open
+        None => return queue_early_packet(packet).unwrap_or_else(|| {
+            Err(io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active"))
+        }),
+    };
+
+    ENCODE_BUF.with(|buf_cell| {
+        let mut buf = buf_cell.borrow_mut();
+        // Encapsulate under tunnel state lock (fast crypto, ~microseconds)
+        // then send directly from the buffer - zero allocation hot path.
+        // The encrypted `data` slice borrows `buf` (not the lock), so we can
+        // send while still in the match arm without copying.
+        let mut st = c.state.lock();
+        if let Some(ref priority) = priority {
+            priority.record_lock_acquired();
+        }
+        match st.tunnel.encapsulate(packet, &mut buf) {
             TunnResult::WriteToNetwork(data) => {
                 // Send directly from encode buffer - eliminates to_vec() heap allocation
                 // Holding the tunnel lock during send() is acceptable: send() on a
                 // connected UDP socket is a fast non-blocking syscall (~1µs), much
                 // cheaper than a 1-64KB heap allocation + memcpy.
-                let result = c.send_socket.send(data);
+                let result = c.send(data, dscp);
                 drop(st);
+                record_endpoint_send(&result);
+                record_tunnel_tx(&result, packet.len());
                 result.map(|_| ())
             }
             TunnResult::Done => {
@@ -1033,15 +2785,19 @@ pub fn wg_send_ip_packet(packet: &[u8]) -> io::Result<()> {
                 // Retry encapsulate after timer flush
                 match st.tunnel.encapsulate(packet, &mut buf) {
                     TunnResult::WriteToNetwork(data) => {
-                        let result = c.send_socket.send(data);
+                        let result = c.send(data, dscp);
                         drop(st);
+                        record_endpoint_send(&result);
+                        record_tunnel_tx(&result, packet.len());
                         result.map(|_| ())
                     }
                     _ => {
                         drop(st);
                         warn!("encapsulate returned Done after timer flush — packet dropped");
+                        // WouldBlock (not NotConnected): the tunnel is up and a
+                        // handshake is in progress, so callers should not fall back.
                         Err(io::Error::new(
-                            io::ErrorKind::Other,
+                            io::ErrorKind::WouldBlock,
                             "WireGuard tunnel not ready (no session keys)",
                         ))
                     }
@@ -1062,18 +2818,39 @@ pub fn wg_send_ip_packet(packet: &[u8]) -> io::Result<()> {
     })
 }
 
+/// Whether a send error from `wg_send_ip_packet*` means the streaming tunnel is
+/// down (stopped or never started), as opposed to a transient failure such as
+/// missing session keys during a handshake (`WouldBlock`) or a socket error.
+///
+/// Callers with an alternative path (e.g. the HTTP proxy's own tunnel) should
+/// only fall back on this condition; transient failures recover by themselves.
+pub fn wg_is_tunnel_down(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::NotConnected
+}
+
 /// Batch-send multiple IP packets through the WireGuard tunnel.
 /// Single lock acquisition for all packets, minimizing lock contention.
+///
+/// Returns `NotConnected` only when the tunnel is down; packets dropped for
+/// lack of session keys are logged but do not fail the batch.
 pub fn wg_send_ip_packets_batch(packets: &[Vec<u8>]) -> io::Result<()> {
     if packets.is_empty() {
         return Ok(());
     }
 
+    yield_to_priority_senders();
+
     let cache = WG_SEND_CACHE.lock();
     let c = cache.as_ref().ok_or_else(|| {
         io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active")
     })?;
+    send_batch_with_cache(c, packets);
+    Ok(())
+}
 
+/// Encapsulate and send `packets` under one tunnel state lock. Send failures and
+/// packets without session keys are logged, not returned.
+fn send_batch_with_cache(c: &WgSendCache, packets: &[Vec<u8>]) {
     ENCODE_BUF.with(|buf_cell| {
         let mut buf = buf_cell.borrow_mut();
         // Encrypt and send each packet under a single lock acquisition.
@@ -1083,7 +2860,10 @@ pub fn wg_send_ip_packets_batch(packets: &[Vec<u8>]) -> io::Result<()> {
         for pkt in packets {
             match st.tunnel.encapsulate(pkt, &mut buf) {
                 TunnResult::WriteToNetwork(data) => {
-                    if let Err(e) = c.send_socket.send(data) {
+                    let result = c.send_socket.send(data);
+                    record_endpoint_send(&result);
+                    record_tunnel_tx(&result, pkt.len());
+                    if let Err(e) = result {
                         warn!("Batch send error: {}", e);
                     }
                 }
@@ -1103,7 +2883,10 @@ pub fn wg_send_ip_packets_batch(packets: &[Vec<u8>]) -> io::Result<()> {
                         // Retry after timer flush
                         match st.tunnel.encapsulate(pkt, &mut buf) {
                             TunnResult::WriteToNetwork(data) => {
-                                if let Err(e) = c.send_socket.send(data) {
+                                let result = c.send_socket.send(data);
+                                record_endpoint_send(&result);
+                                record_tunnel_tx(&result, pkt.len());
+                                if let Err(e) = result {
                                     warn!("Batch send error (retry): {}", e);
                                 }
                             }
@@ -1121,8 +2904,6 @@ pub fn wg_send_ip_packets_batch(packets: &[Vec<u8>]) -> io::Result<()> {
                 _ => {}
             }
         }
-        drop(st);
-        Ok(())
     })
 }
 
@@ -1134,6 +2915,19 @@ pub fn wg_send_ip_packets_batch(packets: &[Vec<u8>]) -> io::Result<()> {
 /// and replaces the old socket so the tunnel can continue operating on the
 /// new network path.  A fresh handshake is initiated automatically.
 pub fn wg_rebind_endpoint() -> io::Result<()> {
+    match rebind_endpoint() {
+        Ok(()) => {
+            STAT_ENDPOINT_REBINDS.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+        Err(e) => {
+            record_tunnel_error(format!("rebind: {}", e));
+            Err(e)
+        }
+    }
+}
+
+fn rebind_endpoint() -> io::Result<()> {
     let global = GLOBAL_TUNNEL.lock();
     let tunnel = global.as_ref().ok_or_else(|| {
         io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active")
@@ -1144,48 +2938,20 @@ pub fn wg_rebind_endpoint() -> io::Result<()> {
     }
 
     // Build the new socket under the state lock, then update the send cache outside it.
-    let new_send_socket: UdpSocket;
-    {
+    let new_send_socket = {
         let mut st = tunnel.state.lock();
-        let endpoint_addr = st.resolved_endpoint;
-
-        info!("Rebinding WireGuard endpoint socket to {} (network change)", endpoint_addr);
-
-        let new_socket = UdpSocket::bind(bind_addr_for(&endpoint_addr))?;
-        new_socket.connect(endpoint_addr)?;
-        new_socket.set_nonblocking(false)?;
-        new_socket.set_read_timeout(Some(Duration::from_millis(10)))?;
-        WireGuardTunnel::set_socket_buffer_sizes(&new_socket);
-
-        // Clone for send cache update (before moving into state)
-        new_send_socket = new_socket.try_clone()?;
-
-        // Replace socket in tunnel state
-        st.endpoint_socket = new_socket;
-        st.socket_generation += 1;
-
-        // Re-initiate handshake on the new socket
-        let mut dst_buf = vec![0u8; WG_BUFFER_SIZE];
-        match st.tunnel.format_handshake_initiation(&mut dst_buf, false) {
-            TunnResult::WriteToNetwork(data) => {
-                if let Err(e) = st.endpoint_socket.send(data) {
-                    warn!("Rebind: failed to send handshake initiation: {}", e);
-                } else {
-                    info!("Rebind: sent handshake initiation on new socket (gen={})", st.socket_generation);
-                }
-            }
-            _ => {}
-        }
+        info!("Rebinding WireGuard endpoint socket to {} (network change)", st.resolved_endpoint);
+        WireGuardTunnel::replace_endpoint_socket(&mut st, &tunnel.config, false)?
+    };
 
-        // Reset last_handshake so the timer thread doesn't immediately try DDNS re-resolution
-        st.last_handshake = Instant::now();
-    }
+    // Data has to be confirmed again on the new path
+    DATA_FLOWING_REPORTED.store(false, Ordering::Release);
 
     // Update send cache OUTSIDE the state lock to avoid deadlock
     {
         let mut cache = WG_SEND_CACHE.lock();
         if let Some(ref mut c) = *cache {
-            c.send_socket = new_send_socket;
+            c.replace_send_socket(new_send_socket);
             info!("Rebind: updated send cache with new socket");
         }
     }
@@ -1195,21 +2961,31 @@ pub fn wg_rebind_endpoint() -> io::Result<()> {
 }
 
 /// Enable direct WireGuard routing for UDP/TCP traffic.
-pub fn wg_enable_direct_routing(server_ip: Ipv4Addr) -> io::Result<()> {
+/// The tunnel needs an address of the server's family; on a dual-stack tunnel
+/// both addresses are routed, each used for destinations of its own family.
+pub fn wg_enable_direct_routing(server_ip: impl Into<IpAddr>) -> io::Result<()> {
+    let server_ip = server_ip.into();
     let global = GLOBAL_TUNNEL.lock();
     match global.as_ref() {
         Some(tunnel) => {
-            let tunnel_ip = match tunnel.config.tunnel_address {
-                IpAddr::V4(ip) => ip,
-                IpAddr::V6(_) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::InvalidInput,
-                        "IPv6 tunnel address not yet supported for direct routing",
-                    ));
-                }
-            };
-            crate::platform_sockets::enable_wg_routing(tunnel_ip, server_ip);
-            info!("Direct WireGuard routing enabled: tunnel_ip={}, server_ip={}", tunnel_ip, server_ip);
+            if tunnel.config.tunnel_address_for(server_ip).is_none() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("No tunnel address of the same family as server {} (have {:?})",
+                            server_ip, tunnel.config.tunnel_addresses),
+                ));
+            }
+            crate::platform_sockets::enable_wg_routing(
+                &tunnel.config.tunnel_addresses,
+                server_ip,
+                tunnel.config.inner_ttl,
+                tunnel.config.inner_traffic_class(),
+            );
+            // Each connection gets its own first-data report, even on a tunnel
+            // that is kept up between sessions
+            DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+            info!("Direct WireGuard routing enabled: tunnel_ips={:?}, server_ip={}",
+                  tunnel.config.tunnel_addresses, server_ip);
             Ok(())
         }
         None => Err(io::Error::new(io::ErrorKind::NotConnected, "WireGuard tunnel not active")),
@@ -1220,6 +2996,680 @@ pub fn wg_enable_direct_routing(server_ip: Ipv4Addr) -> io::Result<()> {
 mod tests {
     use super::*;
 
+    /// Serializes tests that send with no tunnel, since an open early send
+    /// queue changes what those sends return, and tests that install a global
+    /// tunnel or depend on the counters and flags it updates
+    static EARLY_SEND_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Receive one datagram on `socket` (with IP_RECVTOS set) and return its TOS byte
+    fn recv_tos(socket: &UdpSocket) -> Option<u8> {
+        use std::os::unix::io::AsRawFd;
+        let mut data = [0u8; 64];
+        let mut cmsg_buf = [0u64; 8];
+        let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = cmsg_buf.as_mut_ptr() as *mut libc::c_void;
+            msg.msg_controllen = std::mem::size_of_val(&cmsg_buf) as _;
+            assert!(libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) > 0);
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                if (*cmsg).cmsg_level == libc::IPPROTO_IP && (*cmsg).cmsg_type == libc::IP_TOS {
+                    return Some(*libc::CMSG_DATA(cmsg));
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_dscp_marks_only_its_own_packet() {
+        use std::os::unix::io::AsRawFd;
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let on: libc::c_int = 1;
+        let rc = unsafe {
+            libc::setsockopt(
+                receiver.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_RECVTOS,
+                &on as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(rc, 0);
+
+        // The send cache holds a dup of the endpoint socket the timer thread uses
+        let endpoint = UdpSocket::bind("127.0.0.1:0").unwrap();
+        endpoint.connect(receiver.local_addr().unwrap()).unwrap();
+        let send_socket = endpoint.try_clone().unwrap();
+
+        send_with_dscp(&send_socket, b"video", 46, false).unwrap();
+        assert_eq!(recv_tos(&receiver), Some(46 << 2));
+
+        // A handshake sent afterwards on the shared socket stays best effort
+        endpoint.send(b"handshake").unwrap();
+        assert_eq!(recv_tos(&receiver), Some(0));
+        send_with_dscp(&send_socket, b"audio", 0, false).unwrap();
+        assert_eq!(recv_tos(&receiver), Some(0));
+    }
+
+    #[test]
+    fn test_early_send_queue() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let packet = [0x45, 0, 0, 20];
+
+        // Off by default: opening does nothing and sends fail as before
+        open_early_send_queue();
+        assert!(wg_is_tunnel_down(&wg_send_ip_packet(&packet).unwrap_err()));
+
+        wg_set_early_send_queue(2);
+        open_early_send_queue();
+        assert!(wg_send_ip_packet(&packet).is_ok());
+        assert!(wg_send_ip_packet(&packet).is_ok());
+        let full = wg_send_ip_packet(&packet).unwrap_err();
+        assert_eq!(full.kind(), io::ErrorKind::WouldBlock);
+        assert!(!wg_is_tunnel_down(&full));
+        assert_eq!(close_early_send_queue(), 2);
+
+        // Closed again: back to NotConnected
+        assert!(wg_is_tunnel_down(&wg_send_ip_packet(&packet).unwrap_err()));
+        wg_set_early_send_queue(0);
+    }
+
+    #[test]
+    fn test_tunnel_down_is_distinct_from_transient_failure() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        // No tunnel has been started in tests, so the send cache is empty
+        let err = wg_send_ip_packet(&[0x45, 0, 0, 20]).unwrap_err();
+        assert!(wg_is_tunnel_down(&err));
+        let err = wg_send_ip_packets_batch(&[vec![0x45, 0, 0, 20]]).unwrap_err();
+        assert!(wg_is_tunnel_down(&err));
+
+        let no_keys = io::Error::new(io::ErrorKind::WouldBlock, "no session keys");
+        assert!(!wg_is_tunnel_down(&no_keys));
+        let encap = io::Error::new(io::ErrorKind::Other, "Encapsulate error");
+        assert!(!wg_is_tunnel_down(&encap));
+    }
+
+    #[test]
+    fn test_tunnel_stats_reset() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        STAT_REHANDSHAKES.fetch_add(2, Ordering::Relaxed);
+        STAT_ENDPOINT_REBINDS.fetch_add(4, Ordering::Relaxed);
+        record_tunnel_error("rebind: network unreachable".to_string());
+        let stats = wg_get_tunnel_stats();
+        assert!(stats.contains("endpoint_rebinds=4"));
+        assert!(stats.ends_with("last_error=rebind: network unreachable"));
+
+        reset_tunnel_stats();
+        assert_eq!(
+            wg_get_tunnel_stats(),
+            "rehandshakes=0\nddns_reresolutions=0\nendpoint_rebinds=0\nlast_error="
+        );
+    }
+
+    #[test]
+    fn test_handshake_escalation_threshold() {
+        assert!(!handshake_escalated(0));
+        assert!(!handshake_escalated(DEFAULT_HANDSHAKE_ESCALATION_THRESHOLD - 1));
+        assert!(handshake_escalated(DEFAULT_HANDSHAKE_ESCALATION_THRESHOLD));
+
+        wg_set_handshake_escalation_threshold(5);
+        assert!(!handshake_escalated(4));
+        assert!(handshake_escalated(5));
+        assert!(handshake_escalated(6));
+
+        // 0 disables escalation entirely
+        wg_set_handshake_escalation_threshold(0);
+        assert!(!handshake_escalated(0));
+        assert!(!handshake_escalated(u32::MAX));
+
+        wg_set_handshake_escalation_threshold(DEFAULT_HANDSHAKE_ESCALATION_THRESHOLD);
+    }
+
+    #[test]
+    fn test_proactive_rekey_lead() {
+        let lead = Duration::from_secs(DEFAULT_PROACTIVE_REKEY_LEAD_SECS);
+        assert!(!proactive_rekey_due(Duration::from_secs(30)));
+        assert!(!proactive_rekey_due(REKEY_AFTER_TIME - lead - Duration::from_secs(1)));
+        assert!(proactive_rekey_due(REKEY_AFTER_TIME - lead));
+        assert!(proactive_rekey_due(REKEY_AFTER_TIME));
+
+        wg_set_proactive_rekey_lead(30);
+        assert!(proactive_rekey_due(Duration::from_secs(90)));
+
+        // Clamped, so a fresh session is never re-keyed immediately
+        wg_set_proactive_rekey_lead(u64::MAX);
+        assert!(!proactive_rekey_due(Duration::from_secs(1)));
+
+        // 0 leaves re-keying to boringtun
+        wg_set_proactive_rekey_lead(0);
+        assert!(!proactive_rekey_due(REKEY_AFTER_TIME * 2));
+
+        wg_set_proactive_rekey_lead(DEFAULT_PROACTIVE_REKEY_LEAD_SECS);
+    }
+
+    #[test]
+    fn test_failure_diagnostics() {
+        let peer_secret = StaticSecret::from([8u8; 32]);
+        let config = WireGuardConfig {
+            private_key: [7u8; 32],
+            peer_public_key: PublicKey::from(&peer_secret).to_bytes(),
+            endpoint: "127.0.0.1:9".to_string(),
+            ..Default::default()
+        };
+        let tunnel = WireGuardTunnel::new(config).unwrap();
+        let st = tunnel.state.lock();
+
+        let line = failure_diagnostics("handshake timeout", &st, "127.0.0.1:9", 4);
+        assert!(line.starts_with("WireGuard diagnostics (handshake timeout): "));
+        assert!(line.contains(" resolved=127.0.0.1:9 "));
+        assert!(line.contains(" retries=4 "));
+        assert!(line.contains(&format!(" socket_gen={} ", st.socket_generation)));
+        assert!(!line.contains('\n'));
+    }
+
+    #[test]
+    fn test_cancel_handshake_wait() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let peer_secret = StaticSecret::from([6u8; 32]);
+        let config = WireGuardConfig {
+            private_key: [5u8; 32],
+            peer_public_key: PublicKey::from(&peer_secret).to_bytes(),
+            // Nothing answers here, so the handshake never completes
+            endpoint: "127.0.0.1:9".to_string(),
+            ..Default::default()
+        };
+        let tunnel = WireGuardTunnel::new(config).unwrap();
+
+        HANDSHAKE_CANCELLED.store(false, Ordering::Release);
+        let canceller = thread::spawn(|| {
+            thread::sleep(Duration::from_millis(200));
+            wg_cancel_handshake();
+        });
+        let start = Instant::now();
+        assert!(!tunnel.wait_for_handshake(Duration::from_secs(15)));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+        HANDSHAKE_CANCELLED.store(false, Ordering::Release);
+    }
+
+    #[test]
+    fn test_pause_resume() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        wg_pause();
+        assert!(wg_is_paused());
+        // Idempotent
+        wg_pause();
+        assert!(wg_is_paused());
+        wg_resume();
+        assert!(!wg_is_paused());
+
+        assert!(!pause_needs_rehandshake(Duration::from_secs(5)));
+        assert!(!pause_needs_rehandshake(PAUSE_REHANDSHAKE_AFTER));
+        assert!(pause_needs_rehandshake(Duration::from_secs(60)));
+    }
+
+    /// Tunnel whose endpoint is a local socket standing in for the peer
+    fn tunnel_with_local_peer(peer_public: PublicKey) -> (WireGuardTunnel, UdpSocket) {
+        let peer_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = WireGuardConfig {
+            private_key: [8u8; 32],
+            peer_public_key: peer_public.to_bytes(),
+            endpoint: peer_socket.local_addr().unwrap().to_string(),
+            ..Default::default()
+        };
+        (WireGuardTunnel::new(config).unwrap(), peer_socket)
+    }
+
+    /// Run the peer side of tunnel_with_local_peer on `peer_socket`: it answers
+    /// handshakes and echoes data packets back until the returned flag is set
+    fn spawn_echo_peer(peer_secret: StaticSecret, peer_socket: UdpSocket) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+        let client_public = PublicKey::from(&StaticSecret::from([8u8; 32]));
+        let stop = Arc::new(AtomicBool::new(false));
+        let peer_stop = stop.clone();
+        let peer = thread::spawn(move || {
+            let mut peer = Tunn::new(peer_secret, client_public, None, None, 1, None);
+            peer_socket.set_read_timeout(Some(Duration::from_millis(50))).unwrap();
+            let mut buf = vec![0u8; WG_BUFFER_SIZE];
+            let mut out = vec![0u8; WG_BUFFER_SIZE];
+            let mut echo = vec![0u8; WG_BUFFER_SIZE];
+            while !peer_stop.load(Ordering::Relaxed) {
+                if let Ok((n, from)) = peer_socket.recv_from(&mut buf) {
+                    match peer.decapsulate(None, &buf[..n], &mut out) {
+                        TunnResult::WriteToNetwork(data) => {
+                            peer_socket.send_to(data, from).unwrap();
+                        }
+                        TunnResult::WriteToTunnelV4(data, _) => {
+                            if let TunnResult::WriteToNetwork(data) = peer.encapsulate(data, &mut echo) {
+                                peer_socket.send_to(data, from).unwrap();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        });
+        (stop, peer)
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_simulated_network_changes() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        assert!(wg_debug_simulate_network_change().is_err());
+
+        let (tunnel, peer_socket) = tunnel_with_local_peer(PublicKey::from(&StaticSecret::from([9u8; 32])));
+        let peer_addr = peer_socket.local_addr().unwrap();
+        peer_socket.set_read_timeout(Some(Duration::from_secs(2))).unwrap();
+        // Marked running without its threads, so nothing else reads the peer socket
+        tunnel.running.store(true, Ordering::Release);
+        *GLOBAL_TUNNEL.lock() = Some(tunnel);
+
+        // A simulated network change moves to a new socket generation...
+        let before = wg_debug_socket_generation().unwrap();
+        assert_eq!(wg_debug_simulate_network_change().unwrap(), before + 1);
+
+        // ...and re-initiates the handshake from the new socket
+        let mut buf = vec![0u8; WG_BUFFER_SIZE];
+        let (n, from) = peer_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(classify_wg_message(&buf[..n]), WgMessageType::HandshakeInitiation);
+        let new_local = GLOBAL_TUNNEL.lock().as_ref().unwrap().state.lock().endpoint_socket.local_addr().unwrap();
+        assert_eq!(from, new_local);
+
+        // A simulated DNS change re-resolves once to the new address, same port
+        let new_ip: IpAddr = "127.0.0.2".parse().unwrap();
+        wg_debug_simulate_endpoint_ip_change(new_ip).unwrap();
+        assert!(debug_ddns_forced());
+        assert!(!debug_ddns_forced());
+        let config = GLOBAL_TUNNEL.lock().as_ref().unwrap().config.clone();
+        assert_eq!(config.resolve_endpoint().unwrap(), SocketAddr::new(new_ip, peer_addr.port()));
+        assert_eq!(config.resolve_endpoint().unwrap(), peer_addr);
+
+        GLOBAL_TUNNEL.lock().take();
+        reset_tunnel_stats();
+    }
+
+    #[test]
+    fn test_probe_tunnel() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let peer_secret = StaticSecret::from([7u8; 32]);
+        let peer_public = PublicKey::from(&peer_secret);
+
+        // Answering peer
+        let (tunnel, peer_socket) = tunnel_with_local_peer(peer_public);
+        tunnel.start().unwrap();
+        let (stop, peer) = spawn_echo_peer(peer_secret, peer_socket);
+        assert!(WireGuardTunnel::probe(&tunnel.state, Duration::from_secs(3)));
+        stop.store(true, Ordering::Relaxed);
+        peer.join().unwrap();
+        tunnel.stop();
+
+        // Silent peer
+        let (tunnel, _peer_socket) = tunnel_with_local_peer(PublicKey::from(&StaticSecret::from([9u8; 32])));
+        tunnel.start().unwrap();
+        let start = Instant::now();
+        assert!(!WireGuardTunnel::probe(&tunnel.state, Duration::from_millis(300)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        tunnel.stop();
+    }
+
+    #[test]
+    fn test_tunnel_traffic_counters() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let peer_secret = StaticSecret::from([7u8; 32]);
+        let peer_public = PublicKey::from(&peer_secret);
+
+        // Peer that answers the handshake and echoes data packets back
+        let (tunnel, peer_socket) = tunnel_with_local_peer(peer_public);
+        tunnel.start().unwrap();
+        let (stop, peer) = spawn_echo_peer(peer_secret, peer_socket);
+        // Counters are process-wide; measure this test's traffic as a delta
+        let before = wg_tunnel_traffic();
+        assert!(tunnel.wait_for_handshake(Duration::from_secs(5)));
+        let send_socket = tunnel.state.lock().endpoint_socket.try_clone().unwrap();
+        *WG_SEND_CACHE.lock() = Some(WgSendCache::new(tunnel.state.clone(), send_socket));
+
+        let src: SocketAddr = "10.0.0.2:40000".parse().unwrap();
+        let dst: SocketAddr = "10.0.0.1:9".parse().unwrap();
+        let packet = build_udp_ip_packet(src, dst, b"stats");
+        for _ in 0..3 {
+            wg_send_ip_packet(&packet).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(3);
+        while wg_tunnel_traffic().rx_packets < before.rx_packets + 3 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        *WG_SEND_CACHE.lock() = None;
+        stop.store(true, Ordering::Relaxed);
+        peer.join().unwrap();
+        tunnel.stop();
+
+        // Still readable after the tunnel stopped
+        let after = wg_tunnel_traffic();
+        let bytes = 3 * packet.len() as u64;
+        assert!(after.tx_packets - before.tx_packets >= 3);
+        assert!(after.rx_packets - before.rx_packets >= 3);
+        assert!(after.tx_bytes - before.tx_bytes >= bytes);
+        assert!(after.rx_bytes - before.rx_bytes >= bytes);
+    }
+
+    #[test]
+    fn test_stop_joins_tunnel_threads() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let (tunnel, _peer_socket) = tunnel_with_local_peer(PublicKey::from(&StaticSecret::from([9u8; 32])));
+        tunnel.start().unwrap();
+        assert_eq!(tunnel.threads.lock().len(), 2);
+
+        let start = Instant::now();
+        tunnel.stop();
+        // Both threads exited before the deadline rather than being detached
+        assert!(start.elapsed() < TUNNEL_STOP_TIMEOUT);
+        assert!(tunnel.threads.lock().is_empty());
+
+        // A second stop (e.g. from Drop) is a no-op
+        tunnel.stop();
+    }
+
+    #[test]
+    fn test_stop_does_not_block_receiver_in_inject_path() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let _maps = crate::platform_sockets::GLOBAL_MAPS_LOCK.lock();
+        let (tunnel, _peer_socket) = tunnel_with_local_peer(PublicKey::from(&StaticSecret::from([9u8; 32])));
+        tunnel.running.store(true, Ordering::Release);
+
+        // Stands in for the receiver finishing a wg_http_inject_packet call as
+        // the stop begins: flush_outgoing checks wg_is_tunnel_active()
+        let running = tunnel.running.clone();
+        let finished = Arc::new(AtomicBool::new(false));
+        let receiver_finished = finished.clone();
+        let receiver = thread::spawn(move || {
+            while running.load(Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(1));
+            }
+            assert!(!wg_is_tunnel_active());
+            receiver_finished.store(true, Ordering::Release);
+        });
+        tunnel.threads.lock().push(receiver);
+        *GLOBAL_TUNNEL.lock() = Some(tunnel);
+
+        // The receiver is joined, not detached after the timeout
+        let start = Instant::now();
+        wg_stop_tunnel();
+        assert!(start.elapsed() < TUNNEL_STOP_TIMEOUT);
+        assert!(finished.load(Ordering::Acquire));
+        assert!(GLOBAL_TUNNEL.lock().is_none());
+        reset_tunnel_stats();
+    }
+
+    #[test]
+    fn test_receiver_stall_detection() {
+        let ms = Duration::from_millis;
+        let window = DEFAULT_RECEIVER_STALL_WINDOW_MS;
+        assert!(!receiver_stalled(ms(window), ms(250)));
+        assert!(receiver_stalled(ms(window + 1), ms(250)));
+        // The timer was frozen as well: not a stall
+        assert!(!receiver_stalled(ms(window * 4), ms(window * 4)));
+
+        let liveness = ReceiverLiveness::new();
+        liveness.beat();
+        assert!(liveness.silent_for() < ms(window));
+
+        // 0 never reports
+        wg_set_receiver_stall_window(0);
+        assert!(!receiver_stalled(ms(3_600_000), ms(250)));
+        wg_set_receiver_stall_window(DEFAULT_RECEIVER_STALL_WINDOW_MS);
+    }
+
+    #[test]
+    fn test_overhead_bytes() {
+        // IPv4 inside and out: 28 inner + 32 WireGuard + 28 outer
+        assert_eq!(wg_overhead_bytes(20 + 8, false), 88);
+        // IPv6 inside and out
+        assert_eq!(wg_overhead_bytes(40 + 8, true), 128);
+        // Mixed families
+        assert_eq!(wg_overhead_bytes(20 + 8, true), 108);
+        assert_eq!(wg_overhead_bytes(40 + 8, false), 108);
+    }
+
+    #[test]
+    fn test_no_data_window() {
+        assert!(!no_data_overdue(Duration::from_secs(DEFAULT_NO_DATA_WINDOW_SECS - 1)));
+        assert!(no_data_overdue(Duration::from_secs(DEFAULT_NO_DATA_WINDOW_SECS)));
+
+        // 0 never reports
+        wg_set_no_data_window(0);
+        assert!(!no_data_overdue(Duration::from_secs(3600)));
+        wg_set_no_data_window(DEFAULT_NO_DATA_WINDOW_SECS);
+    }
+
+    #[test]
+    fn test_first_data_packet_one_shot() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+        assert!(take_first_data_packet());
+        assert!(!take_first_data_packet());
+        assert!(!take_first_data_packet());
+
+        // Restart/rebind re-arms it
+        DATA_FLOWING_REPORTED.store(false, Ordering::Release);
+        assert!(take_first_data_packet());
+    }
+
+    #[test]
+    fn test_mtu_probe_echo_round_trip() {
+        let src = Ipv4Addr::new(10, 0, 0, 2);
+        let dst = Ipv4Addr::new(10, 0, 0, 1);
+        let request = build_icmp_echo_request(src, dst, MTU_PROBE_LARGE_SEQ | 2, 1420, 64);
+        assert_eq!(request.len(), 1420);
+        assert_eq!(u16::from_be_bytes([request[10], request[11]]), ip_checksum(&request[..20]));
+        // A valid ICMP checksum sums the whole message to zero
+        assert_eq!(internet_checksum(&request[20..], None), 0);
+        // Our own request is not a reply
+        assert_eq!(parse_mtu_probe_reply(&request), None);
+
+        // The host answers with the same message as an echo reply
+        let mut reply = request.clone();
+        reply[12..16].copy_from_slice(&dst.octets());
+        reply[16..20].copy_from_slice(&src.octets());
+        reply[20] = 0;
+        assert_eq!(parse_mtu_probe_reply(&reply), Some(MTU_PROBE_LARGE_SEQ | 2));
+
+        let mut foreign = reply.clone();
+        foreign[24] ^= 0xFF;
+        assert_eq!(parse_mtu_probe_reply(&foreign), None);
+
+        MTU_PROBE_SMALL_REPLY.store(false, Ordering::Release);
+        MTU_PROBE_LARGE_REPLY.store(false, Ordering::Release);
+        let mut small_reply = build_icmp_echo_request(dst, src, 0, MTU_PROBE_SMALL_SIZE, 64);
+        small_reply[20] = 0;
+        record_mtu_probe_reply(&small_reply);
+        assert!(MTU_PROBE_SMALL_REPLY.load(Ordering::Acquire));
+        assert!(!MTU_PROBE_LARGE_REPLY.load(Ordering::Acquire));
+        record_mtu_probe_reply(&reply);
+        assert!(MTU_PROBE_LARGE_REPLY.load(Ordering::Acquire));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn test_session_tracker_counts_rekeys() {
+        let mut tracker = SessionTracker::new();
+
+        // Before the first handshake
+        let info = tracker.observe(None, 0, 0);
+        assert_eq!(info.session_number, 0);
+        assert!(!info.keys_current);
+
+        // First session, aging
+        assert_eq!(tracker.observe(Some(Duration::from_secs(1)), 148, 92).session_number, 1);
+        let info = tracker.observe(Some(Duration::from_secs(100)), 10_148, 50_092);
+        assert_eq!(info.session_number, 1);
+        assert!(info.keys_current);
+        assert_eq!((info.tx_bytes, info.rx_bytes), (10_000, 50_000));
+
+        // Rekey: the handshake age drops and the byte counts restart
+        let info = tracker.observe(Some(Duration::from_secs(2)), 12_000, 60_000);
+        assert_eq!(info.session_number, 2);
+        assert_eq!((info.tx_bytes, info.rx_bytes), (0, 0));
+
+        // Past REJECT_AFTER_TIME without a new handshake
+        let info = tracker.observe(Some(REJECT_AFTER_TIME), 12_000, 60_000);
+        assert_eq!(info.session_number, 2);
+        assert!(!info.keys_current);
+    }
+
+    #[test]
+    fn test_source_port_rotation_due() {
+        wg_set_source_port_rotation_retries(3);
+        let due: Vec<u32> = (0..10).filter(|&n| source_port_rotation_due(n)).collect();
+        assert_eq!(due, vec![3, 6, 9]);
+
+        wg_set_source_port_rotation_retries(0);
+        assert!((0..10).all(|n| !source_port_rotation_due(n)));
+
+        wg_set_source_port_rotation_retries(DEFAULT_SOURCE_PORT_ROTATION_RETRIES);
+    }
+
+    #[test]
+    fn test_rotated_port_sends_initiation_immediately() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let (tunnel, peer_socket) = tunnel_with_local_peer(PublicKey::from(&StaticSecret::from([9u8; 32])));
+        peer_socket.set_read_timeout(Some(Duration::from_millis(500))).unwrap();
+        let mut buf = vec![0u8; WG_BUFFER_SIZE];
+
+        // First initiation goes unanswered
+        tunnel.initiate_handshake().unwrap();
+        let (n, old_from) = peer_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(classify_wg_message(&buf[..n]), WgMessageType::HandshakeInitiation);
+
+        // Rotating re-sends it from the new port right away
+        assert!(tunnel.rotate_source_port());
+        let (n, from) = peer_socket.recv_from(&mut buf).unwrap();
+        assert_eq!(classify_wg_message(&buf[..n]), WgMessageType::HandshakeInitiation);
+        assert_ne!(from, old_from);
+        assert_eq!(from, tunnel.state.lock().endpoint_socket.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_rx_queue_threshold() {
+        assert!(!rx_queue_over_threshold(0, 212992));
+        assert!(!rx_queue_over_threshold(7500, 10000));
+        assert!(rx_queue_over_threshold(7501, 10000));
+        assert!(rx_queue_over_threshold(10500, 10000));
+        assert!(!rx_queue_over_threshold(100, 0));
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_rx_queue_usage_counts_unread_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let (empty, capacity) = rx_queue_usage(&receiver).unwrap();
+        assert_eq!(empty, 0);
+        assert!(capacity > 0);
+
+        for _ in 0..4 {
+            sender.send_to(&[0u8; 1000], receiver.local_addr().unwrap()).unwrap();
+        }
+        let (queued, _) = rx_queue_usage(&receiver).unwrap();
+        assert!(queued >= 4000);
+    }
+
+    #[test]
+    fn test_eperm_rebind_threshold() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let eperm = || -> io::Result<usize> { Err(io::Error::from_raw_os_error(libc::EPERM)) };
+        wg_set_eperm_rebind_threshold(3);
+        CONSECUTIVE_SEND_EPERMS.store(0, Ordering::Relaxed);
+
+        // Transient EPERMs separated by successful sends never trigger
+        for _ in 0..10 {
+            record_endpoint_send(&eperm());
+            record_endpoint_send(&eperm());
+            record_endpoint_send(&Ok(64));
+            assert!(!eperm_rebind_due());
+        }
+
+        // Other errors neither count nor end the run
+        record_endpoint_send(&eperm());
+        record_endpoint_send(&Err::<usize, _>(io::Error::from_raw_os_error(libc::ENETUNREACH)));
+        record_endpoint_send(&eperm());
+        assert!(!eperm_rebind_due());
+        record_endpoint_send(&eperm());
+        assert!(eperm_rebind_due());
+
+        // 0 disables automatic rebinds
+        wg_set_eperm_rebind_threshold(0);
+        assert!(!eperm_rebind_due());
+
+        wg_set_eperm_rebind_threshold(DEFAULT_EPERM_REBIND_THRESHOLD);
+        CONSECUTIVE_SEND_EPERMS.store(0, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_evaluate_mtu_probe() {
+        assert_eq!(evaluate_mtu_probe(true, true), MtuProbeResult::Ok);
+        assert_eq!(evaluate_mtu_probe(true, false), MtuProbeResult::PossibleMtuIssue);
+        assert_eq!(evaluate_mtu_probe(false, false), MtuProbeResult::Inconclusive);
+        // Nothing to conclude from a lost small echo if the large one made it
+        assert_eq!(evaluate_mtu_probe(false, true), MtuProbeResult::Ok);
+    }
+
+    #[test]
+    fn test_classify_wg_message() {
+        let mut initiation = [0u8; 148];
+        initiation[0] = 1;
+        assert_eq!(classify_wg_message(&initiation), WgMessageType::HandshakeInitiation);
+
+        let mut response = [0u8; 92];
+        response[0] = 2;
+        assert_eq!(classify_wg_message(&response), WgMessageType::HandshakeResponse);
+
+        // Right type, wrong size
+        assert_eq!(classify_wg_message(&initiation[..92]), WgMessageType::Unknown);
+        assert_eq!(classify_wg_message(&[4, 0]), WgMessageType::Unknown);
+    }
+
+    #[test]
+    fn test_peer_initiated_handshake_gets_response() {
+        let server_secret = StaticSecret::from([3u8; 32]);
+        let client_secret = StaticSecret::from([4u8; 32]);
+        let server_public = PublicKey::from(&server_secret);
+        let client_public = PublicKey::from(&client_secret);
+        let mut server = Tunn::new(server_secret, client_public, None, None, 1, None);
+        let mut client = Tunn::new(client_secret, server_public, None, None, 0, None);
+
+        // The server initiates (e.g. after its own restart)
+        let mut init_buf = vec![0u8; WG_BUFFER_SIZE];
+        let initiation = match server.format_handshake_initiation(&mut init_buf, false) {
+            TunnResult::WriteToNetwork(data) => data.to_vec(),
+            _ => panic!("expected a handshake initiation"),
+        };
+        assert_eq!(classify_wg_message(&initiation), WgMessageType::HandshakeInitiation);
+
+        // Our side answers it with a handshake response
+        let mut out_buf = vec![0u8; WG_BUFFER_SIZE];
+        let response = match client.decapsulate(None, &initiation, &mut out_buf) {
+            TunnResult::WriteToNetwork(data) => data.to_vec(),
+            _ => panic!("expected a handshake response"),
+        };
+        assert_eq!(classify_wg_message(&response), WgMessageType::HandshakeResponse);
+
+        // The server accepts it and confirms the session
+        let mut confirm_buf = vec![0u8; WG_BUFFER_SIZE];
+        match server.decapsulate(None, &response, &mut confirm_buf) {
+            TunnResult::WriteToNetwork(data) => {
+                assert_eq!(classify_wg_message(data), WgMessageType::TransportData);
+            }
+            _ => panic!("expected the server to confirm the session"),
+        }
+    }
+
     #[test]
     fn test_ip_checksum() {
         let header: [u8; 20] = [
@@ -1247,6 +3697,38 @@ mod tests {
         assert_eq!(data, payload);
     }
 
+    #[test]
+    fn test_parse_udp_ipv4_with_options() {
+        let src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 47998);
+        let dst = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 40000);
+        let payload = b"video frame";
+        let plain = build_udp_ip_packet(src, dst, payload);
+
+        // Insert a 4-byte Router Alert option: IHL 6, total length + 4
+        let mut packet = plain[..20].to_vec();
+        packet.extend_from_slice(&[0x94, 0x04, 0x00, 0x00]);
+        packet.extend_from_slice(&plain[20..]);
+        packet[0] = 0x46;
+        let total_len = packet.len() as u16;
+        packet[2..4].copy_from_slice(&total_len.to_be_bytes());
+
+        let (src_port, dst_port, data) = parse_udp_from_ip_packet(&packet).unwrap();
+        assert_eq!(src_port, 47998);
+        assert_eq!(dst_port, 40000);
+        assert_eq!(data, payload);
+
+        // Trailing bytes past the IPv4 total length are not part of the datagram
+        let mut padded = packet.clone();
+        padded.extend_from_slice(&[0u8; 6]);
+        let (_, _, data) = parse_udp_from_ip_packet(&padded).unwrap();
+        assert_eq!(data, payload);
+
+        // An IHL below the 20-byte minimum is malformed
+        let mut bad_ihl = plain.clone();
+        bad_ihl[0] = 0x44;
+        assert!(parse_udp_from_ip_packet(&bad_ihl).is_none());
+    }
+
     #[test]
     fn test_build_parse_udp_ipv6_packet() {
         let src = SocketAddr::new(
@@ -1267,13 +3749,62 @@ mod tests {
         assert_eq!(data, payload);
     }
 
+    #[test]
+    fn test_build_udp_packet_carries_ttl() {
+        let payload = b"ttl";
+        let mut buf = [0u8; 256];
+
+        let src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 5000);
+        let dst = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 6000);
+        let len = build_udp_ip_packet_into(&mut buf, src, dst, payload, 5, 0);
+        assert_eq!(buf[8], 5);
+        assert_eq!(u16::from_be_bytes([buf[10], buf[11]]), ip_checksum(&buf[..20]));
+        assert!(parse_udp_from_ip_packet(&buf[..len]).is_some());
+
+        let src = SocketAddr::new(IpAddr::V6("fd00::2".parse().unwrap()), 5000);
+        let dst = SocketAddr::new(IpAddr::V6("fd00::1".parse().unwrap()), 6000);
+        build_udp_ip_packet_into(&mut buf, src, dst, payload, 200, 0);
+        assert_eq!(buf[7], 200);
+
+        let packet = build_udp_ip_packet(src, dst, payload);
+        assert_eq!(packet[7], WireGuardConfig::DEFAULT_INNER_TTL);
+    }
+
+    #[test]
+    fn test_build_udp_packet_ecn_marking() {
+        let payload = b"ecn";
+        let mut buf = [0u8; 256];
+
+        let src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 5000);
+        let dst = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 6000);
+        build_udp_ip_packet_into(&mut buf, src, dst, payload, 64, WireGuardConfig::ECN_ECT0);
+        assert_eq!(buf[1] & 0b11, WireGuardConfig::ECN_ECT0);
+        assert_eq!(buf[1] >> 2, 0); // DSCP untouched
+        assert_eq!(u16::from_be_bytes([buf[10], buf[11]]), ip_checksum(&buf[..20]));
+        build_udp_ip_packet_into(&mut buf, src, dst, payload, 64, WireGuardConfig::ECN_NOT_ECT);
+        assert_eq!(buf[1] & 0b11, WireGuardConfig::ECN_NOT_ECT);
+
+        // IPv6 traffic class straddles bytes 0 and 1
+        let src = SocketAddr::new(IpAddr::V6("fd00::2".parse().unwrap()), 5000);
+        let dst = SocketAddr::new(IpAddr::V6("fd00::1".parse().unwrap()), 6000);
+        let len = build_udp_ip_packet_into(&mut buf, src, dst, payload, 64, WireGuardConfig::ECN_ECT0);
+        assert_eq!(buf[0], 0x60);
+        assert_eq!((((buf[0] & 0x0F) << 4) | (buf[1] >> 4)) & 0b11, WireGuardConfig::ECN_ECT0);
+        assert_eq!(buf[1] & 0x0F, 0); // Flow label untouched
+        assert!(parse_udp_from_ip_packet(&buf[..len]).is_some());
+
+        // Default packets are Not-ECT
+        let packet = build_udp_ip_packet(src, dst, payload);
+        assert_eq!((packet[1] >> 4) & 0b11, WireGuardConfig::ECN_NOT_ECT);
+    }
+
     #[test]
     fn test_build_udp_ip_packet_into_zero_alloc() {
         let src = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)), 5000);
         let dst = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 6000);
         let payload = b"test";
         let mut buf = [0u8; 256];
-        let len = build_udp_ip_packet_into(&mut buf, src, dst, payload);
+        let len = build_udp_ip_packet_into(&mut buf, src, dst, payload, 64, 0);
         assert_eq!(len, 20 + 8 + 4);
         let parsed = parse_udp_from_ip_packet(&buf[..len]);
         assert!(parsed.is_some());
@@ -1282,4 +3813,47 @@ mod tests {
         assert_eq!(dp, 6000);
         assert_eq!(d, payload);
     }
+
+    #[test]
+    fn test_bulk_yields_to_priority_send() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        // Nothing pending: bulk path takes the lock immediately
+        assert!(!yield_to_priority_senders());
+
+        // A stuck high-priority sender only delays bulk traffic by a bounded amount
+        let guard = PrioritySendGuard::new();
+        let started = Instant::now();
+        assert!(yield_to_priority_senders());
+        let waited = started.elapsed();
+        assert!(waited >= MAX_PRIORITY_WAIT && waited < Duration::from_secs(1), "waited {:?}", waited);
+        guard.record_lock_acquired();
+        drop(guard);
+
+        assert!(!yield_to_priority_senders());
+    }
+
+    #[test]
+    fn test_bulk_send_runs_after_priority_send() {
+        let _guard = EARLY_SEND_TEST_LOCK.lock();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let (pending_tx, pending_rx) = std::sync::mpsc::channel();
+
+        let priority_order = order.clone();
+        let priority = thread::spawn(move || {
+            let guard = PrioritySendGuard::new();
+            pending_tx.send(()).unwrap();
+            thread::sleep(Duration::from_millis(20));
+            priority_order.lock().push("priority");
+            drop(guard);
+        });
+
+        // Bulk sender arrives while the priority send is in flight and is woken once it finishes
+        pending_rx.recv().unwrap();
+        assert!(wait_for_priority_senders(Duration::from_secs(5)));
+        order.lock().push("bulk");
+        priority.join().unwrap();
+
+        assert_eq!(*order.lock(), vec!["priority", "bulk"]);
+        assert_eq!(PRIORITY_SENDERS_PENDING.load(Ordering::Acquire), 0);
+    }
 }
@@ -3,16 +3,237 @@
 //! This module contains the configuration structures and utilities for WireGuard tunnels.
 //! Separated from the main wireguard module for better modularity and reusability.
 
-use std::net::{IpAddr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs, UdpSocket};
 use std::io;
-use log::info;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use log::{info, warn};
+use parking_lot::Mutex;
+
+/// How long a warm-resolved endpoint is reused before DNS is consulted again
+pub const ENDPOINT_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// An endpoint resolved ahead of tunnel start (see warm_resolve_endpoint)
+struct CachedEndpoint {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+}
+
+/// Map from endpoint string ("host:port") → warm-resolved addresses
+static ENDPOINT_CACHE: LazyLock<Mutex<HashMap<String, CachedEndpoint>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Resolve `endpoint` now and cache the result for ENDPOINT_CACHE_TTL, so a
+/// subsequent tunnel start doesn't block on DNS.
+///
+/// Returns the address the tunnel would connect to.
+pub fn warm_resolve_endpoint(endpoint: &str) -> io::Result<SocketAddr> {
+    let addrs = lookup_endpoint_all(endpoint)?;
+    ENDPOINT_CACHE.lock().insert(endpoint.to_string(), CachedEndpoint {
+        addrs,
+        resolved_at: Instant::now(),
+    });
+
+    let config = WireGuardConfig {
+        endpoint: endpoint.to_string(),
+        ..Default::default()
+    };
+    config.resolve_endpoint()
+}
+
+/// Drop the cached resolution for `endpoint`, forcing the next resolve to hit DNS.
+/// Used before DDNS re-resolution so a stale entry can't mask an address change.
+pub fn forget_cached_endpoint(endpoint: &str) {
+    ENDPOINT_CACHE.lock().remove(endpoint);
+}
+
+/// Address the next resolution of an endpoint returns instead of DNS, set by
+/// wg_debug_simulate_endpoint_ip_change
+#[cfg(debug_assertions)]
+static DEBUG_NEXT_RESOLUTION: LazyLock<Mutex<HashMap<String, IpAddr>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Make the next resolution of `endpoint` return `ip` with the endpoint's port.
+/// Debug builds only.
+#[cfg(debug_assertions)]
+pub fn debug_override_next_resolution(endpoint: &str, ip: IpAddr) {
+    DEBUG_NEXT_RESOLUTION.lock().insert(endpoint.to_string(), ip);
+}
+
+/// Take the pending debug override for `endpoint`, if any
+#[cfg(debug_assertions)]
+fn take_debug_resolution(endpoint: &str) -> Option<SocketAddr> {
+    let ip = DEBUG_NEXT_RESOLUTION.lock().remove(endpoint)?;
+    let port = endpoint.rsplit_once(':')?.1.parse().ok()?;
+    Some(SocketAddr::new(ip, port))
+}
+
+/// Get the cached addresses for `endpoint` if they are younger than `ttl`.
+fn cached_endpoint_all(endpoint: &str, ttl: Duration) -> Option<Vec<SocketAddr>> {
+    let mut cache = ENDPOINT_CACHE.lock();
+    match cache.get(endpoint) {
+        Some(entry) if entry.resolved_at.elapsed() < ttl => Some(entry.addrs.clone()),
+        Some(_) => {
+            cache.remove(endpoint);
+            None
+        }
+        None => None,
+    }
+}
+
+/// Resolve the endpoint string to all SocketAddrs, bypassing the cache.
+/// Returns addresses with IPv6 first (preferred).
+fn lookup_endpoint_all(endpoint: &str) -> io::Result<Vec<SocketAddr>> {
+    if let Some(scoped) = parse_scoped_endpoint(endpoint) {
+        return scoped.map(|addr| vec![addr]);
+    }
 
-/// Return the unspecified bind address matching the address family of `addr`.
-/// IPv4 endpoints bind to `0.0.0.0:0`, IPv6 endpoints bind to `[::]:0`.
-fn bind_addr_for(addr: &SocketAddr) -> &'static str {
-    match addr {
-        SocketAddr::V4(_) => "0.0.0.0:0",
-        SocketAddr::V6(_) => "[::]:0",
+    let mut addrs: Vec<SocketAddr> = endpoint.to_socket_addrs()
+        .map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Failed to resolve endpoint '{}': {}", endpoint, e)
+        ))?
+        .collect();
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("DNS resolution returned no addresses for '{}'", endpoint)
+        ));
+    }
+
+    // Sort addresses: IPv6 first, then IPv4
+    addrs.sort_by_key(|addr| match addr {
+        SocketAddr::V6(_) => 0,
+        SocketAddr::V4(_) => 1,
+    });
+
+    Ok(addrs)
+}
+
+/// Parse an endpoint carrying an IPv6 scope id, e.g. `[fe80::1%wlan0]:51820`.
+///
+/// Returns `None` if the endpoint has no scope id (so the caller falls back to
+/// regular resolution). The scope may be an interface name, resolved to its index,
+/// or a numeric index. The returned address keeps the scope id so that `connect`
+/// on the endpoint socket reaches the link-local peer on the right interface.
+pub fn parse_scoped_endpoint(endpoint: &str) -> Option<io::Result<SocketAddr>> {
+    if !endpoint.contains('%') {
+        return None;
+    }
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+    let result = (|| {
+        let (host, port) = endpoint.rsplit_once(':').ok_or_else(|| {
+            invalid(format!("Invalid endpoint format '{}' (expected host:port)", endpoint))
+        })?;
+        let port: u16 = port.parse().map_err(|_| {
+            invalid(format!("Invalid port in endpoint '{}'", endpoint))
+        })?;
+        let host = host.strip_prefix('[').and_then(|h| h.strip_suffix(']')).unwrap_or(host);
+        let (addr, scope) = host.split_once('%').ok_or_else(|| {
+            invalid(format!("Invalid scoped address in endpoint '{}'", endpoint))
+        })?;
+        let addr: Ipv6Addr = addr.parse().map_err(|_| {
+            invalid(format!("Scope id in endpoint '{}' requires an IPv6 address", endpoint))
+        })?;
+        let scope_id = resolve_scope_id(scope)?;
+        Ok(SocketAddr::V6(SocketAddrV6::new(addr, port, 0, scope_id)))
+    })();
+
+    Some(result)
+}
+
+/// Resolve an IPv6 scope (interface name or numeric index) to an interface index.
+fn resolve_scope_id(scope: &str) -> io::Result<u32> {
+    if scope.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Empty IPv6 scope id"));
+    }
+    if let Ok(index) = scope.parse::<u32>() {
+        return Ok(index);
+    }
+
+    let name = std::ffi::CString::new(scope).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid interface name '{}'", scope))
+    })?;
+    let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+    if index == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("Unknown network interface '{}' in IPv6 scope id", scope),
+        ));
+    }
+    Ok(index)
+}
+
+/// When the handshake initiation is re-sent while wg_start_tunnel waits for the
+/// initial handshake. The first retry comes `initial` after start, and each
+/// following interval is the previous one times `multiplier`, capped at `max`.
+/// Retries only happen within the overall start timeout, so a slower schedule
+/// means fewer attempts: the default retries at 1s, 3s, 7s and 11s.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HandshakeRetrySchedule {
+    /// Delay before the first retry
+    pub initial: Duration,
+    /// Growth factor applied to the interval after each retry (1.0 to MAX_MULTIPLIER)
+    pub multiplier: f32,
+    /// Cap on the retry interval
+    pub max: Duration,
+}
+
+impl HandshakeRetrySchedule {
+    /// 1s initial interval, doubling up to 4s
+    pub const DEFAULT: HandshakeRetrySchedule = HandshakeRetrySchedule {
+        initial: Duration::from_secs(1),
+        multiplier: 2.0,
+        max: Duration::from_secs(4),
+    };
+
+    /// Largest accepted multiplier; beyond this the cap is reached after one retry anyway
+    pub const MAX_MULTIPLIER: f32 = 10.0;
+
+    /// Interval to wait after a retry that followed `interval`. Never panics,
+    /// even for a schedule that skipped validate(): an interval too large for
+    /// a Duration is taken as the cap.
+    pub fn next_interval(&self, interval: Duration) -> Duration {
+        Duration::try_from_secs_f32(interval.as_secs_f32() * self.multiplier)
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    /// Check that the schedule makes progress and doesn't shrink
+    pub fn validate(&self) -> io::Result<()> {
+        if self.initial.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Handshake retry interval must be greater than 0",
+            ));
+        }
+        if !(1.0..=Self::MAX_MULTIPLIER).contains(&self.multiplier) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Handshake retry multiplier {} must be between 1 and {}",
+                    self.multiplier,
+                    Self::MAX_MULTIPLIER
+                ),
+            ));
+        }
+        if self.max < self.initial {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Handshake retry cap must not be below the initial interval",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for HandshakeRetrySchedule {
+    fn default() -> Self {
+        Self::DEFAULT
     }
 }
 
@@ -27,16 +248,62 @@ pub struct WireGuardConfig {
     pub preshared_key: Option<[u8; 32]>,
     /// Peer endpoint as "host:port" string - resolved dynamically for DDNS support
     pub endpoint: String,
-    /// Local tunnel IP address (the virtual IP assigned to this client)
-    pub tunnel_address: IpAddr,
+    /// Local tunnel IP addresses (the virtual IPs assigned to this client), at
+    /// most one per address family. A dual-stack tunnel has an IPv4 and an IPv6
+    /// address; inner packets use the one matching the destination's family.
+    pub tunnel_addresses: Vec<IpAddr>,
     /// MTU for the tunnel
     pub mtu: u16,
+    /// Maximum handshake messages processed per second before boringtun's rate
+    /// limiter switches to cookie replies. Only handshake initiation/response
+    /// packets are counted; transport data packets are never rate-limited.
+    pub handshake_rate_limit: u64,
+    /// Optional local address the endpoint socket is bound to, forcing the tunnel
+    /// to originate from a specific source IP on multi-homed devices. Must be of
+    /// the same address family as the endpoint. `None` binds to the wildcard address.
+    pub bind_address: Option<IpAddr>,
+    /// TTL (IPv4) / hop limit (IPv6) of the inner packets built for routed UDP
+    /// traffic. Raise it when the server sits behind routers that decrement TTL
+    /// on the tunnel's inner packets.
+    pub inner_ttl: u8,
+    /// Mark inner packets ECN-capable (ECT(0)) so ECN-aware paths can signal
+    /// congestion instead of dropping. Off by default (Not-ECT), since some
+    /// middleboxes mishandle ECN-marked packets.
+    pub inner_ecn: bool,
+    /// When the initiation is re-sent while waiting for the initial handshake.
+    /// Slow down the first retry on high-latency (e.g. satellite) links, where
+    /// the first initiation may still be in flight after a second.
+    pub handshake_retry: HandshakeRetrySchedule,
 }
 
 impl WireGuardConfig {
     /// Default MTU for the tunnel
     pub const DEFAULT_MTU: u16 = 1420;
 
+    /// Minimum MTU for an IPv4 tunnel (minimum IPv4 datagram size every host must accept)
+    pub const MIN_MTU_V4: u16 = 576;
+
+    /// Minimum MTU for an IPv6 tunnel (IPv6 minimum link MTU, RFC 8200)
+    pub const MIN_MTU_V6: u16 = 1280;
+
+    /// Smallest stream packet payload the client requests (remote streaming packet size).
+    /// An MTU that can't carry this plus the inner IP/UDP headers forces fragmentation.
+    pub const MIN_STREAM_PAYLOAD: u16 = 1024;
+
+    /// Default handshake rate limit (packets per second).
+    /// Matches boringtun's per-peer default; a single client never legitimately
+    /// exchanges more than a couple of handshakes per second.
+    pub const DEFAULT_HANDSHAKE_RATE_LIMIT: u64 = 10;
+
+    /// Default TTL / hop limit of inner packets
+    pub const DEFAULT_INNER_TTL: u8 = 64;
+
+    /// ECN codepoint Not-ECT (transport is not ECN-capable)
+    pub const ECN_NOT_ECT: u8 = 0b00;
+
+    /// ECN codepoint ECT(0) (ECN-capable transport)
+    pub const ECN_ECT0: u8 = 0b01;
+
     /// Create a new WireGuard configuration with the minimum required parameters.
     ///
     /// # Arguments
@@ -55,8 +322,13 @@ impl WireGuardConfig {
             peer_public_key,
             preshared_key: None,
             endpoint,
-            tunnel_address,
+            tunnel_addresses: vec![tunnel_address],
             mtu: Self::DEFAULT_MTU,
+            handshake_rate_limit: Self::DEFAULT_HANDSHAKE_RATE_LIMIT,
+            bind_address: None,
+            inner_ttl: Self::DEFAULT_INNER_TTL,
+            inner_ecn: false,
+            handshake_retry: HandshakeRetrySchedule::DEFAULT,
         }
     }
 
@@ -85,30 +357,24 @@ impl WireGuardConfig {
     }
 
     /// Resolve the endpoint string to all SocketAddrs.
-    /// This performs DNS resolution if the endpoint contains a hostname.
+    /// Uses a warm-resolved cache entry if one is still fresh, otherwise performs
+    /// DNS resolution if the endpoint contains a hostname.
     /// Returns addresses with IPv6 first (preferred).
     pub fn resolve_endpoint_all(&self) -> io::Result<Vec<SocketAddr>> {
-        let mut addrs: Vec<SocketAddr> = self.endpoint.to_socket_addrs()
-            .map_err(|e| io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("Failed to resolve endpoint '{}': {}", self.endpoint, e)
-            ))?
-            .collect();
-
-        if addrs.is_empty() {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidInput,
-                format!("DNS resolution returned no addresses for '{}'", self.endpoint)
-            ));
+        #[cfg(debug_assertions)]
+        {
+            if let Some(addr) = take_debug_resolution(&self.endpoint) {
+                info!("Using simulated resolution for endpoint '{}': {}", self.endpoint, addr);
+                return Ok(vec![addr]);
+            }
         }
 
-        // Sort addresses: IPv6 first, then IPv4
-        addrs.sort_by_key(|addr| match addr {
-            SocketAddr::V6(_) => 0,
-            SocketAddr::V4(_) => 1,
-        });
+        if let Some(addrs) = cached_endpoint_all(&self.endpoint, ENDPOINT_CACHE_TTL) {
+            info!("Using cached resolution for endpoint '{}': {:?}", self.endpoint, addrs);
+            return Ok(addrs);
+        }
 
-        Ok(addrs)
+        lookup_endpoint_all(&self.endpoint)
     }
 
     /// Resolve the endpoint string to a SocketAddr.
@@ -120,7 +386,7 @@ impl WireGuardConfig {
 
         // Try each resolved address: pick the first one where we can actually bind a socket
         for addr in &addrs {
-            match UdpSocket::bind(bind_addr_for(addr)) {
+            match self.bind_addr_for(addr).and_then(UdpSocket::bind) {
                 Ok(_) => return Ok(*addr),
                 Err(e) => {
                     info!("Skipping resolved address {} for '{}': {}", addr, self.endpoint, e);
@@ -132,6 +398,40 @@ impl WireGuardConfig {
         Ok(addrs[0])
     }
 
+    /// Return the local address to bind the endpoint socket to for `endpoint`.
+    ///
+    /// Without a configured `bind_address` this is the unspecified address of the
+    /// endpoint's family (`0.0.0.0:0` or `[::]:0`). A configured address must match
+    /// the endpoint's family; the port is always chosen by the OS. For IPv6 the
+    /// endpoint's scope id is carried over so link-local source addresses bind.
+    pub fn bind_addr_for(&self, endpoint: &SocketAddr) -> io::Result<SocketAddr> {
+        match (self.bind_address, endpoint) {
+            (None, SocketAddr::V4(_)) => {
+                Ok(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)))
+            }
+            (None, SocketAddr::V6(_)) => {
+                Ok(SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::UNSPECIFIED, 0, 0, 0)))
+            }
+            (Some(IpAddr::V4(ip)), SocketAddr::V4(_)) => {
+                Ok(SocketAddr::V4(SocketAddrV4::new(ip, 0)))
+            }
+            (Some(IpAddr::V6(ip)), SocketAddr::V6(ep)) => {
+                Ok(SocketAddr::V6(SocketAddrV6::new(ip, 0, 0, ep.scope_id())))
+            }
+            (Some(bind), _) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Bind address {} does not match the address family of endpoint {}",
+                        bind, endpoint),
+            )),
+        }
+    }
+
+    /// Set the local address the endpoint socket binds to.
+    pub fn with_bind_address(mut self, addr: IpAddr) -> Self {
+        self.bind_address = Some(addr);
+        self
+    }
+
     /// Set the preshared key from raw bytes.
     pub fn with_preshared_key(mut self, psk: [u8; 32]) -> Self {
         self.preshared_key = Some(psk);
@@ -145,12 +445,81 @@ impl WireGuardConfig {
         Ok(self)
     }
 
+    /// Add a tunnel address, replacing the existing one of the same family.
+    /// Use it to add the IPv6 address of a dual-stack tunnel (or vice versa).
+    pub fn with_tunnel_address(mut self, addr: IpAddr) -> Self {
+        self.tunnel_addresses.retain(|a| a.is_ipv4() != addr.is_ipv4());
+        self.tunnel_addresses.push(addr);
+        self
+    }
+
     /// Set the MTU for the tunnel.
     pub fn with_mtu(mut self, mtu: u16) -> Self {
         self.mtu = mtu;
         self
     }
 
+    /// Set the handshake rate limit (handshake packets per second).
+    pub fn with_handshake_rate_limit(mut self, limit: u64) -> Self {
+        self.handshake_rate_limit = limit;
+        self
+    }
+
+    /// Set the TTL / hop limit of inner packets.
+    pub fn with_inner_ttl(mut self, ttl: u8) -> Self {
+        self.inner_ttl = ttl;
+        self
+    }
+
+    /// Set the handshake retry schedule used while the tunnel is starting.
+    pub fn with_handshake_retry(mut self, schedule: HandshakeRetrySchedule) -> Self {
+        self.handshake_retry = schedule;
+        self
+    }
+
+    /// Enable or disable ECT(0) marking of inner packets.
+    pub fn with_inner_ecn(mut self, enabled: bool) -> Self {
+        self.inner_ecn = enabled;
+        self
+    }
+
+    /// IPv4 DSCP+ECN byte / IPv6 traffic class for inner packets.
+    /// DSCP is left at 0 (best effort); only the ECN bits are configurable.
+    pub fn inner_traffic_class(&self) -> u8 {
+        if self.inner_ecn { Self::ECN_ECT0 } else { Self::ECN_NOT_ECT }
+    }
+
+    /// The tunnel address to use as the source of inner packets to `dest`: the
+    /// one of the same address family, or None if the tunnel has none.
+    pub fn tunnel_address_for(&self, dest: IpAddr) -> Option<IpAddr> {
+        tunnel_address_for(&self.tunnel_addresses, dest)
+    }
+
+    /// Whether the tunnel has an IPv6 address, which makes IPv6's larger
+    /// header and minimum MTU apply to the tunnel
+    fn has_ipv6_address(&self) -> bool {
+        self.tunnel_addresses.iter().any(IpAddr::is_ipv6)
+    }
+
+    /// Minimum MTU for the tunnel's address families (the IPv6 floor as soon
+    /// as any tunnel address is IPv6).
+    pub fn min_mtu(&self) -> u16 {
+        if self.has_ipv6_address() {
+            Self::MIN_MTU_V6
+        } else {
+            Self::MIN_MTU_V4
+        }
+    }
+
+    /// Inner IP + UDP header length of the largest family the tunnel carries
+    pub fn inner_udp_header_len(&self) -> u16 {
+        if self.has_ipv6_address() {
+            40 + 8
+        } else {
+            20 + 8
+        }
+    }
+
     /// Validate the configuration.
     pub fn validate(&self) -> io::Result<()> {
         // Check that keys are not all zeros
@@ -167,13 +536,66 @@ impl WireGuardConfig {
             ));
         }
 
-        // Check MTU is reasonable
-        if self.mtu < 576 {
+        if self.tunnel_addresses.is_empty() {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "MTU must be at least 576",
+                "At least one tunnel address is required",
             ));
         }
+        let v4_count = self.tunnel_addresses.iter().filter(|a| a.is_ipv4()).count();
+        if v4_count > 1 || self.tunnel_addresses.len() - v4_count > 1 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("At most one tunnel address per address family, got {:?}",
+                        self.tunnel_addresses),
+            ));
+        }
+
+        // Check MTU is reasonable for the tunnel's address family; below the floor
+        // the inner IP packets can't be built within the MTU.
+        let min_mtu = self.min_mtu();
+        if self.mtu < min_mtu {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("MTU {} is below the minimum of {} for tunnel addresses {:?}",
+                        self.mtu, min_mtu, self.tunnel_addresses),
+            ));
+        }
+
+        // Inner IP + UDP headers come out of the MTU before any stream payload
+        let header_len = self.inner_udp_header_len();
+        if self.mtu < Self::MIN_STREAM_PAYLOAD + header_len {
+            warn!("WireGuard MTU {} leaves only {} bytes for stream packets (at least {} recommended)",
+                  self.mtu, self.mtu - header_len, Self::MIN_STREAM_PAYLOAD);
+        }
+
+        if self.handshake_rate_limit == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Handshake rate limit must be greater than 0",
+            ));
+        }
+
+        if self.inner_ttl == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Inner packet TTL must be greater than 0",
+            ));
+        }
+
+        self.handshake_retry.validate()?;
+
+        // A literal endpoint can be checked against the bind address up front;
+        // hostnames are checked when the socket is bound after resolution.
+        if self.bind_address.is_some() {
+            let literal = match parse_scoped_endpoint(&self.endpoint) {
+                Some(scoped) => scoped.ok(),
+                None => self.endpoint.parse::<SocketAddr>().ok(),
+            };
+            if let Some(endpoint) = literal {
+                self.bind_addr_for(&endpoint)?;
+            }
+        }
 
         Ok(())
     }
@@ -186,12 +608,36 @@ impl Default for WireGuardConfig {
             peer_public_key: [0u8; 32],
             preshared_key: None,
             endpoint: "0.0.0.0:0".to_string(),
-            tunnel_address: "10.0.0.2".parse().unwrap(),
+            tunnel_addresses: vec!["10.0.0.2".parse().unwrap()],
             mtu: Self::DEFAULT_MTU,
+            handshake_rate_limit: Self::DEFAULT_HANDSHAKE_RATE_LIMIT,
+            bind_address: None,
+            inner_ttl: Self::DEFAULT_INNER_TTL,
+            inner_ecn: false,
+            handshake_retry: HandshakeRetrySchedule::DEFAULT,
         }
     }
 }
 
+/// The address in `addresses` of the same family as `dest`, if any
+pub fn tunnel_address_for(addresses: &[IpAddr], dest: IpAddr) -> Option<IpAddr> {
+    addresses.iter().copied().find(|a| a.is_ipv4() == dest.is_ipv4())
+}
+
+/// Parse a comma-separated list of tunnel addresses, e.g. "10.0.0.2, fd00::2"
+/// for a dual-stack tunnel. A single address parses as a one-element list.
+pub fn parse_tunnel_addresses(s: &str) -> io::Result<Vec<IpAddr>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.parse().map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid tunnel address '{}': {}", part, e))
+            })
+        })
+        .collect()
+}
+
 /// Decode a base64-encoded WireGuard key to a 32-byte array.
 pub fn decode_base64_key(key_b64: &str) -> io::Result<[u8; 32]> {
     use base64::Engine;
@@ -265,6 +711,9 @@ mod tests {
 
         assert_eq!(config.mtu, 1400);
         assert!(config.preshared_key.is_none());
+        assert_eq!(config.handshake_rate_limit, WireGuardConfig::DEFAULT_HANDSHAKE_RATE_LIMIT);
+        assert_eq!(config.inner_traffic_class(), WireGuardConfig::ECN_NOT_ECT);
+        assert_eq!(config.with_inner_ecn(true).inner_traffic_class(), WireGuardConfig::ECN_ECT0);
     }
 
     #[test]
@@ -298,9 +747,204 @@ mod tests {
         config.peer_public_key = [2u8; 32];
         assert!(config.validate().is_ok());
 
+        // Rate limiter cannot be disabled entirely
+        config.handshake_rate_limit = 0;
+        assert!(config.validate().is_err());
+        config.handshake_rate_limit = WireGuardConfig::DEFAULT_HANDSHAKE_RATE_LIMIT;
+
         // Invalid MTU
         config.mtu = 100;
         assert!(config.validate().is_err());
+        config.mtu = WireGuardConfig::DEFAULT_MTU;
+
+        // Bind address must match the endpoint's address family
+        config.endpoint = "192.168.1.1:51820".to_string();
+        config.bind_address = Some("10.1.2.3".parse().unwrap());
+        assert!(config.validate().is_ok());
+        config.bind_address = Some("2001:db8::2".parse().unwrap());
+        assert!(config.validate().is_err());
     }
-}
 
+    #[test]
+    fn test_handshake_retry_schedule() {
+        // Default: 1s, doubling, capped at 4s
+        let schedule = HandshakeRetrySchedule::DEFAULT;
+        let mut interval = schedule.initial;
+        let mut intervals = vec![interval];
+        for _ in 0..3 {
+            interval = schedule.next_interval(interval);
+            intervals.push(interval);
+        }
+        assert_eq!(intervals, [1, 2, 4, 4].map(Duration::from_secs));
+
+        let satellite = HandshakeRetrySchedule {
+            initial: Duration::from_secs(3),
+            multiplier: 1.5,
+            max: Duration::from_secs(6),
+        };
+        assert!(satellite.validate().is_ok());
+        assert_eq!(satellite.next_interval(satellite.initial), Duration::from_millis(4500));
+        assert_eq!(satellite.next_interval(Duration::from_millis(4500)), Duration::from_secs(6));
+
+        let mut config = WireGuardConfig::default();
+        config.private_key = [1u8; 32];
+        config.peer_public_key = [2u8; 32];
+        assert!(config.clone().with_handshake_retry(satellite).validate().is_ok());
+        let shrinking = HandshakeRetrySchedule { multiplier: 0.5, ..satellite };
+        assert!(config.clone().with_handshake_retry(shrinking).validate().is_err());
+        let zero = HandshakeRetrySchedule { initial: Duration::ZERO, ..satellite };
+        assert!(config.clone().with_handshake_retry(zero).validate().is_err());
+        let capped_low = HandshakeRetrySchedule { max: Duration::from_secs(1), ..satellite };
+        assert!(config.clone().with_handshake_retry(capped_low).validate().is_err());
+        let nan = HandshakeRetrySchedule { multiplier: f32::NAN, ..satellite };
+        assert!(config.clone().with_handshake_retry(nan).validate().is_err());
+
+        // A huge multiplier is rejected, and even unvalidated it only reaches the cap
+        let huge = HandshakeRetrySchedule { multiplier: 1e20, ..satellite };
+        assert!(config.with_handshake_retry(huge).validate().is_err());
+        assert_eq!(huge.next_interval(huge.initial), huge.max);
+        let infinite = HandshakeRetrySchedule { multiplier: f32::INFINITY, ..satellite };
+        assert_eq!(infinite.next_interval(infinite.initial), infinite.max);
+    }
+
+    #[test]
+    fn test_mtu_floor_per_address_family() {
+        let mut config = WireGuardConfig::default();
+        config.private_key = [1u8; 32];
+        config.peer_public_key = [2u8; 32];
+
+        // IPv4 tunnel: 576 is the floor
+        config.tunnel_addresses = vec!["10.0.0.2".parse().unwrap()];
+        config.mtu = WireGuardConfig::MIN_MTU_V4 - 1;
+        assert_eq!(config.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        config.mtu = WireGuardConfig::MIN_MTU_V4;
+        assert!(config.validate().is_ok());
+
+        // IPv6 tunnel: 1280 is the floor, so 576 is no longer enough
+        config.tunnel_addresses = vec!["fd00::2".parse().unwrap()];
+        assert!(config.validate().is_err());
+        config.mtu = WireGuardConfig::MIN_MTU_V6 - 1;
+        assert!(config.validate().is_err());
+        config.mtu = WireGuardConfig::MIN_MTU_V6;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_dual_stack_tunnel_addresses() {
+        let v4: IpAddr = "10.0.0.2".parse().unwrap();
+        let v6: IpAddr = "fd00::2".parse().unwrap();
+        let mut config = WireGuardConfig::new([1u8; 32], [2u8; 32], "192.168.1.1:51820".to_string(), v4)
+            .with_mtu(WireGuardConfig::MIN_MTU_V6)
+            .with_tunnel_address(v6);
+        assert_eq!(config.tunnel_addresses, vec![v4, v6]);
+        assert!(config.validate().is_ok());
+
+        // Each destination gets the source address of its own family
+        assert_eq!(config.tunnel_address_for("10.0.0.1".parse().unwrap()), Some(v4));
+        assert_eq!(config.tunnel_address_for("fd00::1".parse().unwrap()), Some(v6));
+        assert_eq!(config.inner_udp_header_len(), 48);
+
+        // Adding another address of a family replaces the old one
+        let v6b: IpAddr = "fd00::3".parse().unwrap();
+        config = config.with_tunnel_address(v6b);
+        assert_eq!(config.tunnel_addresses, vec![v4, v6b]);
+
+        // A single-family tunnel has no source for the other family
+        config.tunnel_addresses = vec![v4];
+        assert_eq!(config.tunnel_address_for("fd00::1".parse().unwrap()), None);
+
+        config.tunnel_addresses = vec![v4, "10.0.0.3".parse().unwrap()];
+        assert_eq!(config.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        config.tunnel_addresses.clear();
+        assert_eq!(config.validate().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_parse_tunnel_addresses() {
+        let v4: IpAddr = "10.0.0.2".parse().unwrap();
+        let v6: IpAddr = "fd00::2".parse().unwrap();
+        assert_eq!(parse_tunnel_addresses("10.0.0.2").unwrap(), vec![v4]);
+        assert_eq!(parse_tunnel_addresses(" 10.0.0.2 , fd00::2 ").unwrap(), vec![v4, v6]);
+        assert!(parse_tunnel_addresses("10.0.0.2, nope").is_err());
+        assert!(parse_tunnel_addresses("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_endpoint_cache() {
+        let endpoint = "cached.invalid:51820";
+        let cached: SocketAddr = "203.0.113.7:51820".parse().unwrap();
+        let config = WireGuardConfig {
+            endpoint: endpoint.to_string(),
+            ..Default::default()
+        };
+
+        // A fresh entry is used instead of DNS
+        ENDPOINT_CACHE.lock().insert(endpoint.to_string(), CachedEndpoint {
+            addrs: vec![cached],
+            resolved_at: Instant::now(),
+        });
+        assert_eq!(config.resolve_endpoint_all().unwrap(), vec![cached]);
+
+        // An expired entry is dropped
+        assert!(cached_endpoint_all(endpoint, Duration::ZERO).is_none());
+        assert!(!ENDPOINT_CACHE.lock().contains_key(endpoint));
+
+        // Forgetting an entry falls back to DNS (which fails for .invalid)
+        ENDPOINT_CACHE.lock().insert(endpoint.to_string(), CachedEndpoint {
+            addrs: vec![cached],
+            resolved_at: Instant::now(),
+        });
+        forget_cached_endpoint(endpoint);
+        assert!(config.resolve_endpoint_all().is_err());
+    }
+
+    #[test]
+    fn test_bind_addr_for() {
+        let v4: SocketAddr = "192.168.1.1:51820".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:51820".parse().unwrap();
+
+        // Wildcard address of the endpoint's family by default
+        let mut config = WireGuardConfig::default();
+        assert_eq!(config.bind_addr_for(&v4).unwrap(), "0.0.0.0:0".parse().unwrap());
+        assert_eq!(config.bind_addr_for(&v6).unwrap(), "[::]:0".parse().unwrap());
+
+        // Configured address is used with an OS-chosen port
+        config = config.with_bind_address("10.1.2.3".parse().unwrap());
+        assert_eq!(config.bind_addr_for(&v4).unwrap(), "10.1.2.3:0".parse().unwrap());
+        let err = config.bind_addr_for(&v6).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Link-local source addresses keep the endpoint's scope id
+        config.bind_address = Some("fe80::2".parse().unwrap());
+        let scoped = SocketAddr::V6(SocketAddrV6::new("fe80::1".parse().unwrap(), 51820, 0, 3));
+        match config.bind_addr_for(&scoped).unwrap() {
+            SocketAddr::V6(v6) => assert_eq!(v6.scope_id(), 3),
+            SocketAddr::V4(_) => panic!("expected IPv6 address"),
+        }
+    }
+
+    #[test]
+    fn test_parse_scoped_endpoint() {
+        // No scope: regular resolution applies
+        assert!(parse_scoped_endpoint("[fe80::1]:51820").is_none());
+        assert!(parse_scoped_endpoint("vpn.example.com:51820").is_none());
+
+        // Numeric scope id is preserved
+        let addr = parse_scoped_endpoint("[fe80::1%3]:51820").unwrap().unwrap();
+        match addr {
+            SocketAddr::V6(v6) => {
+                assert_eq!(*v6.ip(), "fe80::1".parse::<Ipv6Addr>().unwrap());
+                assert_eq!(v6.port(), 51820);
+                assert_eq!(v6.scope_id(), 3);
+            }
+            SocketAddr::V4(_) => panic!("expected IPv6 address"),
+        }
+
+        // Unknown interface gives a clear NotFound error
+        let err = parse_scoped_endpoint("[fe80::1%nosuchif0]:51820").unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        // Scope ids only make sense for IPv6
+        assert!(parse_scoped_endpoint("10.0.0.1%3:51820").unwrap().is_err());
+    }
+}